@@ -1,11 +1,34 @@
 use crate::chatml::ChatMLPrompt;
+use crate::error::AppError;
+use crate::metrics::{metrics, observe_upstream};
 use crate::models::{KoboldCppGenerate, KoboldCppResponse, Settings, TtsRequest, Voice};
 
+trait WithApiKey {
+    /// Authenticate with a bearer token when an API key is configured
+    fn with_api_key(self, api_key: &Option<String>) -> Self;
+}
+
+impl WithApiKey for reqwest::RequestBuilder {
+    fn with_api_key(self, api_key: &Option<String>) -> Self {
+        match api_key.as_deref().filter(|key| !key.is_empty()) {
+            Some(key) => self.bearer_auth(key),
+            None => self,
+        }
+    }
+}
+
 /// Calls the LLM API with a ChatML prompt and returns the response parsed as a ChatMLPrompt
 pub(crate) async fn call_llm_chatml(
     settings: &Settings,
     chatml_prompt: &ChatMLPrompt,
-) -> Result<ChatMLPrompt, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<ChatMLPrompt, AppError> {
+    observe_upstream("llm", request_llm_chatml(settings, chatml_prompt)).await
+}
+
+async fn request_llm_chatml(
+    settings: &Settings,
+    chatml_prompt: &ChatMLPrompt,
+) -> Result<ChatMLPrompt, AppError> {
     let prompt_string = chatml_prompt.to_chatml_for_generation();
 
     let kobold_request = KoboldCppGenerate {
@@ -32,19 +55,20 @@ pub(crate) async fn call_llm_chatml(
     let timer = std::time::Instant::now();
     let response = client
         .post(&settings.llm_api)
+        .with_api_key(&settings.llm_api_key)
         .json(&kobold_request)
         .send()
-        .await?;
+        .await
+        .map_err(|e| AppError::UpstreamLlm(format!("LLM API request failed: {e}")))?;
 
     let duration = timer.elapsed();
 
     if !response.status().is_success() {
-        return Err(format!(
+        return Err(AppError::UpstreamLlm(format!(
             "LLM API returned error: {}, duration: {:?}",
             response.status(),
             duration
-        )
-        .into());
+        )));
     } else {
         tracing::debug!(
             "LLM API returned success: {}, duration: {:?}",
@@ -53,12 +77,18 @@ pub(crate) async fn call_llm_chatml(
         );
     }
 
-    let kobold_response: KoboldCppResponse = response.json().await?;
+    let kobold_response: KoboldCppResponse = response
+        .json()
+        .await
+        .map_err(|e| AppError::UpstreamLlm(format!("LLM API returned invalid JSON: {e}")))?;
 
     // Debug trace the raw response
     tracing::trace!("Raw Kobold response: {:#?}", kobold_response);
 
     if let Some(result) = kobold_response.results.first() {
+        if let Some(tokens) = result.completion_tokens {
+            metrics().generated_tokens.inc_by(tokens);
+        }
         // Create a complete ChatML string with the original prompt + assistant response
         let complete_chatml = format!("{}{}", prompt_string, result.text.trim());
 
@@ -80,15 +110,18 @@ pub(crate) async fn call_llm_chatml(
             }
         }
     } else {
-        Err("No results returned from LLM".into())
+        Err(AppError::UpstreamLlm(
+            "No results returned from LLM".to_string(),
+        ))
     }
 }
 
 /// Calls the LLM API with the given prompt and returns the processed result as lines
-pub(crate) async fn call_llm(
-    settings: &Settings,
-    prompt: &str,
-) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+pub(crate) async fn call_llm(settings: &Settings, prompt: &str) -> Result<Vec<String>, AppError> {
+    observe_upstream("llm", request_llm(settings, prompt)).await
+}
+
+async fn request_llm(settings: &Settings, prompt: &str) -> Result<Vec<String>, AppError> {
     let kobold_request = KoboldCppGenerate {
         max_context_length: 8192,
         max_length: 200,
@@ -114,19 +147,20 @@ pub(crate) async fn call_llm(
     let timer = std::time::Instant::now();
     let response = client
         .post(&settings.llm_api)
+        .with_api_key(&settings.llm_api_key)
         .json(&kobold_request)
         .send()
-        .await?;
+        .await
+        .map_err(|e| AppError::UpstreamLlm(format!("LLM API request failed: {e}")))?;
 
     let duration = timer.elapsed();
 
     if !response.status().is_success() {
-        return Err(format!(
+        return Err(AppError::UpstreamLlm(format!(
             "LLM API returned error: {}, duration: {:?}",
             response.status(),
             duration
-        )
-        .into());
+        )));
     } else {
         tracing::debug!(
             "LLM API returned success: {}, duration: {:?}",
@@ -135,12 +169,18 @@ pub(crate) async fn call_llm(
         );
     }
 
-    let kobold_response: KoboldCppResponse = response.json().await?;
+    let kobold_response: KoboldCppResponse = response
+        .json()
+        .await
+        .map_err(|e| AppError::UpstreamLlm(format!("LLM API returned invalid JSON: {e}")))?;
 
     // Debug trace the raw response
     tracing::info!("Raw Kobold response: {:#?}", kobold_response);
 
     if let Some(result) = kobold_response.results.first() {
+        if let Some(tokens) = result.completion_tokens {
+            metrics().generated_tokens.inc_by(tokens);
+        }
         tracing::debug!("Raw result text: {}", result.text);
 
         let marked_content = extract_marked_content(&result.text, "<|im_start|>", "<|im_end|>");
@@ -153,7 +193,9 @@ pub(crate) async fn call_llm(
             Ok(vec![result.text.clone()])
         }
     } else {
-        Err("No results returned from LLM".into())
+        Err(AppError::UpstreamLlm(
+            "No results returned from LLM".to_string(),
+        ))
     }
 }
 
@@ -186,7 +228,11 @@ pub(crate) async fn call_tts(
     settings: &Settings,
     text: &str,
     voice: &Voice,
-) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<Vec<u8>, AppError> {
+    observe_upstream("tts", request_tts(settings, text, voice)).await
+}
+
+async fn request_tts(settings: &Settings, text: &str, voice: &Voice) -> Result<Vec<u8>, AppError> {
     let tts_request = TtsRequest {
         text: text.to_string(),
         temperature: voice.temperature,
@@ -208,18 +254,19 @@ pub(crate) async fn call_tts(
     let timer = std::time::Instant::now();
     let response = client
         .post(&settings.tts_api)
+        .with_api_key(&settings.tts_api_key)
         .json(&tts_request)
         .send()
-        .await?;
+        .await
+        .map_err(|e| AppError::UpstreamTts(format!("TTS API request failed: {e}")))?;
 
     let duration = timer.elapsed();
     if !response.status().is_success() {
-        return Err(format!(
+        return Err(AppError::UpstreamTts(format!(
             "TTS API returned error: {}, duration: {:?}",
             response.status(),
             duration
-        )
-        .into());
+        )));
     } else {
         tracing::debug!(
             "TTS API returned success: {}, duration: {:?}",
@@ -228,6 +275,103 @@ pub(crate) async fn call_tts(
         );
     }
 
-    let audio_bytes = response.bytes().await?;
+    let audio_bytes = response
+        .bytes()
+        .await
+        .map_err(|e| AppError::UpstreamTts(format!("Failed to read TTS audio: {e}")))?;
     Ok(audio_bytes.to_vec())
 }
+
+/// The KoboldCpp model endpoint on the same server as the configured generate endpoint
+fn llm_model_url(llm_api: &str) -> Result<reqwest::Url, AppError> {
+    let mut url = reqwest::Url::parse(llm_api)
+        .map_err(|e| AppError::Validation(format!("Invalid LLM API URL '{llm_api}': {e}")))?;
+    // Keep any prefix before /api, for servers behind a reverse proxy path
+    let prefix = url
+        .path()
+        .split_once("/api/")
+        .map(|(prefix, _)| prefix.to_string())
+        .unwrap_or_default();
+    url.set_path(&format!("{prefix}/api/v1/model"));
+    url.set_query(None);
+    Ok(url)
+}
+
+/// Check that the LLM backend answers, returning the name of the loaded model
+pub(crate) async fn check_llm(
+    settings: &Settings,
+    timeout: std::time::Duration,
+) -> Result<Option<String>, AppError> {
+    let url = llm_model_url(&settings.llm_api)?;
+    let response = reqwest::Client::new()
+        .get(url)
+        .with_api_key(&settings.llm_api_key)
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(|e| AppError::UpstreamLlm(format!("LLM API request failed: {e}")))?;
+
+    if !response.status().is_success() {
+        return Err(AppError::UpstreamLlm(format!(
+            "LLM API returned error: {}",
+            response.status()
+        )));
+    }
+
+    // KoboldCpp answers with {"result": "<model name>"}
+    let model = response
+        .json::<serde_json::Value>()
+        .await
+        .ok()
+        .and_then(|body| body.get("result")?.as_str().map(str::to_string));
+    Ok(model)
+}
+
+/// Check that the TTS backend answers
+/// TTS servers have no common status endpoint, so any response from the server's root short of
+/// a server error counts as up
+pub(crate) async fn check_tts(
+    settings: &Settings,
+    timeout: std::time::Duration,
+) -> Result<Option<String>, AppError> {
+    let mut url = reqwest::Url::parse(&settings.tts_api).map_err(|e| {
+        AppError::Validation(format!("Invalid TTS API URL '{}': {e}", settings.tts_api))
+    })?;
+    url.set_path("/");
+    url.set_query(None);
+
+    let response = reqwest::Client::new()
+        .get(url)
+        .with_api_key(&settings.tts_api_key)
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(|e| AppError::UpstreamTts(format!("TTS API request failed: {e}")))?;
+
+    if response.status().is_server_error() {
+        return Err(AppError::UpstreamTts(format!(
+            "TTS API returned error: {}",
+            response.status()
+        )));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_llm_model_url() {
+        let url = llm_model_url("http://localhost:5001/api/v1/generate").unwrap();
+        assert_eq!(url.as_str(), "http://localhost:5001/api/v1/model");
+
+        let url = llm_model_url("https://example.com/kobold/api/v1/generate?x=1").unwrap();
+        assert_eq!(url.as_str(), "https://example.com/kobold/api/v1/model");
+
+        let url = llm_model_url("http://localhost:5001").unwrap();
+        assert_eq!(url.as_str(), "http://localhost:5001/api/v1/model");
+
+        assert!(llm_model_url("not a url").is_err());
+    }
+}
@@ -0,0 +1,602 @@
+use argon2::{
+    Argon2,
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
+};
+use axum::{
+    extract::{FromRequestParts, MatchedPath, Request, State},
+    http::{HeaderMap, Method, header, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+use tokio::fs;
+
+use crate::config::data_dir;
+use crate::error::AppError;
+use crate::models::{ApiToken, Scope, User};
+use crate::settings::SharedSettings;
+use crate::utils::{
+    chat_dir, check_new_slug, push_subscriptions_file_path, token_file_path, user_file_path,
+    user_slug, write_file_atomic,
+};
+
+/// Name of the cookie holding the SPA's signed session
+pub const SESSION_COOKIE: &str = "storytime_session";
+
+/// The user requests act for when nobody is signed in, and who owns chats from before users
+pub const DEFAULT_USER: &str = "default";
+
+/// Cookie and header naming the user to act for while authentication is disabled
+/// The SPA sets the cookie from its user picker; scripts can send the header instead
+const USER_COOKIE: &str = "storytime_user";
+const USER_HEADER: &str = "x-storytime-user";
+
+/// Prefix of every API token secret, so leaked tokens are easy to recognise
+const TOKEN_PREFIX: &str = "st_";
+
+/// File in the data directory holding the key that signs session cookies
+const SESSION_KEY_FILE: &str = "session.key";
+
+static SESSION_KEY: Mutex<Option<[u8; 32]>> = Mutex::new(None);
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Who made a request, added to the request extensions when authentication is enabled
+#[derive(Debug, Clone)]
+pub struct AuthContext {
+    pub user: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl AuthContext {
+    /// Whether the caller may do something needing `scope`; admins may do anything
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+}
+
+// Passwords
+
+/// Hash a password into an Argon2 PHC string
+/// This is deliberately slow, so call it from a blocking task
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::Internal(format!("Failed to hash password: {e}")))
+}
+
+/// Check a password against an Argon2 PHC string
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+// Users
+
+/// Load a user by name
+pub async fn load_user(name: &str) -> Result<User, AppError> {
+    let content = fs::read_to_string(user_file_path(name))
+        .await
+        .map_err(|e| AppError::from_io(e, format!("User '{name}' not found")))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Load every user from the data/users directory
+pub async fn load_all_users() -> Result<Vec<User>, AppError> {
+    let mut users: Vec<User> = load_json_dir("users").await?;
+    users.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(users)
+}
+
+/// Check that a new user's name produces a usable slug that no other user has
+pub async fn check_new_user_name(name: &str) -> Result<(), AppError> {
+    let existing = async { load_user(name).await.map(|user| user.name) };
+    check_new_slug("User", "name", name, &user_slug(name), existing).await
+}
+
+/// Save a user to the data/users directory
+/// New users' names are checked with `check_new_user_name` first
+pub async fn save_user(user: &User) -> Result<(), AppError> {
+    fs::create_dir_all(data_dir().join("users")).await?;
+    write_file_atomic(user_file_path(&user.name), serde_json::to_vec_pretty(user)?).await?;
+    Ok(())
+}
+
+/// Delete a user along with their API tokens and chats
+pub async fn delete_user(name: &str) -> Result<(), AppError> {
+    let user = load_user(name).await?;
+    for token in load_all_tokens().await? {
+        if token.user == user.name {
+            delete_token(&token.id).await?;
+        }
+    }
+    fs::remove_file(user_file_path(&user.name))
+        .await
+        .map_err(|e| AppError::from_io(e, format!("User '{name}' not found")))?;
+    match fs::remove_dir_all(chat_dir(&user.name)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    match fs::remove_file(push_subscriptions_file_path(&user.name)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Names of every user, or just the default user when none have been added
+pub async fn user_names() -> Result<Vec<String>, AppError> {
+    let names: Vec<String> = load_all_users()
+        .await?
+        .into_iter()
+        .map(|user| user.name)
+        .collect();
+    if names.is_empty() {
+        Ok(vec![DEFAULT_USER.to_string()])
+    } else {
+        Ok(names)
+    }
+}
+
+// API tokens
+
+/// Create a token for `user` and return it with its secret, which is not stored anywhere
+pub async fn create_token(
+    user: &str,
+    name: &str,
+    scopes: Vec<Scope>,
+) -> Result<(ApiToken, String), AppError> {
+    let secret = format!("{TOKEN_PREFIX}{}", hex::encode(rand::random::<[u8; 32]>()));
+    let token = ApiToken {
+        id: uuid::Uuid::new_v4(),
+        name: name.to_string(),
+        user: user.to_string(),
+        scopes,
+        created_at: Utc::now(),
+        secret_hash: hash_secret(&secret),
+    };
+
+    fs::create_dir_all(data_dir().join("tokens")).await?;
+    write_file_atomic(
+        token_file_path(&token.id),
+        serde_json::to_vec_pretty(&token)?,
+    )
+    .await?;
+    Ok((token, secret))
+}
+
+/// Load every token from the data/tokens directory, oldest first
+pub async fn load_all_tokens() -> Result<Vec<ApiToken>, AppError> {
+    let mut tokens: Vec<ApiToken> = load_json_dir("tokens").await?;
+    tokens.sort_by_key(|token| token.created_at);
+    Ok(tokens)
+}
+
+pub async fn delete_token(id: &uuid::Uuid) -> Result<(), AppError> {
+    fs::remove_file(token_file_path(id))
+        .await
+        .map_err(|e| AppError::from_io(e, format!("Token '{id}' not found")))
+}
+
+/// Find the token a secret belongs to
+async fn find_token(secret: &str) -> Result<Option<ApiToken>, AppError> {
+    let hash = hash_secret(secret);
+    Ok(load_all_tokens()
+        .await?
+        .into_iter()
+        .find(|token| token.secret_hash == hash))
+}
+
+/// Token secrets are long and random, so a fast hash is enough to keep them off disk
+pub(crate) fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// Read every JSON file in a data directory subfolder, which may not exist yet
+async fn load_json_dir<T: serde::de::DeserializeOwned>(name: &str) -> Result<Vec<T>, AppError> {
+    let mut items = Vec::new();
+    let mut dir = match fs::read_dir(data_dir().join(name)).await {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(items),
+        Err(e) => return Err(e.into()),
+    };
+
+    while let Some(entry) = dir.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        let content = fs::read_to_string(&path).await?;
+        items.push(serde_json::from_str(&content)?);
+    }
+    Ok(items)
+}
+
+// Sessions
+
+/// What a session cookie vouches for
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SessionClaims {
+    user: String,
+    /// Unix time after which the cookie is no longer accepted
+    expires: i64,
+    /// Changes when the user's password does, which signs out their other sessions
+    fingerprint: String,
+}
+
+/// Create a session cookie for `user` that lasts `hours`
+pub fn start_session(user: &User, hours: u64) -> Result<String, AppError> {
+    let max_age = hours * 60 * 60;
+    let claims = SessionClaims {
+        user: user.name.clone(),
+        expires: Utc::now().timestamp() + max_age as i64,
+        fingerprint: session_fingerprint(user),
+    };
+    let value = sign_session(&session_key()?, &claims)?;
+    Ok(format!(
+        "{SESSION_COOKIE}={value}; Path=/; HttpOnly; SameSite=Lax; Max-Age={max_age}"
+    ))
+}
+
+/// A cookie that removes the session cookie
+pub fn end_session() -> String {
+    format!("{SESSION_COOKIE}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0")
+}
+
+fn session_fingerprint(user: &User) -> String {
+    let hash = user.password_hash.as_deref().unwrap_or_default();
+    hex::encode(&Sha256::digest(hash.as_bytes())[..8])
+}
+
+fn sign_session(key: &[u8], claims: &SessionClaims) -> Result<String, AppError> {
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?);
+    let mut mac = HmacSha256::new_from_slice(key)
+        .map_err(|e| AppError::Internal(format!("Invalid session key: {e}")))?;
+    mac.update(payload.as_bytes());
+    let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+    Ok(format!("{payload}.{signature}"))
+}
+
+/// Check a session cookie's signature and expiry
+fn verify_session(key: &[u8], value: &str, now: i64) -> Option<SessionClaims> {
+    let (payload, signature) = value.split_once('.')?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    let mut mac = HmacSha256::new_from_slice(key).ok()?;
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&signature).ok()?;
+
+    let claims: SessionClaims =
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+    (claims.expires > now).then_some(claims)
+}
+
+/// The key that signs session cookies, generated on first use
+/// It is kept in the data directory so sessions survive restarts, but is left out of backups
+fn session_key() -> Result<[u8; 32], AppError> {
+    let mut cached = SESSION_KEY
+        .lock()
+        .map_err(|_| AppError::Internal("Session key lock poisoned".to_string()))?;
+    if let Some(key) = *cached {
+        return Ok(key);
+    }
+
+    let path = data_dir().join(SESSION_KEY_FILE);
+    let key = match std::fs::read_to_string(&path) {
+        Ok(content) => hex::decode(content.trim())
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .ok_or_else(|| AppError::Storage(format!("{} is corrupt", path.display())))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let key = rand::random::<[u8; 32]>();
+            std::fs::write(&path, hex::encode(key))?;
+            key
+        }
+        Err(e) => return Err(e.into()),
+    };
+    *cached = Some(key);
+    Ok(key)
+}
+
+// Request authentication
+
+/// Work out who sent a request from its bearer token or session cookie
+/// A bad bearer token is an error, but a stale cookie is treated as no cookie so
+/// public pages keep working after a logout elsewhere
+pub async fn authenticate(headers: &HeaderMap) -> Result<Option<AuthContext>, AppError> {
+    if let Some(secret) = api_token(headers) {
+        let token = find_token(&secret)
+            .await?
+            .ok_or_else(|| AppError::Unauthorized("Invalid API token".to_string()))?;
+        let user = load_user(&token.user).await.map_err(|_| {
+            AppError::Unauthorized(format!("The user of token '{}' was removed", token.name))
+        })?;
+        // A token never grants more than its user currently has
+        let granted = user.scopes();
+        let scopes = token
+            .scopes
+            .into_iter()
+            .filter(|scope| granted.contains(scope))
+            .collect();
+        return Ok(Some(AuthContext {
+            user: user.name,
+            scopes,
+        }));
+    }
+
+    let Some(cookie) = cookie_value(headers, SESSION_COOKIE) else {
+        return Ok(None);
+    };
+    let Some(claims) = verify_session(&session_key()?, cookie, Utc::now().timestamp()) else {
+        return Ok(None);
+    };
+    match load_user(&claims.user).await {
+        Ok(user) if session_fingerprint(&user) == claims.fingerprint => Ok(Some(AuthContext {
+            scopes: user.scopes(),
+            user: user.name,
+        })),
+        _ => Ok(None),
+    }
+}
+
+/// An API token sent as a bearer token, or as the password of HTTP Basic credentials for
+/// clients such as podcast apps that cannot send anything else
+fn api_token(headers: &HeaderMap) -> Option<String> {
+    let authorization = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    if let Some(token) = authorization.strip_prefix("Bearer ") {
+        return Some(token.trim().to_string());
+    }
+    let credentials = STANDARD
+        .decode(authorization.strip_prefix("Basic ")?.trim())
+        .ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (_, password) = credentials.split_once(':')?;
+    Some(password.to_string())
+}
+
+fn cookie_value<'a>(headers: &'a HeaderMap, cookie: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            (name == cookie).then_some(value)
+        })
+}
+
+/// The user a request acts for: whoever is signed in or, while authentication is disabled,
+/// the user picked in the SPA, falling back to the default user
+pub struct CurrentUser(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for CurrentUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(context) = parts.extensions.get::<AuthContext>() {
+            return Ok(CurrentUser(context.user.clone()));
+        }
+
+        let picked = parts
+            .headers
+            .get(USER_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| cookie_value(&parts.headers, USER_COOKIE))
+            .map(str::trim)
+            .filter(|name| !name.is_empty() && *name != DEFAULT_USER);
+        match picked {
+            Some(name) => Ok(CurrentUser(load_user(name).await?.name)),
+            None => Ok(CurrentUser(DEFAULT_USER.to_string())),
+        }
+    }
+}
+
+/// The scope a request needs, by method and route template
+/// `None` means the route is public: health checks, login and the frontend itself
+pub fn required_scope(method: &Method, route: Option<&str>) -> Option<Scope> {
+    let Some(route) = route else {
+        // Static frontend files
+        return None;
+    };
+
+    match route {
+        "/health" | "/health/live" | "/health/ready" | "/api/hello" | "/api/hello/{name}"
+        | "/api/auth/login" | "/api/auth/logout" | "/api/auth/session" | "/api/hooks"
+        | "/api/hooks/{token}" => None,
+        // Anyone signed in may manage their own tokens
+        "/api/auth/tokens" | "/api/auth/tokens/{id}" => Some(Scope::Read),
+        "/api/settings"
+        | "/api/settings/reload"
+        | "/api/backup"
+        | "/api/restore"
+        | "/api/fsck/repair"
+        | "/api/webhooks/deliveries"
+        | "/api/webhooks/{name}/test" => Some(Scope::Admin),
+        "/api/migrations" | "/api/users" | "/api/users/{name}"
+            if method != Method::GET && method != Method::HEAD =>
+        {
+            Some(Scope::Admin)
+        }
+        "/api/jobs/{slug}/run" | "/api/jobs/run" | "/api/test/prompt" | "/api/test/character" => {
+            Some(Scope::Run)
+        }
+        _ if method == Method::GET || method == Method::HEAD => Some(Scope::Read),
+        _ => Some(Scope::Write),
+    }
+}
+
+/// Middleware rejecting requests without the scope their route needs
+/// Does nothing while authentication is disabled in settings
+pub async fn require_auth(
+    State(settings): State<SharedSettings>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !settings.current().auth.enabled {
+        return next.run(request).await;
+    }
+
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let required = required_scope(request.method(), route.as_deref());
+
+    let context = match authenticate(request.headers()).await {
+        Ok(context) => context,
+        Err(e) if required.is_some() => return e.into_response(),
+        Err(_) => None,
+    };
+
+    if let Some(scope) = required {
+        match &context {
+            None => {
+                return AppError::Unauthorized(
+                    "Sign in or send an API token to use this endpoint".to_string(),
+                )
+                .into_response();
+            }
+            Some(context) if !context.has_scope(scope) => {
+                return AppError::Forbidden(format!("This request needs the '{scope}' scope"))
+                    .into_response();
+            }
+            Some(_) => {}
+        }
+    }
+
+    if let Some(context) = context {
+        request.extensions_mut().insert(context);
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_round_trip() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2"));
+        assert!(verify_password(&hash, "correct horse"));
+        assert!(!verify_password(&hash, "wrong horse"));
+        assert!(!verify_password("not a hash", "correct horse"));
+    }
+
+    #[test]
+    fn test_session_signing() {
+        let key = [7; 32];
+        let claims = SessionClaims {
+            user: "ada".to_string(),
+            expires: 1_000,
+            fingerprint: "abc".to_string(),
+        };
+        let cookie = sign_session(&key, &claims).unwrap();
+
+        assert_eq!(verify_session(&key, &cookie, 999), Some(claims));
+        // Expired
+        assert_eq!(verify_session(&key, &cookie, 1_000), None);
+        // Signed with another key
+        assert_eq!(verify_session(&[8; 32], &cookie, 999), None);
+
+        // A payload edited to claim another user no longer matches its signature
+        let (_, signature) = cookie.split_once('.').unwrap();
+        let forged =
+            URL_SAFE_NO_PAD.encode(br#"{"user":"admin","expires":1000,"fingerprint":"abc"}"#);
+        assert_eq!(
+            verify_session(&key, &format!("{forged}.{signature}"), 999),
+            None
+        );
+    }
+
+    #[test]
+    fn test_session_cookie_parsing() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            "theme=dark; storytime_session=abc.def".parse().unwrap(),
+        );
+        assert_eq!(cookie_value(&headers, SESSION_COOKIE), Some("abc.def"));
+        assert_eq!(cookie_value(&headers, USER_COOKIE), None);
+
+        headers.insert(header::AUTHORIZATION, "Bearer st_123".parse().unwrap());
+        assert_eq!(api_token(&headers).as_deref(), Some("st_123"));
+        // Podcast apps send the token as the password, with any user name
+        headers.insert(
+            header::AUTHORIZATION,
+            "Basic a2lkczpzdF80NTY=".parse().unwrap(),
+        );
+        assert_eq!(api_token(&headers).as_deref(), Some("st_456"));
+    }
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(required_scope(&Method::GET, None), None);
+        assert_eq!(required_scope(&Method::GET, Some("/health/ready")), None);
+        assert_eq!(required_scope(&Method::POST, Some("/api/auth/login")), None);
+        assert_eq!(required_scope(&Method::POST, Some("/api/hooks")), None);
+        assert_eq!(
+            required_scope(&Method::GET, Some("/api/characters")),
+            Some(Scope::Read)
+        );
+        assert_eq!(
+            required_scope(&Method::POST, Some("/api/characters")),
+            Some(Scope::Write)
+        );
+        assert_eq!(
+            required_scope(&Method::POST, Some("/api/jobs/{slug}/run")),
+            Some(Scope::Run)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, Some("/api/settings")),
+            Some(Scope::Admin)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, Some("/api/migrations")),
+            Some(Scope::Read)
+        );
+        assert_eq!(
+            required_scope(&Method::POST, Some("/api/migrations")),
+            Some(Scope::Admin)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, Some("/api/users")),
+            Some(Scope::Read)
+        );
+        assert_eq!(
+            required_scope(&Method::DELETE, Some("/api/users/{name}")),
+            Some(Scope::Admin)
+        );
+    }
+
+    #[test]
+    fn test_admin_has_every_scope() {
+        let admin = AuthContext {
+            user: "root".to_string(),
+            scopes: vec![Scope::Admin],
+        };
+        assert!(Scope::ALL.iter().all(|scope| admin.has_scope(*scope)));
+
+        let reader = AuthContext {
+            user: "guest".to_string(),
+            scopes: vec![Scope::Read],
+        };
+        assert!(reader.has_scope(Scope::Read));
+        assert!(!reader.has_scope(Scope::Write));
+    }
+}
@@ -0,0 +1,938 @@
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use crate::config::data_dir;
+use crate::migrations::{
+    SCHEMA_VERSION, migrate_staged, read_schema_version, write_schema_version,
+};
+use crate::models::{Character, Chat, Job, Persona, Prompt, Settings, User};
+use crate::settings::SharedSettings;
+
+const BACKUP_FORMAT: &str = "storytime-backup";
+const MANIFEST_NAME: &str = "manifest.json";
+const SETTINGS_NAME: &str = "settings.json";
+/// Data subdirectories included in a backup
+const BACKUP_DIRS: [&str; 8] = [
+    "characters",
+    "prompts",
+    "personas",
+    "jobs",
+    "chats",
+    "users",
+    "audio",
+    "images",
+];
+/// Automatic backups are written inside the data directory but never included in a backup
+const BACKUPS_DIR: &str = "backups";
+/// Backups of each kind written before a restore or migration that are kept
+const SAFETY_BACKUP_RETENTION: usize = 5;
+/// Most a restored archive may extract to, so that a small compressed upload cannot fill the disk
+const MAX_RESTORE_BYTES: u64 = 16 * 1024 * 1024 * 1024;
+/// Most entries a restored archive may have
+const MAX_RESTORE_ENTRIES: usize = 100_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    /// The uploaded archive is malformed, unsafe or from an unsupported version
+    #[error("Invalid backup archive: {0}")]
+    Invalid(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    /// The data directory's schema file could not be read or written
+    #[error("Schema version error: {0}")]
+    Schema(String),
+}
+
+fn invalid(e: impl std::fmt::Display) -> BackupError {
+    BackupError::Invalid(e.to_string())
+}
+
+fn schema_error(e: impl std::fmt::Display) -> BackupError {
+    BackupError::Schema(e.to_string())
+}
+
+/// Archive format for downloaded and uploaded backups
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "tar.gz")]
+    #[value(name = "tar.gz")]
+    TarGz,
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::Zip => "application/zip",
+        }
+    }
+
+    /// Detect the format of an uploaded archive from its magic bytes
+    fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if bytes.starts_with(b"PK\x03\x04") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// How a restored archive is combined with the existing data
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreMode {
+    /// Add the archived files, overwriting existing files with the same name
+    #[default]
+    Merge,
+    /// Remove all existing data first, keeping only what is in the archive
+    Replace,
+}
+
+/// Describes the contents of a backup archive, stored as `manifest.json`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupManifest {
+    pub format: String,
+    pub schema_version: u32,
+    pub app_version: String,
+    pub created_at: DateTime<Utc>,
+    /// Paths of all archived files, relative to the data directory
+    pub files: Vec<String>,
+}
+
+/// Result of restoring a backup archive
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RestoreSummary {
+    pub mode: RestoreMode,
+    pub schema_version: u32,
+    pub created_at: DateTime<Utc>,
+    pub files: usize,
+}
+
+/// Writes archive entries in either supported format
+enum ArchiveWriter<W: Write + Seek> {
+    TarGz(tar::Builder<GzEncoder<W>>),
+    Zip(zip::ZipWriter<W>),
+}
+
+impl<W: Write + Seek> ArchiveWriter<W> {
+    fn new(format: ArchiveFormat, out: W) -> Self {
+        match format {
+            ArchiveFormat::TarGz => ArchiveWriter::TarGz(tar::Builder::new(GzEncoder::new(
+                out,
+                Compression::default(),
+            ))),
+            ArchiveFormat::Zip => ArchiveWriter::Zip(zip::ZipWriter::new(out)),
+        }
+    }
+
+    fn add(&mut self, name: &str, contents: &[u8]) -> Result<(), BackupError> {
+        match self {
+            ArchiveWriter::TarGz(builder) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(Utc::now().timestamp().max(0) as u64);
+                header.set_cksum();
+                builder.append_data(&mut header, name, contents)?;
+            }
+            ArchiveWriter::Zip(writer) => {
+                let options = zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated);
+                writer.start_file(name, options)?;
+                writer.write_all(contents)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), BackupError> {
+        match self {
+            ArchiveWriter::TarGz(builder) => {
+                builder.into_inner()?.finish()?;
+            }
+            ArchiveWriter::Zip(writer) => {
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write a backup archive of the data directory
+/// The manifest is written last so that it lists exactly the files that made it into the archive,
+/// even if files are deleted while the backup is running
+pub fn write_backup<W: Write + Seek>(
+    data_dir: &Path,
+    format: ArchiveFormat,
+    out: W,
+) -> Result<BackupManifest, BackupError> {
+    let mut writer = ArchiveWriter::new(format, out);
+    let mut archived = Vec::new();
+
+    for name in collect_backup_files(data_dir)? {
+        let contents = match fs::read(data_dir.join(&name)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        writer.add(&name, &contents)?;
+        archived.push(name);
+    }
+
+    let manifest = BackupManifest {
+        format: BACKUP_FORMAT.to_string(),
+        schema_version: read_schema_version(data_dir).map_err(schema_error)?,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: Utc::now(),
+        files: archived,
+    };
+    writer.add(MANIFEST_NAME, &serde_json::to_vec_pretty(&manifest)?)?;
+    writer.finish()?;
+
+    Ok(manifest)
+}
+
+/// List every file that belongs in a backup, relative to the data directory
+fn collect_backup_files(data_dir: &Path) -> Result<Vec<String>, BackupError> {
+    let mut files = Vec::new();
+    if data_dir.join(SETTINGS_NAME).is_file() {
+        files.push(SETTINGS_NAME.to_string());
+    }
+    for dir in BACKUP_DIRS {
+        collect_files_recursive(&data_dir.join(dir), dir, &mut files)?;
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn collect_files_recursive(
+    dir: &Path,
+    prefix: &str,
+    files: &mut Vec<String>,
+) -> Result<(), BackupError> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            tracing::warn!("Skipping non UTF-8 file name in {}", dir.display());
+            continue;
+        };
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files_recursive(&entry.path(), &format!("{prefix}/{name}"), files)?;
+        } else if file_type.is_file() {
+            files.push(format!("{prefix}/{name}"));
+        }
+    }
+    Ok(())
+}
+
+/// Validate a backup archive and import it into the data directory
+/// The archive is extracted and checked in a staging directory first, so a rejected archive
+/// leaves the existing data untouched. Replace mode writes a safety backup before deleting anything.
+/// Merged files from an older archive are migrated in the staging directory, so that the data
+/// directory keeps its schema version
+pub async fn restore_archive(
+    data_dir: &Path,
+    archive: Vec<u8>,
+    mode: RestoreMode,
+) -> Result<RestoreSummary, BackupError> {
+    let staging_parent = data_dir.to_path_buf();
+    let (staging, manifest) = blocking(move || {
+        let limit = ExtractLimit {
+            bytes: MAX_RESTORE_BYTES,
+            entries: MAX_RESTORE_ENTRIES,
+        };
+        stage_archive(&staging_parent, &archive, limit)
+    })
+    .await?;
+
+    let current_version = read_schema_version(data_dir).map_err(schema_error)?;
+    let mut files = manifest.files.clone();
+    if mode == RestoreMode::Merge && manifest.schema_version < current_version {
+        write_schema_version(staging.path(), manifest.schema_version).map_err(schema_error)?;
+        migrate_staged(staging.path(), current_version)
+            .await
+            .map_err(schema_error)?;
+        // Migrations may rename and move files
+        let staged = staging.path().to_path_buf();
+        files = blocking(move || collect_backup_files(&staged)).await?;
+    }
+
+    let target = data_dir.to_path_buf();
+    let installed = files.len();
+    blocking(move || install_staged(&target, staging, &files, mode)).await?;
+
+    // Replaced data is entirely from the archive, so pending migrations must run over it again.
+    // Merged data was brought up to the data directory's version, which stays as it is
+    if mode == RestoreMode::Replace {
+        write_schema_version(data_dir, manifest.schema_version).map_err(schema_error)?;
+    }
+
+    tracing::info!(
+        "Restored {} files from backup created at {}",
+        installed,
+        manifest.created_at
+    );
+
+    Ok(RestoreSummary {
+        mode,
+        schema_version: manifest.schema_version,
+        created_at: manifest.created_at,
+        files: installed,
+    })
+}
+
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, BackupError> + Send + 'static,
+) -> Result<T, BackupError> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| BackupError::Io(io::Error::other(e)))?
+}
+
+/// Extract and validate an archive in a staging directory inside the data directory
+fn stage_archive(
+    data_dir: &Path,
+    archive: &[u8],
+    limit: ExtractLimit,
+) -> Result<(tempfile::TempDir, BackupManifest), BackupError> {
+    let format = ArchiveFormat::detect(archive)
+        .ok_or_else(|| invalid("unrecognised archive format, expected tar.gz or zip"))?;
+
+    fs::create_dir_all(data_dir)?;
+    let staging = tempfile::Builder::new()
+        .prefix(".restore-")
+        .tempdir_in(data_dir)?;
+
+    extract_archive(format, archive, staging.path(), limit)?;
+    let manifest = validate_staged(staging.path())?;
+    Ok((staging, manifest))
+}
+
+/// Move staged files into the data directory, first clearing it in replace mode
+fn install_staged(
+    data_dir: &Path,
+    staging: tempfile::TempDir,
+    files: &[String],
+    mode: RestoreMode,
+) -> Result<(), BackupError> {
+    if mode == RestoreMode::Replace {
+        let safety_backup = create_safety_backup(data_dir, "pre-restore", "pre-restore")?;
+        tracing::info!(
+            "Saved current data to {} before replacing it",
+            safety_backup.display()
+        );
+        for dir in BACKUP_DIRS {
+            let path = data_dir.join(dir);
+            if path.exists() {
+                fs::remove_dir_all(&path)?;
+            }
+            fs::create_dir_all(&path)?;
+        }
+    }
+
+    for name in files {
+        let relative = sanitize_entry_path(name)?;
+        let target = data_dir.join(&relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(staging.path().join(&relative), &target)?;
+    }
+    Ok(())
+}
+
+/// What is left of the size and entry budget while an archive is extracted
+struct ExtractLimit {
+    bytes: u64,
+    entries: usize,
+}
+
+impl ExtractLimit {
+    fn take_entry(&mut self) -> Result<(), BackupError> {
+        self.entries = self
+            .entries
+            .checked_sub(1)
+            .ok_or_else(|| invalid(format!("more than {MAX_RESTORE_ENTRIES} entries")))?;
+        Ok(())
+    }
+}
+
+/// Extract every regular file of an archive into the staging directory
+/// Fails as soon as the archive has more entries or extracts to more bytes than `limit` allows
+fn extract_archive(
+    format: ArchiveFormat,
+    archive: &[u8],
+    staging: &Path,
+    mut limit: ExtractLimit,
+) -> Result<(), BackupError> {
+    match format {
+        ArchiveFormat::TarGz => {
+            let mut tar = tar::Archive::new(GzDecoder::new(archive));
+            for entry in tar.entries().map_err(invalid)? {
+                let mut entry = entry.map_err(invalid)?;
+                limit.take_entry()?;
+                let name = entry.path().map_err(invalid)?.to_string_lossy().to_string();
+                match entry.header().entry_type() {
+                    tar::EntryType::Regular => {
+                        write_staged(staging, &name, &mut entry, &mut limit)?
+                    }
+                    tar::EntryType::Directory => {}
+                    other => {
+                        return Err(invalid(format!(
+                            "unsupported entry type {other:?} for '{name}'"
+                        )));
+                    }
+                }
+            }
+        }
+        ArchiveFormat::Zip => {
+            let mut zip = zip::ZipArchive::new(Cursor::new(archive)).map_err(invalid)?;
+            for i in 0..zip.len() {
+                limit.take_entry()?;
+                let mut file = zip.by_index(i).map_err(invalid)?;
+                let name = file.name().to_string();
+                if file.is_dir() {
+                    continue;
+                }
+                if file.is_symlink() {
+                    return Err(invalid(format!("unsupported symlink '{name}'")));
+                }
+                write_staged(staging, &name, &mut file, &mut limit)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sizes in archive headers can lie, so the bytes are counted as they are written
+fn write_staged(
+    staging: &Path,
+    name: &str,
+    reader: &mut impl Read,
+    limit: &mut ExtractLimit,
+) -> Result<(), BackupError> {
+    let target = staging.join(sanitize_entry_path(name)?);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(&target)?;
+    let written = io::copy(&mut reader.take(limit.bytes + 1), &mut file).map_err(invalid)?;
+    limit.bytes = limit
+        .bytes
+        .checked_sub(written)
+        .ok_or_else(|| invalid(format!("extracts to more than {MAX_RESTORE_BYTES} bytes")))?;
+    Ok(())
+}
+
+/// Turn an archive entry name into a relative path inside the data directory
+/// Rejects absolute paths, `..` components and anything outside the backed up entries
+fn sanitize_entry_path(name: &str) -> Result<PathBuf, BackupError> {
+    let mut clean = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => clean.push(part),
+            Component::CurDir => {}
+            _ => return Err(invalid(format!("unsafe path '{name}'"))),
+        }
+    }
+
+    let parts: Vec<&str> = clean.iter().filter_map(|part| part.to_str()).collect();
+    let allowed = match parts.as_slice() {
+        [single] => *single == MANIFEST_NAME || *single == SETTINGS_NAME,
+        [dir, _, ..] => BACKUP_DIRS.contains(dir),
+        [] => false,
+    };
+    if !allowed || parts.len() != clean.iter().count() {
+        return Err(invalid(format!("unexpected entry '{name}'")));
+    }
+    Ok(clean)
+}
+
+/// Check the manifest and parse every data file in the staging directory
+fn validate_staged(staging: &Path) -> Result<BackupManifest, BackupError> {
+    let manifest_path = staging.join(MANIFEST_NAME);
+    if !manifest_path.is_file() {
+        return Err(invalid("missing manifest.json"));
+    }
+    let manifest: BackupManifest =
+        serde_json::from_str(&fs::read_to_string(&manifest_path)?).map_err(invalid)?;
+
+    if manifest.format != BACKUP_FORMAT {
+        return Err(invalid(format!(
+            "unknown archive format '{}'",
+            manifest.format
+        )));
+    }
+    if manifest.schema_version > SCHEMA_VERSION {
+        return Err(invalid(format!(
+            "schema version {} is newer than the supported version {}",
+            manifest.schema_version, SCHEMA_VERSION
+        )));
+    }
+
+    for name in &manifest.files {
+        let relative = sanitize_entry_path(name)?;
+        if !staging.join(&relative).is_file() {
+            return Err(invalid(format!("file '{name}' is listed but missing")));
+        }
+        match relative.iter().next().and_then(|part| part.to_str()) {
+            Some(SETTINGS_NAME) => validate_json::<Settings>(staging, name)?,
+            _ if !name.ends_with(".json") => {}
+            Some("characters") => validate_json::<Character>(staging, name)?,
+            Some("prompts") => validate_json::<Prompt>(staging, name)?,
+            Some("personas") => validate_json::<Persona>(staging, name)?,
+            Some("jobs") => validate_json::<Job>(staging, name)?,
+            Some("chats") => validate_json::<Chat>(staging, name)?,
+            Some("users") => validate_json::<User>(staging, name)?,
+            _ => {}
+        }
+    }
+
+    Ok(manifest)
+}
+
+fn validate_json<T: DeserializeOwned>(staging: &Path, name: &str) -> Result<(), BackupError> {
+    let content = fs::read_to_string(staging.join(name))?;
+    serde_json::from_str::<T>(&content)
+        .map(|_| ())
+        .map_err(|e| invalid(format!("'{name}' failed to parse: {e}")))
+}
+
+/// Write a timestamped tar.gz backup into `data/backups`
+/// The archive is written under a temporary name first so that an interrupted backup is never
+/// mistaken for a complete one
+pub fn create_backup_file(data_dir: &Path, label: &str) -> Result<PathBuf, BackupError> {
+    let backups_dir = data_dir.join(BACKUPS_DIR);
+    fs::create_dir_all(&backups_dir)?;
+
+    let file_name = format!(
+        "storytime-{label}-{}.{}",
+        Utc::now().format("%Y%m%dT%H%M%SZ"),
+        ArchiveFormat::TarGz.extension()
+    );
+    let mut temp = tempfile::NamedTempFile::new_in(&backups_dir)?;
+    write_backup(data_dir, ArchiveFormat::TarGz, temp.as_file_mut())?;
+
+    let path = backups_dir.join(file_name);
+    temp.persist(&path).map_err(|e| e.error)?;
+    Ok(path)
+}
+
+/// Write a backup before a restore or migration changes the data
+/// Only the newest few backups of the same kind are kept, so they do not pile up
+pub fn create_safety_backup(
+    data_dir: &Path,
+    kind: &str,
+    label: &str,
+) -> Result<PathBuf, BackupError> {
+    let path = create_backup_file(data_dir, label)?;
+    prune_backups(data_dir, kind, SAFETY_BACKUP_RETENTION)?;
+    Ok(path)
+}
+
+/// Delete all but the newest `retention` backups whose label starts with `label`
+pub fn prune_backups(
+    data_dir: &Path,
+    label: &str,
+    retention: usize,
+) -> Result<Vec<PathBuf>, BackupError> {
+    let backups_dir = data_dir.join(BACKUPS_DIR);
+    if !backups_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    // Every file name ends with a timestamp, which sorts chronologically whatever the label
+    let prefix = format!("storytime-{label}-");
+    let mut backups: Vec<(String, PathBuf)> = fs::read_dir(&backups_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let timestamp = name.strip_prefix(&prefix)?.rsplit('-').next()?.to_string();
+            Some((timestamp, entry.path()))
+        })
+        .collect();
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(retention);
+    let removed: Vec<PathBuf> = backups
+        .into_iter()
+        .take(excess)
+        .map(|(_, path)| path)
+        .collect();
+    for path in &removed {
+        fs::remove_file(path)?;
+        tracing::info!("Removed old backup {}", path.display());
+    }
+    Ok(removed)
+}
+
+/// Start writing automatic backups on the configured cron schedule
+/// The schedule follows the shared settings, so enabling backups or changing the cadence
+/// takes effect without a restart
+pub fn start_backup_scheduler(settings: SharedSettings) {
+    let mut changes = settings.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let backup = changes.borrow_and_update().backup.clone();
+            let next_run = if backup.enabled {
+                match Schedule::from_str(&backup.cadence) {
+                    Ok(schedule) => schedule.upcoming(Utc).next(),
+                    Err(e) => {
+                        tracing::error!(
+                            "Automatic backups disabled, invalid cron expression '{}': {}",
+                            backup.cadence,
+                            e
+                        );
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            let Some(next_run) = next_run else {
+                // Nothing to do until the settings change
+                if changes.changed().await.is_err() {
+                    return;
+                }
+                continue;
+            };
+
+            tracing::debug!("Next automatic backup at {}", next_run);
+            let wait = (next_run - Utc::now()).to_std().unwrap_or_default();
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => run_automatic_backup(backup.retention).await,
+                changed = changes.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    tracing::info!("Backup settings changed, rescheduling automatic backups");
+                }
+            }
+        }
+    });
+}
+
+async fn run_automatic_backup(retention: usize) {
+    let result = tokio::task::spawn_blocking(move || {
+        let data_dir = data_dir();
+        let path = create_backup_file(data_dir, "auto")?;
+        prune_backups(data_dir, "auto", retention)?;
+        Ok::<_, BackupError>(path)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(path)) => tracing::info!("Automatic backup written to {}", path.display()),
+        Ok(Err(e)) => tracing::error!("Automatic backup failed: {}", e),
+        Err(e) => tracing::error!("Automatic backup task panicked: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn setup_data_dir(data: &Path) {
+        write(
+            &data.join("settings.json"),
+            r#"{"ttsApi": "http://tts", "llmApi": "http://llm"}"#,
+        );
+        write(
+            &data.join("characters/knight.json"),
+            r#"{"name": "Knight", "description": "", "personality": "", "background": ""}"#,
+        );
+        write(&data.join("audio/knight/clip.mp3"), "mp3");
+        write(&data.join("backups/old.tar.gz"), "not included");
+        write_schema_version(data, SCHEMA_VERSION).unwrap();
+    }
+
+    fn backup_bytes(data: &Path, format: ArchiveFormat) -> (BackupManifest, Vec<u8>) {
+        let mut out = Cursor::new(Vec::new());
+        let manifest = write_backup(data, format, &mut out).unwrap();
+        (manifest, out.into_inner())
+    }
+
+    #[tokio::test]
+    async fn test_backup_round_trip() {
+        for format in [ArchiveFormat::TarGz, ArchiveFormat::Zip] {
+            let source = tempdir().unwrap();
+            setup_data_dir(source.path());
+            let (manifest, archive) = backup_bytes(source.path(), format);
+
+            assert_eq!(manifest.schema_version, SCHEMA_VERSION);
+            assert_eq!(
+                manifest.files,
+                vec![
+                    "audio/knight/clip.mp3",
+                    "characters/knight.json",
+                    "settings.json"
+                ]
+            );
+
+            let target = tempdir().unwrap();
+            let summary = restore_archive(target.path(), archive, RestoreMode::Merge)
+                .await
+                .unwrap();
+
+            assert_eq!(summary.files, 3);
+            assert_eq!(
+                fs::read_to_string(target.path().join("audio/knight/clip.mp3")).unwrap(),
+                "mp3"
+            );
+            assert!(target.path().join("characters/knight.json").exists());
+            assert!(!target.path().join(MANIFEST_NAME).exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restore_merge_and_replace() {
+        let source = tempdir().unwrap();
+        setup_data_dir(source.path());
+        let (_, archive) = backup_bytes(source.path(), ArchiveFormat::TarGz);
+
+        let target = tempdir().unwrap();
+        write(&target.path().join("prompts/extra.json"), "{}");
+        write_schema_version(target.path(), SCHEMA_VERSION).unwrap();
+        restore_archive(target.path(), archive.clone(), RestoreMode::Merge)
+            .await
+            .unwrap();
+        assert!(target.path().join("prompts/extra.json").exists());
+        assert!(target.path().join("characters/knight.json").exists());
+        assert_eq!(read_schema_version(target.path()).unwrap(), SCHEMA_VERSION);
+
+        restore_archive(target.path(), archive, RestoreMode::Replace)
+            .await
+            .unwrap();
+        assert!(!target.path().join("prompts/extra.json").exists());
+        assert!(target.path().join("characters/knight.json").exists());
+        assert_eq!(read_schema_version(target.path()).unwrap(), SCHEMA_VERSION);
+
+        // The previous data is kept as a safety backup
+        let backups: Vec<_> = fs::read_dir(target.path().join(BACKUPS_DIR))
+            .unwrap()
+            .collect();
+        assert_eq!(backups.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_merge_migrates_older_archives() {
+        let mut out = Cursor::new(Vec::new());
+        let mut writer = ArchiveWriter::new(ArchiveFormat::TarGz, &mut out);
+        let chat = br#"{"character": "Knight", "messages": []}"#;
+        writer.add("chats/knight.json", chat).unwrap();
+        let manifest = BackupManifest {
+            format: BACKUP_FORMAT.to_string(),
+            schema_version: 3,
+            app_version: "0.3.0".to_string(),
+            created_at: Utc::now(),
+            files: vec!["chats/knight.json".to_string()],
+        };
+        writer
+            .add(MANIFEST_NAME, &serde_json::to_vec(&manifest).unwrap())
+            .unwrap();
+        writer.finish().unwrap();
+
+        let target = tempdir().unwrap();
+        write(&target.path().join("chats/default/queen.json"), "{}");
+        write_schema_version(target.path(), SCHEMA_VERSION).unwrap();
+        let summary = restore_archive(target.path(), out.into_inner(), RestoreMode::Merge)
+            .await
+            .unwrap();
+
+        // The archived chat is moved where version 4 keeps chats, and nothing is migrated twice
+        assert_eq!(summary.files, 1);
+        assert!(target.path().join("chats/default/knight.json").exists());
+        assert!(!target.path().join("chats/knight.json").exists());
+        assert!(target.path().join("chats/default/queen.json").exists());
+        assert_eq!(read_schema_version(target.path()).unwrap(), SCHEMA_VERSION);
+        assert!(!target.path().join(BACKUPS_DIR).exists());
+    }
+
+    #[test]
+    fn test_extraction_is_limited() {
+        let source = tempdir().unwrap();
+        setup_data_dir(source.path());
+        let (_, archive) = backup_bytes(source.path(), ArchiveFormat::TarGz);
+
+        let staging = tempdir().unwrap();
+        let limit = ExtractLimit {
+            bytes: 10,
+            entries: MAX_RESTORE_ENTRIES,
+        };
+        let err = extract_archive(ArchiveFormat::TarGz, &archive, staging.path(), limit);
+        assert!(matches!(err, Err(BackupError::Invalid(_))));
+
+        let staging = tempdir().unwrap();
+        let limit = ExtractLimit {
+            bytes: MAX_RESTORE_BYTES,
+            entries: 2,
+        };
+        let err = extract_archive(ArchiveFormat::TarGz, &archive, staging.path(), limit);
+        assert!(matches!(err, Err(BackupError::Invalid(_))));
+    }
+
+    #[tokio::test]
+    async fn test_restore_rejects_invalid_archives() {
+        let target = tempdir().unwrap();
+
+        let err = restore_archive(target.path(), b"plain text".to_vec(), RestoreMode::Merge)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, BackupError::Invalid(_)));
+
+        // Path traversal
+        let mut out = Cursor::new(Vec::new());
+        let mut writer = zip::ZipWriter::new(&mut out);
+        writer
+            .start_file("../escape.json", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"{}").unwrap();
+        writer.finish().unwrap();
+        let err = restore_archive(target.path(), out.into_inner(), RestoreMode::Merge)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, BackupError::Invalid(_)));
+        assert!(!target.path().parent().unwrap().join("escape.json").exists());
+
+        // Newer schema version
+        let mut out = Cursor::new(Vec::new());
+        let mut writer = ArchiveWriter::new(ArchiveFormat::TarGz, &mut out);
+        let manifest = BackupManifest {
+            format: BACKUP_FORMAT.to_string(),
+            schema_version: SCHEMA_VERSION + 1,
+            app_version: "99.0.0".to_string(),
+            created_at: Utc::now(),
+            files: vec![],
+        };
+        writer
+            .add(MANIFEST_NAME, &serde_json::to_vec(&manifest).unwrap())
+            .unwrap();
+        writer.finish().unwrap();
+        let err = restore_archive(target.path(), out.into_inner(), RestoreMode::Replace)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, BackupError::Invalid(_)));
+
+        // Broken entity files
+        let mut out = Cursor::new(Vec::new());
+        let mut writer = ArchiveWriter::new(ArchiveFormat::TarGz, &mut out);
+        let manifest = BackupManifest {
+            files: vec!["jobs/broken.json".to_string()],
+            ..manifest
+        };
+        writer.add("jobs/broken.json", b"{ not json").unwrap();
+        writer
+            .add(
+                MANIFEST_NAME,
+                &serde_json::to_vec(&BackupManifest {
+                    schema_version: SCHEMA_VERSION,
+                    ..manifest
+                })
+                .unwrap(),
+            )
+            .unwrap();
+        writer.finish().unwrap();
+        let err = restore_archive(target.path(), out.into_inner(), RestoreMode::Merge)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, BackupError::Invalid(_)));
+        assert!(!target.path().join("jobs/broken.json").exists());
+    }
+
+    #[test]
+    fn test_prune_backups_keeps_newest() {
+        let data = tempdir().unwrap();
+        for stamp in ["20250101T000000Z", "20250102T000000Z", "20250103T000000Z"] {
+            write(
+                &data
+                    .path()
+                    .join(BACKUPS_DIR)
+                    .join(format!("storytime-auto-{stamp}.tar.gz")),
+                "",
+            );
+        }
+        write(
+            &data
+                .path()
+                .join(BACKUPS_DIR)
+                .join("storytime-pre-restore-20240101T000000Z.tar.gz"),
+            "",
+        );
+
+        let removed = prune_backups(data.path(), "auto", 2).unwrap();
+
+        assert_eq!(removed.len(), 1);
+        assert!(removed[0].ends_with("storytime-auto-20250101T000000Z.tar.gz"));
+        assert_eq!(
+            fs::read_dir(data.path().join(BACKUPS_DIR)).unwrap().count(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_safety_backups_are_pruned() {
+        let data = tempdir().unwrap();
+        setup_data_dir(data.path());
+        for stamp in ["20250101T000000Z", "20250102T000000Z", "20250103T000000Z"] {
+            for label in ["pre-migration-v9", "pre-migration-v10", "pre-restore"] {
+                write(
+                    &data
+                        .path()
+                        .join(BACKUPS_DIR)
+                        .join(format!("storytime-{label}-{stamp}.tar.gz")),
+                    "",
+                );
+            }
+        }
+
+        let path = create_safety_backup(data.path(), "pre-migration", "pre-migration-v4").unwrap();
+
+        let mut kept: Vec<String> = fs::read_dir(data.path().join(BACKUPS_DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("storytime-pre-migration-"))
+            .collect();
+        kept.sort();
+        assert_eq!(kept.len(), SAFETY_BACKUP_RETENTION);
+        assert!(path.exists());
+        // The oldest are removed by date, not by the version in their label
+        assert!(!kept.contains(&"storytime-pre-migration-v10-20250101T000000Z.tar.gz".to_string()));
+        assert!(!kept.contains(&"storytime-pre-migration-v9-20250101T000000Z.tar.gz".to_string()));
+        assert!(kept.contains(&"storytime-pre-migration-v9-20250102T000000Z.tar.gz".to_string()));
+    }
+}
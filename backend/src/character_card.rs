@@ -0,0 +1,270 @@
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Character, Voice};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const CARD_V2_SPEC: &str = "chara_card_v2";
+const CARD_V3_SPEC: &str = "chara_card_v3";
+/// Key under `extensions` where Storytime-only fields such as the voice are kept
+const STORYTIME_EXTENSION: &str = "storytime";
+
+/// A SillyTavern / TavernAI Character Card V2
+/// See https://github.com/malfoyslastname/character-card-spec-v2
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CharacterCardV2 {
+    pub spec: String,
+    pub spec_version: String,
+    pub data: CharacterCardData,
+}
+
+/// The character fields of a card; V1 cards have these at the top level
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CharacterCardData {
+    pub name: String,
+    pub description: String,
+    pub personality: String,
+    pub scenario: String,
+    pub first_mes: String,
+    pub mes_example: String,
+    pub creator_notes: String,
+    pub system_prompt: String,
+    pub post_history_instructions: String,
+    pub alternate_greetings: Vec<String>,
+    pub tags: Vec<String>,
+    pub creator: String,
+    pub character_version: String,
+    pub extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CardError {
+    #[error("PNG has no character card data")]
+    MissingCardChunk,
+    #[error("Malformed PNG: {0}")]
+    MalformedPng(String),
+    #[error("Card data is not valid base64: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("Card is not valid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Card has no character name")]
+    MissingName,
+    #[error("Unrecognised card format, expected a JSON or PNG character card")]
+    UnknownFormat,
+}
+
+/// Parse a character card from the bytes of a `.json` or `.png` card file
+pub fn parse_card(bytes: &[u8]) -> Result<CharacterCardData, CardError> {
+    let json = if bytes.starts_with(PNG_SIGNATURE) {
+        extract_png_card_json(bytes)?
+    } else if bytes
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|b| *b == b'{')
+    {
+        bytes.to_vec()
+    } else {
+        return Err(CardError::UnknownFormat);
+    };
+
+    let value: serde_json::Value = serde_json::from_slice(&json)?;
+    let spec = value.get("spec").and_then(|spec| spec.as_str());
+    let data: CharacterCardData = match (spec, value.get("data")) {
+        // V3 keeps every V2 field in `data`, so it can be read the same way
+        (Some(CARD_V2_SPEC | CARD_V3_SPEC), Some(data)) => serde_json::from_value(data.clone())?,
+        _ => serde_json::from_value(value)?,
+    };
+
+    if data.name.trim().is_empty() {
+        return Err(CardError::MissingName);
+    }
+    Ok(data)
+}
+
+/// Find the base64 encoded card JSON in a PNG's `tEXt` chunks
+/// Prefers the V2 `chara` keyword and falls back to the V3 `ccv3` keyword
+fn extract_png_card_json(bytes: &[u8]) -> Result<Vec<u8>, CardError> {
+    let mut v2 = None;
+    let mut v3 = None;
+    let mut pos = PNG_SIGNATURE.len();
+
+    while pos + 8 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let chunk_type = &bytes[pos + 4..pos + 8];
+        let data_start = pos + 8;
+        let data_end = data_start
+            .checked_add(length)
+            .filter(|end| end + 4 <= bytes.len())
+            .ok_or_else(|| CardError::MalformedPng("chunk extends past end of file".into()))?;
+
+        if chunk_type == b"tEXt" {
+            let chunk = &bytes[data_start..data_end];
+            if let Some(separator) = chunk.iter().position(|b| *b == 0) {
+                let (keyword, text) = (&chunk[..separator], &chunk[separator + 1..]);
+                match keyword {
+                    b"chara" => v2 = Some(text),
+                    b"ccv3" => v3 = Some(text),
+                    _ => {}
+                }
+            }
+        } else if chunk_type == b"IEND" {
+            break;
+        }
+
+        // Skip the chunk data and its CRC
+        pos = data_end + 4;
+    }
+
+    let encoded = v2.or(v3).ok_or(CardError::MissingCardChunk)?;
+    Ok(base64::engine::general_purpose::STANDARD.decode(encoded.trim_ascii())?)
+}
+
+impl CharacterCardData {
+    /// Convert a card into a Storytime character
+    /// The card's scenario becomes the character's background
+    pub fn into_character(self) -> Character {
+        let voice = self
+            .extensions
+            .get(STORYTIME_EXTENSION)
+            .and_then(|extension| extension.get("voice"))
+            .and_then(|voice| serde_json::from_value::<Voice>(voice.clone()).ok());
+
+        Character {
+            name: self.name.trim().to_string(),
+            description: self.description,
+            personality: self.personality,
+            background: self.scenario,
+            voice,
+            first_message: self.first_mes,
+            example_dialogue: self.mes_example,
+        }
+    }
+}
+
+impl From<&Character> for CharacterCardV2 {
+    fn from(character: &Character) -> Self {
+        let mut extensions = serde_json::Map::new();
+        if let Some(voice) = &character.voice {
+            extensions.insert(
+                STORYTIME_EXTENSION.to_string(),
+                serde_json::json!({ "voice": voice }),
+            );
+        }
+
+        CharacterCardV2 {
+            spec: CARD_V2_SPEC.to_string(),
+            spec_version: "2.0".to_string(),
+            data: CharacterCardData {
+                name: character.name.clone(),
+                description: character.description.clone(),
+                personality: character.personality.clone(),
+                scenario: character.background.clone(),
+                first_mes: character.first_message.clone(),
+                mes_example: character.example_dialogue.clone(),
+                creator: "Storytime".to_string(),
+                extensions,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_chunk(chunk_type: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(chunk_type);
+        chunk.extend_from_slice(data);
+        // The CRC is not checked when reading cards
+        chunk.extend_from_slice(&[0, 0, 0, 0]);
+        chunk
+    }
+
+    fn png_with_text(keyword: &str, text: &str) -> Vec<u8> {
+        let mut text_chunk = keyword.as_bytes().to_vec();
+        text_chunk.push(0);
+        text_chunk.extend_from_slice(text.as_bytes());
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(png_chunk(b"IHDR", &[0; 13]));
+        png.extend(png_chunk(b"tEXt", &text_chunk));
+        png.extend(png_chunk(b"IEND", &[]));
+        png
+    }
+
+    #[test]
+    fn test_parse_v1_json_card() {
+        let card = parse_card(
+            br#"{"name": "Aria", "description": "A bard", "personality": "Cheerful",
+                 "scenario": "A tavern", "first_mes": "Hello!", "mes_example": "<START>"}"#,
+        )
+        .unwrap();
+        let character = card.into_character();
+
+        assert_eq!(character.name, "Aria");
+        assert_eq!(character.background, "A tavern");
+        assert_eq!(character.first_message, "Hello!");
+        assert_eq!(character.example_dialogue, "<START>");
+    }
+
+    #[test]
+    fn test_parse_v2_png_card() {
+        let json = r#"{"spec": "chara_card_v2", "spec_version": "2.0",
+                       "data": {"name": "Aria", "description": "A bard", "first_mes": "Hi"}}"#;
+        let encoded = base64::engine::general_purpose::STANDARD.encode(json);
+
+        let card = parse_card(&png_with_text("chara", &encoded)).unwrap();
+
+        assert_eq!(card.name, "Aria");
+        assert_eq!(card.first_mes, "Hi");
+        assert_eq!(card.personality, "");
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_cards() {
+        assert!(matches!(
+            parse_card(&png_with_text("other", "x")),
+            Err(CardError::MissingCardChunk)
+        ));
+        assert!(matches!(
+            parse_card(br#"{"description": "nameless"}"#),
+            Err(CardError::MissingName)
+        ));
+        assert!(matches!(
+            parse_card(b"GIF89a"),
+            Err(CardError::UnknownFormat)
+        ));
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let character = Character {
+            name: "Sir Test".to_string(),
+            description: "A knight".to_string(),
+            personality: "Brave".to_string(),
+            background: "The realm".to_string(),
+            voice: Some(Voice::default()),
+            first_message: "Hail!".to_string(),
+            example_dialogue: "{{char}}: Hail!".to_string(),
+        };
+
+        let card = CharacterCardV2::from(&character);
+        assert_eq!(card.spec, "chara_card_v2");
+        assert_eq!(card.data.scenario, "The realm");
+
+        let json = serde_json::to_vec(&card).unwrap();
+        let imported = parse_card(&json).unwrap().into_character();
+
+        assert_eq!(imported.name, character.name);
+        assert_eq!(imported.background, character.background);
+        assert_eq!(imported.first_message, character.first_message);
+        assert_eq!(imported.example_dialogue, character.example_dialogue);
+        assert_eq!(
+            imported.voice.unwrap().voice_name,
+            Voice::default().voice_name
+        );
+    }
+}
@@ -0,0 +1,526 @@
+use chrono::Utc;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::auth::{
+    DEFAULT_USER, check_new_user_name, create_token, delete_token, delete_user, hash_password,
+    load_all_tokens, load_all_users, load_user, save_user,
+};
+use crate::backup::{ArchiveFormat, RestoreMode, restore_archive, write_backup};
+use crate::config::{RuntimeConfig, data_dir};
+use crate::controllers::character_controller::load_all_characters;
+use crate::controllers::job_controller::{
+    job_recipients, load_all_jobs, load_job_by_slug, preview_job, remove_subscriber,
+    run_job_internal,
+};
+use crate::controllers::persona_controller::load_all_personas;
+use crate::controllers::prompt_controller::load_all_prompts;
+use crate::fsck::{FsckReport, run_fsck};
+use crate::migrations::{migration_status, run_migrations};
+use crate::models::{Scope, Settings, User};
+use crate::settings::{load_settings, validate_settings};
+use crate::utils::{character_slug, persona_slug, prompt_slug};
+
+type CommandResult = Result<bool, Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Parser)]
+#[command(version, about = "Storytime backend")]
+pub struct Cli {
+    #[command(flatten)]
+    pub config: RuntimeConfig,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP server and job scheduler (the default)
+    Serve,
+    /// Run a job once and save the result to the chat history
+    RunJob {
+        /// The job's id, as used in /api/jobs/{slug}
+        slug: String,
+        /// Print the first LLM request instead of calling the LLM and TTS services
+        #[arg(long)]
+        dry_run: bool,
+        /// Run for this user only, instead of every user the job writes to
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// List saved jobs, characters, prompts or personas
+    List {
+        #[arg(value_enum)]
+        kind: ListKind,
+    },
+    /// Check settings, job schedules and references between files
+    Validate,
+    /// Check the data directory for problems
+    Fsck {
+        /// Fix the problems that can be fixed automatically
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Write a backup archive of the data directory
+    Export {
+        /// File to write; defaults to a timestamped name in the current directory
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = ArchiveFormat::TarGz)]
+        format: ArchiveFormat,
+    },
+    /// Restore a backup archive into the data directory
+    Import {
+        archive: PathBuf,
+        #[arg(long, value_enum, default_value_t = RestoreMode::Merge)]
+        mode: RestoreMode,
+    },
+    /// Run pending data migrations
+    Migrate {
+        /// Only show the data version and pending migrations
+        #[arg(long)]
+        status: bool,
+    },
+    /// Manage the users who can sign in when authentication is enabled
+    User {
+        #[command(subcommand)]
+        action: UserAction,
+    },
+    /// Manage API tokens for scripts
+    Token {
+        #[command(subcommand)]
+        action: TokenAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum UserAction {
+    /// Add a user, reading their password from stdin
+    Add {
+        name: String,
+        /// Allow the user to change settings, restore backups and manage users
+        #[arg(long)]
+        admin: bool,
+    },
+    /// Change a user's password, reading it from stdin; signs out their sessions
+    Passwd {
+        name: String,
+    },
+    /// Remove a user with their chats, revoke their tokens and unsubscribe them from jobs
+    Remove {
+        name: String,
+    },
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum TokenAction {
+    /// Create a token and print its secret, which is not shown again
+    Create {
+        /// The user the token acts as
+        #[arg(long)]
+        user: String,
+        /// A label to tell tokens apart
+        name: String,
+        /// Repeat for several scopes
+        #[arg(long = "scope", value_enum, required = true)]
+        scopes: Vec<Scope>,
+    },
+    List,
+    /// Revoke a token by its id
+    Revoke {
+        id: uuid::Uuid,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ListKind {
+    Jobs,
+    Characters,
+    Prompts,
+    Personas,
+}
+
+/// Run any command other than `serve` and return the process exit code
+/// Commands exit with 1 when they fail or find problems
+pub async fn run_command(command: Command) -> i32 {
+    let result = match command {
+        Command::Serve => unreachable!("serve is run by the binary"),
+        Command::RunJob {
+            slug,
+            dry_run,
+            user,
+        } => run_job(&slug, dry_run, user).await,
+        Command::List { kind } => list(kind).await,
+        Command::Validate => validate().await,
+        Command::Fsck { repair } => fsck(repair).await,
+        Command::Export { output, format } => export(output, format).await,
+        Command::Import { archive, mode } => import(archive, mode).await,
+        Command::Migrate { status } => migrate(status).await,
+        Command::User { action } => user(action).await,
+        Command::Token { action } => token(action).await,
+    };
+
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            1
+        }
+    }
+}
+
+async fn run_job(slug: &str, dry_run: bool, user: Option<String>) -> CommandResult {
+    let job = load_job_by_slug(slug)
+        .await
+        .map_err(|e| format!("Failed to load job '{slug}': {e}"))?;
+    let recipients = match user {
+        Some(user) => vec![user],
+        None => job_recipients(&job).await?,
+    };
+
+    if dry_run {
+        // Messages differ between users only by name, so one preview is enough
+        let user = &recipients[0];
+        let preview = preview_job(&job, user).await?;
+        println!(
+            "Character: {}\nPrompt: {}\nUser: {}\n\n{}",
+            preview.character, preview.prompt, user, preview.llm_prompt
+        );
+        if preview.remaining_requests > 0 {
+            println!(
+                "\n{} more LLM requests would follow, built from the responses",
+                preview.remaining_requests
+            );
+        }
+        return Ok(true);
+    }
+
+    let settings: Arc<Settings> = load_settings()
+        .map_err(|e| format!("Failed to load settings: {e}"))?
+        .into();
+    for user in &recipients {
+        if recipients.len() > 1 {
+            println!("[user] {user}");
+        }
+        let response = run_job_internal(job.clone(), Arc::clone(&settings), true, user).await?;
+        if let Some(message) = &response.0.data {
+            for line in &message.text {
+                println!("{line}");
+            }
+            for audio in &message.audio {
+                println!("[audio] {audio}.mp3");
+            }
+        }
+    }
+    Ok(true)
+}
+
+async fn list(kind: ListKind) -> CommandResult {
+    match kind {
+        ListKind::Jobs => {
+            for job in load_all_jobs().await? {
+                let id = job.id.map(|id| id.to_string()).unwrap_or_default();
+                let state = if job.enabled { "" } else { " (disabled)" };
+                println!(
+                    "{}\t{}{}\t{} / {}",
+                    id,
+                    job.cadence,
+                    state,
+                    job.characters.join(", "),
+                    job.prompts.join(", ")
+                );
+            }
+        }
+        ListKind::Characters => {
+            for character in load_all_characters().await? {
+                println!("{}\t{}", character_slug(&character.name), character.name);
+            }
+        }
+        ListKind::Prompts => {
+            for prompt in load_all_prompts().await? {
+                println!("{}\t{}", prompt_slug(&prompt.title), prompt.title);
+            }
+        }
+        ListKind::Personas => {
+            for persona in load_all_personas().await? {
+                let user = persona.user.as_deref().unwrap_or(DEFAULT_USER);
+                println!(
+                    "{}\t{}\t{}",
+                    persona_slug(&persona.name),
+                    persona.name,
+                    user
+                );
+            }
+        }
+    }
+    Ok(true)
+}
+
+async fn validate() -> CommandResult {
+    let mut valid = true;
+
+    match load_settings() {
+        Ok(settings) => {
+            if let Err(errors) = validate_settings(&settings) {
+                valid = false;
+                for error in errors {
+                    println!("[error] settings.json: {error}");
+                }
+            }
+        }
+        Err(e) => {
+            valid = false;
+            println!("[error] settings.json: {e}");
+        }
+    }
+
+    let report = run_fsck(false).await?;
+    print_report(&report);
+    Ok(valid && !report.has_errors())
+}
+
+async fn fsck(repair: bool) -> CommandResult {
+    let report = run_fsck(repair).await?;
+    print_report(&report);
+    Ok(!report.has_errors())
+}
+
+fn print_report(report: &FsckReport) {
+    for issue in &report.issues {
+        let status = if issue.repaired {
+            " (repaired)"
+        } else if issue.repairable {
+            " (repairable)"
+        } else {
+            ""
+        };
+        println!(
+            "[{}] {}: {}{}",
+            issue.severity, issue.path, issue.message, status
+        );
+    }
+    println!(
+        "{} issues found, {} repaired",
+        report.issues.len(),
+        report.repaired
+    );
+}
+
+async fn export(output: Option<PathBuf>, format: ArchiveFormat) -> CommandResult {
+    let output = output.unwrap_or_else(|| {
+        PathBuf::from(format!(
+            "storytime-backup-{}.{}",
+            Utc::now().format("%Y%m%dT%H%M%SZ"),
+            format.extension()
+        ))
+    });
+
+    let path = output.clone();
+    let manifest = tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::create(&path)?;
+        write_backup(data_dir(), format, &mut file)
+    })
+    .await??;
+
+    println!(
+        "Exported {} files to {}",
+        manifest.files.len(),
+        output.display()
+    );
+    Ok(true)
+}
+
+async fn import(archive: PathBuf, mode: RestoreMode) -> CommandResult {
+    let bytes = tokio::fs::read(&archive)
+        .await
+        .map_err(|e| format!("Failed to read {}: {e}", archive.display()))?;
+    let summary = restore_archive(data_dir(), bytes, mode).await?;
+    let applied = run_migrations(data_dir()).await?;
+
+    println!(
+        "Imported {} files from backup created at {}",
+        summary.files, summary.created_at
+    );
+    if !applied.is_empty() {
+        println!("Applied {} data migrations", applied.len());
+    }
+    println!("A running server picks up restored jobs after POST /api/scheduler/reload");
+    Ok(true)
+}
+
+async fn migrate(status_only: bool) -> CommandResult {
+    if status_only {
+        let status = migration_status(data_dir())?;
+        println!(
+            "Data version {}, supported version {}",
+            status.data_version, status.supported_version
+        );
+        for migration in status.pending {
+            println!("pending {}: {}", migration.version, migration.description);
+        }
+        return Ok(true);
+    }
+
+    let applied = run_migrations(data_dir()).await?;
+    for migration in &applied {
+        println!(
+            "applied {}: {} ({} changes)",
+            migration.version,
+            migration.description,
+            migration.changes.len()
+        );
+    }
+    println!("{} migrations applied", applied.len());
+    Ok(true)
+}
+
+async fn user(action: UserAction) -> CommandResult {
+    match action {
+        UserAction::Add { name, admin } => {
+            check_new_user_name(&name).await?;
+            let password_hash = read_password_hash().await?;
+            save_user(&User {
+                name: name.clone(),
+                password_hash: Some(password_hash),
+                admin,
+            })
+            .await?;
+            println!("Added user '{name}'");
+        }
+        UserAction::Passwd { name } => {
+            let mut user = load_user(&name).await?;
+            user.password_hash = Some(read_password_hash().await?);
+            save_user(&user).await?;
+            println!("Changed the password of '{name}'");
+        }
+        UserAction::Remove { name } => {
+            let user = load_user(&name).await?;
+            delete_user(&user.name).await?;
+            remove_subscriber(&user.name).await?;
+            println!("Removed user '{}'", user.name);
+        }
+        UserAction::List => {
+            for user in load_all_users().await? {
+                let role = if user.admin { "admin" } else { "user" };
+                println!("{}\t{}", user.name, role);
+            }
+        }
+    }
+    Ok(true)
+}
+
+async fn token(action: TokenAction) -> CommandResult {
+    match action {
+        TokenAction::Create { user, name, scopes } => {
+            let user = load_user(&user).await?;
+            if let Some(scope) = scopes.iter().find(|scope| !user.scopes().contains(scope)) {
+                return Err(
+                    format!("User '{}' does not have the '{scope}' scope", user.name).into(),
+                );
+            }
+            let (token, secret) = create_token(&user.name, &name, scopes).await?;
+            eprintln!("Created token {}; the secret is not shown again", token.id);
+            println!("{secret}");
+        }
+        TokenAction::List => {
+            for token in load_all_tokens().await? {
+                let scopes: Vec<String> = token.scopes.iter().map(Scope::to_string).collect();
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    token.id,
+                    token.user,
+                    token.name,
+                    scopes.join(",")
+                );
+            }
+        }
+        TokenAction::Revoke { id } => {
+            delete_token(&id).await?;
+            println!("Revoked token {id}");
+        }
+    }
+    Ok(true)
+}
+
+/// Read a password from the first line of stdin, prompting when it is a terminal
+async fn read_password_hash() -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    use std::io::{BufRead, IsTerminal, Write};
+
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        eprint!("Password: ");
+        std::io::stderr().flush()?;
+    }
+    let mut password = String::new();
+    stdin.lock().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        return Err("Password must not be empty".into());
+    }
+    Ok(tokio::task::spawn_blocking(move || hash_password(&password)).await??)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_subcommands() {
+        let cli = Cli::try_parse_from(["backend", "run-job", "abc", "--dry-run"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::RunJob { ref slug, dry_run: true, user: None }) if slug == "abc"
+        ));
+
+        let cli = Cli::try_parse_from(["backend", "list", "characters"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::List {
+                kind: ListKind::Characters
+            })
+        ));
+
+        let cli = Cli::try_parse_from(["backend", "export", "--format", "zip"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Export {
+                output: None,
+                format: ArchiveFormat::Zip
+            })
+        ));
+
+        let cli = Cli::try_parse_from(["backend", "import", "backup.tar.gz", "--mode", "replace"])
+            .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Import {
+                mode: RestoreMode::Replace,
+                ..
+            })
+        ));
+
+        // Global flags work after the subcommand too
+        let cli = Cli::try_parse_from(["backend", "migrate", "--data-dir", "/tmp/x"]).unwrap();
+        assert_eq!(cli.config.data_dir, PathBuf::from("/tmp/x"));
+
+        let cli = Cli::try_parse_from([
+            "backend", "token", "create", "cron", "--user", "ada", "--scope", "read", "--scope",
+            "run",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Token {
+                action: TokenAction::Create { ref scopes, .. }
+            }) if scopes == &[Scope::Read, Scope::Run]
+        ));
+        assert!(
+            Cli::try_parse_from(["backend", "token", "create", "cron", "--user", "ada"]).is_err()
+        );
+
+        assert!(Cli::try_parse_from(["backend", "list", "chats"]).is_err());
+        assert!(Cli::try_parse_from(["backend"]).unwrap().command.is_none());
+    }
+}
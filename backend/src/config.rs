@@ -0,0 +1,138 @@
+use clap::Args;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const DEFAULT_DATA_DIR: &str = "./data";
+const DEFAULT_ASSETS_DIR: &str = "assets";
+const DEFAULT_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+const DEFAULT_PORT: u16 = 3000;
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 30;
+
+/// Where an instance keeps its files and where it listens
+/// Set once at startup from command line flags or `STORYTIME_*` environment variables
+#[derive(Args, Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    /// Directory holding characters, prompts, jobs, chats, media and settings.json
+    #[arg(long, env = "STORYTIME_DATA_DIR", default_value = DEFAULT_DATA_DIR, global = true)]
+    pub data_dir: PathBuf,
+    /// Directory holding the built frontend
+    #[arg(long, env = "STORYTIME_ASSETS_DIR", default_value = DEFAULT_ASSETS_DIR, global = true)]
+    pub assets_dir: PathBuf,
+    /// Address to listen on
+    #[arg(long, env = "STORYTIME_ADDRESS", default_value_t = DEFAULT_ADDRESS, global = true)]
+    pub address: IpAddr,
+    /// Port to listen on
+    #[arg(long, env = "STORYTIME_PORT", default_value_t = DEFAULT_PORT, global = true)]
+    pub port: u16,
+    /// Seconds to wait for running jobs and requests when shutting down
+    /// Docker kills the container after 10 seconds unless `stop_grace_period` is raised
+    #[arg(
+        long,
+        env = "STORYTIME_SHUTDOWN_TIMEOUT",
+        default_value_t = DEFAULT_SHUTDOWN_TIMEOUT,
+        global = true
+    )]
+    pub shutdown_timeout: u64,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: PathBuf::from(DEFAULT_DATA_DIR),
+            assets_dir: PathBuf::from(DEFAULT_ASSETS_DIR),
+            address: DEFAULT_ADDRESS,
+            port: DEFAULT_PORT,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+        }
+    }
+}
+
+impl RuntimeConfig {
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+}
+
+/// Global runtime configuration, shared like the job scheduler
+static CONFIG: OnceLock<RuntimeConfig> = OnceLock::new();
+
+/// Install the runtime configuration
+/// Must be called before anything touches the data directory; fails if it was already set
+pub fn init_config(config: RuntimeConfig) -> Result<(), RuntimeConfig> {
+    CONFIG.set(config)
+}
+
+/// The runtime configuration, or the defaults if `init_config` was never called
+pub fn config() -> &'static RuntimeConfig {
+    CONFIG.get_or_init(RuntimeConfig::default)
+}
+
+/// Root of the data directory
+pub fn data_dir() -> &'static Path {
+    #[cfg(test)]
+    if let Ok(dir) = TEST_DATA_DIR.try_with(|dir| *dir) {
+        return dir;
+    }
+    &config().data_dir
+}
+
+#[cfg(test)]
+tokio::task_local! {
+    static TEST_DATA_DIR: &'static Path;
+}
+
+/// Run a test with the data directory pointed at a fresh temporary directory
+/// Only the test's own task sees it, so tests using it can run in parallel
+#[cfg(test)]
+pub async fn with_test_data_dir<F: std::future::Future>(test: F) -> F::Output {
+    let dir = tempfile::tempdir().expect("failed to create the test data directory");
+    let path: &'static Path = Box::leak(dir.path().to_path_buf().into_boxed_path());
+    TEST_DATA_DIR.scope(path, test).await
+}
+
+/// Root of the frontend assets
+pub fn assets_dir() -> &'static Path {
+    &config().assets_dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        config: RuntimeConfig,
+    }
+
+    #[test]
+    fn test_runtime_config_flags() {
+        let defaults = TestCli::try_parse_from(["storytime"]).unwrap().config;
+        assert_eq!(defaults, RuntimeConfig::default());
+        assert_eq!(defaults.socket_addr().to_string(), "0.0.0.0:3000");
+
+        let config = TestCli::try_parse_from([
+            "storytime",
+            "--data-dir",
+            "/srv/storytime-a",
+            "--address",
+            "127.0.0.1",
+            "--port",
+            "3100",
+        ])
+        .unwrap()
+        .config;
+        assert_eq!(config.data_dir, PathBuf::from("/srv/storytime-a"));
+        assert_eq!(config.assets_dir, PathBuf::from("assets"));
+        assert_eq!(config.socket_addr().to_string(), "127.0.0.1:3100");
+
+        assert!(TestCli::try_parse_from(["storytime", "--port", "http"]).is_err());
+    }
+}
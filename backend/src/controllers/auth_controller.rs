@@ -0,0 +1,179 @@
+use axum::{
+    Extension,
+    extract::{Json as JsonExtract, Path, State},
+    http::header,
+    response::{IntoResponse, Json, Response},
+};
+
+use crate::auth::{
+    AuthContext, create_token as create_api_token, delete_token as delete_api_token, end_session,
+    load_all_tokens, load_user, start_session, verify_password,
+};
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    ApiResponse, CreateTokenRequest, CreatedToken, LoginRequest, Scope, SessionInfo, TokenInfo,
+    UserInfo,
+};
+use crate::settings::SharedSettings;
+
+/// Check a user's password and set a session cookie
+pub async fn login(
+    State(shared_settings): State<SharedSettings>,
+    JsonExtract(request): JsonExtract<LoginRequest>,
+) -> Result<Response, AppError> {
+    let settings = shared_settings.current();
+    if !settings.auth.enabled {
+        return Err(AppError::Validation(
+            "Authentication is disabled".to_string(),
+        ));
+    }
+
+    // Unknown users and wrong passwords get the same answer
+    let invalid = || AppError::Unauthorized("Invalid name or password".to_string());
+    let user = load_user(&request.name).await.map_err(|_| invalid())?;
+    let Some(hash) = user.password_hash.clone() else {
+        return Err(invalid());
+    };
+    let valid =
+        tokio::task::spawn_blocking(move || verify_password(&hash, &request.password)).await?;
+    if !valid {
+        tracing::warn!("Failed login for user '{}'", user.name);
+        return Err(invalid());
+    }
+
+    let cookie = start_session(&user, settings.auth.session_hours)?;
+    tracing::info!("User '{}' signed in", user.name);
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Json(ApiResponse::success(
+            UserInfo::from(&user),
+            "Signed in successfully",
+        )),
+    )
+        .into_response())
+}
+
+/// Clear the session cookie
+pub async fn logout() -> Response {
+    (
+        [(header::SET_COOKIE, end_session())],
+        Json(ApiResponse::success((), "Signed out successfully")),
+    )
+        .into_response()
+}
+
+/// Whether authentication is enabled and who is signed in, so the SPA knows to show a login form
+pub async fn get_session(
+    State(shared_settings): State<SharedSettings>,
+    context: Option<Extension<AuthContext>>,
+) -> AppResult<SessionInfo> {
+    let user = match context {
+        Some(Extension(context)) => {
+            let user = load_user(&context.user).await?;
+            Some(UserInfo {
+                name: user.name,
+                admin: user.admin,
+                scopes: context.scopes,
+            })
+        }
+        None => None,
+    };
+    Ok(Json(ApiResponse::success(
+        SessionInfo {
+            auth_enabled: shared_settings.current().auth.enabled,
+            user,
+        },
+        "Session retrieved successfully",
+    )))
+}
+
+/// List the caller's API tokens, or every token for admins
+pub async fn get_tokens(context: Option<Extension<AuthContext>>) -> AppResult<Vec<TokenInfo>> {
+    let context = caller(context)?;
+    let tokens = load_all_tokens()
+        .await
+        .map_err(|e| e.context("Failed to load tokens"))?
+        .iter()
+        .filter(|token| context.has_scope(Scope::Admin) || token.user == context.user)
+        .map(TokenInfo::from)
+        .collect();
+    Ok(Json(ApiResponse::success(
+        tokens,
+        "Tokens retrieved successfully",
+    )))
+}
+
+/// Create an API token for the caller
+/// A token can only have scopes the caller has, so a read-only token cannot mint a writer
+pub async fn create_token(
+    context: Option<Extension<AuthContext>>,
+    JsonExtract(request): JsonExtract<CreateTokenRequest>,
+) -> AppResult<CreatedToken> {
+    let context = caller(context)?;
+    if request.name.trim().is_empty() {
+        return Err(AppError::Validation("Token name is required".to_string()));
+    }
+    if request.scopes.is_empty() {
+        return Err(AppError::Validation(
+            "A token needs at least one scope".to_string(),
+        ));
+    }
+    if let Some(scope) = request
+        .scopes
+        .iter()
+        .find(|scope| !context.has_scope(**scope))
+    {
+        return Err(AppError::Forbidden(format!(
+            "You cannot grant the '{scope}' scope"
+        )));
+    }
+
+    let mut scopes = request.scopes;
+    scopes.sort();
+    scopes.dedup();
+    let (token, secret) = create_api_token(&context.user, request.name.trim(), scopes)
+        .await
+        .map_err(|e| e.context("Failed to create token"))?;
+    tracing::info!("User '{}' created token '{}'", context.user, token.name);
+
+    Ok(Json(ApiResponse::success(
+        CreatedToken {
+            token: TokenInfo::from(&token),
+            secret,
+        },
+        "Token created successfully; copy the secret now, it is not shown again",
+    )))
+}
+
+/// Revoke one of the caller's tokens; admins may revoke anyone's
+pub async fn delete_token(
+    context: Option<Extension<AuthContext>>,
+    Path(id): Path<uuid::Uuid>,
+) -> AppResult<()> {
+    let context = caller(context)?;
+    let not_found = || AppError::NotFound(format!("Token '{id}' not found"));
+    let token = load_all_tokens()
+        .await?
+        .into_iter()
+        .find(|token| token.id == id)
+        .ok_or_else(not_found)?;
+    if token.user != context.user && !context.has_scope(Scope::Admin) {
+        return Err(not_found());
+    }
+
+    delete_api_token(&id)
+        .await
+        .map_err(|e| e.context("Failed to revoke token"))?;
+    tracing::info!("User '{}' revoked token '{}'", context.user, token.name);
+    Ok(Json(ApiResponse::success(
+        (),
+        format!("Token '{}' revoked successfully", token.name),
+    )))
+}
+
+/// The signed-in caller; tokens only exist while authentication is enabled
+fn caller(context: Option<Extension<AuthContext>>) -> Result<AuthContext, AppError> {
+    context.map(|Extension(context)| context).ok_or_else(|| {
+        AppError::Validation("API tokens need authentication to be enabled".to_string())
+    })
+}
@@ -0,0 +1,105 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use chrono::Utc;
+use serde::Deserialize;
+use std::io::{Seek, SeekFrom};
+use tokio_util::io::ReaderStream;
+
+use crate::backup::{
+    ArchiveFormat, BackupError, RestoreMode, RestoreSummary, restore_archive, write_backup,
+};
+use crate::config::data_dir;
+use crate::error::{AppError, AppResult};
+use crate::job_scheduler::reload_jobs;
+use crate::migrations::run_migrations;
+use crate::models::ApiResponse;
+use crate::settings::SharedSettings;
+
+#[derive(Deserialize, Debug)]
+pub struct BackupQuery {
+    #[serde(default)]
+    pub format: ArchiveFormat,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RestoreQuery {
+    #[serde(default)]
+    pub mode: RestoreMode,
+}
+
+/// Download a backup archive of the whole data directory
+/// The archive is written to a temporary file first so the download is a single snapshot
+pub async fn download_backup(Query(query): Query<BackupQuery>) -> Result<Response, AppError> {
+    let format = query.format;
+    let (manifest, file) = tokio::task::spawn_blocking(move || {
+        let mut file = tempfile::tempfile()?;
+        let manifest = write_backup(data_dir(), format, &mut file)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok::<_, BackupError>((manifest, file))
+    })
+    .await?
+    .map_err(|e| AppError::from(e).context("Failed to create backup"))?;
+
+    tracing::info!("Streaming backup of {} files", manifest.files.len());
+
+    let file_name = format!(
+        "storytime-backup-{}.{}",
+        Utc::now().format("%Y%m%dT%H%M%SZ"),
+        format.extension()
+    );
+    let body = Body::from_stream(ReaderStream::new(tokio::fs::File::from_std(file)));
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{file_name}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Restore a backup archive uploaded as the request body
+pub async fn restore_backup(
+    State(shared_settings): State<SharedSettings>,
+    Query(query): Query<RestoreQuery>,
+    body: Bytes,
+) -> AppResult<RestoreSummary> {
+    let mode = query.mode;
+    let summary = restore_archive(data_dir(), body.into(), mode)
+        .await
+        .map_err(|e| match e {
+            // The message already says the archive is invalid
+            BackupError::Invalid(_) => AppError::from(e),
+            _ => AppError::from(e).context("Failed to restore backup"),
+        })?;
+
+    // Archives from older versions need the same migrations as old data at startup
+    run_migrations(data_dir())
+        .await
+        .map_err(|e| e.context("Restored backup but failed to migrate it"))?;
+
+    // Restored jobs replace whatever the scheduler currently has loaded
+    if let Err(e) = reload_jobs().await {
+        tracing::warn!("Failed to reload scheduler after restoring backup: {e}");
+    }
+
+    // Restored settings apply straight away, like an update through the settings API
+    if let Err(e) = shared_settings.reload() {
+        tracing::warn!("Failed to reload settings after restoring backup: {e}");
+    }
+
+    let count = summary.files;
+    Ok(Json(ApiResponse::success(
+        summary,
+        format!("Restored {} files from backup", count),
+    )))
+}
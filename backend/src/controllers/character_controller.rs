@@ -1,84 +1,45 @@
-use axum::{Json as JsonExtract, extract::Path, http::StatusCode, response::Json};
+use axum::{
+    Json as JsonExtract,
+    body::Bytes,
+    extract::Path,
+    http::header,
+    response::{IntoResponse, Json, Response},
+};
 use tokio::fs;
 
-use crate::models::{ApiResponse, Character, CreateCharacterRequest, UpdateCharacterRequest};
-use crate::utils::{character_file_path, character_slug};
+use crate::character_card::{CharacterCardV2, parse_card};
+use crate::config::data_dir;
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    ApiResponse, Character, CreateCharacterRequest, UpdateCharacterRequest, WithSlug,
+};
+use crate::utils::{character_file_path, character_slug, check_new_slug};
 
 /// Get all characters
-pub async fn get_characters()
--> Result<Json<ApiResponse<Vec<Character>>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match load_all_characters().await {
-        Ok(characters) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(characters),
-            message: "Characters retrieved successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to load characters: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to load characters: {e}"),
-                }),
-            ))
-        }
-    }
+pub async fn get_characters() -> AppResult<Vec<WithSlug<Character>>> {
+    let characters = load_all_characters()
+        .await
+        .map_err(|e| e.context("Failed to load characters"))?;
+    Ok(Json(ApiResponse::success(
+        characters.into_iter().map(with_slug).collect(),
+        "Characters retrieved successfully",
+    )))
 }
 
 /// Get a specific character by slug
-pub async fn get_character(
-    Path(slug): Path<String>,
-) -> Result<Json<ApiResponse<Character>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match load_character_by_slug(&slug).await {
-        Ok(character) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(character),
-            message: "Character retrieved successfully".to_string(),
-        })),
-        Err(e) => {
-            if e.to_string().contains("No such file or directory")
-                || e.to_string().contains("Character not found")
-            {
-                Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Character with slug '{slug}' not found"),
-                    }),
-                ))
-            } else {
-                tracing::error!("Failed to load character with slug '{slug}': {e}");
-                Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load character: {e}"),
-                    }),
-                ))
-            }
-        }
-    }
+pub async fn get_character(Path(slug): Path<String>) -> AppResult<WithSlug<Character>> {
+    let character = load_character_by_slug(&slug).await?;
+    Ok(Json(ApiResponse::success(
+        with_slug(character),
+        "Character retrieved successfully",
+    )))
 }
 
 /// Create a new character
 pub async fn create_character(
     JsonExtract(request): JsonExtract<CreateCharacterRequest>,
-) -> Result<Json<ApiResponse<Character>>, (StatusCode, Json<ApiResponse<()>>)> {
-    // Check if character already exists
-    if load_character(&request.name).await.is_ok() {
-        return Err((
-            StatusCode::CONFLICT,
-            Json(ApiResponse {
-                success: false,
-                data: None,
-                message: format!("Character '{}' already exists", request.name),
-            }),
-        ));
-    }
+) -> AppResult<WithSlug<Character>> {
+    check_new_character_name(&request.name).await?;
 
     let character = Character {
         name: request.name.clone(),
@@ -86,60 +47,25 @@ pub async fn create_character(
         personality: request.personality,
         background: request.background,
         voice: request.voice,
+        first_message: request.first_message,
+        example_dialogue: request.example_dialogue,
     };
 
-    match save_character(&character).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(character),
-            message: "Character created successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to create character '{}': {}", request.name, e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to create character: {e}"),
-                }),
-            ))
-        }
-    }
+    save_character(&character)
+        .await
+        .map_err(|e| e.context("Failed to create character"))?;
+    Ok(Json(ApiResponse::success(
+        with_slug(character),
+        "Character created successfully",
+    )))
 }
 
 /// Update an existing character
 pub async fn update_character(
     Path(slug): Path<String>,
     JsonExtract(request): JsonExtract<UpdateCharacterRequest>,
-) -> Result<Json<ApiResponse<Character>>, (StatusCode, Json<ApiResponse<()>>)> {
-    let mut character = match load_character_by_slug(&slug).await {
-        Ok(character) => character,
-        Err(e) => {
-            if e.to_string().contains("No such file or directory")
-                || e.to_string().contains("Character not found")
-            {
-                return Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Character with slug '{slug}' not found"),
-                    }),
-                ));
-            } else {
-                tracing::error!("Failed to load character with slug '{slug}': {e}");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load character: {e}"),
-                    }),
-                ));
-            }
-        }
-    };
+) -> AppResult<WithSlug<Character>> {
+    let mut character = load_character_by_slug(&slug).await?;
 
     // Update only provided fields
     if let Some(description) = request.description {
@@ -154,84 +80,86 @@ pub async fn update_character(
     if let Some(voice) = request.voice {
         character.voice = Some(voice);
     }
-
-    match save_character(&character).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(character),
-            message: "Character updated successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to update character with slug '{slug}': {e}");
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to update character: {e}"),
-                }),
-            ))
-        }
+    if let Some(first_message) = request.first_message {
+        character.first_message = first_message;
+    }
+    if let Some(example_dialogue) = request.example_dialogue {
+        character.example_dialogue = example_dialogue;
     }
+
+    save_character(&character)
+        .await
+        .map_err(|e| e.context("Failed to update character"))?;
+    Ok(Json(ApiResponse::success(
+        with_slug(character),
+        "Character updated successfully",
+    )))
 }
 
 /// Delete a character by slug
-pub async fn delete_character(
-    Path(slug): Path<String>,
-) -> Result<Json<ApiResponse<()>>, (StatusCode, Json<ApiResponse<()>>)> {
+pub async fn delete_character(Path(slug): Path<String>) -> AppResult<()> {
     // First, find the character to get its name for deletion
-    let character = match load_character_by_slug(&slug).await {
-        Ok(character) => character,
-        Err(e) => {
-            if e.to_string().contains("Character not found") {
-                return Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Character with slug '{slug}' not found"),
-                    }),
-                ));
-            } else {
-                tracing::error!("Failed to find character with slug '{slug}': {e}");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to find character: {e}"),
-                    }),
-                ));
-            }
-        }
-    };
+    let character = load_character_by_slug(&slug).await?;
+
+    delete_character_file(&character.name)
+        .await
+        .map_err(|e| e.context("Failed to delete character"))?;
+    Ok(Json(ApiResponse::success(
+        (),
+        format!("Character '{}' deleted successfully", character.name),
+    )))
+}
 
-    match delete_character_file(&character.name).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: None,
-            message: format!("Character '{}' deleted successfully", character.name),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to delete character '{}': {e}", character.name);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to delete character: {e}"),
-                }),
-            ))
-        }
+/// Import a SillyTavern / TavernAI character card (.json or .png) as a new character
+pub async fn import_character_card(body: Bytes) -> AppResult<WithSlug<Character>> {
+    let character = parse_card(&body)
+        .map_err(|e| AppError::Validation(format!("Failed to import character card: {e}")))?
+        .into_character();
+
+    check_new_character_name(&character.name).await?;
+
+    save_character(&character)
+        .await
+        .map_err(|e| e.context("Failed to import character"))?;
+    Ok(Json(ApiResponse::success(
+        with_slug(character),
+        "Character imported successfully",
+    )))
+}
+
+/// Export a character as a Character Card V2 JSON file
+pub async fn export_character_card(Path(slug): Path<String>) -> Result<Response, AppError> {
+    let character = load_character_by_slug(&slug).await?;
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{slug}.json\""),
+        )],
+        Json(CharacterCardV2::from(&character)),
+    )
+        .into_response())
+}
+
+fn with_slug(character: Character) -> WithSlug<Character> {
+    WithSlug {
+        slug: character_slug(&character.name),
+        item: character,
     }
 }
 
+/// Check that a new character's name produces a usable slug that no other character has
+async fn check_new_character_name(name: &str) -> Result<(), AppError> {
+    let existing = async { load_character(name).await.map(|character| character.name) };
+    check_new_slug("Character", "name", name, &character_slug(name), existing).await
+}
+
 // File I/O utility functions
 
 /// Load all characters from the data/characters directory
-async fn load_all_characters() -> Result<Vec<Character>, Box<dyn std::error::Error + Send + Sync>> {
+pub async fn load_all_characters() -> Result<Vec<Character>, AppError> {
     let mut characters = Vec::new();
-    let dir_path = "./data/characters";
+    let dir_path = data_dir().join("characters");
 
     let mut entries = fs::read_dir(dir_path).await?;
     while let Some(entry) = entries.next_entry().await? {
@@ -249,19 +177,21 @@ async fn load_all_characters() -> Result<Vec<Character>, Box<dyn std::error::Err
 }
 
 /// Load a specific character by name
-async fn load_character(name: &str) -> Result<Character, Box<dyn std::error::Error + Send + Sync>> {
+async fn load_character(name: &str) -> Result<Character, AppError> {
     let file_path = character_file_path(name);
-    let content = fs::read_to_string(&file_path).await?;
+    let content = fs::read_to_string(&file_path)
+        .await
+        .map_err(|e| AppError::from_io(e, format!("Character '{name}' not found")))?;
     let character: Character = serde_json::from_str(&content)?;
     Ok(character)
 }
 
 /// Load a specific character by slug
 /// This searches all characters to find one whose name converts to the given slug
-async fn load_character_by_slug(
-    slug: &str,
-) -> Result<Character, Box<dyn std::error::Error + Send + Sync>> {
-    let characters = load_all_characters().await?;
+async fn load_character_by_slug(slug: &str) -> Result<Character, AppError> {
+    let characters = load_all_characters()
+        .await
+        .map_err(|e| e.context("Failed to load character"))?;
 
     for character in characters {
         if character_slug(&character.name) == slug {
@@ -269,13 +199,13 @@ async fn load_character_by_slug(
         }
     }
 
-    Err(format!("Character not found with slug: {slug}").into())
+    Err(AppError::NotFound(format!(
+        "Character with slug '{slug}' not found"
+    )))
 }
 
 /// Save a character to a JSON file
-async fn save_character(
-    character: &Character,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn save_character(character: &Character) -> Result<(), AppError> {
     let file_path = character_file_path(&character.name);
     let json_content = serde_json::to_string_pretty(character)?;
     fs::write(&file_path, json_content).await?;
@@ -283,8 +213,10 @@ async fn save_character(
 }
 
 /// Delete a character file
-async fn delete_character_file(name: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn delete_character_file(name: &str) -> Result<(), AppError> {
     let file_path = character_file_path(name);
-    fs::remove_file(&file_path).await?;
+    fs::remove_file(&file_path)
+        .await
+        .map_err(|e| AppError::from_io(e, format!("Character '{name}' not found")))?;
     Ok(())
 }
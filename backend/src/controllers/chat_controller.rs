@@ -1,280 +1,161 @@
-use axum::{Json as JsonExtract, extract::Path, http::StatusCode, response::Json};
+use axum::{Json as JsonExtract, extract::Path, response::Json};
 use chrono::Utc;
 use std::path::PathBuf;
 use tokio::fs;
 
+use crate::auth::CurrentUser;
+use crate::error::{AppError, AppResult};
+use crate::events::{Event, events};
+use crate::media_store::{MediaKind, MediaStore};
 use crate::models::{
     AddMessageRequest, ApiResponse, Chat, CreateChatRequest, Message, UpdateChatRequest,
-    UpdateMessageRequest,
+    UpdateMessageRequest, WithSlug,
 };
-use crate::utils::{audio_file_path, image_file_path};
-
-/// Get all chats
-pub async fn get_chats()
--> Result<Json<ApiResponse<Vec<String>>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match load_all_chat_names().await {
-        Ok(chat_names) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(chat_names),
-            message: "Chat names retrieved successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to load chat names: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to load chat names: {e}"),
-                }),
-            ))
-        }
-    }
+use crate::utils::{
+    character_slug, chat_dir, chat_file_path, lock_chat, lock_chats, write_file_atomic,
+};
+
+/// Get the characters the current user has chats with
+pub async fn get_chats(CurrentUser(user): CurrentUser) -> AppResult<Vec<String>> {
+    let chat_names = load_all_chat_names(&user)
+        .await
+        .map_err(|e| e.context("Failed to load chat names"))?;
+    Ok(Json(ApiResponse::success(
+        chat_names,
+        "Chat names retrieved successfully",
+    )))
 }
 
 /// Get a specific chat by character name
 pub async fn get_chat(
+    CurrentUser(user): CurrentUser,
     Path(character): Path<String>,
-) -> Result<Json<ApiResponse<Chat>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match load_chat(&character).await {
-        Ok(chat) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(chat),
-            message: "Chat retrieved successfully".to_string(),
-        })),
-        Err(e) => {
-            if e.to_string().contains("No such file or directory") {
-                Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Chat for character '{character}' not found"),
-                    }),
-                ))
-            } else {
-                tracing::error!("Failed to load chat for character '{character}': {e}");
-                Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load chat: {e}"),
-                    }),
-                ))
-            }
-        }
-    }
+) -> AppResult<WithSlug<Chat>> {
+    let chat = load_chat(&user, &character).await?;
+    Ok(Json(ApiResponse::success(
+        with_slug(chat),
+        "Chat retrieved successfully",
+    )))
 }
 
 /// Create a new chat
 pub async fn create_chat(
+    CurrentUser(user): CurrentUser,
     JsonExtract(payload): JsonExtract<CreateChatRequest>,
-) -> Result<Json<ApiResponse<Chat>>, (StatusCode, Json<ApiResponse<()>>)> {
+) -> AppResult<WithSlug<Chat>> {
+    let _chat = lock_chat(&user, &payload.character).await;
     // Check if chat already exists
-    if (load_chat(&payload.character).await).is_ok() {
-        return Err((
-            StatusCode::CONFLICT,
-            Json(ApiResponse {
-                success: false,
-                data: None,
-                message: format!("Chat for character '{}' already exists", payload.character),
-            }),
-        ));
+    if (load_chat(&user, &payload.character).await).is_ok() {
+        return Err(AppError::Conflict(format!(
+            "Chat for character '{}' already exists",
+            payload.character
+        )));
     }
 
     let chat = Chat {
         character: payload.character.clone(),
         messages: vec![],
+        muted: false,
     };
 
-    match save_chat(&payload.character, &chat).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(chat),
-            message: "Chat created successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!(
-                "Failed to create chat for character '{}': {}",
-                payload.character,
-                e
-            );
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to create chat: {e}"),
-                }),
-            ))
-        }
-    }
+    save_chat(&user, &payload.character, &chat)
+        .await
+        .map_err(|e| e.context("Failed to create chat"))?;
+    Ok(Json(ApiResponse::success(
+        with_slug(chat),
+        "Chat created successfully",
+    )))
 }
 
 /// Update a chat (change character name)
 pub async fn update_chat(
+    CurrentUser(user): CurrentUser,
     Path(character): Path<String>,
     JsonExtract(payload): JsonExtract<UpdateChatRequest>,
-) -> Result<Json<ApiResponse<Chat>>, (StatusCode, Json<ApiResponse<()>>)> {
-    let mut chat = match load_chat(&character).await {
-        Ok(chat) => chat,
-        Err(e) => {
-            if e.to_string().contains("No such file or directory") {
-                return Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Chat for character '{character}' not found"),
-                    }),
-                ));
-            } else {
-                tracing::error!("Failed to load chat for character '{character}': {e}");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load chat: {e}"),
-                    }),
-                ));
-            }
-        }
-    };
+) -> AppResult<WithSlug<Chat>> {
+    let mut characters = vec![character.as_str()];
+    characters.extend(payload.character.as_deref());
+    let _chats = lock_chats(&user, &characters).await;
+    let mut chat = load_chat(&user, &character).await?;
 
     let old_character = character.clone();
 
+    if let Some(muted) = payload.muted {
+        chat.muted = muted;
+    }
+
     // Update character if provided
     if let Some(new_character) = payload.character {
         chat.character = new_character.clone();
 
-        // If character name changed, delete old file and save with new name
-        if old_character != new_character {
+        // Names with the same slug share a file, so only a new slug moves the chat
+        if character_slug(&old_character) != character_slug(&new_character) {
             // Check if new character chat already exists
-            if (load_chat(&new_character).await).is_ok() {
-                return Err((
-                    StatusCode::CONFLICT,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Chat for character '{new_character}' already exists"),
-                    }),
-                ));
+            if fs::try_exists(chat_file_path(&user, &new_character)).await? {
+                return Err(AppError::Conflict(format!(
+                    "Chat for character '{new_character}' already exists (slug '{}')",
+                    character_slug(&new_character)
+                )));
             }
 
             // Save with new character name
-            match save_chat(&new_character, &chat).await {
-                Ok(_) => {
-                    // Delete old file
-                    if let Err(e) = delete_chat(&old_character).await {
-                        tracing::error!(
-                            "Failed to delete old chat file for '{}': {}",
-                            old_character,
-                            e
-                        );
-                        // Continue anyway since the new file was saved successfully
-                    }
-                }
-                Err(e) => {
-                    tracing::error!("Failed to save updated chat: {}", e);
-                    return Err((
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(ApiResponse {
-                            success: false,
-                            data: None,
-                            message: format!("Failed to update chat: {e}"),
-                        }),
-                    ));
-                }
+            save_chat(&user, &new_character, &chat)
+                .await
+                .map_err(|e| e.context("Failed to update chat"))?;
+
+            // Delete old file
+            if let Err(e) = delete_chat(&user, &old_character).await {
+                tracing::error!(
+                    "Failed to delete old chat file for '{}': {}",
+                    old_character,
+                    e
+                );
+                // Continue anyway since the new file was saved successfully
             }
         } else {
-            // Same character name, just update in place
-            match save_chat(&old_character, &chat).await {
-                Ok(_) => {}
-                Err(e) => {
-                    tracing::error!("Failed to save updated chat: {}", e);
-                    return Err((
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(ApiResponse {
-                            success: false,
-                            data: None,
-                            message: format!("Failed to update chat: {e}"),
-                        }),
-                    ));
-                }
-            }
+            // Same slug, just update in place
+            save_chat(&user, &old_character, &chat)
+                .await
+                .map_err(|e| e.context("Failed to update chat"))?;
         }
+    } else if payload.muted.is_some() {
+        save_chat(&user, &old_character, &chat)
+            .await
+            .map_err(|e| e.context("Failed to update chat"))?;
     }
 
-    Ok(Json(ApiResponse {
-        success: true,
-        data: Some(chat),
-        message: "Chat updated successfully".to_string(),
-    }))
+    Ok(Json(ApiResponse::success(
+        with_slug(chat),
+        "Chat updated successfully",
+    )))
 }
 
 /// Delete a chat
 pub async fn delete_chat_endpoint(
+    CurrentUser(user): CurrentUser,
     Path(character): Path<String>,
-) -> Result<Json<ApiResponse<()>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match delete_chat(&character).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: None,
-            message: "Chat deleted successfully".to_string(),
-        })),
-        Err(e) => {
-            if e.to_string().contains("No such file or directory") {
-                Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Chat for character '{character}' not found"),
-                    }),
-                ))
-            } else {
-                tracing::error!("Failed to delete chat for character '{character}': {e}");
-                Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to delete chat: {e}"),
-                    }),
-                ))
-            }
-        }
-    }
+) -> AppResult<()> {
+    let _chat = lock_chat(&user, &character).await;
+    delete_chat(&user, &character).await?;
+    Ok(Json(ApiResponse::success((), "Chat deleted successfully")))
 }
 
 /// Add a message to a chat
 pub async fn add_message(
+    CurrentUser(user): CurrentUser,
     Path(character): Path<String>,
     JsonExtract(payload): JsonExtract<AddMessageRequest>,
-) -> Result<Json<ApiResponse<Chat>>, (StatusCode, Json<ApiResponse<()>>)> {
-    let mut chat = match load_chat(&character).await {
+) -> AppResult<WithSlug<Chat>> {
+    let _chat = lock_chat(&user, &character).await;
+    let mut chat = match load_chat(&user, &character).await {
         Ok(chat) => chat,
-        Err(e) => {
-            if e.to_string().contains("No such file or directory") {
-                // Chat doesn't exist, create a new one
-                Chat {
-                    character: character.clone(),
-                    messages: vec![],
-                }
-            } else {
-                tracing::error!("Failed to load chat for character '{character}': {e}");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load chat: {e}"),
-                    }),
-                ));
-            }
-        }
+        // Chat doesn't exist, create a new one
+        Err(AppError::NotFound(_)) => Chat {
+            character: character.clone(),
+            messages: vec![],
+            muted: false,
+        },
+        Err(e) => return Err(e),
     };
 
     let message = Message {
@@ -283,71 +164,36 @@ pub async fn add_message(
         images: payload.images,
         read: payload.read,
         timestamp: payload.timestamp.unwrap_or_else(Utc::now),
+        from_user: payload.from_user,
     };
 
-    chat.messages.push(message);
-
-    match save_chat(&character, &chat).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(chat),
-            message: "Message added successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to save chat after adding message: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to add message: {e}"),
-                }),
-            ))
-        }
-    }
+    chat.messages.push(message.clone());
+
+    save_chat(&user, &character, &chat)
+        .await
+        .map_err(|e| e.context("Failed to add message"))?;
+    events().publish(Event::MessageCreated {
+        user,
+        character: chat.character.clone(),
+        index: chat.messages.len() - 1,
+        message,
+        job: None,
+    });
+    Ok(Json(ApiResponse::success(
+        with_slug(chat),
+        "Message added successfully",
+    )))
 }
 
 /// Update a specific message in a chat
 pub async fn update_message(
+    CurrentUser(user): CurrentUser,
     Path((character, message_index)): Path<(String, usize)>,
     JsonExtract(payload): JsonExtract<UpdateMessageRequest>,
-) -> Result<Json<ApiResponse<Chat>>, (StatusCode, Json<ApiResponse<()>>)> {
-    let mut chat = match load_chat(&character).await {
-        Ok(chat) => chat,
-        Err(e) => {
-            if e.to_string().contains("No such file or directory") {
-                return Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Chat for character '{character}' not found"),
-                    }),
-                ));
-            } else {
-                tracing::error!("Failed to load chat for character '{character}': {e}");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load chat: {e}"),
-                    }),
-                ));
-            }
-        }
-    };
-
-    if message_index >= chat.messages.len() {
-        return Err((
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse {
-                success: false,
-                data: None,
-                message: format!("Message at index {message_index} not found"),
-            }),
-        ));
-    }
+) -> AppResult<WithSlug<Chat>> {
+    let _chat = lock_chat(&user, &character).await;
+    let mut chat = load_chat(&user, &character).await?;
+    check_message_index(&chat, message_index)?;
 
     let message = &mut chat.messages[message_index];
 
@@ -368,228 +214,125 @@ pub async fn update_message(
         message.timestamp = timestamp;
     }
 
-    match save_chat(&character, &chat).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(chat),
-            message: "Message updated successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to save chat after updating message: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to update message: {e}"),
-                }),
-            ))
-        }
-    }
+    save_chat(&user, &character, &chat)
+        .await
+        .map_err(|e| e.context("Failed to update message"))?;
+    events().publish(Event::MessageUpdated {
+        user,
+        character: chat.character.clone(),
+        index: message_index,
+        message: chat.messages[message_index].clone(),
+    });
+    Ok(Json(ApiResponse::success(
+        with_slug(chat),
+        "Message updated successfully",
+    )))
 }
 
 /// Delete a specific message from a chat
 pub async fn delete_message(
+    CurrentUser(user): CurrentUser,
     Path((character, message_index)): Path<(String, usize)>,
-) -> Result<Json<ApiResponse<Chat>>, (StatusCode, Json<ApiResponse<()>>)> {
-    let mut chat = match load_chat(&character).await {
-        Ok(chat) => chat,
-        Err(e) => {
-            if e.to_string().contains("No such file or directory") {
-                return Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Chat for character '{character}' not found"),
-                    }),
-                ));
-            } else {
-                tracing::error!("Failed to load chat for character '{character}': {e}");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load chat: {e}"),
-                    }),
-                ));
-            }
-        }
-    };
-
-    if message_index >= chat.messages.len() {
-        return Err((
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse {
-                success: false,
-                data: None,
-                message: format!("Message at index {message_index} not found"),
-            }),
-        ));
-    }
+) -> AppResult<WithSlug<Chat>> {
+    let _chat = lock_chat(&user, &character).await;
+    let mut chat = load_chat(&user, &character).await?;
+    check_message_index(&chat, message_index)?;
 
     // Get the message before deletion to clean up associated files
     let message_to_delete = &chat.messages[message_index];
 
     // Clean up associated audio files
-    if let Err(e) = delete_message_files(&character, message_to_delete).await {
-        tracing::warn!(
-            "Failed to delete some associated files for message {}: {}",
-            message_index,
-            e
-        );
-        // Don't fail the entire operation if file cleanup fails
-    }
+    delete_message_files(&character, message_to_delete).await;
 
     chat.messages.remove(message_index);
 
-    match save_chat(&character, &chat).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(chat),
-            message: "Message deleted successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to save chat after deleting message: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to delete message: {e}"),
-                }),
-            ))
-        }
-    }
+    save_chat(&user, &character, &chat)
+        .await
+        .map_err(|e| e.context("Failed to delete message"))?;
+    events().publish(Event::MessageDeleted {
+        user,
+        character: chat.character.clone(),
+        index: message_index,
+    });
+    Ok(Json(ApiResponse::success(
+        with_slug(chat),
+        "Message deleted successfully",
+    )))
 }
 
 /// Mark a specific message as read
 pub async fn mark_message_as_read(
+    CurrentUser(user): CurrentUser,
     Path((character, message_index)): Path<(String, usize)>,
-) -> Result<Json<ApiResponse<Chat>>, (StatusCode, Json<ApiResponse<()>>)> {
-    let mut chat = match load_chat(&character).await {
-        Ok(chat) => chat,
-        Err(e) => {
-            if e.to_string().contains("No such file or directory") {
-                return Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Chat for character '{character}' not found"),
-                    }),
-                ));
-            } else {
-                tracing::error!("Failed to load chat for character '{character}': {e}");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load chat: {e}"),
-                    }),
-                ));
-            }
-        }
-    };
-
-    if message_index >= chat.messages.len() {
-        return Err((
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse {
-                success: false,
-                data: None,
-                message: format!("Message at index {message_index} not found"),
-            }),
-        ));
-    }
+) -> AppResult<WithSlug<Chat>> {
+    let _chat = lock_chat(&user, &character).await;
+    let mut chat = load_chat(&user, &character).await?;
+    check_message_index(&chat, message_index)?;
 
     // Mark the message as read
     chat.messages[message_index].read = true;
 
-    match save_chat(&character, &chat).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(chat),
-            message: "Message marked as read successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to save chat after marking message as read: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to mark message as read: {e}"),
-                }),
-            ))
-        }
-    }
+    save_chat(&user, &character, &chat)
+        .await
+        .map_err(|e| e.context("Failed to mark message as read"))?;
+    events().publish(Event::MessageRead {
+        user,
+        character: chat.character.clone(),
+        index: Some(message_index),
+    });
+    Ok(Json(ApiResponse::success(
+        with_slug(chat),
+        "Message marked as read successfully",
+    )))
 }
 
 /// Mark all messages in a chat as read
 pub async fn mark_all_messages_as_read(
+    CurrentUser(user): CurrentUser,
     Path(character): Path<String>,
-) -> Result<Json<ApiResponse<Chat>>, (StatusCode, Json<ApiResponse<()>>)> {
-    let mut chat = match load_chat(&character).await {
-        Ok(chat) => chat,
-        Err(e) => {
-            if e.to_string().contains("No such file or directory") {
-                return Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Chat for character '{character}' not found"),
-                    }),
-                ));
-            } else {
-                tracing::error!("Failed to load chat for character '{character}': {e}");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load chat: {e}"),
-                    }),
-                ));
-            }
-        }
-    };
+) -> AppResult<WithSlug<Chat>> {
+    let _chat = lock_chat(&user, &character).await;
+    let mut chat = load_chat(&user, &character).await?;
 
     // Mark all messages as read
     for message in &mut chat.messages {
         message.read = true;
     }
 
-    match save_chat(&character, &chat).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(chat),
-            message: "All messages marked as read successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!(
-                "Failed to save chat after marking all messages as read: {}",
-                e
-            );
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to mark all messages as read: {e}"),
-                }),
-            ))
-        }
-    }
+    save_chat(&user, &character, &chat)
+        .await
+        .map_err(|e| e.context("Failed to mark all messages as read"))?;
+    events().publish(Event::MessageRead {
+        user,
+        character: chat.character.clone(),
+        index: None,
+    });
+    Ok(Json(ApiResponse::success(
+        with_slug(chat),
+        "All messages marked as read successfully",
+    )))
 }
 
 // Helper functions
 
-async fn load_all_chat_names() -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
-    let chats_dir = PathBuf::from("data/chats");
+fn with_slug(chat: Chat) -> WithSlug<Chat> {
+    WithSlug {
+        slug: character_slug(&chat.character),
+        item: chat,
+    }
+}
+
+fn check_message_index(chat: &Chat, message_index: usize) -> Result<(), AppError> {
+    if message_index >= chat.messages.len() {
+        return Err(AppError::NotFound(format!(
+            "Message at index {message_index} not found"
+        )));
+    }
+    Ok(())
+}
+
+async fn load_all_chat_names(user: &str) -> Result<Vec<String>, AppError> {
+    let chats_dir = PathBuf::from(chat_dir(user));
 
     if !chats_dir.exists() {
         fs::create_dir_all(&chats_dir).await?;
@@ -612,57 +355,105 @@ async fn load_all_chat_names() -> Result<Vec<String>, Box<dyn std::error::Error
     Ok(chat_names)
 }
 
-async fn load_chat(character: &str) -> Result<Chat, Box<dyn std::error::Error + Send + Sync>> {
-    let file_path = PathBuf::from("data/chats").join(format!("{character}.json"));
-    let contents = fs::read_to_string(file_path).await?;
+async fn load_chat(user: &str, character: &str) -> Result<Chat, AppError> {
+    let file_path = chat_file_path(user, character);
+    let contents = fs::read_to_string(file_path)
+        .await
+        .map_err(|e| AppError::from_io(e, format!("Chat for character '{character}' not found")))?;
     let chat: Chat = serde_json::from_str(&contents)?;
     Ok(chat)
 }
 
-async fn save_chat(
-    character: &str,
-    chat: &Chat,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let chats_dir = PathBuf::from("data/chats");
+async fn save_chat(user: &str, character: &str, chat: &Chat) -> Result<(), AppError> {
+    let chats_dir = PathBuf::from(chat_dir(user));
 
     if !chats_dir.exists() {
         fs::create_dir_all(&chats_dir).await?;
     }
 
-    let file_path = chats_dir.join(format!("{character}.json"));
+    let file_path = chat_file_path(user, character);
     let contents = serde_json::to_string_pretty(chat)?;
-    fs::write(file_path, contents).await?;
+    write_file_atomic(file_path, contents).await?;
     Ok(())
 }
 
-async fn delete_chat(character: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let file_path = PathBuf::from("data/chats").join(format!("{character}.json"));
-    fs::remove_file(file_path).await?;
+async fn delete_chat(user: &str, character: &str) -> Result<(), AppError> {
+    let file_path = chat_file_path(user, character);
+    fs::remove_file(file_path)
+        .await
+        .map_err(|e| AppError::from_io(e, format!("Chat for character '{character}' not found")))?;
     Ok(())
 }
 
 /// Delete associated files (audio and images) for a message
-async fn delete_message_files(
-    character: &str,
-    message: &Message,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Delete audio files
-    for audio_file in &message.audio {
-        let file_path = audio_file_path(character, audio_file);
-        if let Err(e) = fs::remove_file(&file_path).await {
-            tracing::warn!("Failed to delete audio file '{}': {}", file_path, e);
+/// Failures are logged rather than returned so that deleting the message itself still succeeds
+async fn delete_message_files(character: &str, message: &Message) {
+    let store = MediaStore::data();
+
+    // Audio is stored by ID, as `{id}.mp3`
+    for audio_id in &message.audio {
+        let filename = format!("{audio_id}.mp3");
+        if let Err(e) = store.delete(MediaKind::Audio, character, &filename).await {
+            tracing::warn!("Failed to delete audio file '{}': {}", filename, e);
             // Continue with other files instead of failing entirely
         }
     }
 
     // Delete image files
     for image_file in &message.images {
-        let file_path = image_file_path(character, image_file);
-        if let Err(e) = fs::remove_file(&file_path).await {
-            tracing::warn!("Failed to delete image file '{}': {}", file_path, e);
+        if let Err(e) = store.delete(MediaKind::Image, character, image_file).await {
+            tracing::warn!("Failed to delete image file '{}': {}", image_file, e);
             // Continue with other files instead of failing entirely
         }
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::with_test_data_dir;
+
+    fn chat(character: &str) -> Chat {
+        Chat {
+            character: character.to_string(),
+            messages: vec![],
+            muted: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rename_to_existing_slug_conflicts() {
+        with_test_data_dir(rename_to_existing_slug_conflicts()).await;
+    }
+
+    async fn rename_to_existing_slug_conflicts() {
+        let user = "default";
+        save_chat(user, "Zoë", &chat("Zoë")).await.unwrap();
+        save_chat(user, "Bob", &chat("Bob")).await.unwrap();
+
+        let rename = |character: &str| UpdateChatRequest {
+            character: Some(character.to_string()),
+            muted: None,
+        };
+        let result = update_chat(
+            CurrentUser(user.to_string()),
+            Path("bob".to_string()),
+            JsonExtract(rename("ZOE!")),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::Conflict(_))));
+        assert_eq!(load_chat(user, "bob").await.unwrap().character, "Bob");
+
+        // A new name with the same slug is updated in place
+        let Json(response) = update_chat(
+            CurrentUser(user.to_string()),
+            Path("bob".to_string()),
+            JsonExtract(rename("Bob!")),
+        )
+        .await
+        .unwrap();
+        let renamed = response.data.unwrap();
+        assert_eq!(renamed.slug, "bob");
+        assert_eq!(renamed.item.character, "Bob!");
+    }
 }
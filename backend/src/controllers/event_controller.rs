@@ -0,0 +1,40 @@
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use futures_util::stream::{self, Stream};
+use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::auth::CurrentUser;
+use crate::events::{Event, events};
+
+/// Server-sent event stream of new messages, job progress and scheduler reloads
+/// Each event's data is a JSON object whose `type` says what happened; users only see events
+/// about their own chats
+pub async fn stream_events(
+    CurrentUser(user): CurrentUser,
+) -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
+    let (receiver, closed) = events().subscribe_client();
+
+    let stream = stream::unfold(
+        (receiver, closed, user),
+        |(mut receiver, closed, user)| async move {
+            loop {
+                let event = tokio::select! {
+                    _ = closed.cancelled() => return None,
+                    event = receiver.recv() => event,
+                };
+                let event = match event {
+                    Ok(event) if event.is_for(&user) => event,
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(missed)) => Event::Lagged { missed },
+                    Err(RecvError::Closed) => return None,
+                };
+                match SseEvent::default().json_data(&event) {
+                    Ok(sse_event) => return Some((Ok(sse_event), (receiver, closed, user))),
+                    Err(e) => tracing::warn!("Failed to serialize event: {}", e),
+                }
+            }
+        },
+    );
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
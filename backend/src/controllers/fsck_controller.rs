@@ -0,0 +1,39 @@
+use axum::response::Json;
+
+use crate::error::AppResult;
+use crate::fsck::{FsckReport, run_fsck};
+use crate::job_scheduler::reload_jobs;
+use crate::models::ApiResponse;
+
+/// Check the data directory without changing anything
+pub async fn check_data() -> AppResult<FsckReport> {
+    let report = run_fsck(false)
+        .await
+        .map_err(|e| e.context("Failed to check data directory"))?;
+
+    let count = report.issues.len();
+    Ok(Json(ApiResponse::success(
+        report,
+        format!("Data check found {} issues", count),
+    )))
+}
+
+/// Check the data directory, deleting orphaned media and disabling broken jobs
+pub async fn repair_data() -> AppResult<FsckReport> {
+    let report = run_fsck(true)
+        .await
+        .map_err(|e| e.context("Failed to repair data directory"))?;
+
+    // Disabled jobs must be dropped from the running scheduler
+    if report.repaired > 0
+        && let Err(e) = reload_jobs().await
+    {
+        tracing::warn!("Failed to reload scheduler after repairing data: {e}");
+    }
+
+    let count = report.repaired;
+    Ok(Json(ApiResponse::success(
+        report,
+        format!("Data check repaired {} issues", count),
+    )))
+}
@@ -1,8 +1,15 @@
-use axum::{extract::Path, http::StatusCode, response::Json};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
 
+use crate::error::AppError;
+use crate::health::{ReadinessReport, ReadinessStatus, check_readiness};
 use crate::models::{ApiResponse, HealthResponse};
+use crate::settings::SharedSettings;
 
-/// Health check endpoint
+/// Liveness endpoint: the process is up and serving requests
 pub async fn health_check() -> Json<HealthResponse> {
     Json(HealthResponse {
         status: "OK".to_string(),
@@ -10,32 +17,83 @@ pub async fn health_check() -> Json<HealthResponse> {
     })
 }
 
+/// Readiness endpoint: checks the data directory, settings, scheduler and AI backends
+/// Responds with per-component status and latency, and 503 only when Storytime itself is down
+pub async fn readiness_check(
+    State(shared_settings): State<SharedSettings>,
+) -> (StatusCode, Json<ReadinessReport>) {
+    readiness_response(check_readiness(&shared_settings.current()).await)
+}
+
+/// A degraded backend still serves the UI and API, so only a down one is taken out of rotation
+fn readiness_response(report: ReadinessReport) -> (StatusCode, Json<ReadinessReport>) {
+    let status = match report.status {
+        ReadinessStatus::Ready | ReadinessStatus::Degraded => StatusCode::OK,
+        ReadinessStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(report))
+}
+
 /// Simple hello endpoint
 pub async fn hello() -> Json<ApiResponse<String>> {
-    Json(ApiResponse {
-        success: true,
-        data: Some("Hello, World!".to_string()),
-        message: "Hello endpoint".to_string(),
-    })
+    Json(ApiResponse::success(
+        "Hello, World!".to_string(),
+        "Hello endpoint",
+    ))
 }
 
 /// Hello with name parameter
 pub async fn hello_name(Path(name): Path<String>) -> Json<ApiResponse<String>> {
-    Json(ApiResponse {
-        success: true,
-        data: Some(format!("Hello, {name}!")),
-        message: "Hello endpoint with name".to_string(),
-    })
+    Json(ApiResponse::success(
+        format!("Hello, {name}!"),
+        "Hello endpoint with name",
+    ))
 }
 
 /// 404 not found handler
-pub async fn not_found() -> (StatusCode, Json<ApiResponse<()>>) {
-    (
-        StatusCode::NOT_FOUND,
-        Json(ApiResponse {
-            success: false,
-            data: None,
-            message: "Route not found".to_string(),
-        }),
-    )
+pub async fn not_found() -> AppError {
+    AppError::NotFound("Route not found".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::{ComponentHealth, ComponentStatus};
+    use axum::response::IntoResponse;
+
+    async fn respond(status: ReadinessStatus) -> (StatusCode, serde_json::Value) {
+        let report = ReadinessReport {
+            status,
+            components: vec![ComponentHealth {
+                name: "llm".to_string(),
+                status: ComponentStatus::Down,
+                latency_ms: 0,
+                message: Some("refused".to_string()),
+                external: true,
+            }],
+        };
+        let response = readiness_response(report).into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_readiness_status_codes() {
+        let (status, body) = respond(ReadinessStatus::Ready).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+
+        let (status, body) = respond(ReadinessStatus::Degraded).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["components"][0]["name"], "llm");
+        assert_eq!(body["components"][0]["status"], "down");
+
+        let (status, body) = respond(ReadinessStatus::Down).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "down");
+    }
 }
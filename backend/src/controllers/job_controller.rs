@@ -1,331 +1,423 @@
 use axum::{
+    body::Bytes,
     extract::{Json as JsonExtract, Path, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::Json,
 };
 use base64::Engine;
 use chrono::Utc;
+use serde_json::Value;
 use std::sync::Arc;
 use tokio::fs;
 use uuid::Uuid;
 
+use crate::auth::{CurrentUser, DEFAULT_USER, hash_secret, load_user, user_names};
 use crate::chatml::ChatMLPrompt;
-use crate::llm_prompt::build_setup_item_chatml_prompt;
+use crate::config::data_dir;
+use crate::controllers::persona_controller::recipient;
+use crate::error::{AppError, AppResult};
+use crate::events::{Event, events};
+use crate::llm_prompt::{build_setup_item_chatml_prompt, expand_payload_variables};
+use crate::media_store::{MediaKind, MediaStore};
+use crate::metrics::metrics;
 use crate::models::{
-    ApiResponse, Character, Chat, CreateJobRequest, Job, Message, Prompt, RunJobRequest, Settings,
-    TestCharacterRequest, TestPromptRequest, UpdateJobRequest, Voice,
+    ApiResponse, Character, Chat, CreateJobRequest, Job, JobTrigger, Message, Prompt,
+    RunJobRequest, Settings, TestCharacterRequest, TestPromptRequest, UpdateJobRequest, Voice,
 };
+use crate::settings::SharedSettings;
 use crate::utils::{
-    character_file_path, chat_file_path_from_character, generate_job_id, job_file_path_from_id,
-    job_file_path_from_slug, job_slug, prompt_file_path,
+    character_file_path, chat_dir, chat_file_path, generate_job_id, job_file_path_from_id,
+    job_file_path_from_slug, job_slug, lock_chat, prompt_file_path, write_file_atomic,
 };
 use crate::{
     ai_services::{call_llm, call_llm_chatml, call_tts},
-    job_scheduler::reload_jobs,
-    utils::to_slug,
+    job_scheduler::{reload_jobs, run_job_now},
 };
 
+/// Header a trigger's token is sent in, so it stays out of URLs and the logs they end up in
+pub const TRIGGER_TOKEN_HEADER: &str = "X-Trigger-Token";
+
+/// Prompt context for answering a message the user sent a character
+const REPLY_CONTEXT: &str =
+    "{{user}} has just texted you. Answer them in character with a short text message.";
+
+/// What the character is asked for when answering; the message itself is the last one in the
+/// chat history the prompt includes
+const REPLY_REQUEST: &str = "Answer {{user}}'s last message.";
+
 /// Get all jobs
-pub async fn get_jobs() -> Result<Json<ApiResponse<Vec<Job>>>, (StatusCode, Json<ApiResponse<()>>)>
-{
-    match load_all_jobs().await {
-        Ok(jobs) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(jobs),
-            message: "Jobs retrieved successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to load jobs: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to load jobs: {e}"),
-                }),
-            ))
-        }
-    }
+pub async fn get_jobs() -> AppResult<Vec<Job>> {
+    let jobs = load_all_jobs()
+        .await
+        .map_err(|e| e.context("Failed to load jobs"))?;
+    Ok(Json(ApiResponse::success(
+        jobs,
+        "Jobs retrieved successfully",
+    )))
 }
 
 /// Get a specific job by slug
-pub async fn get_job(
-    Path(slug): Path<String>,
-) -> Result<Json<ApiResponse<Job>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match load_job_by_slug(&slug).await {
-        Ok(job) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(job),
-            message: "Job retrieved successfully".to_string(),
-        })),
-        Err(e) => {
-            if e.to_string().contains("No such file or directory") {
-                Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Job with slug '{slug}' not found"),
-                    }),
-                ))
-            } else {
-                tracing::error!("Failed to load job with slug '{slug}': {e}");
-                Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load job: {e}"),
-                    }),
-                ))
-            }
-        }
-    }
+pub async fn get_job(Path(slug): Path<String>) -> AppResult<Job> {
+    let job = load_job_by_slug(&slug).await?;
+    Ok(Json(ApiResponse::success(
+        job,
+        "Job retrieved successfully",
+    )))
 }
 
 /// Create a new job
-pub async fn create_job(
-    JsonExtract(request): JsonExtract<CreateJobRequest>,
-) -> Result<Json<ApiResponse<Job>>, (StatusCode, Json<ApiResponse<()>>)> {
+pub async fn create_job(JsonExtract(request): JsonExtract<CreateJobRequest>) -> AppResult<Job> {
     let mut job = Job {
         id: None, // Will be generated by save_job
         characters: request.characters,
         prompts: request.prompts,
         cadence: request.cadence,
         prompt_override: request.prompt_override,
+        enabled: request.enabled,
+        subscribers: request.subscribers,
+        trigger_hash: None,
     };
 
-    match save_job(&mut job).await {
-        Ok(id) => {
-            // Reload scheduler to pick up the new job
-            if let Err(e) = reload_jobs().await {
-                tracing::warn!("Failed to reload scheduler after creating job: {e}");
-            } else {
-                tracing::info!("Scheduler reloaded after creating job with ID '{id}'");
-            }
+    check_subscribers(&job).await?;
+    let id = save_job(&mut job)
+        .await
+        .map_err(|e| e.context("Failed to create job"))?;
 
-            Ok(Json(ApiResponse {
-                success: true,
-                data: Some(job),
-                message: format!("Job created successfully with ID '{id}'"),
-            }))
-        }
-        Err(e) => {
-            tracing::error!("Failed to create job: {e}");
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to create job: {e}"),
-                }),
-            ))
-        }
+    // Reload scheduler to pick up the new job
+    if let Err(e) = reload_jobs().await {
+        tracing::warn!("Failed to reload scheduler after creating job: {e}");
+    } else {
+        tracing::info!("Scheduler reloaded after creating job with ID '{id}'");
     }
+
+    Ok(Json(ApiResponse::success(
+        job,
+        format!("Job created successfully with ID '{id}'"),
+    )))
 }
 
 /// Update an existing job
 pub async fn update_job(
     Path(slug): Path<String>,
     JsonExtract(request): JsonExtract<UpdateJobRequest>,
-) -> Result<Json<ApiResponse<Job>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match load_job_by_slug(&slug).await {
-        Ok(existing_job) => {
-            // PUT replaces the entire resource but keeps the existing ID if present, or uses the one from request
-            let mut job = Job {
-                id: request.id.or(existing_job.id),
-                characters: request.characters,
-                prompts: request.prompts,
-                cadence: request.cadence,
-                prompt_override: request.prompt_override,
-            };
-
-            match save_job(&mut job).await {
-                Ok(id) => {
-                    // Reload scheduler to pick up the updated job
-                    if let Err(e) = reload_jobs().await {
-                        tracing::warn!("Failed to reload scheduler after updating job: {e}");
-                    } else {
-                        tracing::info!("Scheduler reloaded after updating job with ID '{id}'");
-                    }
-
-                    Ok(Json(ApiResponse {
-                        success: true,
-                        data: Some(job),
-                        message: format!("Job updated successfully with ID '{id}'"),
-                    }))
-                }
-                Err(e) => {
-                    tracing::error!("Failed to update job with slug '{slug}': {e}");
-                    Err((
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(ApiResponse {
-                            success: false,
-                            data: None,
-                            message: format!("Failed to update job: {e}"),
-                        }),
-                    ))
-                }
-            }
-        }
-        Err(e) => {
-            if e.to_string().contains("No such file or directory") {
-                Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Job with slug '{slug}' not found"),
-                    }),
-                ))
-            } else {
-                tracing::error!("Failed to load job with slug '{slug}': {e}");
-                Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to update job: {e}"),
-                    }),
-                ))
-            }
-        }
+) -> AppResult<Job> {
+    let existing_job = load_job_by_slug(&slug).await?;
+
+    // PUT replaces the entire resource but keeps the existing ID if present, or uses the one from request
+    let mut job = Job {
+        id: request.id.or(existing_job.id),
+        characters: request.characters,
+        prompts: request.prompts,
+        cadence: request.cadence,
+        prompt_override: request.prompt_override,
+        enabled: request.enabled.unwrap_or(existing_job.enabled),
+        subscribers: request.subscribers,
+        trigger_hash: existing_job.trigger_hash,
+    };
+
+    check_subscribers(&job).await?;
+    let id = save_job(&mut job)
+        .await
+        .map_err(|e| e.context("Failed to update job"))?;
+
+    // Reload scheduler to pick up the updated job
+    if let Err(e) = reload_jobs().await {
+        tracing::warn!("Failed to reload scheduler after updating job: {e}");
+    } else {
+        tracing::info!("Scheduler reloaded after updating job with ID '{id}'");
     }
+
+    Ok(Json(ApiResponse::success(
+        job,
+        format!("Job updated successfully with ID '{id}'"),
+    )))
 }
 
 /// Delete a job by slug
-pub async fn delete_job(
-    Path(slug): Path<String>,
-) -> Result<Json<ApiResponse<()>>, (StatusCode, Json<ApiResponse<()>>)> {
+pub async fn delete_job(Path(slug): Path<String>) -> AppResult<()> {
     // First, find the job to get its character and prompt for deletion
-    let job = match load_job_by_slug(&slug).await {
-        Ok(job) => job,
-        Err(e) => {
-            if e.to_string().contains("No such file or directory") {
-                return Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Job with slug '{slug}' not found"),
-                    }),
-                ));
-            } else {
-                tracing::error!("Failed to find job with slug '{slug}': {e}");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to find job: {e}"),
-                    }),
-                ));
-            }
-        }
-    };
+    let job = load_job_by_slug(&slug).await?;
 
-    match delete_job_by_slug(&slug).await {
-        Ok(_) => {
-            let job_name = job_slug(
-                job.characters.first().unwrap_or(&"default".to_string()),
-                job.prompts.first().unwrap_or(&"default".to_string()),
-            );
+    delete_job_by_slug(&slug)
+        .await
+        .map_err(|e| e.context("Failed to delete job"))?;
 
-            // Reload scheduler to remove the deleted job
-            if let Err(e) = reload_jobs().await {
-                tracing::warn!("Failed to reload scheduler after deleting job: {e}");
-            } else {
-                tracing::info!("Scheduler reloaded after deleting job '{}'", job_name);
-            }
+    let job_name = job_slug(
+        job.characters.first().unwrap_or(&"default".to_string()),
+        job.prompts.first().unwrap_or(&"default".to_string()),
+    );
 
-            Ok(Json(ApiResponse {
-                success: true,
-                data: None,
-                message: format!("Job '{}' deleted successfully", job_name),
-            }))
-        }
-        Err(e) => {
-            tracing::error!("Failed to delete job with slug '{slug}': {e}");
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to delete job: {e}"),
-                }),
-            ))
-        }
+    // Reload scheduler to remove the deleted job
+    if let Err(e) = reload_jobs().await {
+        tracing::warn!("Failed to reload scheduler after deleting job: {e}");
+    } else {
+        tracing::info!("Scheduler reloaded after deleting job '{}'", job_name);
     }
+
+    Ok(Json(ApiResponse::success(
+        (),
+        format!("Job '{}' deleted successfully", job_name),
+    )))
 }
 
-/// Run a job by its slug
+/// Run a job by its slug for the current user, whether or not they subscribe to it
 pub async fn run_job_by_slug(
-    State(settings): State<Arc<Settings>>,
+    State(shared_settings): State<SharedSettings>,
+    CurrentUser(user): CurrentUser,
     Path(slug): Path<String>,
-) -> Result<Json<ApiResponse<Message>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match load_job_by_slug(&slug).await {
-        Ok(job) => run_job_internal(job, settings, true).await,
-        Err(e) => {
-            if e.to_string().contains("No such file or directory") {
-                Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Job with slug '{slug}' not found"),
-                    }),
-                ))
-            } else {
-                tracing::error!("Failed to load job by slug {}: {}", slug, e);
-                Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load job: {e}"),
-                    }),
-                ))
-            }
-        }
-    }
+) -> AppResult<Message> {
+    let job = load_job_by_slug(&slug).await?;
+    run_job_internal(job, shared_settings.current(), true, &user).await
 }
 
-/// Run a job via a run job request
+/// Run a job via a run job request for the current user
 pub async fn run_job(
-    State(settings): State<Arc<Settings>>,
+    State(shared_settings): State<SharedSettings>,
+    CurrentUser(user): CurrentUser,
     JsonExtract(request): JsonExtract<RunJobRequest>,
-) -> Result<Json<ApiResponse<Message>>, (StatusCode, Json<ApiResponse<()>>)> {
-    run_job_internal(request.job, settings, request.save_to_chat_history).await
+) -> AppResult<Message> {
+    run_job_internal(
+        request.job,
+        shared_settings.current(),
+        request.save_to_chat_history,
+        &user,
+    )
+    .await
+}
+
+/// Give a job a secret trigger URL, replacing the one it had
+/// Only a hash of the token is stored, so a lost URL has to be replaced rather than looked up
+pub async fn create_job_trigger(
+    State(shared_settings): State<SharedSettings>,
+    Path(slug): Path<String>,
+) -> AppResult<JobTrigger> {
+    let mut job = load_job_by_slug(&slug).await?;
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    job.trigger_hash = Some(hash_secret(&token));
+    save_job(&mut job)
+        .await
+        .map_err(|e| e.context("Failed to save job trigger"))?;
+
+    let base = shared_settings
+        .current()
+        .public_url
+        .as_deref()
+        .unwrap_or_default()
+        .trim_end_matches('/')
+        .to_string();
+    Ok(Json(ApiResponse::success(
+        JobTrigger {
+            url: format!("{base}/api/hooks"),
+            token,
+            header: TRIGGER_TOKEN_HEADER.to_string(),
+        },
+        "Job trigger created successfully",
+    )))
+}
+
+/// Remove a job's trigger URL, so it no longer runs the job
+pub async fn delete_job_trigger(Path(slug): Path<String>) -> AppResult<()> {
+    let mut job = load_job_by_slug(&slug).await?;
+    if job.trigger_hash.take().is_none() {
+        return Err(AppError::NotFound(format!(
+            "Job with slug '{slug}' has no trigger"
+        )));
+    }
+    save_job(&mut job)
+        .await
+        .map_err(|e| e.context("Failed to delete job trigger"))?;
+    Ok(Json(ApiResponse::success(
+        (),
+        "Job trigger deleted successfully",
+    )))
+}
+
+/// Run the job whose trigger token is in the `X-Trigger-Token` header
+pub async fn trigger_job(
+    State(shared_settings): State<SharedSettings>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<ApiResponse<Vec<String>>>), AppError> {
+    let token = headers
+        .get(TRIGGER_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| {
+            AppError::Unauthorized(format!("Send the trigger token in {TRIGGER_TOKEN_HEADER}"))
+        })?;
+    run_trigger(&shared_settings, token, &body).await
+}
+
+/// Run the job whose trigger token ends the URL, for tools that cannot send headers
+pub async fn trigger_job_by_path(
+    State(shared_settings): State<SharedSettings>,
+    Path(token): Path<String>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<ApiResponse<Vec<String>>>), AppError> {
+    run_trigger(&shared_settings, &token, &body).await
+}
+
+/// Run the job a trigger token belongs to for each of its recipients, in the background
+/// A JSON body is available to the prompt as `{{payload.field}}`. Disabled jobs can still be
+/// triggered, since disabling a job only takes it off the schedule
+async fn run_trigger(
+    shared_settings: &SharedSettings,
+    token: &str,
+    body: &[u8],
+) -> Result<(StatusCode, Json<ApiResponse<Vec<String>>>), AppError> {
+    let hash = hash_secret(token);
+    let job = load_all_jobs()
+        .await
+        .map_err(|e| e.context("Failed to load jobs"))?
+        .into_iter()
+        .find(|job| job.trigger_hash.as_deref() == Some(hash.as_str()))
+        .ok_or_else(|| AppError::NotFound("Unknown job trigger".to_string()))?;
+
+    let payload = if body.iter().all(u8::is_ascii_whitespace) {
+        None
+    } else {
+        Some(
+            serde_json::from_slice::<Value>(body)
+                .map_err(|e| AppError::Validation(format!("Trigger body must be JSON: {e}")))?,
+        )
+    };
+
+    let recipients = job_recipients(&job).await?;
+    run_job_now(job, shared_settings.current(), payload)
+        .await
+        .map_err(|e| e.context("Failed to start triggered job"))?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(
+            recipients,
+            "Job triggered successfully",
+        )),
+    ))
+}
+
+/// Save a message `user` sent a character, then have the character answer it
+/// The answer is saved to the chat like a job's message and returned
+pub async fn reply_to_user(
+    character_name: &str,
+    text: &str,
+    settings: Arc<Settings>,
+    user: &str,
+    create_audio: bool,
+) -> AppResult<Message> {
+    let character = load_character(character_name).await?;
+
+    let message = Message {
+        text: vec![text.to_string()],
+        audio: vec![],
+        images: vec![],
+        read: true,
+        timestamp: Utc::now(),
+        from_user: true,
+    };
+    let index = save_message_to_chat(user, &character.name, &message)
+        .await
+        .map_err(|e| e.context("Failed to save reply"))?;
+    events().publish(Event::MessageCreated {
+        user: user.to_string(),
+        character: character.name.clone(),
+        index,
+        message,
+        job: None,
+    });
+
+    let prompt = Prompt {
+        title: "Reply".to_string(),
+        description: "Answer a message from the user".to_string(),
+        context: REPLY_CONTEXT.to_string(),
+        setup: vec![REPLY_REQUEST.to_string()],
+        create_audio,
+        create_images: false,
+    };
+    let job = Job {
+        id: None,
+        characters: vec![character.name.clone()],
+        prompts: vec![prompt.title.clone()],
+        cadence: "once".to_string(),
+        prompt_override: None,
+        enabled: true,
+        subscribers: vec![],
+        trigger_hash: None,
+    };
+    let result =
+        run_job_with_character_and_prompt(job, character, prompt, settings, true, user).await;
+    metrics().record_job(&result);
+    result
 }
 
 /// Internal job execution implementation
+/// The message is written for `user` and saved to their chat
 pub async fn run_job_internal(
     job: Job,
     settings: Arc<Settings>,
     save_to_chat_history: bool,
-) -> Result<Json<ApiResponse<Message>>, (StatusCode, Json<ApiResponse<()>>)> {
+    user: &str,
+) -> AppResult<Message> {
+    run_job_with_payload(job, settings, save_to_chat_history, user, None).await
+}
+
+/// Run a job whose prompt may use the fields of `payload`, the body it was triggered with
+pub async fn run_job_with_payload(
+    job: Job,
+    settings: Arc<Settings>,
+    save_to_chat_history: bool,
+    user: &str,
+    payload: Option<&Value>,
+) -> AppResult<Message> {
+    let result = run_random_job(job, settings, save_to_chat_history, user, payload).await;
+    metrics().record_job(&result);
+    result
+}
+
+/// The users a scheduled run of a job writes to: its subscribers, or every user if it has none
+pub async fn job_recipients(job: &Job) -> Result<Vec<String>, AppError> {
+    if job.subscribers.is_empty() {
+        user_names().await
+    } else {
+        Ok(job.subscribers.clone())
+    }
+}
+
+/// Unsubscribe a deleted user from every job
+pub(crate) async fn remove_subscriber(user: &str) -> Result<(), AppError> {
+    let mut changed = false;
+    for mut job in load_all_jobs().await? {
+        if job.subscribers.iter().any(|name| name == user) {
+            job.subscribers.retain(|name| name != user);
+            save_job(&mut job).await?;
+            changed = true;
+        }
+    }
+    if changed && let Err(e) = reload_jobs().await {
+        tracing::warn!("Failed to reload scheduler after unsubscribing '{user}': {e}");
+    }
+    Ok(())
+}
+
+/// Run a job with a randomly picked character and prompt
+async fn run_random_job(
+    mut job: Job,
+    settings: Arc<Settings>,
+    save_to_chat_history: bool,
+    user: &str,
+    payload: Option<&Value>,
+) -> AppResult<Message> {
     use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
 
     // Validate that we have at least one character and prompt
     if job.characters.is_empty() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse {
-                success: false,
-                data: None,
-                message: "Job must have at least one character".to_string(),
-            }),
+        return Err(AppError::Validation(
+            "Job must have at least one character".to_string(),
         ));
     }
 
     if job.prompts.is_empty() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse {
-                success: false,
-                data: None,
-                message: "Job must have at least one prompt".to_string(),
-            }),
+        return Err(AppError::Validation(
+            "Job must have at least one prompt".to_string(),
         ));
     }
 
@@ -340,54 +432,29 @@ pub async fn run_job_internal(
         selected_prompt_name
     );
 
-    // Load the selected character
-    let character = match load_character(selected_character_name).await {
-        Ok(character) => character,
-        Err(e) => {
-            tracing::error!(
-                "Failed to load character '{}': {}",
-                selected_character_name,
-                e
-            );
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!(
-                        "Failed to load character '{}': {}",
-                        selected_character_name, e
-                    ),
-                }),
-            ));
-        }
-    };
+    let character = load_character(selected_character_name).await?;
+    let mut prompt = load_prompt(selected_prompt_name).await?;
 
-    // Load the selected prompt
-    let prompt = match load_prompt(selected_prompt_name).await {
-        Ok(prompt) => prompt,
-        Err(e) => {
-            tracing::error!("Failed to load prompt '{}': {}", selected_prompt_name, e);
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to load prompt '{}': {}", selected_prompt_name, e),
-                }),
-            ));
+    if let Some(payload) = payload {
+        prompt.context = expand_payload_variables(&prompt.context, payload);
+        for item in &mut prompt.setup {
+            *item = expand_payload_variables(item, payload);
         }
-    };
+        job.prompt_override = job
+            .prompt_override
+            .map(|text| expand_payload_variables(&text, payload));
+    }
 
-    run_job_with_character_and_prompt(job, character, prompt, settings, save_to_chat_history).await
+    run_job_with_character_and_prompt(job, character, prompt, settings, save_to_chat_history, user)
+        .await
 }
 
 // File I/O utility functions
 
 /// Load all jobs from the data directory
-pub async fn load_all_jobs() -> Result<Vec<Job>, Box<dyn std::error::Error + Send + Sync>> {
+pub async fn load_all_jobs() -> Result<Vec<Job>, AppError> {
     let mut jobs = Vec::new();
-    let dir_path = "./data/jobs";
+    let dir_path = data_dir().join("jobs");
 
     let mut entries = fs::read_dir(dir_path).await?;
     while let Some(entry) = entries.next_entry().await? {
@@ -413,58 +480,19 @@ pub async fn load_all_jobs() -> Result<Vec<Job>, Box<dyn std::error::Error + Sen
     Ok(jobs)
 }
 
-/// Migrate all existing jobs to have UUIDs and rename their files
-pub async fn migrate_jobs_to_uuids() -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>>
-{
-    let mut migrated_jobs = Vec::new();
-    let dir_path = "./data/jobs";
-
-    let mut entries = fs::read_dir(dir_path).await?;
-    while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
-        if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("json") {
-            let content = fs::read_to_string(&path).await?;
-            let mut job: Job = serde_json::from_str(&content)?;
-
-            // Only migrate jobs that don't already have UUIDs
-            if job.id.is_none() {
-                let old_file_path = path.clone();
-
-                // Generate a new UUID for the job
-                job.id = Some(generate_job_id());
-                let new_file_path = job_file_path_from_id(&job.id.unwrap());
-
-                // Save the job with the new UUID-based filename
-                let json_content = serde_json::to_string_pretty(&job)?;
-                fs::write(&new_file_path, json_content).await?;
-
-                // Remove the old file
-                fs::remove_file(&old_file_path).await?;
-
-                migrated_jobs.push(job.id.unwrap().to_string());
-                tracing::info!(
-                    "Migrated job {} to UUID {}",
-                    old_file_path.display(),
-                    job.id.unwrap()
-                );
-            }
-        }
-    }
-
-    Ok(migrated_jobs)
-}
-
 /// Load a specific job by slug
-async fn load_job_by_slug(slug: &str) -> Result<Job, Box<dyn std::error::Error + Send + Sync>> {
+pub async fn load_job_by_slug(slug: &str) -> Result<Job, AppError> {
     let file_path = job_file_path_from_slug(slug);
-    let content = fs::read_to_string(&file_path).await?;
+    let content = fs::read_to_string(&file_path)
+        .await
+        .map_err(|e| AppError::from_io(e, format!("Job with slug '{slug}' not found")))?;
     let job: Job = serde_json::from_str(&content)?;
     Ok(job)
 }
 
 /// Save a job to a JSON file
 /// Returns the job ID (UUID) as a string
-async fn save_job(job: &mut Job) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+async fn save_job(job: &mut Job) -> Result<String, AppError> {
     // Generate a new UUID if the job doesn't have one
     let job_id = match job.id {
         Some(id) => id,
@@ -481,61 +509,154 @@ async fn save_job(job: &mut Job) -> Result<String, Box<dyn std::error::Error + S
     Ok(job_id.to_string())
 }
 
+/// Check that every subscriber of a job is a user
+async fn check_subscribers(job: &Job) -> Result<(), AppError> {
+    for name in &job.subscribers {
+        if name != DEFAULT_USER && load_user(name).await.is_err() {
+            return Err(AppError::Validation(format!(
+                "Subscriber '{name}' is not a user"
+            )));
+        }
+    }
+    Ok(())
+}
+
 /// Delete a job file by slug
-async fn delete_job_by_slug(slug: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn delete_job_by_slug(slug: &str) -> Result<(), AppError> {
     let file_path = job_file_path_from_slug(slug);
-    fs::remove_file(&file_path).await?;
+    fs::remove_file(&file_path)
+        .await
+        .map_err(|e| AppError::from_io(e, format!("Job with slug '{slug}' not found")))?;
     Ok(())
 }
 
 /// Load a character by name
-async fn load_character(name: &str) -> Result<Character, Box<dyn std::error::Error + Send + Sync>> {
+async fn load_character(name: &str) -> Result<Character, AppError> {
     let file_path = character_file_path(name);
-    let content = fs::read_to_string(&file_path).await?;
+    let content = fs::read_to_string(&file_path)
+        .await
+        .map_err(|e| AppError::from_io(e, format!("Character '{name}' not found")))?;
     let character: Character = serde_json::from_str(&content)?;
     Ok(character)
 }
 
 /// Load a prompt by title
-async fn load_prompt(title: &str) -> Result<Prompt, Box<dyn std::error::Error + Send + Sync>> {
+async fn load_prompt(title: &str) -> Result<Prompt, AppError> {
     let file_path = prompt_file_path(title);
-    let content = fs::read_to_string(&file_path).await?;
+    let content = fs::read_to_string(&file_path)
+        .await
+        .map_err(|e| AppError::from_io(e, format!("Prompt '{title}' not found")))?;
     let prompt: Prompt = serde_json::from_str(&content)?;
     Ok(prompt)
 }
 
-/// Load a chat by character name
-async fn load_chat(character: &str) -> Result<Chat, Box<dyn std::error::Error + Send + Sync>> {
-    let file_path = chat_file_path_from_character(character);
-    let content = fs::read_to_string(&file_path).await?;
+/// Load a user's chat by character name
+async fn load_chat(user: &str, character: &str) -> Result<Chat, AppError> {
+    let file_path = chat_file_path(user, character);
+    let content = fs::read_to_string(&file_path)
+        .await
+        .map_err(|e| AppError::from_io(e, format!("Chat for character '{character}' not found")))?;
     let chat: Chat = serde_json::from_str(&content)?;
     Ok(chat)
 }
 
-/// Save a message to chat history
+/// Load a user's chat, or an empty one if they have none with the character yet
+/// Any other error is returned, so that an unreadable chat is not replaced by an empty one
+async fn load_chat_or_new(user: &str, character: &str) -> Result<Chat, AppError> {
+    match load_chat(user, character).await {
+        Err(AppError::NotFound(_)) => Ok(Chat {
+            character: character.to_string(),
+            messages: Vec::new(),
+            muted: false,
+        }),
+        result => result,
+    }
+}
+
+/// Save a message to a user's chat history, returning its index in the chat
 async fn save_message_to_chat(
+    user: &str,
     character: &str,
     message: &Message,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let file_path = chat_file_path_from_character(character);
+) -> Result<usize, AppError> {
+    let file_path = chat_file_path(user, character);
+    fs::create_dir_all(chat_dir(user)).await?;
 
-    // Load existing chat or create new one
-    let mut chat = match load_chat(character).await {
-        Ok(chat) => chat,
-        Err(_) => Chat {
-            character: character.to_string(),
-            messages: Vec::new(),
-        },
-    };
+    let _chat = lock_chat(user, character).await;
+    let mut chat = load_chat_or_new(user, character).await?;
 
     // Add the new message
     chat.messages.push(message.clone());
 
     // Save back to file
     let json_content = serde_json::to_string_pretty(&chat)?;
-    fs::write(&file_path, json_content).await?;
+    write_file_atomic(&file_path, json_content).await?;
 
-    Ok(())
+    Ok(chat.messages.len() - 1)
+}
+
+/// What running a job would first send to the LLM
+#[derive(Debug, Clone)]
+pub struct JobPreview {
+    pub character: String,
+    pub prompt: String,
+    /// The first LLM request, exactly as it would be sent
+    pub llm_prompt: String,
+    /// Further LLM requests that depend on earlier responses and so cannot be previewed
+    pub remaining_requests: usize,
+}
+
+/// Build the first LLM request for a job without calling any AI service
+/// Every character and prompt the job references must load; the preview uses the first of each
+/// rather than a random pick so repeated previews are comparable
+pub async fn preview_job(job: &Job, user: &str) -> Result<JobPreview, AppError> {
+    let mut characters = Vec::new();
+    for name in &job.characters {
+        characters.push(load_character(name).await?);
+    }
+    let mut prompts = Vec::new();
+    for title in &job.prompts {
+        prompts.push(load_prompt(title).await?);
+    }
+
+    let character = characters
+        .into_iter()
+        .next()
+        .ok_or_else(|| AppError::Validation("Job must have at least one character".to_string()))?;
+    let prompt = prompts
+        .into_iter()
+        .next()
+        .ok_or_else(|| AppError::Validation("Job must have at least one prompt".to_string()))?;
+
+    let recipient = recipient(user).await;
+    let (llm_prompt, remaining_requests) = match &job.prompt_override {
+        Some(override_prompt) => (override_prompt.replace("{{user}}", &recipient.name), 0),
+        None => {
+            let chat_history = load_chat_or_new(user, &character.name).await?;
+            let setup_item = prompt.setup.first().ok_or_else(|| {
+                AppError::Validation(format!("Prompt '{}' has no setup items", prompt.title))
+            })?;
+            let chatml_prompt = build_setup_item_chatml_prompt(
+                &character,
+                &prompt,
+                setup_item,
+                &[],
+                &chat_history,
+                &recipient,
+            );
+            (
+                chatml_prompt.to_chatml_for_generation(),
+                prompt.setup.len() - 1,
+            )
+        }
+    };
+
+    Ok(JobPreview {
+        character: character.name,
+        prompt: prompt.title,
+        llm_prompt,
+        remaining_requests,
+    })
 }
 
 /// Internal job execution implementation using provided prompt
@@ -544,36 +665,18 @@ async fn run_job_internal_with_prompt(
     prompt: Prompt,
     settings: Arc<Settings>,
     save_to_chat_history: bool,
-) -> Result<Json<ApiResponse<Message>>, (StatusCode, Json<ApiResponse<()>>)> {
+    user: &str,
+) -> AppResult<Message> {
     // Get the first character name (for testing, we expect only one)
-    let character_name = job.characters.first().ok_or_else(|| {
-        (
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse {
-                success: false,
-                data: None,
-                message: "Job must have at least one character".to_string(),
-            }),
-        )
-    })?;
-
-    // Load the character
-    let character = match load_character(character_name).await {
-        Ok(character) => character,
-        Err(e) => {
-            tracing::error!("Failed to load character '{}': {}", character_name, e);
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to load character '{}': {}", character_name, e),
-                }),
-            ));
-        }
-    };
+    let character_name = job
+        .characters
+        .first()
+        .ok_or_else(|| AppError::Validation("Job must have at least one character".to_string()))?;
+
+    let character = load_character(character_name).await?;
 
-    run_job_with_character_and_prompt(job, character, prompt, settings, save_to_chat_history).await
+    run_job_with_character_and_prompt(job, character, prompt, settings, save_to_chat_history, user)
+        .await
 }
 
 /// Internal job execution implementation using provided character
@@ -583,8 +686,10 @@ async fn run_job_internal_with_character(
     prompt: Prompt,
     settings: Arc<Settings>,
     save_to_chat_history: bool,
-) -> Result<Json<ApiResponse<Message>>, (StatusCode, Json<ApiResponse<()>>)> {
-    run_job_with_character_and_prompt(job, character, prompt, settings, save_to_chat_history).await
+    user: &str,
+) -> AppResult<Message> {
+    run_job_with_character_and_prompt(job, character, prompt, settings, save_to_chat_history, user)
+        .await
 }
 
 /// Unified AI workflow execution - the ONLY function that should call AI services directly
@@ -594,23 +699,42 @@ async fn execute_ai_workflow(
     prompt: &Prompt,
     settings: &Arc<Settings>,
     chat_history: &Chat,
-) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    user: &str,
+) -> Result<Vec<String>, AppError> {
+    let recipient = recipient(user).await;
+    let progress = |step| {
+        events().publish(Event::JobProgress {
+            job: job.id,
+            user: user.to_string(),
+            character: character.name.clone(),
+            step,
+            steps: job_steps(job, prompt),
+        })
+    };
+
     // Generate LLM responses based on prompt configuration
     if let Some(override_prompt) = &job.prompt_override {
         // Use simple prompt override approach
-        call_llm(settings, override_prompt).await
+        progress(1);
+        call_llm(
+            settings,
+            &override_prompt.replace("{{user}}", &recipient.name),
+        )
+        .await
     } else {
         // Use iterative ChatML approach for complex prompts
         let mut accumulated_chatml_responses: Vec<ChatMLPrompt> = Vec::new();
         let mut final_responses = Vec::new();
 
         for (i, setup_item) in prompt.setup.iter().enumerate() {
+            progress(i + 1);
             let chatml_prompt = build_setup_item_chatml_prompt(
                 character,
                 prompt,
                 setup_item,
                 &accumulated_chatml_responses,
                 chat_history,
+                &recipient,
             );
 
             let response_prompt = call_llm_chatml(settings, &chatml_prompt).await?;
@@ -650,72 +774,53 @@ async fn execute_ai_services(
     prompt: &Prompt,
     settings: Arc<Settings>,
     save_to_chat_history: bool,
-) -> Result<Message, (StatusCode, Json<ApiResponse<()>>)> {
-    // Try to load existing chat history (optional)
-    let chat_history = Chat {
-        character: character.name.clone(),
-        messages: Vec::new(),
-    };
+    user: &str,
+) -> Result<Message, AppError> {
+    let chat_history = load_chat_or_new(user, &character.name)
+        .await
+        .map_err(|e| e.context("Failed to load chat history"))?;
 
     // Execute the unified AI workflow
-    let llm_responses =
-        match execute_ai_workflow(job, character, prompt, &settings, &chat_history).await {
-            Ok(responses) => responses,
-            Err(e) => {
-                tracing::error!("AI workflow execution failed: {}", e);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("AI workflow execution failed: {e}"),
-                    }),
-                ));
-            }
-        };
+    let llm_responses = execute_ai_workflow(job, character, prompt, &settings, &chat_history, user)
+        .await
+        .map_err(|e| e.context("AI workflow execution failed"))?;
 
     // Generate TTS audio if requested
     let mut audio: Vec<String> = vec![];
     if prompt.create_audio {
+        let steps = job_steps(job, prompt);
+        events().publish(Event::JobProgress {
+            job: job.id,
+            user: user.to_string(),
+            character: character.name.clone(),
+            step: steps,
+            steps,
+        });
         let default_voice = Voice::default();
         let voice = character.voice.as_ref().unwrap_or(&default_voice);
-        match call_tts(&settings, &llm_responses.join("\n"), voice).await {
-            Ok(tts_audio) => {
-                if save_to_chat_history {
-                    // Save the audio to a file in data/audio/{character}/{id}.mp3
-                    let id = Uuid::new_v4();
-                    let character_slug = to_slug(&character.name);
-                    let chat_audio_path = format!("./data/audio/{character_slug}/{id}.mp3");
-
-                    // Ensure the directory exists
-                    let audio_dir = format!("./data/audio/{character_slug}");
-                    if let Err(e) = fs::create_dir_all(&audio_dir).await {
-                        tracing::warn!("Failed to create audio directory '{}': {}", audio_dir, e);
-                    }
-
-                    if let Err(e) = fs::write(&chat_audio_path, &tts_audio).await {
-                        tracing::warn!("Failed to save TTS audio to '{}': {}", chat_audio_path, e);
-                    } else {
-                        tracing::info!("TTS audio saved to '{}'", chat_audio_path);
-                    }
-                    audio.push(id.to_string());
-                } else {
-                    // Return base64 encoded audio for test endpoints
-                    let base64_audio = base64::engine::general_purpose::STANDARD.encode(&tts_audio);
-                    audio.push(base64_audio);
-                }
-            }
-            Err(e) => {
-                tracing::error!("TTS call failed: {}", e);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("TTS call failed: {e}"),
-                    }),
-                ));
+        let tts_audio = call_tts(&settings, &llm_responses.join("\n"), voice)
+            .await
+            .map_err(|e| e.context("TTS call failed"))?;
+        if save_to_chat_history {
+            // Save the audio to a file in data/audio/{character}/{id}.mp3
+            let id = Uuid::new_v4();
+            match MediaStore::data()
+                .write(
+                    MediaKind::Audio,
+                    &character.name,
+                    &format!("{id}.mp3"),
+                    &tts_audio,
+                )
+                .await
+            {
+                Ok(path) => tracing::info!("TTS audio saved to '{}'", path.display()),
+                Err(e) => tracing::warn!("Failed to save TTS audio '{id}.mp3': {e}"),
             }
+            audio.push(id.to_string());
+        } else {
+            // Return base64 encoded audio for test endpoints
+            let base64_audio = base64::engine::general_purpose::STANDARD.encode(&tts_audio);
+            audio.push(base64_audio);
         }
     }
 
@@ -726,9 +831,21 @@ async fn execute_ai_services(
         images: vec![], // TODO: Add image generation support if needed
         read: false,    // New messages are unread by default
         timestamp: Utc::now(),
+        from_user: false,
     })
 }
 
+/// How many AI calls a run makes: one LLM call per setup item, or one for an override, plus
+/// one TTS call when the prompt creates audio
+fn job_steps(job: &Job, prompt: &Prompt) -> usize {
+    let llm_calls = if job.prompt_override.is_some() {
+        1
+    } else {
+        prompt.setup.len()
+    };
+    llm_calls + usize::from(prompt.create_audio)
+}
+
 /// Core job execution logic using provided character and prompt
 async fn run_job_with_character_and_prompt(
     job: Job,
@@ -736,29 +853,63 @@ async fn run_job_with_character_and_prompt(
     prompt: Prompt,
     settings: Arc<Settings>,
     save_to_chat_history: bool,
-) -> Result<Json<ApiResponse<Message>>, (StatusCode, Json<ApiResponse<()>>)> {
+    user: &str,
+) -> AppResult<Message> {
+    events().publish(Event::JobStarted {
+        job: job.id,
+        user: user.to_string(),
+        character: character.name.clone(),
+        prompt: prompt.title.clone(),
+    });
+
     // Execute AI services (LLM + TTS)
-    let message =
-        execute_ai_services(&job, &character, &prompt, settings, save_to_chat_history).await?;
+    let result = execute_ai_services(
+        &job,
+        &character,
+        &prompt,
+        settings,
+        save_to_chat_history,
+        user,
+    )
+    .await;
+    events().publish(Event::JobFinished {
+        job: job.id,
+        user: user.to_string(),
+        character: character.name.clone(),
+        success: result.is_ok(),
+        error: result.as_ref().err().map(ToString::to_string),
+    });
+    let message = result?;
 
     // Optionally save the message to chat history
-    if save_to_chat_history && let Err(e) = save_message_to_chat(&character.name, &message).await {
-        tracing::warn!("Failed to save message to chat history: {}", e);
-        // Don't fail the entire operation if we can't save to chat history
+    if save_to_chat_history {
+        match save_message_to_chat(user, &character.name, &message).await {
+            Ok(index) => events().publish(Event::MessageCreated {
+                user: user.to_string(),
+                character: character.name.clone(),
+                index,
+                message: message.clone(),
+                job: job.id,
+            }),
+            // Don't fail the entire operation if we can't save to chat history
+            Err(e) => tracing::warn!("Failed to save message to chat history: {}", e),
+        }
     }
 
-    Ok(Json(ApiResponse {
-        success: true,
-        data: Some(message),
-        message: "Job executed successfully".to_string(),
-    }))
+    Ok(Json(ApiResponse::success(
+        message,
+        "Job executed successfully",
+    )))
 }
 
 /// Test a prompt with a specific character
 pub async fn test_prompt_with_character(
-    State(settings): State<Arc<Settings>>,
+    State(shared_settings): State<SharedSettings>,
+    CurrentUser(user): CurrentUser,
     JsonExtract(request): JsonExtract<TestPromptRequest>,
-) -> Result<Json<ApiResponse<Message>>, (StatusCode, Json<ApiResponse<()>>)> {
+) -> AppResult<Message> {
+    let settings = shared_settings.current();
+
     // Create a temporary job with the provided prompt and character
     let job = Job {
         id: None, // Temporary job doesn't need an ID
@@ -766,58 +917,32 @@ pub async fn test_prompt_with_character(
         prompts: vec![request.prompt.title.clone()],
         cadence: "once".to_string(),
         prompt_override: None,
+        enabled: true,
+        subscribers: vec![],
+        trigger_hash: None,
     };
 
     // Create a modified run_job_internal call that uses the provided prompt directly
-    run_job_internal_with_prompt(job, request.prompt, settings, request.save_to_chat_history).await
-}
-
-/// Migrate all existing jobs to use UUIDs
-pub async fn migrate_jobs()
--> Result<Json<ApiResponse<Vec<String>>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match migrate_jobs_to_uuids().await {
-        Ok(migrated_ids) => {
-            let count = migrated_ids.len();
-            Ok(Json(ApiResponse {
-                success: true,
-                data: Some(migrated_ids),
-                message: format!("Successfully migrated {} jobs to UUID format", count),
-            }))
-        }
-        Err(e) => {
-            tracing::error!("Failed to migrate jobs: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to migrate jobs: {}", e),
-                }),
-            ))
-        }
-    }
+    run_job_internal_with_prompt(
+        job,
+        request.prompt,
+        settings,
+        request.save_to_chat_history,
+        &user,
+    )
+    .await
 }
 
 /// Test a character with a specific prompt
 pub async fn test_character_with_prompt(
-    State(settings): State<Arc<Settings>>,
+    State(shared_settings): State<SharedSettings>,
+    CurrentUser(user): CurrentUser,
     JsonExtract(request): JsonExtract<TestCharacterRequest>,
-) -> Result<Json<ApiResponse<Message>>, (StatusCode, Json<ApiResponse<()>>)> {
+) -> AppResult<Message> {
+    let settings = shared_settings.current();
+
     // Load the prompt by name
-    let prompt = match load_prompt(&request.prompt_name).await {
-        Ok(prompt) => prompt,
-        Err(e) => {
-            tracing::error!("Failed to load prompt '{}': {}", request.prompt_name, e);
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to load prompt '{}': {}", request.prompt_name, e),
-                }),
-            ));
-        }
-    };
+    let prompt = load_prompt(&request.prompt_name).await?;
 
     // Create a temporary job with the provided character and prompt
     let job = Job {
@@ -826,6 +951,9 @@ pub async fn test_character_with_prompt(
         prompts: vec![request.prompt_name],
         cadence: "once".to_string(),
         prompt_override: None,
+        enabled: true,
+        subscribers: vec![],
+        trigger_hash: None,
     };
 
     // Create a modified run_job_internal call that uses the provided character directly
@@ -835,6 +963,145 @@ pub async fn test_character_with_prompt(
         prompt,
         settings,
         request.save_to_chat_history,
+        &user,
     )
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{data_dir, with_test_data_dir};
+
+    #[tokio::test]
+    async fn test_update_keeps_enabled_when_omitted() {
+        with_test_data_dir(update_keeps_enabled_when_omitted()).await;
+    }
+
+    async fn update_keeps_enabled_when_omitted() {
+        fs::create_dir_all(data_dir().join("jobs")).await.unwrap();
+        let mut job = Job {
+            id: None,
+            characters: vec!["Brave Knight".to_string()],
+            prompts: vec!["Bedtime Story".to_string()],
+            cadence: "0 0 19 * * *".to_string(),
+            prompt_override: None,
+            enabled: false,
+            subscribers: vec![],
+            trigger_hash: None,
+        };
+        let id = save_job(&mut job).await.unwrap();
+
+        let update: UpdateJobRequest = serde_json::from_value(serde_json::json!({
+            "characters": ["Brave Knight"],
+            "prompts": ["Bedtime Story"],
+            "cadence": "0 30 19 * * *",
+            "prompt-override": null
+        }))
+        .unwrap();
+        let Json(response) = update_job(Path(id.clone()), JsonExtract(update))
+            .await
+            .unwrap();
+        assert!(!response.data.unwrap().enabled);
+        let job = load_job_by_slug(&id).await.unwrap();
+        assert!(!job.enabled);
+        assert_eq!(job.cadence, "0 30 19 * * *");
+
+        let update: UpdateJobRequest = serde_json::from_value(serde_json::json!({
+            "characters": ["Brave Knight"],
+            "prompts": ["Bedtime Story"],
+            "cadence": "0 30 19 * * *",
+            "prompt-override": null,
+            "enabled": true
+        }))
+        .unwrap();
+        let Json(response) = update_job(Path(id.clone()), JsonExtract(update))
+            .await
+            .unwrap();
+        assert!(response.data.unwrap().enabled);
+        assert!(load_job_by_slug(&id).await.unwrap().enabled);
+    }
+
+    #[tokio::test]
+    async fn test_trigger_token_is_kept_out_of_the_url() {
+        with_test_data_dir(trigger_token_is_kept_out_of_the_url()).await;
+    }
+
+    async fn trigger_token_is_kept_out_of_the_url() {
+        fs::create_dir_all(data_dir().join("jobs")).await.unwrap();
+        let mut job = Job {
+            id: None,
+            characters: vec!["Brave Knight".to_string()],
+            prompts: vec!["Bedtime Story".to_string()],
+            cadence: "0 0 19 * * *".to_string(),
+            prompt_override: None,
+            enabled: true,
+            subscribers: vec![],
+            trigger_hash: None,
+        };
+        let id = save_job(&mut job).await.unwrap();
+        let mut settings: Settings =
+            serde_json::from_str(r#"{"ttsApi": "http://tts", "llmApi": "http://llm"}"#).unwrap();
+        settings.public_url = Some("https://story.test/".to_string());
+        let settings = SharedSettings::new(settings);
+
+        let Json(response) = create_job_trigger(State(settings.clone()), Path(id))
+            .await
+            .unwrap();
+        let trigger = response.data.unwrap();
+        assert_eq!(trigger.url, "https://story.test/api/hooks");
+        assert_eq!(trigger.header, TRIGGER_TOKEN_HEADER);
+
+        let missing = trigger_job(State(settings.clone()), HeaderMap::new(), Bytes::new()).await;
+        assert!(matches!(missing, Err(AppError::Unauthorized(_))));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(TRIGGER_TOKEN_HEADER, "not-the-token".parse().unwrap());
+        let unknown = trigger_job(State(settings), headers, Bytes::new()).await;
+        assert!(matches!(unknown, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_chat_saves_take_the_chat_lock() {
+        with_test_data_dir(chat_saves_take_the_chat_lock()).await;
+    }
+
+    async fn chat_saves_take_the_chat_lock() {
+        let message = |i: usize| Message {
+            text: vec![format!("Message {i}")],
+            audio: vec![],
+            images: vec![],
+            read: false,
+            timestamp: Utc::now(),
+            from_user: i.is_multiple_of(2),
+        };
+        save_message_to_chat("default", "Brave Knight", &message(0))
+            .await
+            .unwrap();
+
+        // A save waits while anything else is changing the chat
+        let held = lock_chat("default", "brave-knight").await;
+        let second = message(1);
+        let save = save_message_to_chat("default", "Brave Knight", &second);
+        tokio::pin!(save);
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(50), &mut save)
+                .await
+                .is_err()
+        );
+        drop(held);
+        assert_eq!(save.await.unwrap(), 1);
+        let chat = load_chat("default", "Brave Knight").await.unwrap();
+        assert_eq!(chat.messages.len(), 2);
+
+        // An unreadable chat is reported rather than replaced by an empty one
+        let path = chat_file_path("default", "Brave Knight");
+        fs::write(&path, "{ not json").await.unwrap();
+        assert!(
+            save_message_to_chat("default", "Brave Knight", &message(2))
+                .await
+                .is_err()
+        );
+        assert_eq!(fs::read_to_string(&path).await.unwrap(), "{ not json");
+    }
+}
@@ -0,0 +1,71 @@
+use axum::{extract::Path, http::HeaderMap, response::Response};
+
+use crate::error::AppError;
+use crate::media::serve_file;
+use crate::media_store::{MediaKind, MediaStore};
+
+/// Serve audio files from the data/audio directory
+pub async fn serve_audio(
+    Path((character, filename)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    serve_media(MediaKind::Audio, &character, &filename, &headers).await
+}
+
+/// Serve message images from the data/images directory
+pub async fn serve_image(
+    Path((character, filename)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    serve_media(MediaKind::Image, &character, &filename, &headers).await
+}
+
+async fn serve_media(
+    kind: MediaKind,
+    character: &str,
+    filename: &str,
+    headers: &HeaderMap,
+) -> Result<Response, AppError> {
+    let content_type = kind
+        .content_type(filename)
+        .ok_or_else(|| AppError::Validation("Invalid file type".to_string()))?;
+    let file_path = MediaStore::data()
+        .resolve(kind, character, filename)
+        .await?;
+    serve_file(&file_path, content_type, headers).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, http::Request, http::StatusCode, routing::get};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_encoded_traversal_is_rejected() {
+        let app = Router::new()
+            .route("/audio/{character}/{filename}", get(serve_audio))
+            .route("/images/{character}/{filename}", get(serve_image));
+
+        for uri in [
+            "/audio/../settings.mp3",
+            "/audio/%2e%2e/settings.mp3",
+            "/audio/%2E%2E/settings.mp3",
+            "/audio/..%2F..%2Fusers/admin.mp3",
+            "/audio/brave-knight/..%2F..%2Fsettings.mp3",
+            "/audio/brave-knight/%2e%2e%2f%2e%2e%2fsettings.mp3",
+            "/audio/brave-knight/..%5C..%5Csettings.mp3",
+            "/audio/%2Fetc/passwd.mp3",
+            "/audio/brave-knight/abc%00.mp3",
+            "/images/..%2Fchats/default.png",
+            "/images/brave-knight/%2e%2e%2f%2e%2e%2fportrait.png",
+        ] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+}
@@ -0,0 +1,13 @@
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::AppError;
+use crate::metrics::metrics;
+
+/// Prometheus scrape endpoint
+pub async fn get_metrics() -> Result<Response, AppError> {
+    let body = metrics().render().await?;
+    Ok(([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response())
+}
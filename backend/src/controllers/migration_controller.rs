@@ -0,0 +1,30 @@
+use axum::response::Json;
+
+use crate::config::data_dir;
+use crate::error::AppResult;
+use crate::migrations::{AppliedMigration, MigrationStatus, migration_status, run_migrations};
+use crate::models::ApiResponse;
+
+/// Get the data directory's schema version and any pending migrations
+pub async fn get_migration_status() -> AppResult<MigrationStatus> {
+    let status =
+        migration_status(data_dir()).map_err(|e| e.context("Failed to read migration status"))?;
+    Ok(Json(ApiResponse::success(
+        status,
+        "Migration status retrieved successfully",
+    )))
+}
+
+/// Run any pending data migrations
+/// Migrations also run at startup, so this is only needed after merging an older backup
+pub async fn run_pending_migrations() -> AppResult<Vec<AppliedMigration>> {
+    let applied = run_migrations(data_dir())
+        .await
+        .map_err(|e| e.context("Failed to run migrations"))?;
+
+    let count = applied.len();
+    Ok(Json(ApiResponse::success(
+        applied,
+        format!("Successfully applied {} migrations", count),
+    )))
+}
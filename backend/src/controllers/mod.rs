@@ -1,7 +1,19 @@
-pub mod audio_controller;
+pub mod auth_controller;
+pub mod backup_controller;
 pub mod character_controller;
 pub mod chat_controller;
+pub mod event_controller;
+pub mod fsck_controller;
 pub mod health_controller;
 pub mod job_controller;
+pub mod media_controller;
+pub mod metrics_controller;
+pub mod migration_controller;
+pub mod persona_controller;
+pub mod podcast_controller;
 pub mod prompt_controller;
+pub mod push_controller;
 pub mod scheduler_controller;
+pub mod settings_controller;
+pub mod user_controller;
+pub mod webhook_controller;
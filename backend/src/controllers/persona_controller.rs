@@ -0,0 +1,204 @@
+use axum::{Json as JsonExtract, extract::Path, response::Json};
+use tokio::fs;
+
+use crate::auth::{DEFAULT_USER, load_user};
+use crate::config::data_dir;
+use crate::error::{AppError, AppResult};
+use crate::llm_prompt::Recipient;
+use crate::models::{ApiResponse, CreatePersonaRequest, Persona, UpdatePersonaRequest};
+use crate::utils::{check_new_slug, persona_file_path, persona_slug, user_slug};
+
+/// Get all personas
+pub async fn get_personas() -> AppResult<Vec<Persona>> {
+    let personas = load_all_personas()
+        .await
+        .map_err(|e| e.context("Failed to load personas"))?;
+    Ok(Json(ApiResponse::success(
+        personas,
+        "Personas retrieved successfully",
+    )))
+}
+
+/// Get a specific persona by slug
+pub async fn get_persona(Path(slug): Path<String>) -> AppResult<Persona> {
+    let persona = load_persona_by_slug(&slug).await?;
+    Ok(Json(ApiResponse::success(
+        persona,
+        "Persona retrieved successfully",
+    )))
+}
+
+/// Create a new persona
+pub async fn create_persona(
+    JsonExtract(request): JsonExtract<CreatePersonaRequest>,
+) -> AppResult<Persona> {
+    let slug = persona_slug(&request.name);
+    let existing = async {
+        load_persona_by_slug(&slug)
+            .await
+            .map(|persona| persona.name)
+    };
+    check_new_slug("Persona", "name", &request.name, &slug, existing).await?;
+
+    let persona = Persona {
+        name: request.name,
+        pronouns: request.pronouns,
+        description: request.description,
+        interests: request.interests,
+        relationships: request.relationships,
+        user: None,
+    };
+    let persona = assign_user(persona, request.user).await?;
+
+    save_persona(&persona)
+        .await
+        .map_err(|e| e.context("Failed to create persona"))?;
+    Ok(Json(ApiResponse::success(
+        persona,
+        "Persona created successfully",
+    )))
+}
+
+/// Update an existing persona
+pub async fn update_persona(
+    Path(slug): Path<String>,
+    JsonExtract(request): JsonExtract<UpdatePersonaRequest>,
+) -> AppResult<Persona> {
+    let mut persona = load_persona_by_slug(&slug).await?;
+
+    // Update only provided fields
+    if let Some(pronouns) = request.pronouns {
+        persona.pronouns = pronouns;
+    }
+    if let Some(description) = request.description {
+        persona.description = description;
+    }
+    if let Some(interests) = request.interests {
+        persona.interests = interests;
+    }
+    if let Some(relationships) = request.relationships {
+        persona.relationships = relationships;
+    }
+    if let Some(user) = request.user {
+        persona = assign_user(persona, Some(user)).await?;
+    }
+
+    save_persona(&persona)
+        .await
+        .map_err(|e| e.context("Failed to update persona"))?;
+    Ok(Json(ApiResponse::success(
+        persona,
+        "Persona updated successfully",
+    )))
+}
+
+/// Delete a persona by slug
+pub async fn delete_persona(Path(slug): Path<String>) -> AppResult<()> {
+    let persona = load_persona_by_slug(&slug).await?;
+
+    fs::remove_file(persona_file_path(&persona.name))
+        .await
+        .map_err(|e| {
+            AppError::from_io(e, format!("Persona '{}' not found", persona.name))
+                .context("Failed to delete persona")
+        })?;
+    Ok(Json(ApiResponse::success(
+        (),
+        format!("Persona '{}' deleted successfully", persona.name),
+    )))
+}
+
+/// Who a message for `user` is written to: their persona if they have one, otherwise just
+/// their name
+pub async fn recipient(user: &str) -> Recipient {
+    match load_all_personas().await {
+        Ok(personas) => {
+            let persona = personas
+                .into_iter()
+                .find(|persona| describes_user(persona, user));
+            Recipient::new(user, persona)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to load personas, writing to '{user}' without one: {e}");
+            Recipient::new(user, None)
+        }
+    }
+}
+
+// Helper functions
+
+/// Whether a persona describes the given user
+fn describes_user(persona: &Persona, user: &str) -> bool {
+    match &persona.user {
+        Some(name) => user_slug(name) == user_slug(user),
+        None => user == DEFAULT_USER,
+    }
+}
+
+/// Give a persona to a user, who may have at most one
+/// An empty name or the default user's name leaves the persona to the default user
+async fn assign_user(mut persona: Persona, user: Option<String>) -> Result<Persona, AppError> {
+    persona.user = match user.as_deref().map(str::trim) {
+        None | Some("") | Some(DEFAULT_USER) => None,
+        Some(name) => Some(load_user(name).await?.name),
+    };
+
+    let owner = persona.user.as_deref().unwrap_or(DEFAULT_USER);
+    if let Some(existing) = load_all_personas()
+        .await?
+        .into_iter()
+        .find(|other| other.name != persona.name && describes_user(other, owner))
+    {
+        return Err(AppError::Conflict(format!(
+            "User '{owner}' already has persona '{}'",
+            existing.name
+        )));
+    }
+
+    Ok(persona)
+}
+
+// File I/O utility functions
+
+/// Load all personas from the data/personas directory
+pub async fn load_all_personas() -> Result<Vec<Persona>, AppError> {
+    let mut personas = Vec::new();
+    let dir_path = data_dir().join("personas");
+    if !fs::try_exists(&dir_path).await? {
+        return Ok(personas);
+    }
+
+    let mut entries = fs::read_dir(dir_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("json") {
+            let content = fs::read_to_string(&path).await?;
+            let persona: Persona = serde_json::from_str(&content)?;
+            personas.push(persona);
+        }
+    }
+
+    // Sort personas by name for consistent ordering
+    personas.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(personas)
+}
+
+/// Load a specific persona by slug
+async fn load_persona_by_slug(slug: &str) -> Result<Persona, AppError> {
+    let personas = load_all_personas()
+        .await
+        .map_err(|e| e.context("Failed to load persona"))?;
+
+    personas
+        .into_iter()
+        .find(|persona| persona_slug(&persona.name) == slug)
+        .ok_or_else(|| AppError::NotFound(format!("Persona with slug '{slug}' not found")))
+}
+
+/// Save a persona to a JSON file
+async fn save_persona(persona: &Persona) -> Result<(), AppError> {
+    fs::create_dir_all(data_dir().join("personas")).await?;
+    let json_content = serde_json::to_string_pretty(persona)?;
+    fs::write(persona_file_path(&persona.name), json_content).await?;
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use tokio::fs;
+
+use crate::auth::CurrentUser;
+use crate::error::AppError;
+use crate::models::{Character, Settings};
+use crate::podcast::{Feed, load_episodes, render_feed};
+use crate::settings::SharedSettings;
+use crate::utils::{character_file_path, character_slug};
+
+/// Podcast feed of every character's audio messages to the current user
+pub async fn get_podcast(
+    State(shared_settings): State<SharedSettings>,
+    headers: HeaderMap,
+    CurrentUser(user): CurrentUser,
+) -> Result<Response, AppError> {
+    let episodes = load_episodes(&user, None)
+        .await
+        .map_err(|e| e.context("Failed to load podcast episodes"))?;
+    let feed = Feed {
+        title: "Storytime".to_string(),
+        description: "Audio messages from every character".to_string(),
+        link: "/".to_string(),
+        episodes,
+    };
+    Ok(rss_response(&feed, &shared_settings.current(), &headers))
+}
+
+/// Podcast feed of one character's audio messages to the current user
+pub async fn get_character_podcast(
+    State(shared_settings): State<SharedSettings>,
+    headers: HeaderMap,
+    CurrentUser(user): CurrentUser,
+    Path(character): Path<String>,
+) -> Result<Response, AppError> {
+    let episodes = load_episodes(&user, Some(&character))
+        .await
+        .map_err(|e| e.context("Failed to load podcast episodes"))?;
+
+    // The character's own name and description when it still exists
+    let profile = match fs::read_to_string(character_file_path(&character)).await {
+        Ok(content) => serde_json::from_str::<Character>(&content).ok(),
+        Err(_) => None,
+    };
+    let name = match (&profile, episodes.first()) {
+        (Some(profile), _) => profile.name.clone(),
+        (None, Some(episode)) => episode.character.clone(),
+        (None, None) => {
+            return Err(AppError::NotFound(format!(
+                "Character '{character}' not found"
+            )));
+        }
+    };
+    let description = profile
+        .map(|profile| profile.description)
+        .filter(|description| !description.trim().is_empty())
+        .unwrap_or_else(|| format!("Audio messages from {name}"));
+
+    let feed = Feed {
+        link: format!("/chats/{}", character_slug(&name)),
+        title: name,
+        description,
+        episodes,
+    };
+    Ok(rss_response(&feed, &shared_settings.current(), &headers))
+}
+
+fn rss_response(feed: &Feed, settings: &Settings, headers: &HeaderMap) -> Response {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+        render_feed(feed, &base_url(settings, headers)),
+    )
+        .into_response()
+}
+
+/// Podcast apps need absolute links, so fall back to the address the feed was fetched from
+/// when `publicUrl` is unset
+fn base_url(settings: &Settings, headers: &HeaderMap) -> String {
+    if let Some(public_url) = &settings.public_url {
+        return public_url.clone();
+    }
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("localhost");
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|proto| proto.to_str().ok())
+        .filter(|proto| *proto == "https")
+        .unwrap_or("http");
+    format!("{scheme}://{host}")
+}
@@ -1,73 +1,39 @@
-use axum::{Json as JsonExtract, extract::Path, http::StatusCode, response::Json};
+use axum::{Json as JsonExtract, extract::Path, response::Json};
 use tokio::fs;
 
+use crate::config::data_dir;
+use crate::error::{AppError, AppResult};
 use crate::models::{ApiResponse, CreatePromptRequest, Prompt, UpdatePromptRequest};
-use crate::utils::{prompt_file_path, prompt_slug};
+use crate::utils::{check_new_slug, prompt_file_path, prompt_slug};
 
 /// Get all prompts
-pub async fn get_prompts()
--> Result<Json<ApiResponse<Vec<Prompt>>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match load_all_prompts().await {
-        Ok(prompts) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(prompts),
-            message: "Prompts retrieved successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to load prompts: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to load prompts: {e}"),
-                }),
-            ))
-        }
-    }
+pub async fn get_prompts() -> AppResult<Vec<Prompt>> {
+    let prompts = load_all_prompts()
+        .await
+        .map_err(|e| e.context("Failed to load prompts"))?;
+    Ok(Json(ApiResponse::success(
+        prompts,
+        "Prompts retrieved successfully",
+    )))
 }
 
 /// Get a specific prompt by slug
-pub async fn get_prompt(
-    Path(slug): Path<String>,
-) -> Result<Json<ApiResponse<Prompt>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match load_prompt_by_slug(&slug).await {
-        Ok(prompt) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(prompt),
-            message: "Prompt retrieved successfully".to_string(),
-        })),
-        Err(e) => {
-            if e.to_string().contains("No such file or directory")
-                || e.to_string().contains("Prompt not found")
-            {
-                Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Prompt with slug '{slug}' not found"),
-                    }),
-                ))
-            } else {
-                tracing::error!("Failed to load prompt with slug '{slug}': {e}");
-                Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load prompt: {e}"),
-                    }),
-                ))
-            }
-        }
-    }
+pub async fn get_prompt(Path(slug): Path<String>) -> AppResult<Prompt> {
+    let prompt = load_prompt_by_slug(&slug).await?;
+    Ok(Json(ApiResponse::success(
+        prompt,
+        "Prompt retrieved successfully",
+    )))
 }
 
 /// Create a new prompt
 pub async fn create_prompt(
     JsonExtract(request): JsonExtract<CreatePromptRequest>,
-) -> Result<Json<ApiResponse<Prompt>>, (StatusCode, Json<ApiResponse<()>>)> {
+) -> AppResult<Prompt> {
+    let slug = prompt_slug(&request.title);
+    let existing = async { load_prompt_by_slug(&slug).await.map(|prompt| prompt.title) };
+    check_new_slug("Prompt", "title", &request.title, &slug, existing).await?;
+
     let prompt = Prompt {
         title: request.title.clone(),
         description: request.description,
@@ -77,59 +43,21 @@ pub async fn create_prompt(
         create_images: request.create_images,
     };
 
-    match save_prompt(&prompt).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(prompt),
-            message: "Prompt created successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to create prompt '{}': {}", request.title, e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to create prompt: {e}"),
-                }),
-            ))
-        }
-    }
+    save_prompt(&prompt)
+        .await
+        .map_err(|e| e.context("Failed to create prompt"))?;
+    Ok(Json(ApiResponse::success(
+        prompt,
+        "Prompt created successfully",
+    )))
 }
 
 /// Update an existing prompt
 pub async fn update_prompt(
     Path(slug): Path<String>,
     JsonExtract(request): JsonExtract<UpdatePromptRequest>,
-) -> Result<Json<ApiResponse<Prompt>>, (StatusCode, Json<ApiResponse<()>>)> {
-    // Load the existing prompt
-    let mut prompt = match load_prompt_by_slug(&slug).await {
-        Ok(prompt) => prompt,
-        Err(e) => {
-            if e.to_string().contains("No such file or directory")
-                || e.to_string().contains("Prompt not found")
-            {
-                return Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Prompt with slug '{slug}' not found"),
-                    }),
-                ));
-            } else {
-                tracing::error!("Failed to load prompt with slug '{slug}': {e}");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to load prompt: {e}"),
-                    }),
-                ));
-            }
-        }
-    };
+) -> AppResult<Prompt> {
+    let mut prompt = load_prompt_by_slug(&slug).await?;
 
     // Update fields if provided
     if let Some(description) = request.description {
@@ -148,84 +76,35 @@ pub async fn update_prompt(
         prompt.create_images = create_images;
     }
 
-    // Save the updated prompt
-    match save_prompt(&prompt).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(prompt),
-            message: "Prompt updated successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to update prompt with slug '{slug}': {e}");
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to update prompt: {e}"),
-                }),
-            ))
-        }
-    }
+    save_prompt(&prompt)
+        .await
+        .map_err(|e| e.context("Failed to update prompt"))?;
+    Ok(Json(ApiResponse::success(
+        prompt,
+        "Prompt updated successfully",
+    )))
 }
 
 /// Delete a prompt
-pub async fn delete_prompt(
-    Path(slug): Path<String>,
-) -> Result<Json<ApiResponse<()>>, (StatusCode, Json<ApiResponse<()>>)> {
+pub async fn delete_prompt(Path(slug): Path<String>) -> AppResult<()> {
     // First, find the prompt to get its title for deletion
-    let prompt = match load_prompt_by_slug(&slug).await {
-        Ok(prompt) => prompt,
-        Err(e) => {
-            if e.to_string().contains("Prompt not found") {
-                return Err((
-                    StatusCode::NOT_FOUND,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Prompt with slug '{slug}' not found"),
-                    }),
-                ));
-            } else {
-                tracing::error!("Failed to find prompt with slug '{slug}': {e}");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse {
-                        success: false,
-                        data: None,
-                        message: format!("Failed to find prompt: {e}"),
-                    }),
-                ));
-            }
-        }
-    };
-
-    match delete_prompt_file(&prompt.title).await {
-        Ok(_) => Ok(Json(ApiResponse {
-            success: true,
-            data: None,
-            message: format!("Prompt '{}' deleted successfully", prompt.title),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to delete prompt '{}': {e}", prompt.title);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to delete prompt: {e}"),
-                }),
-            ))
-        }
-    }
+    let prompt = load_prompt_by_slug(&slug).await?;
+
+    delete_prompt_file(&prompt.title)
+        .await
+        .map_err(|e| e.context("Failed to delete prompt"))?;
+    Ok(Json(ApiResponse::success(
+        (),
+        format!("Prompt '{}' deleted successfully", prompt.title),
+    )))
 }
 
 // Helper functions
 
 /// Load all prompts from the data/prompts directory
-async fn load_all_prompts() -> Result<Vec<Prompt>, Box<dyn std::error::Error + Send + Sync>> {
+pub async fn load_all_prompts() -> Result<Vec<Prompt>, AppError> {
     let mut prompts = Vec::new();
-    let mut dir = fs::read_dir("data/prompts").await?;
+    let mut dir = fs::read_dir(data_dir().join("prompts")).await?;
 
     while let Some(entry) = dir.next_entry().await? {
         let path = entry.path();
@@ -251,10 +130,10 @@ async fn load_all_prompts() -> Result<Vec<Prompt>, Box<dyn std::error::Error + S
 
 /// Load a specific prompt by slug
 /// This searches all prompts to find one whose title converts to the given slug
-async fn load_prompt_by_slug(
-    slug: &str,
-) -> Result<Prompt, Box<dyn std::error::Error + Send + Sync>> {
-    let prompts = load_all_prompts().await?;
+async fn load_prompt_by_slug(slug: &str) -> Result<Prompt, AppError> {
+    let prompts = load_all_prompts()
+        .await
+        .map_err(|e| e.context("Failed to load prompt"))?;
 
     for prompt in prompts {
         if prompt_slug(&prompt.title) == slug {
@@ -262,11 +141,13 @@ async fn load_prompt_by_slug(
         }
     }
 
-    Err(format!("Prompt not found with slug: {slug}").into())
+    Err(AppError::NotFound(format!(
+        "Prompt with slug '{slug}' not found"
+    )))
 }
 
 /// Save a prompt to the data/prompts directory
-async fn save_prompt(prompt: &Prompt) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn save_prompt(prompt: &Prompt) -> Result<(), AppError> {
     let path = prompt_file_path(&prompt.title);
     let content = serde_json::to_string_pretty(prompt)?;
     fs::write(path, content).await?;
@@ -274,8 +155,10 @@ async fn save_prompt(prompt: &Prompt) -> Result<(), Box<dyn std::error::Error +
 }
 
 /// Delete a prompt file
-async fn delete_prompt_file(title: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn delete_prompt_file(title: &str) -> Result<(), AppError> {
     let path = prompt_file_path(title);
-    fs::remove_file(path).await?;
+    fs::remove_file(path)
+        .await
+        .map_err(|e| AppError::from_io(e, format!("Prompt '{title}' not found")))?;
     Ok(())
 }
@@ -0,0 +1,53 @@
+use axum::{Json as JsonExtract, response::Json};
+
+use crate::auth::CurrentUser;
+use crate::error::{AppError, AppResult};
+use crate::models::{ApiResponse, PushPublicKey, PushSubscription, UnsubscribePushRequest};
+use crate::push::{add_subscription, remove_subscription, validate_subscription, vapid_keys};
+
+/// The VAPID public key browsers subscribe with
+pub async fn get_public_key() -> AppResult<PushPublicKey> {
+    let keys = vapid_keys()
+        .await
+        .map_err(|e| e.context("Failed to load VAPID keys"))?;
+    Ok(Json(ApiResponse::success(
+        PushPublicKey {
+            public_key: keys.public_key.clone(),
+        },
+        "Push public key retrieved successfully",
+    )))
+}
+
+/// Send the current user's new messages to a browser
+pub async fn subscribe(
+    CurrentUser(user): CurrentUser,
+    JsonExtract(subscription): JsonExtract<PushSubscription>,
+) -> AppResult<()> {
+    validate_subscription(&subscription).map_err(AppError::Validation)?;
+    add_subscription(&user, subscription)
+        .await
+        .map_err(|e| e.context("Failed to save push subscription"))?;
+    Ok(Json(ApiResponse::success(
+        (),
+        "Push subscription saved successfully",
+    )))
+}
+
+/// Stop sending notifications to a browser
+pub async fn unsubscribe(
+    CurrentUser(user): CurrentUser,
+    JsonExtract(request): JsonExtract<UnsubscribePushRequest>,
+) -> AppResult<()> {
+    let removed = remove_subscription(&user, &request.endpoint)
+        .await
+        .map_err(|e| e.context("Failed to remove push subscription"))?;
+    if !removed {
+        return Err(AppError::NotFound(
+            "Push subscription not found".to_string(),
+        ));
+    }
+    Ok(Json(ApiResponse::success(
+        (),
+        "Push subscription removed successfully",
+    )))
+}
@@ -1,69 +1,42 @@
-use axum::{http::StatusCode, response::Json};
+use axum::response::Json;
 
+use crate::error::AppResult;
 use crate::job_scheduler::{get_scheduler_info, reload_jobs};
 use crate::models::ApiResponse;
 
 /// Get scheduler status and information
-pub async fn get_scheduler_status()
--> Result<Json<ApiResponse<SchedulerStatus>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match get_scheduler_info().await {
-        Ok((is_running, scheduled_jobs)) => {
-            let status = SchedulerStatus {
-                is_running,
-                job_count: scheduled_jobs.len(),
-                scheduled_jobs: scheduled_jobs
-                    .into_iter()
-                    .map(|sj| ScheduledJobInfo {
-                        slug: sj.slug,
-                        cadence: sj.job.cadence,
-                        characters: sj.job.characters,
-                        prompts: sj.job.prompts,
-                        next_run: sj.next_run.to_rfc3339(),
-                    })
-                    .collect(),
-            };
+pub async fn get_scheduler_status() -> AppResult<SchedulerStatus> {
+    let (is_running, scheduled_jobs) = get_scheduler_info()
+        .await
+        .map_err(|e| e.context("Failed to get scheduler status"))?;
 
-            Ok(Json(ApiResponse {
-                success: true,
-                data: Some(status),
-                message: "Scheduler status retrieved successfully".to_string(),
-            }))
-        }
-        Err(e) => {
-            tracing::error!("Failed to get scheduler status: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to get scheduler status: {}", e),
-                }),
-            ))
-        }
-    }
+    let status = SchedulerStatus {
+        is_running,
+        job_count: scheduled_jobs.len(),
+        scheduled_jobs: scheduled_jobs
+            .into_iter()
+            .map(|sj| ScheduledJobInfo {
+                slug: sj.slug,
+                cadence: sj.job.cadence,
+                characters: sj.job.characters,
+                prompts: sj.job.prompts,
+                next_run: sj.next_run.to_rfc3339(),
+            })
+            .collect(),
+    };
+
+    Ok(Json(ApiResponse::success(
+        status,
+        "Scheduler status retrieved successfully",
+    )))
 }
 
 /// Reload jobs from disk into the scheduler
-pub async fn reload_scheduler_jobs()
--> Result<Json<ApiResponse<()>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match reload_jobs().await {
-        Ok(()) => Ok(Json(ApiResponse {
-            success: true,
-            data: Some(()),
-            message: "Jobs reloaded successfully".to_string(),
-        })),
-        Err(e) => {
-            tracing::error!("Failed to reload jobs: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    success: false,
-                    data: None,
-                    message: format!("Failed to reload jobs: {}", e),
-                }),
-            ))
-        }
-    }
+pub async fn reload_scheduler_jobs() -> AppResult<()> {
+    reload_jobs()
+        .await
+        .map_err(|e| e.context("Failed to reload jobs"))?;
+    Ok(Json(ApiResponse::success((), "Jobs reloaded successfully")))
 }
 
 /// Response models for scheduler endpoints
@@ -0,0 +1,87 @@
+use axum::{
+    extract::{Json as JsonExtract, State},
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, AppResult};
+use crate::models::{ApiResponse, Settings};
+use crate::settings::{
+    SharedSettings, active_env_overrides, apply_env_overrides, keep_stored_overridden,
+    load_settings_file, save_settings_file, validate_settings,
+};
+
+/// Get the settings currently in effect, with secrets redacted
+pub async fn get_settings(
+    State(shared_settings): State<SharedSettings>,
+) -> Json<ApiResponse<SettingsResponse>> {
+    Json(ApiResponse::success(
+        SettingsResponse::new(&shared_settings.current()),
+        "Settings retrieved successfully",
+    ))
+}
+
+/// Validate and save settings, applying them immediately
+/// Redacted secrets and fields overridden by `STORYTIME_*` environment variables keep their
+/// stored values, and the variables still win
+pub async fn update_settings(
+    State(shared_settings): State<SharedSettings>,
+    JsonExtract(mut request): JsonExtract<Settings>,
+) -> AppResult<SettingsResponse> {
+    let stored = load_settings_file()
+        .map_err(|e| AppError::Storage(format!("Failed to load settings: {e}")))?;
+    request.restore_redacted(&stored);
+    keep_stored_overridden(&mut request, &stored, |name| std::env::var(name).ok());
+
+    validate_settings(&request).map_err(|errors| {
+        AppError::Validation(format!("Invalid settings: {}", errors.join("; ")))
+    })?;
+
+    let mut effective = request.clone();
+    apply_env_overrides(&mut effective, |name| std::env::var(name).ok())
+        .map_err(|e| AppError::Internal(format!("Invalid environment override: {e}")))?;
+
+    save_settings_file(&request)
+        .await
+        .map_err(|e| AppError::Storage(format!("Failed to save settings: {e}")))?;
+
+    shared_settings.replace(effective);
+    tracing::info!("Settings updated");
+
+    Ok(Json(ApiResponse::success(
+        SettingsResponse::new(&shared_settings.current()),
+        "Settings updated successfully",
+    )))
+}
+
+/// Re-read settings.json after it was edited by hand
+pub async fn reload_settings(
+    State(shared_settings): State<SharedSettings>,
+) -> AppResult<SettingsResponse> {
+    let settings = shared_settings
+        .reload()
+        .map_err(|e| AppError::Storage(format!("Failed to reload settings: {e}")))?;
+
+    tracing::info!("Settings reloaded from disk");
+    Ok(Json(ApiResponse::success(
+        SettingsResponse::new(&settings),
+        "Settings reloaded successfully",
+    )))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SettingsResponse {
+    pub settings: Settings,
+    /// `STORYTIME_*` environment variables overriding values from settings.json
+    #[serde(rename = "envOverrides")]
+    pub env_overrides: Vec<String>,
+}
+
+impl SettingsResponse {
+    fn new(settings: &Settings) -> Self {
+        Self {
+            settings: settings.redacted(),
+            env_overrides: active_env_overrides(),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use axum::{
+    extract::{Json as JsonExtract, Path},
+    response::Json,
+};
+
+use crate::auth::{
+    check_new_user_name, delete_user as delete_user_files, hash_password, load_all_users,
+    load_user, save_user,
+};
+use crate::controllers::job_controller::remove_subscriber;
+use crate::error::{AppError, AppResult};
+use crate::models::{ApiResponse, CreateUserRequest, UpdateUserRequest, User, UserInfo};
+
+/// Get all users
+pub async fn get_users() -> AppResult<Vec<UserInfo>> {
+    let users = load_all_users()
+        .await
+        .map_err(|e| e.context("Failed to load users"))?;
+    Ok(Json(ApiResponse::success(
+        users.iter().map(UserInfo::from).collect(),
+        "Users retrieved successfully",
+    )))
+}
+
+/// Get a specific user by name
+pub async fn get_user(Path(name): Path<String>) -> AppResult<UserInfo> {
+    let user = load_user(&name).await?;
+    Ok(Json(ApiResponse::success(
+        UserInfo::from(&user),
+        "User retrieved successfully",
+    )))
+}
+
+/// Create a new user
+pub async fn create_user(
+    JsonExtract(request): JsonExtract<CreateUserRequest>,
+) -> AppResult<UserInfo> {
+    check_new_user_name(&request.name).await?;
+
+    let user = User {
+        name: request.name,
+        password_hash: password_hash(request.password).await?,
+        admin: request.admin,
+    };
+    save_user(&user)
+        .await
+        .map_err(|e| e.context("Failed to create user"))?;
+    Ok(Json(ApiResponse::success(
+        UserInfo::from(&user),
+        "User created successfully",
+    )))
+}
+
+/// Change a user's password or admin flag
+/// Changing the password signs the user out everywhere
+pub async fn update_user(
+    Path(name): Path<String>,
+    JsonExtract(request): JsonExtract<UpdateUserRequest>,
+) -> AppResult<UserInfo> {
+    let mut user = load_user(&name).await?;
+
+    if let Some(password) = request.password {
+        user.password_hash = password_hash(Some(password)).await?;
+    }
+    if let Some(admin) = request.admin {
+        user.admin = admin;
+    }
+
+    save_user(&user)
+        .await
+        .map_err(|e| e.context("Failed to update user"))?;
+    Ok(Json(ApiResponse::success(
+        UserInfo::from(&user),
+        "User updated successfully",
+    )))
+}
+
+/// Delete a user with their chats and tokens, and unsubscribe them from every job
+pub async fn delete_user(Path(name): Path<String>) -> AppResult<()> {
+    let user = load_user(&name).await?;
+
+    delete_user_files(&user.name)
+        .await
+        .map_err(|e| e.context("Failed to delete user"))?;
+    if let Err(e) = remove_subscriber(&user.name).await {
+        tracing::warn!("Failed to unsubscribe '{}' from jobs: {}", user.name, e);
+    }
+
+    Ok(Json(ApiResponse::success(
+        (),
+        format!("User '{}' deleted successfully", user.name),
+    )))
+}
+
+// Helper functions
+
+/// Hash a new password, where an empty or missing one means the user cannot sign in
+async fn password_hash(password: Option<String>) -> Result<Option<String>, AppError> {
+    match password.filter(|password| !password.is_empty()) {
+        Some(password) => Ok(Some(
+            tokio::task::spawn_blocking(move || hash_password(&password)).await??,
+        )),
+        None => Ok(None),
+    }
+}
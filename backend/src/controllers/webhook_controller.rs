@@ -0,0 +1,41 @@
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+
+use crate::error::{AppError, AppResult};
+use crate::models::{ApiResponse, WebhookDelivery};
+use crate::settings::SharedSettings;
+use crate::webhooks::{WebhookPayload, deliver, delivery_log};
+
+/// Recent webhook delivery attempts, newest first
+pub async fn get_deliveries() -> AppResult<Vec<WebhookDelivery>> {
+    Ok(Json(ApiResponse::success(
+        delivery_log().await,
+        "Webhook deliveries retrieved successfully",
+    )))
+}
+
+/// Send a `ping` event to a webhook once and report the outcome
+pub async fn test_webhook(
+    State(shared_settings): State<SharedSettings>,
+    Path(name): Path<String>,
+) -> AppResult<WebhookDelivery> {
+    let settings = shared_settings.current();
+    let webhook = settings
+        .webhooks
+        .iter()
+        .find(|webhook| webhook.name == name)
+        .ok_or_else(|| AppError::NotFound(format!("Webhook '{name}' not found")))?;
+
+    let delivery = deliver(webhook, &WebhookPayload::ping(), 1).await;
+    let message = if delivery.success {
+        format!("Webhook '{name}' delivered successfully")
+    } else {
+        format!(
+            "Webhook '{name}' failed: {}",
+            delivery.error.as_deref().unwrap_or_default()
+        )
+    };
+    Ok(Json(ApiResponse::success(delivery, message)))
+}
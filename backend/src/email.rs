@@ -0,0 +1,540 @@
+use chrono::Utc;
+use cron::Schedule;
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::error::AppError;
+use crate::events::{Event, events, next_event};
+use crate::media_store::{MediaKind, MediaStore};
+use crate::models::{Chat, EmailRecipient, EmailSettings, Message, Settings, SmtpSecurity};
+use crate::settings::SharedSettings;
+use crate::utils::{character_slug, chat_dir, user_slug};
+use crate::webhooks::audio_urls;
+
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sends email through the configured SMTP server
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+/// An email ready to be addressed, with plain-text and HTML versions of its body
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+    /// File names and contents of MP3s to attach
+    pub attachments: Vec<(String, Vec<u8>)>,
+}
+
+impl Mailer {
+    pub fn from_settings(email: &EmailSettings) -> Result<Self, String> {
+        let from = email
+            .from
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid sender '{}': {e}", email.from))?;
+        let builder = match email.security {
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&email.host)
+                    .map_err(|e| e.to_string())?
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&email.host)
+                .map_err(|e| e.to_string())?,
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&email.host)
+            }
+        };
+        let mut builder = builder.port(email.port).timeout(Some(SMTP_TIMEOUT));
+        if let Some(username) = &email.username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                email.password.clone().unwrap_or_default(),
+            ));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            from,
+        })
+    }
+
+    pub async fn send(&self, to: &str, email: Email) -> Result<(), String> {
+        let to = to
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid recipient '{to}': {e}"))?;
+        let alternative = MultiPart::alternative_plain_html(email.text, email.html);
+        let body = if email.attachments.is_empty() {
+            alternative
+        } else {
+            let audio = ContentType::parse("audio/mpeg").map_err(|e| e.to_string())?;
+            email.attachments.into_iter().fold(
+                MultiPart::mixed().multipart(alternative),
+                |body, (name, data)| {
+                    body.singlepart(Attachment::new(name).body(data, audio.clone()))
+                },
+            )
+        };
+        let message = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject)
+            .multipart(body)
+            .map_err(|e| e.to_string())?;
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Whether `recipient` is sent messages from `character` to `user`
+pub fn receives(recipient: &EmailRecipient, user: &str, character: &str) -> bool {
+    user_slug(&recipient.user) == user_slug(user)
+        && (recipient.characters.is_empty()
+            || recipient
+                .characters
+                .iter()
+                .any(|wanted| character_slug(wanted) == character_slug(character)))
+}
+
+/// The email for one new message from `character`
+pub fn message_email(settings: &Settings, character: &str, message: &Message) -> Email {
+    let mut text = format!("{character} sent you a message:\n\n");
+    let mut html = format!("<h2>{}</h2>\n", escape_html(character));
+    append_message(settings, character, message, &mut text, &mut html);
+    append_chat_link(settings, character, &mut text, &mut html);
+
+    Email {
+        subject: format!("New message from {character}"),
+        text,
+        html: layout(&html),
+        attachments: Vec::new(),
+    }
+}
+
+/// The digest email listing `unread` messages, grouped by character in the order given
+pub fn digest_email(settings: &Settings, unread: &[(String, Message)]) -> Email {
+    let count = unread.len();
+    let subject = if count == 1 {
+        "You have 1 unread message".to_string()
+    } else {
+        format!("You have {count} unread messages")
+    };
+    let mut text = format!("{subject}.\n");
+    let mut html = format!("<h1>{}</h1>\n", escape_html(&subject));
+
+    let mut current = None;
+    for (character, message) in unread {
+        if current != Some(character) {
+            if let Some(previous) = current {
+                append_chat_link(settings, previous, &mut text, &mut html);
+            }
+            text.push_str(&format!("\n== {character} ==\n\n"));
+            html.push_str(&format!("<h2>{}</h2>\n", escape_html(character)));
+            current = Some(character);
+        }
+        let time = message.timestamp.format("%Y-%m-%d %H:%M UTC");
+        text.push_str(&format!("[{time}]\n"));
+        html.push_str(&format!("<p style=\"color: #6b7280;\">{time}</p>\n"));
+        append_message(settings, character, message, &mut text, &mut html);
+    }
+    if let Some(previous) = current {
+        append_chat_link(settings, previous, &mut text, &mut html);
+    }
+
+    Email {
+        subject,
+        text,
+        html: layout(&html),
+        attachments: Vec::new(),
+    }
+}
+
+fn append_message(
+    settings: &Settings,
+    character: &str,
+    message: &Message,
+    text: &mut String,
+    html: &mut String,
+) {
+    for paragraph in &message.text {
+        text.push_str(&format!("{paragraph}\n\n"));
+        html.push_str(&format!("<p>{}</p>\n", escape_html(paragraph)));
+    }
+    // Relative links are no use in an email, so audio is only linked with a public URL
+    if settings.public_url.is_some() {
+        for url in audio_urls(settings, character, message) {
+            text.push_str(&format!("Listen: {url}\n\n"));
+            html.push_str(&format!(
+                "<p><a href=\"{}\">Listen</a></p>\n",
+                escape_html(&url)
+            ));
+        }
+    }
+}
+
+fn append_chat_link(settings: &Settings, character: &str, text: &mut String, html: &mut String) {
+    let Some(public_url) = &settings.public_url else {
+        return;
+    };
+    let url = format!(
+        "{}/chats/{}",
+        public_url.trim_end_matches('/'),
+        character_slug(character)
+    );
+    text.push_str(&format!("Open the chat: {url}\n"));
+    html.push_str(&format!(
+        "<p><a href=\"{}\">Open the chat</a></p>\n",
+        escape_html(&url)
+    ));
+}
+
+fn layout(content: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<body style=\"font-family: sans-serif; color: #1f2937; max-width: 600px;\">\n{content}</body>\n</html>\n"
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Email new messages to their recipients, and send the digest of unread messages on its cadence
+pub fn start_email_delivery(settings: SharedSettings) {
+    let (mut receiver, closed) = events().subscribe();
+    let messages_settings = settings.clone();
+
+    events().spawn(async move {
+        while let Some(event) = next_event(&mut receiver, &closed).await {
+            match event {
+                Ok(Event::MessageCreated {
+                    user,
+                    character,
+                    message,
+                    ..
+                }) if !message.from_user => {
+                    let settings = messages_settings.current();
+                    if settings.email.enabled {
+                        events().spawn(async move {
+                            send_message_emails(&settings, &user, &character, &message).await;
+                        });
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Email delivery fell behind, {missed} messages not sent");
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+
+    start_digest_scheduler(settings);
+}
+
+/// Send a new message to every recipient that wants it, with its MP3s attached
+async fn send_message_emails(settings: &Settings, user: &str, character: &str, message: &Message) {
+    let recipients: Vec<_> = settings
+        .email
+        .recipients
+        .iter()
+        .filter(|recipient| recipient.messages && receives(recipient, user, character))
+        .collect();
+    if recipients.is_empty() {
+        return;
+    }
+    let mailer = match Mailer::from_settings(&settings.email) {
+        Ok(mailer) => mailer,
+        Err(e) => {
+            tracing::error!("Failed to set up email delivery: {e}");
+            return;
+        }
+    };
+
+    let mut email = message_email(settings, character, message);
+    let store = MediaStore::data();
+    for id in &message.audio {
+        let filename = format!("{id}.mp3");
+        let data = match store
+            .resolve_for(MediaKind::Audio, character, &filename)
+            .await
+        {
+            Ok(path) => fs::read(&path).await.map_err(AppError::from),
+            Err(e) => Err(e),
+        };
+        match data {
+            Ok(data) => email.attachments.push((filename, data)),
+            Err(e) => tracing::warn!("Failed to attach audio '{filename}' to email: {e}"),
+        }
+    }
+
+    for recipient in recipients {
+        if let Err(e) = mailer.send(&recipient.address, email.clone()).await {
+            tracing::warn!("Failed to email message to {}: {e}", recipient.address);
+        }
+    }
+}
+
+fn start_digest_scheduler(settings: SharedSettings) {
+    let mut changes = settings.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let email = changes.borrow_and_update().email.clone();
+            let next_run = if email.enabled && email.recipients.iter().any(|r| r.digest) {
+                match Schedule::from_str(&email.digest_cadence) {
+                    Ok(schedule) => schedule.upcoming(Utc).next(),
+                    Err(e) => {
+                        tracing::error!(
+                            "Email digest disabled, invalid cron expression '{}': {}",
+                            email.digest_cadence,
+                            e
+                        );
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            let Some(next_run) = next_run else {
+                // Nothing to do until the settings change
+                if changes.changed().await.is_err() {
+                    return;
+                }
+                continue;
+            };
+
+            tracing::debug!("Next email digest at {}", next_run);
+            let wait = (next_run - Utc::now()).to_std().unwrap_or_default();
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => send_digests(settings.current()).await,
+                changed = changes.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    tracing::info!("Email settings changed, rescheduling the digest");
+                }
+            }
+        }
+    });
+}
+
+/// Email each digest recipient the unread messages they are sent, if there are any
+async fn send_digests(settings: Arc<Settings>) {
+    let mailer = match Mailer::from_settings(&settings.email) {
+        Ok(mailer) => mailer,
+        Err(e) => {
+            tracing::error!("Failed to set up email delivery: {e}");
+            return;
+        }
+    };
+
+    for recipient in settings.email.recipients.iter().filter(|r| r.digest) {
+        let unread: Vec<_> = unread_messages(&recipient.user)
+            .await
+            .into_iter()
+            .filter(|(character, _)| receives(recipient, &recipient.user, character))
+            .collect();
+        if unread.is_empty() {
+            continue;
+        }
+        match mailer
+            .send(&recipient.address, digest_email(&settings, &unread))
+            .await
+        {
+            Ok(()) => tracing::info!(
+                "Emailed digest of {} unread messages to {}",
+                unread.len(),
+                recipient.address
+            ),
+            Err(e) => tracing::warn!("Failed to email digest to {}: {e}", recipient.address),
+        }
+    }
+}
+
+/// Every unread message characters sent `user`, grouped by character
+async fn unread_messages(user: &str) -> Vec<(String, Message)> {
+    let Ok(mut entries) = fs::read_dir(chat_dir(user)).await else {
+        return Vec::new();
+    };
+    let mut chats = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let Ok(content) = fs::read_to_string(entry.path()).await else {
+            continue;
+        };
+        match serde_json::from_str::<Chat>(&content) {
+            Ok(chat) => chats.push(chat),
+            Err(e) => tracing::warn!(
+                "Skipping unreadable chat '{}' in digest: {e}",
+                entry.path().display()
+            ),
+        }
+    }
+    chats.sort_by(|a, b| a.character.cmp(&b.character));
+
+    chats
+        .into_iter()
+        .flat_map(|chat| {
+            let character = chat.character;
+            chat.messages
+                .into_iter()
+                .filter(|message| !message.read && !message.from_user)
+                .map(move |message| (character.clone(), message))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    fn test_settings() -> Settings {
+        let mut settings: Settings =
+            serde_json::from_str(r#"{"ttsApi": "https://tts.test", "llmApi": "https://llm.test"}"#)
+                .unwrap();
+        settings.public_url = Some("https://story.example".to_string());
+        settings
+    }
+
+    fn message(text: &str, audio: &[&str]) -> Message {
+        Message {
+            text: vec![text.to_string()],
+            audio: audio.iter().map(|id| id.to_string()).collect(),
+            images: vec![],
+            read: false,
+            timestamp: Utc::now(),
+            from_user: false,
+        }
+    }
+
+    #[test]
+    fn test_receives() {
+        let recipient: EmailRecipient = serde_json::from_str(
+            r#"{"address": "gran@family.test", "characters": ["Brave Knight"]}"#,
+        )
+        .unwrap();
+        assert!(recipient.messages);
+        assert!(!recipient.digest);
+        assert!(receives(&recipient, "default", "brave knight"));
+        assert!(!receives(&recipient, "default", "Wizard"));
+        assert!(!receives(&recipient, "alice", "Brave Knight"));
+    }
+
+    #[test]
+    fn test_templates() {
+        let settings = test_settings();
+        let email = message_email(
+            &settings,
+            "Brave Knight",
+            &message("Fish & <chips>", &["abc"]),
+        );
+        assert_eq!(email.subject, "New message from Brave Knight");
+        assert!(email.text.contains("Fish & <chips>"));
+        assert!(
+            email
+                .text
+                .contains("https://story.example/audio/brave-knight/abc.mp3")
+        );
+        assert!(email.html.contains("<p>Fish &amp; &lt;chips&gt;</p>"));
+        assert!(
+            email
+                .html
+                .contains("https://story.example/chats/brave-knight")
+        );
+
+        let unread = vec![
+            ("Brave Knight".to_string(), message("I ride at dawn.", &[])),
+            ("Brave Knight".to_string(), message("Still riding.", &[])),
+            ("Wizard".to_string(), message("Abracadabra.", &[])),
+        ];
+        let digest = digest_email(&settings, &unread);
+        assert_eq!(digest.subject, "You have 3 unread messages");
+        assert_eq!(digest.text.matches("== Brave Knight ==").count(), 1);
+        assert!(digest.text.find("Still riding.") < digest.text.find("== Wizard =="));
+        assert_eq!(digest.html.matches("Open the chat").count(), 2);
+    }
+
+    /// A local SMTP sink that accepts one email and reports its data
+    async fn smtp_sink() -> (u16, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let command = line.to_ascii_uppercase();
+                let reply: &[u8] = if command.starts_with("EHLO") {
+                    b"250 sink\r\n"
+                } else if command.starts_with("DATA") {
+                    writer.write_all(b"354 go ahead\r\n").await.unwrap();
+                    let mut data = String::new();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        if line == "." {
+                            break;
+                        }
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    let _ = sender.send(data);
+                    b"250 queued\r\n"
+                } else if command.starts_with("QUIT") {
+                    writer.write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+        });
+        (port, receiver)
+    }
+
+    #[tokio::test]
+    async fn test_send_to_smtp_sink() {
+        let (port, mut sink) = smtp_sink().await;
+        let email_settings = EmailSettings {
+            enabled: true,
+            host: "127.0.0.1".to_string(),
+            port,
+            security: SmtpSecurity::None,
+            ..Default::default()
+        };
+        let mailer = Mailer::from_settings(&email_settings).unwrap();
+
+        let mut email = message_email(&test_settings(), "Brave Knight", &message("Hello", &[]));
+        email
+            .attachments
+            .push(("abc.mp3".to_string(), b"ID3 not really".to_vec()));
+        mailer.send("gran@family.test", email).await.unwrap();
+
+        let data = tokio::time::timeout(Duration::from_secs(5), sink.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(data.contains("Subject: New message from Brave Knight"));
+        assert!(data.contains("To: gran@family.test"));
+        assert!(data.contains("Content-Type: text/plain"));
+        assert!(data.contains("Content-Type: text/html"));
+        assert!(data.contains("Content-Type: audio/mpeg"));
+        assert!(data.contains("filename=\"abc.mp3\""));
+    }
+}
@@ -0,0 +1,197 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+
+use crate::backup::BackupError;
+use crate::models::{ApiResponse, ErrorCode};
+
+/// Errors returned by API handlers
+/// Each variant maps to one HTTP status and one machine-readable `ErrorCode`
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    /// The requested entity or file does not exist
+    #[error("{0}")]
+    NotFound(String),
+    /// The request clashes with existing data, such as a duplicate slug
+    #[error("{0}")]
+    Conflict(String),
+    /// The request is malformed or fails validation
+    #[error("{0}")]
+    Validation(String),
+    /// The request has no valid session or API token
+    #[error("{0}")]
+    Unauthorized(String),
+    /// The caller is signed in but lacks the scope the request needs
+    #[error("{0}")]
+    Forbidden(String),
+    /// The LLM backend could not be reached or returned an error
+    #[error("{0}")]
+    UpstreamLlm(String),
+    /// The TTS backend could not be reached or returned an error
+    #[error("{0}")]
+    UpstreamTts(String),
+    /// A service the request needs is not running, such as the scheduler during a shutdown
+    #[error("{0}")]
+    Unavailable(String),
+    /// Reading or writing the data directory failed
+    #[error("{0}")]
+    Storage(String),
+    /// Anything else that went wrong on our side
+    #[error("{0}")]
+    Internal(String),
+}
+
+pub type AppResult<T> = Result<Json<ApiResponse<T>>, AppError>;
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::UpstreamLlm(_) | AppError::UpstreamTts(_) => StatusCode::BAD_GATEWAY,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Storage(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::NotFound(_) => ErrorCode::NotFound,
+            AppError::Conflict(_) => ErrorCode::Conflict,
+            AppError::Validation(_) => ErrorCode::Validation,
+            AppError::Unauthorized(_) => ErrorCode::Unauthorized,
+            AppError::Forbidden(_) => ErrorCode::Forbidden,
+            AppError::UpstreamLlm(_) => ErrorCode::UpstreamLlm,
+            AppError::UpstreamTts(_) => ErrorCode::UpstreamTts,
+            AppError::Unavailable(_) => ErrorCode::Unavailable,
+            AppError::Storage(_) => ErrorCode::Storage,
+            AppError::Internal(_) => ErrorCode::Internal,
+        }
+    }
+
+    /// Prefix the message with what was being attempted, keeping the kind of error
+    pub fn context(self, context: impl std::fmt::Display) -> Self {
+        let wrap = |message: String| format!("{context}: {message}");
+        match self {
+            AppError::NotFound(m) => AppError::NotFound(wrap(m)),
+            AppError::Conflict(m) => AppError::Conflict(wrap(m)),
+            AppError::Validation(m) => AppError::Validation(wrap(m)),
+            AppError::Unauthorized(m) => AppError::Unauthorized(wrap(m)),
+            AppError::Forbidden(m) => AppError::Forbidden(wrap(m)),
+            AppError::UpstreamLlm(m) => AppError::UpstreamLlm(wrap(m)),
+            AppError::UpstreamTts(m) => AppError::UpstreamTts(wrap(m)),
+            AppError::Unavailable(m) => AppError::Unavailable(wrap(m)),
+            AppError::Storage(m) => AppError::Storage(wrap(m)),
+            AppError::Internal(m) => AppError::Internal(wrap(m)),
+        }
+    }
+
+    /// Classify a file error, reporting a missing file as `not_found` instead of the OS message
+    pub fn from_io(e: std::io::Error, not_found: impl std::fmt::Display) -> Self {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            AppError::NotFound(not_found.to_string())
+        } else {
+            AppError::Storage(e.to_string())
+        }
+    }
+}
+
+/// Any file error is a storage failure, including a missing file, since a file that should be
+/// there is not; use [`AppError::from_io`] where the file is an entity that may not exist
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        AppError::Storage(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(e: serde_json::Error) -> Self {
+        AppError::Storage(format!("Invalid JSON: {e}"))
+    }
+}
+
+impl From<tokio::task::JoinError> for AppError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        AppError::Internal(format!("Background task failed: {e}"))
+    }
+}
+
+impl From<BackupError> for AppError {
+    fn from(e: BackupError) -> Self {
+        match e {
+            BackupError::Invalid(_) => AppError::Validation(e.to_string()),
+            _ => AppError::Storage(e.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!("{} ({:?})", self, self.code());
+        } else {
+            tracing::debug!("{} ({:?})", self, self.code());
+        }
+
+        (
+            status,
+            Json(ApiResponse::<()> {
+                success: false,
+                data: None,
+                message: self.to_string(),
+                code: Some(self.code()),
+            }),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_errors_are_classified_by_kind() {
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(matches!(
+            AppError::from_io(missing, "Character 'x' not found"),
+            AppError::NotFound(m) if m == "Character 'x' not found"
+        ));
+
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert_eq!(AppError::from(denied).code(), ErrorCode::Storage);
+
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert_eq!(
+            AppError::from(missing).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn test_context_keeps_kind() {
+        let error = AppError::UpstreamLlm("timed out".to_string()).context("Failed to run job");
+        assert_eq!(error.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(error.to_string(), "Failed to run job: timed out");
+    }
+
+    #[tokio::test]
+    async fn test_error_response_body() {
+        let response =
+            AppError::Conflict("Character 'Zoë' already exists".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["success"], false);
+        assert_eq!(json["code"], "conflict");
+        assert_eq!(json["message"], "Character 'Zoë' already exists");
+    }
+}
@@ -0,0 +1,253 @@
+use serde::Serialize;
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::models::Message;
+use crate::utils::user_slug;
+
+/// Events a slow client may fall behind by before it misses some
+const EVENT_BUFFER: usize = 256;
+
+/// Something that happened which open clients may want to show straight away
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    MessageCreated {
+        user: String,
+        character: String,
+        index: usize,
+        message: Message,
+        /// The job that wrote the message, if it was not added by hand
+        #[serde(skip_serializing_if = "Option::is_none")]
+        job: Option<uuid::Uuid>,
+    },
+    MessageUpdated {
+        user: String,
+        character: String,
+        index: usize,
+        message: Message,
+    },
+    MessageDeleted {
+        user: String,
+        character: String,
+        index: usize,
+    },
+    /// A message was read, or every message in the chat when `index` is missing
+    MessageRead {
+        user: String,
+        character: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<usize>,
+    },
+    /// A job started writing to a user, so the character is "typing"
+    JobStarted {
+        job: Option<uuid::Uuid>,
+        user: String,
+        character: String,
+        prompt: String,
+    },
+    /// A job is about to make the `step`th of its `steps` AI calls
+    JobProgress {
+        job: Option<uuid::Uuid>,
+        user: String,
+        character: String,
+        step: usize,
+        steps: usize,
+    },
+    JobFinished {
+        job: Option<uuid::Uuid>,
+        user: String,
+        character: String,
+        success: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    SchedulerReloaded {
+        jobs: usize,
+    },
+    /// The client fell behind and missed events, so it should reload what it shows
+    Lagged {
+        missed: u64,
+    },
+}
+
+impl Event {
+    /// The user an event is about, or `None` for events everyone may see
+    fn user(&self) -> Option<&str> {
+        match self {
+            Event::MessageCreated { user, .. }
+            | Event::MessageUpdated { user, .. }
+            | Event::MessageDeleted { user, .. }
+            | Event::MessageRead { user, .. }
+            | Event::JobStarted { user, .. }
+            | Event::JobProgress { user, .. }
+            | Event::JobFinished { user, .. } => Some(user),
+            Event::SchedulerReloaded { .. } | Event::Lagged { .. } => None,
+        }
+    }
+
+    /// Whether `user` may see this event; other users' chats stay private
+    pub fn is_for(&self, user: &str) -> bool {
+        self.user()
+            .is_none_or(|event_user| user_slug(event_user) == user_slug(user))
+    }
+}
+
+/// In-process bus that controllers and the scheduler publish events to
+/// Clients' streams end as soon as a shutdown starts, while dispatchers that pass events on,
+/// such as webhooks, run until the bus is closed once nothing can publish any more
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+    clients_ended: CancellationToken,
+    closed: CancellationToken,
+    /// Dispatchers and the deliveries they started
+    dispatchers: TaskTracker,
+}
+
+static EVENTS: OnceLock<EventBus> = OnceLock::new();
+
+/// Get the global event bus
+pub fn events() -> &'static EventBus {
+    EVENTS.get_or_init(EventBus::new)
+}
+
+impl EventBus {
+    pub(crate) fn new() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_BUFFER).0,
+            clients_ended: CancellationToken::new(),
+            closed: CancellationToken::new(),
+            dispatchers: TaskTracker::new(),
+        }
+    }
+
+    /// Send an event to every open client; with none open it is simply dropped
+    pub fn publish(&self, event: Event) {
+        let _ = self.sender.send(event);
+    }
+
+    /// Receive events for a dispatcher from now on, until the bus is closed
+    /// Read them with [`next_event`] so none published before the close are missed
+    pub fn subscribe(&self) -> (broadcast::Receiver<Event>, CancellationToken) {
+        (self.sender.subscribe(), self.closed.clone())
+    }
+
+    /// Receive events for a client's stream from now on, until a shutdown starts
+    pub fn subscribe_client(&self) -> (broadcast::Receiver<Event>, CancellationToken) {
+        (self.sender.subscribe(), self.clients_ended.clone())
+    }
+
+    /// Run a dispatcher, or a delivery it started, so that closing the bus waits for it
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.dispatchers.spawn(task);
+    }
+
+    /// End every client's event stream, so they do not hold up a graceful shutdown
+    pub fn end_client_streams(&self) {
+        self.clients_ended.cancel();
+    }
+
+    /// Tell dispatchers to stop once they have handled every event published so far, and give
+    /// them and their deliveries up to `timeout` to finish
+    /// Call this only once nothing publishes any more; returns false if the timeout expired
+    pub async fn close(&self, timeout: Duration) -> bool {
+        self.end_client_streams();
+        self.closed.cancel();
+        self.dispatchers.close();
+        let drained = tokio::time::timeout(timeout, self.dispatchers.wait())
+            .await
+            .is_ok();
+        if !drained {
+            tracing::warn!(
+                "{} event deliveries still running after {:?}, abandoning them",
+                self.dispatchers.len(),
+                timeout
+            );
+        }
+        drained
+    }
+}
+
+/// The next event for a dispatcher, or `None` once the bus is closed and every event published
+/// before that has been returned
+/// Cancel safe, so it can be one branch of a `select!`
+pub async fn next_event(
+    receiver: &mut broadcast::Receiver<Event>,
+    closed: &CancellationToken,
+) -> Option<Result<Event, RecvError>> {
+    tokio::select! {
+        biased;
+        event = receiver.recv() => Some(event),
+        _ = closed.cancelled() => match receiver.try_recv() {
+            Ok(event) => Some(Ok(event)),
+            Err(TryRecvError::Lagged(missed)) => Some(Err(RecvError::Lagged(missed))),
+            Err(TryRecvError::Empty | TryRecvError::Closed) => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_are_private_to_their_user() {
+        let event = Event::MessageRead {
+            user: "Alice".to_string(),
+            character: "Knight".to_string(),
+            index: None,
+        };
+        assert!(event.is_for("alice"));
+        assert!(!event.is_for("bob"));
+        assert!(Event::SchedulerReloaded { jobs: 1 }.is_for("bob"));
+    }
+
+    #[tokio::test]
+    async fn test_dispatchers_drain_before_close() {
+        let bus = EventBus::new();
+        let (mut receiver, closed) = bus.subscribe();
+        let (mut client, client_ended) = bus.subscribe_client();
+        let received = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let dispatched = std::sync::Arc::clone(&received);
+        bus.spawn(async move {
+            while let Some(event) = next_event(&mut receiver, &closed).await {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                dispatched.lock().unwrap().push(event.unwrap());
+            }
+        });
+
+        for jobs in 0..3 {
+            bus.publish(Event::SchedulerReloaded { jobs });
+        }
+        bus.end_client_streams();
+        assert!(client_ended.is_cancelled());
+        assert!(client.try_recv().is_ok());
+
+        assert!(bus.close(Duration::from_secs(5)).await);
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_event_serialization() {
+        let event = Event::JobProgress {
+            job: None,
+            user: "default".to_string(),
+            character: "Knight".to_string(),
+            step: 1,
+            steps: 2,
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "job_progress");
+        assert_eq!(json["step"], 1);
+        assert_eq!(json["steps"], 2);
+    }
+}
@@ -0,0 +1,613 @@
+use cron::Schedule;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::fs;
+
+use crate::auth::DEFAULT_USER;
+use crate::config::data_dir;
+use crate::error::AppError;
+use crate::models::{Character, Chat, Job, Message, Persona, Prompt, Settings, User};
+use crate::utils::{to_slug, user_slug};
+
+/// How serious a problem found by the checker is
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Wasted space or stale data; nothing is broken
+    Warning,
+    /// Data that cannot be loaded or a job that cannot run
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The kind of problem found by the checker
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// A JSON file that does not parse
+    UnreadableFile,
+    /// A media file not referenced by any message
+    OrphanMedia,
+    /// A media folder for a character that no longer exists and has no chat
+    OrphanMediaFolder,
+    /// A message referencing a media file that does not exist
+    MissingMedia,
+    /// A job referencing a character or prompt that does not exist
+    MissingReference,
+    /// A job whose cadence is not a valid cron expression
+    InvalidCron,
+    /// A job with no characters or no prompts
+    InvalidJob,
+}
+
+/// A single problem found in the data directory
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FsckIssue {
+    pub severity: Severity,
+    pub kind: IssueKind,
+    /// Path relative to the data directory
+    pub path: String,
+    pub message: String,
+    /// Whether running with repair enabled would fix this issue
+    pub repairable: bool,
+    pub repaired: bool,
+}
+
+/// The result of checking the data directory
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FsckReport {
+    pub issues: Vec<FsckIssue>,
+    pub repaired: usize,
+}
+
+impl FsckReport {
+    /// Whether any error-level issues were found that have not been repaired
+    pub fn has_errors(&self) -> bool {
+        self.issues
+            .iter()
+            .any(|issue| issue.severity == Severity::Error && !issue.repaired)
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        kind: IssueKind,
+        path: String,
+        message: String,
+        repairable: bool,
+    ) {
+        self.issues.push(FsckIssue {
+            severity,
+            kind,
+            path,
+            message,
+            repairable,
+            repaired: false,
+        });
+    }
+
+    /// Mark the most recently added issue as repaired
+    fn mark_repaired(&mut self) {
+        if let Some(issue) = self.issues.last_mut() {
+            issue.repaired = true;
+            self.repaired += 1;
+        }
+    }
+}
+
+/// Check the data directory for orphaned, broken or unreadable data
+/// With `repair` set, orphaned media is deleted and broken jobs are disabled
+pub async fn run_fsck(repair: bool) -> Result<FsckReport, AppError> {
+    check_data_dir(data_dir(), repair).await
+}
+
+/// Data directory check against an explicit data directory
+pub(crate) async fn check_data_dir(data_dir: &Path, repair: bool) -> Result<FsckReport, AppError> {
+    let mut report = FsckReport::default();
+
+    let settings_path = data_dir.join("settings.json");
+    if fs::try_exists(&settings_path).await? {
+        let content = fs::read_to_string(&settings_path).await?;
+        if let Err(e) = serde_json::from_str::<Settings>(&content) {
+            report.push(
+                Severity::Error,
+                IssueKind::UnreadableFile,
+                "settings.json".to_string(),
+                format!("Settings failed to parse: {e}"),
+                false,
+            );
+        }
+    }
+
+    let characters: HashSet<String> =
+        read_entities::<Character>(data_dir, "characters", &mut report)
+            .await?
+            .into_iter()
+            .map(|(slug, _, _)| slug)
+            .collect();
+    let prompts: HashSet<String> = read_entities::<Prompt>(data_dir, "prompts", &mut report)
+        .await?
+        .into_iter()
+        .map(|(slug, _, _)| slug)
+        .collect();
+    let users: HashSet<String> = read_entities::<User>(data_dir, "users", &mut report)
+        .await?
+        .into_iter()
+        .map(|(slug, _, _)| slug)
+        .collect();
+    let chats = read_chats(data_dir, &mut report).await?;
+    check_personas(data_dir, &users, &mut report).await?;
+
+    check_jobs(data_dir, &characters, &prompts, &users, repair, &mut report).await?;
+    check_media(
+        data_dir,
+        "audio",
+        |message| &message.audio,
+        &characters,
+        &chats,
+        repair,
+        &mut report,
+    )
+    .await?;
+    check_media(
+        data_dir,
+        "images",
+        |message| &message.images,
+        &characters,
+        &chats,
+        repair,
+        &mut report,
+    )
+    .await?;
+
+    Ok(report)
+}
+
+/// Parse every JSON file in a data subdirectory, reporting the ones that fail
+/// Returns the slug (file name without ".json"), path and value of each readable file
+async fn read_entities<T: DeserializeOwned>(
+    data_dir: &Path,
+    dir_name: &str,
+    report: &mut FsckReport,
+) -> Result<Vec<(String, PathBuf, T)>, AppError> {
+    let mut entities = Vec::new();
+    let dir = data_dir.join(dir_name);
+    if !fs::try_exists(&dir).await? {
+        return Ok(entities);
+    }
+
+    let mut entries = fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let Some(slug) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_suffix(".json"))
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        if !path.is_file() {
+            continue;
+        }
+
+        let parsed = fs::read_to_string(&path)
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str::<T>(&content).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(value) => entities.push((slug, path, value)),
+            Err(e) => report.push(
+                Severity::Error,
+                IssueKind::UnreadableFile,
+                relative_path(data_dir, &path),
+                format!("Failed to parse: {e}"),
+                false,
+            ),
+        }
+    }
+
+    entities.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(entities)
+}
+
+/// Read every user's chats, grouped by character slug
+async fn read_chats(
+    data_dir: &Path,
+    report: &mut FsckReport,
+) -> Result<HashMap<String, Vec<(PathBuf, Chat)>>, AppError> {
+    let mut chats: HashMap<String, Vec<(PathBuf, Chat)>> = HashMap::new();
+    let chats_dir = data_dir.join("chats");
+    if !fs::try_exists(&chats_dir).await? {
+        return Ok(chats);
+    }
+
+    let mut user_dirs = Vec::new();
+    let mut entries = fs::read_dir(&chats_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.path().is_dir()
+            && let Some(name) = entry.file_name().to_str()
+        {
+            user_dirs.push(name.to_string());
+        }
+    }
+    user_dirs.sort();
+
+    for user in user_dirs {
+        for (slug, path, chat) in
+            read_entities::<Chat>(data_dir, &format!("chats/{user}"), report).await?
+        {
+            chats.entry(slug).or_default().push((path, chat));
+        }
+    }
+
+    Ok(chats)
+}
+
+/// Check that every persona describes an existing user
+async fn check_personas(
+    data_dir: &Path,
+    users: &HashSet<String>,
+    report: &mut FsckReport,
+) -> Result<(), AppError> {
+    for (_, path, persona) in read_entities::<Persona>(data_dir, "personas", report).await? {
+        if let Some(user) = persona.user
+            && !users.contains(&user_slug(&user))
+        {
+            report.push(
+                Severity::Warning,
+                IssueKind::MissingReference,
+                relative_path(data_dir, &path),
+                format!("Persona describes missing user '{user}'"),
+                false,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that every enabled job can actually run, disabling broken ones when repairing
+async fn check_jobs(
+    data_dir: &Path,
+    characters: &HashSet<String>,
+    prompts: &HashSet<String>,
+    users: &HashSet<String>,
+    repair: bool,
+    report: &mut FsckReport,
+) -> Result<(), AppError> {
+    for (_, path, mut job) in read_entities::<Job>(data_dir, "jobs", report).await? {
+        let mut problems = Vec::new();
+
+        if job.characters.is_empty() {
+            problems.push((IssueKind::InvalidJob, "Job has no characters".to_string()));
+        }
+        if job.prompts.is_empty() {
+            problems.push((IssueKind::InvalidJob, "Job has no prompts".to_string()));
+        }
+        for character in &job.characters {
+            if !characters.contains(&to_slug(character)) {
+                problems.push((
+                    IssueKind::MissingReference,
+                    format!("Job references missing character '{character}'"),
+                ));
+            }
+        }
+        for prompt in &job.prompts {
+            if !prompts.contains(&to_slug(prompt)) {
+                problems.push((
+                    IssueKind::MissingReference,
+                    format!("Job references missing prompt '{prompt}'"),
+                ));
+            }
+        }
+        for subscriber in &job.subscribers {
+            if subscriber != DEFAULT_USER && !users.contains(&user_slug(subscriber)) {
+                problems.push((
+                    IssueKind::MissingReference,
+                    format!("Job references missing subscriber '{subscriber}'"),
+                ));
+            }
+        }
+        if let Err(e) = Schedule::from_str(&job.cadence) {
+            problems.push((
+                IssueKind::InvalidCron,
+                format!("Invalid cron expression '{}': {}", job.cadence, e),
+            ));
+        }
+
+        if problems.is_empty() {
+            continue;
+        }
+
+        // Problems in a job that is already disabled cannot break the scheduler
+        let severity = if job.enabled {
+            Severity::Error
+        } else {
+            Severity::Warning
+        };
+        let disable = repair && job.enabled;
+        if disable {
+            job.enabled = false;
+            fs::write(&path, serde_json::to_string_pretty(&job)?).await?;
+            tracing::info!("Disabled broken job {}", path.display());
+        }
+
+        for (kind, message) in problems {
+            report.push(
+                severity,
+                kind,
+                relative_path(data_dir, &path),
+                message,
+                job.enabled || disable,
+            );
+            if disable {
+                report.mark_repaired();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check a media directory (`audio` or `images`) against the messages that reference it
+async fn check_media(
+    data_dir: &Path,
+    media_dir: &str,
+    references: fn(&Message) -> &Vec<String>,
+    characters: &HashSet<String>,
+    chats: &HashMap<String, Vec<(PathBuf, Chat)>>,
+    repair: bool,
+    report: &mut FsckReport,
+) -> Result<(), AppError> {
+    let media_root = data_dir.join(media_dir);
+
+    // Files present on disk, per character folder
+    let mut folders: HashMap<String, Vec<String>> = HashMap::new();
+    if fs::try_exists(&media_root).await? {
+        let mut entries = fs::read_dir(&media_root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.path().is_dir() {
+                continue;
+            }
+            let Some(slug) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+
+            let mut files = Vec::new();
+            let mut folder_entries = fs::read_dir(entry.path()).await?;
+            while let Some(file) = folder_entries.next_entry().await? {
+                if file.path().is_file()
+                    && let Some(name) = file.file_name().to_str()
+                {
+                    files.push(name.to_string());
+                }
+            }
+            files.sort();
+            folders.insert(slug, files);
+        }
+    }
+
+    let mut slugs: Vec<&String> = folders.keys().chain(chats.keys()).collect();
+    slugs.sort();
+    slugs.dedup();
+
+    for slug in slugs {
+        let folder_path = media_root.join(slug);
+        let files = folders.get(slug).map(Vec::as_slice).unwrap_or_default();
+        let slug_chats = chats.get(slug).map(Vec::as_slice).unwrap_or_default();
+        let referenced: HashSet<&str> = slug_chats
+            .iter()
+            .flat_map(|(_, chat)| chat.messages.iter().flat_map(references))
+            .map(String::as_str)
+            .collect();
+
+        // A whole folder is orphaned once neither the character nor any chat with it exist
+        if folders.contains_key(slug) && !characters.contains(slug) && !chats.contains_key(slug) {
+            report.push(
+                Severity::Warning,
+                IssueKind::OrphanMediaFolder,
+                relative_path(data_dir, &folder_path),
+                format!("{media_dir} folder for deleted character '{slug}'"),
+                true,
+            );
+            if repair {
+                fs::remove_dir_all(&folder_path).await?;
+                report.mark_repaired();
+            }
+            continue;
+        }
+
+        for file in files {
+            if is_referenced(file, &referenced) {
+                continue;
+            }
+            let file_path = folder_path.join(file);
+            report.push(
+                Severity::Warning,
+                IssueKind::OrphanMedia,
+                relative_path(data_dir, &file_path),
+                format!("{media_dir} file is not referenced by any message"),
+                true,
+            );
+            if repair {
+                fs::remove_file(&file_path).await?;
+                report.mark_repaired();
+            }
+        }
+
+        for (chat_path, chat) in slug_chats {
+            let mut missing: Vec<&str> = chat
+                .messages
+                .iter()
+                .flat_map(references)
+                .map(String::as_str)
+                .filter(|reference| !files.iter().any(|file| matches_reference(file, reference)))
+                .collect();
+            missing.sort();
+            missing.dedup();
+            for reference in missing {
+                report.push(
+                    Severity::Warning,
+                    IssueKind::MissingMedia,
+                    relative_path(data_dir, chat_path),
+                    format!("Message references missing {media_dir} file '{reference}'"),
+                    false,
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn is_referenced(file_name: &str, referenced: &HashSet<&str>) -> bool {
+    referenced
+        .iter()
+        .any(|reference| matches_reference(file_name, reference))
+}
+
+/// Messages store generated audio by id, without the ".mp3" extension, so a file matches a
+/// reference by either its full name or its name without extension
+fn matches_reference(file_name: &str, reference: &str) -> bool {
+    file_name == reference
+        || Path::new(file_name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .is_some_and(|stem| stem == reference)
+}
+
+fn relative_path(data_dir: &Path, path: &Path) -> String {
+    path.strip_prefix(data_dir)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn write(path: &Path, content: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn setup_data_dir(data: &Path) {
+        write(
+            &data.join("characters/knight.json"),
+            r#"{"name": "Knight", "description": "", "personality": "", "background": ""}"#,
+        );
+        write(
+            &data.join("prompts/joke.json"),
+            r#"{"title": "Joke", "description": "", "context": "", "setup": []}"#,
+        );
+        write(
+            &data.join("chats/default/knight.json"),
+            r#"{"character": "Knight", "messages": [
+                {"text": ["hi"], "audio": ["kept", "gone"], "timestamp": "2025-10-01T00:00:00Z"}
+            ]}"#,
+        );
+        write(&data.join("chats/default/broken.json"), "{ not json");
+        write(
+            &data.join("personas/ada.json"),
+            r#"{"name": "Ada", "user": "Ghost"}"#,
+        );
+        write(&data.join("audio/knight/kept.mp3"), "mp3");
+        write(&data.join("audio/knight/stray.mp3"), "mp3");
+        write(&data.join("images/deleted/pic.png"), "png");
+        write(
+            &data.join("jobs/good.json"),
+            r#"{"id": null, "characters": ["Knight"], "prompts": ["Joke"],
+                "cadence": "0 0 9 * * *", "prompt-override": null}"#,
+        );
+        write(
+            &data.join("jobs/bad.json"),
+            r#"{"id": null, "characters": ["Knight"], "prompts": ["Missing"],
+                "cadence": "whenever", "prompt-override": null, "subscribers": ["Ghost"]}"#,
+        );
+    }
+
+    fn kinds(report: &FsckReport) -> Vec<(IssueKind, String)> {
+        report
+            .issues
+            .iter()
+            .map(|issue| (issue.kind, issue.path.clone()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_check_reports_problems() {
+        let dir = tempdir().unwrap();
+        let data = dir.path();
+        setup_data_dir(data);
+
+        let report = check_data_dir(data, false).await.unwrap();
+        let found = kinds(&report);
+
+        assert!(found.contains(&(
+            IssueKind::UnreadableFile,
+            "chats/default/broken.json".to_string()
+        )));
+        assert!(found.contains(&(IssueKind::OrphanMedia, "audio/knight/stray.mp3".to_string())));
+        assert!(found.contains(&(IssueKind::OrphanMediaFolder, "images/deleted".to_string())));
+        assert!(found.contains(&(
+            IssueKind::MissingMedia,
+            "chats/default/knight.json".to_string()
+        )));
+        assert!(found.contains(&(IssueKind::MissingReference, "jobs/bad.json".to_string())));
+        assert!(found.contains(&(IssueKind::InvalidCron, "jobs/bad.json".to_string())));
+        assert!(
+            report
+                .issues
+                .iter()
+                .any(|issue| issue.message.contains("missing subscriber 'Ghost'"))
+        );
+        assert!(found.contains(&(IssueKind::MissingReference, "personas/ada.json".to_string())));
+        assert!(!found.iter().any(|(_, path)| path == "jobs/good.json"));
+        assert!(
+            !found
+                .iter()
+                .any(|(_, path)| path == "audio/knight/kept.mp3")
+        );
+        assert!(report.has_errors());
+        assert_eq!(report.repaired, 0);
+
+        // A check without repair leaves everything in place
+        assert!(data.join("audio/knight/stray.mp3").exists());
+    }
+
+    #[tokio::test]
+    async fn test_repair_fixes_repairable_problems() {
+        let dir = tempdir().unwrap();
+        let data = dir.path();
+        setup_data_dir(data);
+
+        let report = check_data_dir(data, true).await.unwrap();
+
+        assert!(report.repaired > 0);
+        assert!(!data.join("audio/knight/stray.mp3").exists());
+        assert!(data.join("audio/knight/kept.mp3").exists());
+        assert!(!data.join("images/deleted").exists());
+        let job: Job =
+            serde_json::from_str(&std::fs::read_to_string(data.join("jobs/bad.json")).unwrap())
+                .unwrap();
+        assert!(!job.enabled);
+
+        // Only the problems that cannot be repaired remain
+        let report = check_data_dir(data, false).await.unwrap();
+        assert!(report.issues.iter().all(|issue| !issue.repairable));
+    }
+}
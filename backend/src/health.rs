@@ -0,0 +1,204 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Instant;
+use tokio::fs;
+use tokio::time::Duration;
+
+use crate::ai_services::{check_llm, check_tts};
+use crate::config::data_dir;
+use crate::job_scheduler::{TICK_INTERVAL, get_scheduler_heartbeat};
+use crate::models::Settings;
+use crate::settings::{load_settings, validate_settings};
+
+/// How long a dependency may take to answer before it is reported as down
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether a single component is working
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentStatus {
+    Up,
+    Down,
+}
+
+/// Overall readiness of the backend
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadinessStatus {
+    /// Every component is up
+    Ready,
+    /// Storytime itself works but the LLM or TTS backend is down, so jobs will fail
+    Degraded,
+    /// The data directory, settings or scheduler is broken
+    Down,
+}
+
+/// The result of checking one component
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ComponentHealth {
+    pub name: String,
+    pub status: ComponentStatus,
+    #[serde(rename = "latencyMs")]
+    pub latency_ms: u64,
+    /// Why the component is down, or a short detail such as the loaded model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// External services only degrade readiness; everything else takes it down
+    pub external: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReadinessReport {
+    pub status: ReadinessStatus,
+    pub components: Vec<ComponentHealth>,
+}
+
+impl ReadinessReport {
+    fn from_components(components: Vec<ComponentHealth>) -> Self {
+        let down = |external: bool| {
+            components
+                .iter()
+                .any(|c| c.external == external && c.status == ComponentStatus::Down)
+        };
+        let status = if down(false) {
+            ReadinessStatus::Down
+        } else if down(true) {
+            ReadinessStatus::Degraded
+        } else {
+            ReadinessStatus::Ready
+        };
+        Self { status, components }
+    }
+}
+
+/// Check every component the backend needs to do its work
+/// All checks run concurrently, so the slowest one bounds the total time
+pub async fn check_readiness(settings: &Settings) -> ReadinessReport {
+    let (data, settings_file, scheduler, llm, tts) = tokio::join!(
+        timed("dataDir", false, check_data_dir_writable(data_dir())),
+        timed("settings", false, check_settings()),
+        timed("scheduler", false, check_scheduler()),
+        timed("llm", true, check_llm(settings, CHECK_TIMEOUT)),
+        timed("tts", true, check_tts(settings, CHECK_TIMEOUT)),
+    );
+    ReadinessReport::from_components(vec![data, settings_file, scheduler, llm, tts])
+}
+
+/// Run a check and record how long it took
+async fn timed<E: std::fmt::Display>(
+    name: &str,
+    external: bool,
+    check: impl Future<Output = Result<Option<String>, E>>,
+) -> ComponentHealth {
+    let timer = Instant::now();
+    let result = check.await;
+    let latency_ms = timer.elapsed().as_millis() as u64;
+
+    let (status, message) = match result {
+        Ok(detail) => (ComponentStatus::Up, detail),
+        Err(e) => (ComponentStatus::Down, Some(e.to_string())),
+    };
+    if status == ComponentStatus::Down {
+        tracing::warn!("Health check for {} failed: {:?}", name, message);
+    }
+
+    ComponentHealth {
+        name: name.to_string(),
+        status,
+        latency_ms,
+        message,
+        external,
+    }
+}
+
+/// Write and remove a scratch file to prove the data directory accepts writes
+async fn check_data_dir_writable(data_dir: &Path) -> Result<Option<String>, std::io::Error> {
+    let probe = data_dir.join(format!(".health-check-{}", uuid::Uuid::new_v4()));
+    fs::write(&probe, b"ok").await?;
+    fs::remove_file(&probe).await?;
+    Ok(None)
+}
+
+/// Re-read settings.json so a broken hand edit shows up before the next reload
+async fn check_settings() -> Result<Option<String>, String> {
+    tokio::task::spawn_blocking(|| {
+        let settings = load_settings().map_err(|e| e.to_string())?;
+        validate_settings(&settings).map_err(|errors| errors.join("; "))?;
+        Ok(None)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// The scheduler loop wakes every tick, so a missed tick or two means it has stalled or died
+async fn check_scheduler() -> Result<Option<String>, String> {
+    let (is_running, last_tick) = get_scheduler_heartbeat().await.map_err(|e| e.to_string())?;
+    if !is_running {
+        return Err("Scheduler is not running".to_string());
+    }
+
+    let last_tick = last_tick.ok_or("Scheduler loop has not started")?;
+    let since = (Utc::now() - last_tick).to_std().unwrap_or_default();
+    if since > TICK_INTERVAL * 2 + CHECK_TIMEOUT {
+        return Err(format!("Scheduler loop last ran {}s ago", since.as_secs()));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn component(name: &str, external: bool, status: ComponentStatus) -> ComponentHealth {
+        ComponentHealth {
+            name: name.to_string(),
+            status,
+            latency_ms: 0,
+            message: None,
+            external,
+        }
+    }
+
+    #[test]
+    fn test_readiness_status() {
+        let report = ReadinessReport::from_components(vec![
+            component("dataDir", false, ComponentStatus::Up),
+            component("llm", true, ComponentStatus::Up),
+        ]);
+        assert_eq!(report.status, ReadinessStatus::Ready);
+
+        let report = ReadinessReport::from_components(vec![
+            component("dataDir", false, ComponentStatus::Up),
+            component("llm", true, ComponentStatus::Down),
+        ]);
+        assert_eq!(report.status, ReadinessStatus::Degraded);
+
+        let report = ReadinessReport::from_components(vec![
+            component("dataDir", false, ComponentStatus::Down),
+            component("llm", true, ComponentStatus::Down),
+        ]);
+        assert_eq!(report.status, ReadinessStatus::Down);
+    }
+
+    #[tokio::test]
+    async fn test_data_dir_check() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_data_dir_writable(dir.path()).await.is_ok());
+        // The probe file is cleaned up
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        assert!(
+            check_data_dir_writable(&dir.path().join("missing"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timed_reports_failures() {
+        let health = timed("llm", true, async { Err::<Option<String>, _>("refused") }).await;
+        assert_eq!(health.status, ComponentStatus::Down);
+        assert_eq!(health.message.as_deref(), Some("refused"));
+    }
+}
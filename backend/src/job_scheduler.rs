@@ -1,14 +1,23 @@
 use chrono::{DateTime, Utc};
 use cron::Schedule;
+use serde_json::Value;
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::{Arc, OnceLock};
 use tokio::sync::{Mutex, broadcast};
 use tokio::time::{Duration, sleep};
+use tokio_util::task::TaskTracker;
 use tracing::{error, info, warn};
 
-use crate::controllers::job_controller::{load_all_jobs, run_job_internal};
+use crate::controllers::job_controller::{job_recipients, load_all_jobs, run_job_with_payload};
+use crate::error::AppError;
+use crate::events::{Event, events};
+use crate::metrics::metrics;
 use crate::models::{Job, Settings};
+use crate::settings::SharedSettings;
+
+/// How often the scheduler loop wakes up to run due jobs
+pub const TICK_INTERVAL: Duration = Duration::from_secs(60);
 
 /// A scheduled job with its cron schedule and metadata
 #[derive(Debug, Clone)]
@@ -22,24 +31,30 @@ pub struct ScheduledJob {
 /// Job scheduler that manages and executes cron jobs
 pub struct JobScheduler {
     scheduled_jobs: Arc<Mutex<HashMap<String, ScheduledJob>>>,
-    settings: Arc<Settings>,
+    settings: SharedSettings,
     shutdown_tx: Option<broadcast::Sender<()>>,
     is_running: Arc<Mutex<bool>>,
+    /// When the scheduler loop last woke up, so a stalled or crashed loop can be detected
+    last_tick: Arc<Mutex<Option<DateTime<Utc>>>>,
+    /// Job runs started by the scheduler that have not finished yet
+    in_flight: TaskTracker,
 }
 
 impl JobScheduler {
     /// Create a new job scheduler
-    pub fn new(settings: Arc<Settings>) -> Self {
+    pub fn new(settings: SharedSettings) -> Self {
         Self {
             scheduled_jobs: Arc::new(Mutex::new(HashMap::new())),
             settings,
             shutdown_tx: None,
             is_running: Arc::new(Mutex::new(false)),
+            last_tick: Arc::new(Mutex::new(None)),
+            in_flight: TaskTracker::new(),
         }
     }
 
     /// Start the job scheduler
-    pub async fn start(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    pub async fn start(&mut self) -> Result<(), AppError> {
         let mut is_running = self.is_running.lock().await;
         if *is_running {
             warn!("Job scheduler is already running");
@@ -47,6 +62,7 @@ impl JobScheduler {
         }
         *is_running = true;
         drop(is_running);
+        self.in_flight.reopen();
 
         info!("Starting job scheduler");
 
@@ -58,22 +74,26 @@ impl JobScheduler {
         self.shutdown_tx = Some(shutdown_tx);
 
         let scheduled_jobs = Arc::clone(&self.scheduled_jobs);
-        let settings = Arc::clone(&self.settings);
+        let settings = self.settings.clone();
         let is_running = Arc::clone(&self.is_running);
+        let last_tick = Arc::clone(&self.last_tick);
+        let in_flight = self.in_flight.clone();
 
         // Start the scheduler loop in a background task
         tokio::spawn(async move {
             info!("Job scheduler loop started");
 
             loop {
+                *last_tick.lock().await = Some(Utc::now());
+
                 tokio::select! {
                     _ = shutdown_rx.recv() => {
                         info!("Job scheduler received shutdown signal");
                         break;
                     }
-                    _ = sleep(Duration::from_secs(60)) => {
+                    _ = sleep(TICK_INTERVAL) => {
                         // Check for jobs to run every minute
-                        if let Err(e) = Self::check_and_run_jobs(&scheduled_jobs, &settings).await {
+                        if let Err(e) = Self::check_and_run_jobs(&scheduled_jobs, &settings, &in_flight).await {
                             error!("Error checking and running jobs: {}", e);
                         }
                     }
@@ -90,34 +110,45 @@ impl JobScheduler {
     }
 
     /// Stop the job scheduler
-    pub async fn stop(&mut self) {
+    /// No new jobs are started, and jobs already running get up to `timeout` to finish.
+    /// Returns false if some jobs were still running when the timeout expired
+    pub async fn stop(&mut self, timeout: Duration) -> bool {
+        self.begin_stop().wait(timeout).await
+    }
+
+    /// Stop starting jobs, leaving the running ones to be waited for with [`Stopping::wait`]
+    /// Nothing in the returned handle borrows the scheduler, so it can be waited for without
+    /// holding the scheduler's lock
+    fn begin_stop(&mut self) -> Stopping {
         if let Some(ref shutdown_tx) = self.shutdown_tx {
             let _ = shutdown_tx.send(());
         }
-
-        // Wait a moment for the scheduler to stop gracefully
-        sleep(Duration::from_millis(100)).await;
-
-        let mut is_running = self.is_running.lock().await;
-        *is_running = false;
-
-        info!("Job scheduler stop requested");
+        self.in_flight.close();
+        Stopping {
+            in_flight: self.in_flight.clone(),
+            is_running: Arc::clone(&self.is_running),
+        }
     }
 
     /// Load all jobs from disk and schedule them
-    async fn load_and_schedule_jobs(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    async fn load_and_schedule_jobs(&self) -> Result<(), AppError> {
         let jobs = load_all_jobs().await?;
         let mut scheduled_jobs = self.scheduled_jobs.lock().await;
 
         scheduled_jobs.clear();
 
         for job in jobs {
+            if !job.enabled {
+                info!("Skipping disabled job {:?}", job.id);
+                continue;
+            }
             if let Err(e) = self.schedule_job_internal(&mut scheduled_jobs, job).await {
                 error!("Failed to schedule job: {}", e);
             }
         }
 
         info!("Loaded and scheduled {} jobs", scheduled_jobs.len());
+        metrics().scheduled_jobs.set(scheduled_jobs.len() as i64);
         Ok(())
     }
 
@@ -126,16 +157,17 @@ impl JobScheduler {
         &self,
         scheduled_jobs: &mut HashMap<String, ScheduledJob>,
         job: Job,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<(), AppError> {
         // Parse the cron expression
-        let schedule = Schedule::from_str(&job.cadence)
-            .map_err(|e| format!("Invalid cron expression '{}': {}", job.cadence, e))?;
+        let schedule = Schedule::from_str(&job.cadence).map_err(|e| {
+            AppError::Validation(format!("Invalid cron expression '{}': {}", job.cadence, e))
+        })?;
 
         // Calculate next run time
         let next_run = schedule
             .upcoming(Utc)
             .next()
-            .ok_or("Could not calculate next run time")?;
+            .ok_or_else(|| AppError::Validation("Could not calculate next run time".to_string()))?;
 
         // Use the job's UUID as the key, or generate a fallback slug for compatibility
         let job_key = match job.id {
@@ -168,8 +200,9 @@ impl JobScheduler {
     /// Check for jobs that need to run and execute them
     async fn check_and_run_jobs(
         scheduled_jobs: &Arc<Mutex<HashMap<String, ScheduledJob>>>,
-        settings: &Arc<Settings>,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        settings: &SharedSettings,
+        in_flight: &TaskTracker,
+    ) -> Result<(), AppError> {
         let now = Utc::now();
         let mut jobs_to_run = Vec::new();
         // Collect jobs that need to run
@@ -178,6 +211,9 @@ impl JobScheduler {
             for (job_key, scheduled_job) in jobs.iter_mut() {
                 if scheduled_job.next_run <= now {
                     info!("Job '{}' is due for execution", job_key);
+                    // Jobs are only checked once per tick, so up to a tick of lag is normal
+                    let lag = (now - scheduled_job.next_run).num_milliseconds() as f64 / 1000.0;
+                    metrics().scheduler_lag.observe(lag);
                     jobs_to_run.push(scheduled_job.job.clone());
 
                     // Update next run time
@@ -193,21 +229,14 @@ impl JobScheduler {
 
         // Execute jobs outside the lock
         for job in jobs_to_run {
-            let settings_clone = Arc::clone(settings);
+            // Each run uses the settings in effect when it starts
+            let settings_clone = settings.current();
 
             // Run job in a separate task to avoid blocking the scheduler
-            tokio::spawn(async move {
-                match run_job_internal(job, settings_clone, true).await {
-                    Ok(response) => {
-                        info!("Job executed successfully: {}", response.0.message);
-                    }
-                    Err((status, error_response)) => {
-                        error!(
-                            "Job execution failed with status {}: {}",
-                            status, error_response.0.message
-                        );
-                    }
-                }
+            metrics().jobs_in_flight.inc();
+            in_flight.spawn(async move {
+                run_for_recipients(job, settings_clone, None).await;
+                metrics().jobs_in_flight.dec();
             });
         }
 
@@ -215,9 +244,13 @@ impl JobScheduler {
     }
 
     /// Reload jobs from disk (useful for when jobs are added/updated)
-    pub async fn reload_jobs(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    pub async fn reload_jobs(&self) -> Result<(), AppError> {
         info!("Reloading jobs from disk");
-        self.load_and_schedule_jobs().await
+        self.load_and_schedule_jobs().await?;
+        events().publish(Event::SchedulerReloaded {
+            jobs: self.scheduled_jobs.lock().await.len(),
+        });
+        Ok(())
     }
 
     /// Get information about scheduled jobs
@@ -230,48 +263,293 @@ impl JobScheduler {
     pub async fn is_running(&self) -> bool {
         *self.is_running.lock().await
     }
+
+    /// When the scheduler loop last woke up, or None if it has never run
+    pub async fn last_tick(&self) -> Option<DateTime<Utc>> {
+        *self.last_tick.lock().await
+    }
+}
+
+/// A scheduler that no longer starts jobs, with the ones already running still to finish
+struct Stopping {
+    in_flight: TaskTracker,
+    is_running: Arc<Mutex<bool>>,
+}
+
+impl Stopping {
+    /// Give running jobs up to `timeout` to finish, returning false if some are still running
+    async fn wait(self, timeout: Duration) -> bool {
+        let running = self.in_flight.len();
+        if running > 0 {
+            info!(
+                "Waiting up to {:?} for {} running jobs to finish",
+                timeout, running
+            );
+        }
+        let drained = tokio::time::timeout(timeout, self.in_flight.wait())
+            .await
+            .is_ok();
+        if !drained {
+            warn!(
+                "{} jobs still running after {:?}, abandoning them",
+                self.in_flight.len(),
+                timeout
+            );
+        }
+
+        let mut is_running = self.is_running.lock().await;
+        *is_running = false;
+
+        info!("Job scheduler stop complete");
+        drained
+    }
+}
+
+/// Stop a shared scheduler, holding its lock only while telling it to stop
+/// Jobs that are started meanwhile, such as by hooks, see it closed and are turned away
+/// rather than waiting for the lock until the drain is over
+async fn stop_shared(scheduler: &Mutex<JobScheduler>, timeout: Duration) -> bool {
+    let stopping = scheduler.lock().await.begin_stop();
+    stopping.wait(timeout).await
 }
 
 /// Global job scheduler instance using OnceLock for thread safety
 static SCHEDULER: OnceLock<Arc<Mutex<JobScheduler>>> = OnceLock::new();
 
 /// Start the global job scheduler
-pub async fn start_scheduler(
-    settings: Arc<Settings>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+pub async fn start_scheduler(settings: SharedSettings) -> Result<(), AppError> {
     let scheduler = SCHEDULER.get_or_init(|| Arc::new(Mutex::new(JobScheduler::new(settings))));
 
     let mut scheduler_lock = scheduler.lock().await;
     scheduler_lock.start().await
 }
 
-/// Stop the global job scheduler
-pub async fn stop_scheduler() {
+/// Stop the global job scheduler, waiting up to `timeout` for running jobs to finish
+/// Returns false if some jobs were still running when the timeout expired
+pub async fn stop_scheduler(timeout: Duration) -> bool {
     if let Some(scheduler) = SCHEDULER.get() {
-        let mut scheduler_lock = scheduler.lock().await;
-        scheduler_lock.stop().await;
+        stop_shared(scheduler, timeout).await
+    } else {
+        true
     }
 }
 
 /// Reload jobs in the global scheduler
-pub async fn reload_jobs() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+pub async fn reload_jobs() -> Result<(), AppError> {
     if let Some(scheduler) = SCHEDULER.get() {
         let scheduler_lock = scheduler.lock().await;
         scheduler_lock.reload_jobs().await
     } else {
-        Err("Scheduler not initialized".into())
+        Err(AppError::Unavailable(
+            "Scheduler not initialized".to_string(),
+        ))
     }
 }
 
 /// Get scheduler status
-pub async fn get_scheduler_info()
--> Result<(bool, Vec<ScheduledJob>), Box<dyn std::error::Error + Send + Sync>> {
+pub async fn get_scheduler_info() -> Result<(bool, Vec<ScheduledJob>), AppError> {
     if let Some(scheduler) = SCHEDULER.get() {
         let scheduler_lock = scheduler.lock().await;
         let is_running = scheduler_lock.is_running().await;
         let scheduled_jobs = scheduler_lock.get_scheduled_jobs().await;
         Ok((is_running, scheduled_jobs))
     } else {
-        Err("Scheduler not initialized".into())
+        Err(AppError::Unavailable(
+            "Scheduler not initialized".to_string(),
+        ))
+    }
+}
+
+/// Get whether the scheduler is running and when its loop last woke up
+pub async fn get_scheduler_heartbeat() -> Result<(bool, Option<DateTime<Utc>>), AppError> {
+    if let Some(scheduler) = SCHEDULER.get() {
+        let scheduler_lock = scheduler.lock().await;
+        Ok((
+            scheduler_lock.is_running().await,
+            scheduler_lock.last_tick().await,
+        ))
+    } else {
+        Err(AppError::Unavailable(
+            "Scheduler not initialized".to_string(),
+        ))
+    }
+}
+
+/// Run a job straight away in the background, outside its schedule
+/// The run is tracked like a scheduled one, so a graceful shutdown waits for it
+pub async fn run_job_now(
+    job: Job,
+    settings: Arc<Settings>,
+    payload: Option<Value>,
+) -> Result<(), AppError> {
+    spawn_tracked(async move {
+        metrics().jobs_in_flight.inc();
+        run_for_recipients(job, settings, payload).await;
+        metrics().jobs_in_flight.dec();
+    })
+    .await
+}
+
+/// Run work that writes to chats in the background, tracked like a job run so a graceful
+/// shutdown waits for it
+/// Fails without running it once the scheduler is stopping
+pub async fn spawn_tracked<F>(task: F) -> Result<(), AppError>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let Some(scheduler) = SCHEDULER.get() else {
+        return Err(AppError::Unavailable(
+            "Scheduler not initialized".to_string(),
+        ));
+    };
+    let in_flight = scheduler.lock().await.in_flight.clone();
+    if in_flight.is_closed() {
+        return Err(AppError::Unavailable(
+            "Scheduler is shutting down".to_string(),
+        ));
+    }
+    in_flight.spawn(task);
+    Ok(())
+}
+
+/// Run a job once for each user it writes to, one after another so the LLM backend is not
+/// flooded by a job with many subscribers
+async fn run_for_recipients(job: Job, settings: Arc<Settings>, payload: Option<Value>) {
+    let recipients = match job_recipients(&job).await {
+        Ok(recipients) => recipients,
+        Err(e) => {
+            error!("Failed to find the users to run a job for: {}", e);
+            return;
+        }
+    };
+
+    for user in recipients {
+        match run_job_with_payload(
+            job.clone(),
+            Arc::clone(&settings),
+            true,
+            &user,
+            payload.as_ref(),
+        )
+        .await
+        {
+            Ok(response) => {
+                info!(
+                    "Job executed successfully for '{}': {}",
+                    user, response.0.message
+                );
+            }
+            Err(e) => {
+                error!(
+                    "Job execution for '{}' failed with status {}: {}",
+                    user,
+                    e.status(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{EventBus, next_event};
+
+    fn test_scheduler() -> JobScheduler {
+        let settings =
+            serde_json::from_str(r#"{"ttsApi": "http://tts", "llmApi": "http://llm"}"#).unwrap();
+        JobScheduler::new(SharedSettings::new(settings))
+    }
+
+    #[tokio::test]
+    async fn test_stop_waits_for_running_jobs() {
+        let mut scheduler = test_scheduler();
+        let finished = Arc::new(Mutex::new(false));
+        let job_finished = Arc::clone(&finished);
+        scheduler.in_flight.spawn(async move {
+            sleep(Duration::from_millis(50)).await;
+            *job_finished.lock().await = true;
+        });
+
+        assert!(scheduler.stop(Duration::from_secs(5)).await);
+        assert!(*finished.lock().await);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_tracked_without_scheduler_is_unavailable() {
+        let error = spawn_tracked(async {}).await.unwrap_err();
+        assert!(matches!(error, AppError::Unavailable(_)));
+        assert_eq!(error.status(), axum::http::StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_stop_gives_up_after_timeout() {
+        let mut scheduler = test_scheduler();
+        scheduler.in_flight.spawn(sleep(Duration::from_secs(60)));
+
+        assert!(!scheduler.stop(Duration::from_millis(20)).await);
+        assert!(!scheduler.is_running().await);
+    }
+
+    #[tokio::test]
+    async fn test_events_of_jobs_finishing_during_shutdown_are_dispatched() {
+        let bus = Arc::new(EventBus::new());
+        let (mut receiver, closed) = bus.subscribe();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let dispatched = Arc::clone(&received);
+        bus.spawn(async move {
+            while let Some(event) = next_event(&mut receiver, &closed).await {
+                dispatched.lock().await.push(event.unwrap());
+            }
+        });
+
+        let mut scheduler = test_scheduler();
+        let job_bus = Arc::clone(&bus);
+        scheduler.in_flight.spawn(async move {
+            sleep(Duration::from_millis(50)).await;
+            job_bus.publish(Event::JobFinished {
+                job: None,
+                user: "default".to_string(),
+                character: "Knight".to_string(),
+                success: true,
+                error: None,
+            });
+        });
+
+        // The order `serve` shuts down in
+        bus.end_client_streams();
+        assert!(scheduler.stop(Duration::from_secs(5)).await);
+        assert!(bus.close(Duration::from_secs(5)).await);
+
+        let received = received.lock().await;
+        assert!(matches!(
+            received.as_slice(),
+            [Event::JobFinished { success: true, .. }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_lock_is_released_while_draining() {
+        let scheduler = Arc::new(Mutex::new(test_scheduler()));
+        scheduler
+            .lock()
+            .await
+            .in_flight
+            .spawn(sleep(Duration::from_millis(200)));
+
+        let stopping = Arc::clone(&scheduler);
+        let stop =
+            tokio::spawn(async move { stop_shared(&stopping, Duration::from_secs(5)).await });
+        sleep(Duration::from_millis(20)).await;
+
+        let in_flight = tokio::time::timeout(Duration::from_millis(50), scheduler.lock())
+            .await
+            .expect("the lock is held while jobs drain")
+            .in_flight
+            .clone();
+        assert!(in_flight.is_closed());
+        assert!(!in_flight.is_empty());
+        assert!(stop.await.unwrap());
     }
 }
@@ -1,12 +1,31 @@
 pub mod ai_services;
+pub mod auth;
+pub mod backup;
+pub mod character_card;
 pub mod chatml;
+pub mod cli;
+pub mod config;
 pub mod controllers;
+pub mod email;
+pub mod error;
+pub mod events;
+pub mod fsck;
+pub mod health;
 pub mod job_scheduler;
 pub mod llm_prompt;
+pub mod media;
+pub mod media_store;
+pub mod metrics;
+pub mod migrations;
 pub mod models;
+pub mod mqtt;
+pub mod podcast;
+pub mod push;
 pub mod routes;
 pub mod settings;
+pub mod telegram;
 pub mod utils;
+pub mod webhooks;
 
 pub use routes::create_app;
 pub use settings::load_settings;
@@ -1,45 +1,158 @@
+use regex::{Captures, Regex};
+use serde_json::Value;
+
 use crate::chatml::ChatMLPrompt;
-use crate::models::{Character, Chat, Job, Prompt};
+use crate::models::{Character, Chat, Job, Persona, Prompt};
+use crate::utils::character_slug;
+
+/// How prompt builders that have no recipient refer to the user
+const UNNAMED_USER: &str = "User";
+
+/// Latest chat messages a prompt includes, so a long chat does not overflow the LLM's context
+const HISTORY_MESSAGES: usize = 20;
+
+/// Who a message is written for
+pub struct Recipient {
+    /// What `{{user}}` expands to: the persona's name, or the user's if they have no persona
+    pub name: String,
+    pub persona: Option<Persona>,
+}
+
+impl Recipient {
+    pub fn new(user: &str, persona: Option<Persona>) -> Self {
+        Self {
+            name: persona
+                .as_ref()
+                .map_or(user, |persona| persona.name.as_str())
+                .to_string(),
+            persona,
+        }
+    }
+}
+
+/// Builds the system message describing the character and the prompt context
+fn character_system_message(
+    character: &Character,
+    prompt: &Prompt,
+    recipient: &Recipient,
+) -> String {
+    let user = recipient.name.as_str();
+    let mut system_message = format!(
+        "You are {}, described as: {}\n\
+         Personality: {}\n\
+         Background: {}\n\n\
+         Context: {}",
+        character.name,
+        expand_card_macros(&character.description, character, user),
+        expand_card_macros(&character.personality, character, user),
+        expand_card_macros(&character.background, character, user),
+        expand_card_macros(&prompt.context, character, user)
+    );
+
+    if !character.example_dialogue.trim().is_empty() {
+        system_message.push_str(&format!(
+            "\n\nExample dialogue:\n{}",
+            expand_card_macros(&character.example_dialogue, character, user)
+        ));
+    }
+
+    if let Some(persona) = &recipient.persona {
+        system_message.push_str(&persona_description(persona, character));
+    }
+
+    system_message
+}
+
+/// Describes who the character is talking to, including how they know each other
+fn persona_description(persona: &Persona, character: &Character) -> String {
+    let mut description = format!("\n\nYou are talking to {}", persona.name);
+    if !persona.pronouns.trim().is_empty() {
+        description.push_str(&format!(" ({})", persona.pronouns.trim()));
+    }
+    if !persona.description.trim().is_empty() {
+        description.push_str(&format!(", described as: {}", persona.description.trim()));
+    }
+    if !persona.interests.is_empty() {
+        description.push_str(&format!("\nInterests: {}", persona.interests.join(", ")));
+    }
+
+    let slug = character_slug(&character.name);
+    if let Some(relationship) = persona
+        .relationships
+        .iter()
+        .find(|(name, _)| character_slug(name) == slug)
+        .map(|(_, relationship)| relationship.trim())
+        .filter(|relationship| !relationship.is_empty())
+    {
+        description.push_str(&format!(
+            "\nRelationship to you: {}",
+            expand_card_macros(relationship, character, &persona.name)
+        ));
+    }
+
+    description
+}
+
+/// Opens the conversation with the character's greeting, if it has one
+fn add_first_message(chatml_prompt: &mut ChatMLPrompt, character: &Character, user: &str) {
+    if !character.first_message.trim().is_empty() {
+        chatml_prompt.add_assistant(expand_card_macros(
+            &character.first_message,
+            character,
+            user,
+        ));
+    }
+}
+
+/// Expands the `{{char}}` and `{{user}}` placeholders used by imported character cards and
+/// prompts, where `user` is the name of the user the message is for
+fn expand_card_macros(text: &str, character: &Character, user: &str) -> String {
+    text.replace("{{char}}", &character.name)
+        .replace("{{user}}", user)
+}
+
+/// Expands `{{payload}}` to the whole body a job was triggered with, as JSON, and
+/// `{{payload.some.field}}` to one field of it, where array elements are picked by index
+/// Strings are inserted as they are and other values as JSON; missing fields expand to nothing
+pub fn expand_payload_variables(text: &str, payload: &Value) -> String {
+    let re = Regex::new(r"\{\{\s*payload((?:\.[^.{}\s]+)*)\s*\}\}").unwrap();
+    re.replace_all(text, |captures: &Captures| {
+        let pointer = captures[1].replace('.', "/");
+        match payload.pointer(&pointer) {
+            Some(Value::String(value)) => value.clone(),
+            Some(Value::Null) | None => String::new(),
+            Some(value) => value.to_string(),
+        }
+    })
+    .into_owned()
+}
 
 /// Builds a ChatML prompt for a single setup item
 /// Used for iterative LLM calls where each setup item results in a separate call
 /// Returns a ChatMLPrompt structure that can be converted to string or used for parsing responses
+/// `{{user}}` in the character and prompt is replaced with the recipient's name, and their persona
+/// is described in the system message
 pub fn build_setup_item_chatml_prompt(
     character: &Character,
     prompt: &Prompt,
     setup_item: &str,
     previous_responses: &[ChatMLPrompt],
     chat_history: &Chat,
+    recipient: &Recipient,
 ) -> ChatMLPrompt {
+    let user = recipient.name.as_str();
     let mut chatml_prompt = ChatMLPrompt::new();
 
     // System message with character information and context
-    let system_message = format!(
-        "You are {}, described as: {}\n\
-         Personality: {}\n\
-         Background: {}\n\n\
-         Context: {}",
-        character.name,
-        character.description,
-        character.personality,
-        character.background,
-        prompt.context
-    );
+    chatml_prompt.add_system(character_system_message(character, prompt, recipient));
+    add_first_message(&mut chatml_prompt, character, user);
 
-    chatml_prompt.add_system(system_message);
-
-    // Add previous chat context if available
-    for message in &chat_history.messages {
-        for text in &message.text {
-            chatml_prompt.add_user(text);
-            chatml_prompt.add_assistant(format!("*{} responds*", character.name));
-        }
-    }
+    add_chat_history(&mut chatml_prompt, chat_history);
 
     // Add previous setup responses to build conversation context
     for (i, prev_response) in previous_responses.iter().enumerate() {
         if i < prompt.setup.len() - 1 {
-            chatml_prompt.add_user(&prompt.setup[i]);
+            chatml_prompt.add_user(expand_card_macros(&prompt.setup[i], character, user));
             // Get the last assistant response from the previous ChatML prompt
             if let Some(last_assistant_msg) = prev_response.last_assistant_message() {
                 chatml_prompt.add_assistant(last_assistant_msg);
@@ -48,11 +161,24 @@ pub fn build_setup_item_chatml_prompt(
     }
 
     // Add the current setup item as user input
-    chatml_prompt.add_user(setup_item);
+    chatml_prompt.add_user(expand_card_macros(setup_item, character, user));
 
     chatml_prompt
 }
 
+/// Add the latest messages of a chat, each in the role of whoever sent it
+fn add_chat_history(chatml_prompt: &mut ChatMLPrompt, chat: &Chat) {
+    let earlier = chat.messages.len().saturating_sub(HISTORY_MESSAGES);
+    for message in &chat.messages[earlier..] {
+        let text = message.text.join("\n");
+        if message.from_user {
+            chatml_prompt.add_user(text);
+        } else {
+            chatml_prompt.add_assistant(text);
+        }
+    }
+}
+
 /// Builds a prompt for a single setup item using ChatML format (string version)
 /// Used for iterative LLM calls where each setup item results in a separate call
 pub fn build_setup_item_prompt(
@@ -65,28 +191,15 @@ pub fn build_setup_item_prompt(
     let mut chatml_prompt = ChatMLPrompt::new();
 
     // System message with character information and context
-    let system_message = format!(
-        "You are {}, described as: {}\n\
-         Personality: {}\n\
-         Background: {}\n\n\
-         Context: {}",
-        character.name,
-        character.description,
-        character.personality,
-        character.background,
-        prompt.context
-    );
-
-    chatml_prompt.add_system(system_message);
+    chatml_prompt.add_system(character_system_message(
+        character,
+        prompt,
+        &Recipient::new(UNNAMED_USER, None),
+    ));
+    add_first_message(&mut chatml_prompt, character, UNNAMED_USER);
 
-    // Add previous chat context if available
     if let Some(chat) = chat_history {
-        for message in &chat.messages {
-            for text in &message.text {
-                chatml_prompt.add_user(text);
-                chatml_prompt.add_assistant(format!("*{} responds*", character.name));
-            }
-        }
+        add_chat_history(&mut chatml_prompt, chat);
     }
 
     // Add previous setup responses to build conversation context
@@ -114,31 +227,15 @@ pub fn build_job_prompt(
     let mut chatml_prompt = ChatMLPrompt::new();
 
     // System message with character information and context
-    let system_message = format!(
-        "You are {}, described as: {}\n\
-         Personality: {}\n\
-         Background: {}\n\n\
-         Context: {}",
-        character.name,
-        character.description,
-        character.personality,
-        character.background,
-        prompt.context
-    );
-
-    chatml_prompt.add_system(system_message);
+    chatml_prompt.add_system(character_system_message(
+        character,
+        prompt,
+        &Recipient::new(UNNAMED_USER, None),
+    ));
+    add_first_message(&mut chatml_prompt, character, UNNAMED_USER);
 
-    // Add previous chat context if available
     if let Some(chat) = chat_history {
-        for message in &chat.messages {
-            // Add user messages (assuming they're from the "user" role)
-            for text in &message.text {
-                chatml_prompt.add_user(text);
-                // For now, we'll add a simple assistant acknowledgment
-                // In a real system, you'd want to track the actual conversation flow
-                chatml_prompt.add_assistant(format!("*{} responds*", character.name));
-            }
-        }
+        add_chat_history(&mut chatml_prompt, chat);
     }
 
     // Add prompt setup items as a conversation flow
@@ -169,11 +266,17 @@ pub fn build_simple_prompt(_job: &Job, character: &Character, prompt: &Prompt) -
         "You are {}. {}\n\
          Personality: {}\n\
          Background: {}\n\n",
-        character.name, character.description, character.personality, character.background
+        character.name,
+        expand_card_macros(&character.description, character, UNNAMED_USER),
+        expand_card_macros(&character.personality, character, UNNAMED_USER),
+        expand_card_macros(&character.background, character, UNNAMED_USER)
     ));
 
     // Context
-    prompt_text.push_str(&format!("Context: {}\n\n", prompt.context));
+    prompt_text.push_str(&format!(
+        "Context: {}\n\n",
+        expand_card_macros(&prompt.context, character, UNNAMED_USER)
+    ));
 
     // Setup items as conversation
     if !prompt.setup.is_empty() {
@@ -207,6 +310,8 @@ mod tests {
                 speed_factor: 1.0,
                 voice_name: "test.wav".to_string(),
             }),
+            first_message: String::new(),
+            example_dialogue: String::new(),
         }
     }
 
@@ -232,6 +337,9 @@ mod tests {
             prompts: vec!["Test Prompt".to_string()],
             cadence: "daily".to_string(),
             prompt_override: None,
+            enabled: true,
+            subscribers: vec![],
+            trigger_hash: None,
         }
     }
 
@@ -288,4 +396,217 @@ mod tests {
         assert!(result.contains("Hello there!")); // First setup item
         assert!(result.contains("Hello!")); // First response
     }
+
+    #[test]
+    fn test_card_fields_in_chatml_prompt() {
+        let mut character = create_test_character();
+        character.first_message = "Well met, {{user}}!".to_string();
+        character.example_dialogue = "<START>\n{{user}}: Hi\n{{char}}: Hail!".to_string();
+        let prompt = create_test_prompt();
+
+        let result = build_setup_item_chatml_prompt(
+            &character,
+            &prompt,
+            "Tell me a joke",
+            &[],
+            &Chat {
+                character: character.name.clone(),
+                messages: vec![],
+                muted: false,
+            },
+            &Recipient::new("User", None),
+        );
+
+        let system = &result.messages[0];
+        assert!(system.content.contains("Example dialogue:"));
+        assert!(system.content.contains("Test Knight: Hail!"));
+        assert_eq!(result.messages[1].role, "assistant");
+        assert_eq!(result.messages[1].content, "Well met, User!");
+    }
+
+    #[test]
+    fn test_chat_history_keeps_roles() {
+        let character = create_test_character();
+        let prompt = create_test_prompt();
+        let message = |text: &str, from_user| crate::models::Message {
+            text: vec![text.to_string()],
+            audio: vec![],
+            images: vec![],
+            read: true,
+            timestamp: chrono::Utc::now(),
+            from_user,
+        };
+        let mut messages = vec![message("Too old to be included", false)];
+        messages.extend((1..HISTORY_MESSAGES).map(|i| message(&format!("Story {i}"), false)));
+        messages.push(message("Tell me about the dragon", true));
+
+        let result = build_setup_item_chatml_prompt(
+            &character,
+            &prompt,
+            "Answer them",
+            &[],
+            &Chat {
+                character: character.name.clone(),
+                messages,
+                muted: false,
+            },
+            &Recipient::new("User", None),
+        );
+
+        let start = result
+            .messages
+            .iter()
+            .position(|message| message.content == "Story 1")
+            .unwrap();
+        let history = &result.messages[start..start + HISTORY_MESSAGES];
+        assert_eq!(history[0].role, "assistant");
+        assert_eq!(history[0].content, "Story 1");
+        assert_eq!(history[HISTORY_MESSAGES - 1].role, "user");
+        assert_eq!(
+            history[HISTORY_MESSAGES - 1].content,
+            "Tell me about the dragon"
+        );
+        assert!(
+            result
+                .messages
+                .iter()
+                .all(|message| message.content != "Too old to be included")
+        );
+    }
+
+    #[test]
+    fn test_card_macros_in_character_fields() {
+        let mut character = create_test_character();
+        character.description = "{{char}} guards {{user}}'s castle".to_string();
+        character.personality = "Loyal to {{user}}".to_string();
+        character.background = "{{char}} was knighted by {{user}}".to_string();
+        let prompt = create_test_prompt();
+
+        let result = build_setup_item_chatml_prompt(
+            &character,
+            &prompt,
+            "Tell me a joke",
+            &[],
+            &Chat {
+                character: character.name.clone(),
+                messages: vec![],
+                muted: false,
+            },
+            &Recipient::new("Ada", None),
+        );
+        let system = &result.messages[0].content;
+        assert!(system.contains("described as: Test Knight guards Ada's castle"));
+        assert!(system.contains("Personality: Loyal to Ada"));
+        assert!(system.contains("Background: Test Knight was knighted by Ada"));
+        assert!(!system.contains("{{"));
+
+        let simple = build_simple_prompt(&create_test_job(), &character, &prompt);
+        assert!(simple.contains("Test Knight guards User's castle"));
+        assert!(!simple.contains("{{"));
+    }
+
+    #[test]
+    fn test_recipient_named_in_chatml_prompt() {
+        let mut character = create_test_character();
+        character.first_message = "Well met, {{user}}!".to_string();
+        let mut prompt = create_test_prompt();
+        prompt.context = "{{char}} is writing to {{user}}".to_string();
+
+        let result = build_setup_item_chatml_prompt(
+            &character,
+            &prompt,
+            "Wish {{user}} good morning",
+            &[],
+            &Chat {
+                character: character.name.clone(),
+                messages: vec![],
+                muted: false,
+            },
+            &Recipient::new("Ada", None),
+        );
+
+        assert!(
+            result.messages[0]
+                .content
+                .contains("Context: Test Knight is writing to Ada")
+        );
+        assert_eq!(result.messages[1].content, "Well met, Ada!");
+        assert_eq!(
+            result.messages.last().unwrap().content,
+            "Wish Ada good morning"
+        );
+    }
+
+    #[test]
+    fn test_persona_in_chatml_prompt() {
+        let character = create_test_character();
+        let mut prompt = create_test_prompt();
+        prompt.context = "{{char}} texts {{user}}".to_string();
+        let persona = Persona {
+            name: "Ada".to_string(),
+            pronouns: "she/her".to_string(),
+            description: "A retired engineer".to_string(),
+            interests: vec!["gardening".to_string(), "chess".to_string()],
+            relationships: [
+                (
+                    "Test Knight".to_string(),
+                    "{{user}} is your sister".to_string(),
+                ),
+                ("Other".to_string(), "A stranger".to_string()),
+            ]
+            .into(),
+            user: Some("alice".to_string()),
+        };
+
+        let result = build_setup_item_chatml_prompt(
+            &character,
+            &prompt,
+            "Say hi",
+            &[],
+            &Chat {
+                character: character.name.clone(),
+                messages: vec![],
+                muted: false,
+            },
+            &Recipient::new("alice", Some(persona)),
+        );
+
+        let system = &result.messages[0].content;
+        assert!(system.contains("Context: Test Knight texts Ada"));
+        assert!(
+            system.contains("You are talking to Ada (she/her), described as: A retired engineer")
+        );
+        assert!(system.contains("Interests: gardening, chess"));
+        assert!(system.contains("Relationship to you: Ada is your sister"));
+        assert!(!system.contains("A stranger"));
+    }
+
+    #[test]
+    fn test_expand_payload_variables() {
+        let payload = serde_json::json!({
+            "event": "doorbell",
+            "sensor": {"name": "Front door", "battery": 80},
+            "people": ["Ada", "Bo"]
+        });
+
+        assert_eq!(
+            expand_payload_variables(
+                "The {{payload.event}} at the {{ payload.sensor.name }} rang for {{payload.people.1}}",
+                &payload
+            ),
+            "The doorbell at the Front door rang for Bo"
+        );
+        assert_eq!(
+            expand_payload_variables("{{payload.sensor.battery}}%", &payload),
+            "80%"
+        );
+        assert_eq!(
+            expand_payload_variables("[{{payload.missing}}] {{user}}", &payload),
+            "[] {{user}}"
+        );
+        assert_eq!(
+            expand_payload_variables("{{payload}}", &serde_json::json!({"a": 1})),
+            r#"{"a":1}"#
+        );
+    }
 }
@@ -1,25 +1,72 @@
-use backend::{create_app, job_scheduler::start_scheduler, load_settings};
-use std::{net::SocketAddr, sync::Arc};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use backend::{
+    auth::load_all_users,
+    backup::start_backup_scheduler,
+    cli::{Cli, Command, run_command},
+    config::{config, data_dir, init_config},
+    create_app,
+    email::start_email_delivery,
+    events::events,
+    job_scheduler::{start_scheduler, stop_scheduler},
+    load_settings,
+    migrations::run_migrations,
+    mqtt::start_mqtt_bridge,
+    push::start_push_dispatcher,
+    settings::{SharedSettings, validate_settings},
+    telegram::start_telegram_bridge,
+    webhooks::start_webhook_dispatcher,
+};
+use clap::Parser;
+use tracing_subscriber::{
+    fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt,
+};
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Serve);
+    let serving = matches!(command, Command::Serve);
+
     // Initialize tracing
+    // Other commands print their results to stdout, so keep their logs quiet and on stderr
+    let (default_filter, writer) = if serving {
+        (
+            "backend=debug,tower_http=debug",
+            BoxMakeWriter::new(std::io::stdout),
+        )
+    } else {
+        ("backend=warn", BoxMakeWriter::new(std::io::stderr))
+    };
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "backend=debug,tower_http=debug".into()),
+                .unwrap_or_else(|_| default_filter.into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_writer(writer))
         .init();
 
+    init_config(cli.config).expect("runtime configuration is only set once");
+
+    if serving {
+        serve().await;
+    } else {
+        std::process::exit(run_command(command).await);
+    }
+}
+
+/// Run the HTTP server and job scheduler
+async fn serve() {
     // Load settings
     let settings = match load_settings() {
         Ok(settings) => {
             tracing::info!("Settings loaded successfully");
             tracing::debug!("TTS API: {}", settings.tts_api);
             tracing::debug!("LLM API: {}", settings.llm_api);
-            Arc::new(settings)
+            if let Err(errors) = validate_settings(&settings) {
+                for error in errors {
+                    tracing::warn!("Settings problem: {}", error);
+                }
+            }
+            SharedSettings::new(settings)
         }
         Err(e) => {
             tracing::error!("Failed to load settings: {}", e);
@@ -27,20 +74,144 @@ async fn main() {
         }
     };
 
+    // Bring the data directory up to date before anything reads it
+    match run_migrations(data_dir()).await {
+        Ok(applied) if !applied.is_empty() => {
+            tracing::info!("Applied {} data migrations", applied.len())
+        }
+        Ok(_) => {}
+        Err(e) => {
+            tracing::error!("Failed to migrate data directory: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if settings.current().auth.enabled {
+        match load_all_users().await {
+            Ok(users) if users.is_empty() => tracing::warn!(
+                "Authentication is enabled but there are no users; add one with `backend user add <name> --admin`"
+            ),
+            Ok(users) => tracing::info!("Authentication enabled for {} users", users.len()),
+            Err(e) => tracing::warn!("Failed to load users: {}", e),
+        }
+    }
+
     // Start the job scheduler
-    if let Err(e) = start_scheduler(Arc::clone(&settings)).await {
+    if let Err(e) = start_scheduler(settings.clone()).await {
         tracing::error!("Failed to start job scheduler: {}", e);
         std::process::exit(1);
     }
     tracing::info!("Job scheduler started successfully");
 
+    // Start automatic backups if they are enabled
+    start_backup_scheduler(settings.clone());
+
+    // Send webhooks for new messages and failed jobs
+    start_webhook_dispatcher(settings.clone());
+
+    // Send Web Push notifications for new messages
+    start_push_dispatcher(settings.clone());
+
+    // Deliver messages through Telegram and answer replies from there
+    start_telegram_bridge(settings.clone());
+
+    // Publish messages to MQTT and run jobs on command from there
+    start_mqtt_bridge(settings.clone());
+
+    // Email new messages and the daily digest of unread ones
+    start_email_delivery(settings.clone());
+
     // Build our application
     let app = create_app(settings);
 
     // Run the server
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
-    tracing::info!("Starting server on {}", addr);
+    let addr = config().socket_addr();
+    tracing::info!(
+        "Starting server on {} with data in {}",
+        addr,
+        data_dir().display()
+    );
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let (stop_accepting, stopped) = tokio::sync::oneshot::channel::<()>();
+    let mut server = tokio::spawn(async move {
+        axum::serve(listener, app)
+            .with_graceful_shutdown(async {
+                let _ = stopped.await;
+            })
+            .await
+    });
+
+    tokio::select! {
+        _ = shutdown_signal() => {}
+        result = &mut server => {
+            tracing::error!("Server stopped unexpectedly: {:?}", result);
+            std::process::exit(1);
+        }
+    }
+
+    // Stop accepting requests and scheduling jobs, then give running work time to finish
+    let timeout = config().shutdown_timeout();
+    tracing::info!(
+        "Shutting down, waiting up to {:?} for running jobs and requests",
+        timeout
+    );
+    let _ = stop_accepting.send(());
+    events().end_client_streams();
+    let (jobs_drained, server_result) = tokio::join!(
+        stop_scheduler(timeout),
+        tokio::time::timeout(timeout, server)
+    );
+
+    let requests_drained = match server_result {
+        Ok(Ok(Ok(()))) => true,
+        Ok(Ok(Err(e))) => {
+            tracing::error!("Server error during shutdown: {}", e);
+            false
+        }
+        Ok(Err(e)) => {
+            tracing::error!("Server task failed during shutdown: {}", e);
+            false
+        }
+        Err(_) => {
+            tracing::warn!(
+                "Requests still running after {:?}, abandoning them",
+                timeout
+            );
+            false
+        }
+    };
+
+    // Nothing publishes events any more, so dispatchers can pass on the last ones and stop
+    let events_drained = events().close(timeout).await;
+
+    if jobs_drained && requests_drained && events_drained {
+        tracing::info!("Shutdown complete");
+    } else {
+        tracing::warn!("Shutdown timed out before all work finished");
+    }
+}
+
+/// Wait for Ctrl+C, or SIGTERM as sent by `docker stop`
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received Ctrl+C"),
+        _ = terminate => tracing::info!("Received SIGTERM"),
+    }
 }
@@ -0,0 +1,312 @@
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::error::AppError;
+
+/// Generated media never changes once written, but may be deleted with its message
+const CACHE_CONTROL: &str = "public, max-age=3600";
+/// Format of `Last-Modified` and `If-Modified-Since`
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// MIME types of the audio files that are served, by extension
+const AUDIO_TYPES: [(&str, &str); 4] = [
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/ogg; codecs=opus"),
+    ("wav", "audio/wav"),
+];
+/// MIME types of the images that are served, by extension
+const IMAGE_TYPES: [(&str, &str); 5] = [
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+];
+
+/// The MIME type of an audio file, or `None` when it is not a type that is served
+pub fn audio_content_type(filename: &str) -> Option<&'static str> {
+    content_type(&AUDIO_TYPES, filename)
+}
+
+/// The MIME type of an image, or `None` when it is not a type that is served
+pub fn image_content_type(filename: &str) -> Option<&'static str> {
+    content_type(&IMAGE_TYPES, filename)
+}
+
+fn content_type(types: &[(&str, &'static str)], filename: &str) -> Option<&'static str> {
+    let (_, extension) = filename.rsplit_once('.')?;
+    types
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map(|(_, content_type)| *content_type)
+}
+
+/// A single byte range requested with `Range`, resolved against the file's length
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// Inclusive start and end offsets
+    Satisfiable(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header for a file of `length` bytes
+/// Returns `None` when the whole file should be sent: the header is malformed, in another
+/// unit, or asks for several ranges, which servers may answer with the whole file
+fn parse_range(value: &str, length: u64) -> Option<ByteRange> {
+    let spec = value.trim().strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = if start.is_empty() {
+        // The last `end` bytes
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || length == 0 {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        (length.saturating_sub(suffix), length - 1)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = match end {
+            "" => u64::MAX,
+            end => end.parse().ok()?,
+        };
+        if end < start {
+            return None;
+        }
+        if start >= length {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        (start, end.min(length - 1))
+    };
+    Some(ByteRange::Satisfiable(range.0, range.1))
+}
+
+/// Stream a media file, answering range and conditional requests
+/// Browsers need `206` responses to seek, and iOS Safari will not play audio without them
+pub async fn serve_file(
+    path: &Path,
+    content_type: &'static str,
+    headers: &HeaderMap,
+) -> Result<Response, AppError> {
+    let mut file = File::open(path)
+        .await
+        .map_err(|e| AppError::from_io(e, "Media file not found"))?;
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+        return Err(AppError::NotFound("Media file not found".to_string()));
+    }
+    let length = metadata.len();
+    let modified: Option<DateTime<Utc>> = metadata.modified().ok().map(DateTime::from);
+
+    let etag = format!(
+        "\"{length:x}-{:x}\"",
+        metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|age| age.as_nanos())
+            .unwrap_or_default()
+    );
+    let last_modified = modified.map(|modified| modified.format(HTTP_DATE).to_string());
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(CACHE_CONTROL),
+    );
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, value);
+    }
+    if let Some(value) = last_modified
+        .as_deref()
+        .and_then(|date| HeaderValue::from_str(date).ok())
+    {
+        response_headers.insert(header::LAST_MODIFIED, value);
+    }
+
+    if is_not_modified(headers, &etag, modified) {
+        return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+    }
+    response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+
+    // A stale `If-Range` means the client's partial copy is outdated, so it gets the whole file
+    let if_range_matches = header_str(headers, header::IF_RANGE)
+        .is_none_or(|if_range| if_range == etag || Some(if_range) == last_modified.as_deref());
+    let range = header_str(headers, header::RANGE)
+        .filter(|_| if_range_matches)
+        .and_then(|range| parse_range(range, length));
+
+    let (status, start, end) = match range {
+        None => (StatusCode::OK, 0, length.saturating_sub(1)),
+        Some(ByteRange::Satisfiable(start, end)) => {
+            if let Ok(value) = HeaderValue::from_str(&format!("bytes {start}-{end}/{length}")) {
+                response_headers.insert(header::CONTENT_RANGE, value);
+            }
+            (StatusCode::PARTIAL_CONTENT, start, end)
+        }
+        Some(ByteRange::Unsatisfiable) => {
+            if let Ok(value) = HeaderValue::from_str(&format!("bytes */{length}")) {
+                response_headers.insert(header::CONTENT_RANGE, value);
+            }
+            response_headers.remove(header::CONTENT_TYPE);
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, response_headers).into_response());
+        }
+    };
+    let body_length = if length == 0 { 0 } else { end - start + 1 };
+    response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body_length));
+
+    if start > 0 {
+        file.seek(SeekFrom::Start(start)).await?;
+    }
+    let body = Body::from_stream(ReaderStream::new(file.take(body_length)));
+    Ok((status, response_headers, body).into_response())
+}
+
+/// Whether the client's cached copy is current, so `304 Not Modified` can be sent
+/// `If-None-Match` takes precedence over `If-Modified-Since`, as in RFC 9110
+fn is_not_modified(headers: &HeaderMap, etag: &str, modified: Option<DateTime<Utc>>) -> bool {
+    if let Some(if_none_match) = header_str(headers, header::IF_NONE_MATCH) {
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+    match (
+        header_str(headers, header::IF_MODIFIED_SINCE)
+            .and_then(|since| DateTime::parse_from_rfc2822(since).ok()),
+        modified,
+    ) {
+        // HTTP dates have no fractions of a second
+        (Some(since), Some(modified)) => modified.timestamp() <= since.timestamp(),
+        _ => false,
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_content_types() {
+        assert_eq!(audio_content_type("abc.mp3"), Some("audio/mpeg"));
+        assert_eq!(audio_content_type("abc.OGG"), Some("audio/ogg"));
+        assert_eq!(
+            audio_content_type("abc.opus"),
+            Some("audio/ogg; codecs=opus")
+        );
+        assert_eq!(audio_content_type("abc.wav"), Some("audio/wav"));
+        assert_eq!(audio_content_type("abc.png"), None);
+        assert_eq!(audio_content_type("mp3"), None);
+        assert_eq!(image_content_type("portrait.jpeg"), Some("image/jpeg"));
+        assert_eq!(image_content_type("portrait.svg"), None);
+    }
+
+    #[test]
+    fn test_parse_range() {
+        use ByteRange::*;
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Satisfiable(0, 99)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(Satisfiable(900, 999)));
+        assert_eq!(
+            parse_range("bytes=900-5000", 1000),
+            Some(Satisfiable(900, 999))
+        );
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Satisfiable(900, 999)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(Satisfiable(0, 999)));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Unsatisfiable));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Unsatisfiable));
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), None);
+        assert_eq!(parse_range("bytes=9-1", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+        assert_eq!(parse_range("bytes=abc", 1000), None);
+    }
+
+    async fn serve(path: &Path, headers: &[(header::HeaderName, &str)]) -> Response {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(name.clone(), value.parse().unwrap());
+        }
+        serve_file(path, "audio/mpeg", &map).await.unwrap()
+    }
+
+    async fn body(response: Response) -> Vec<u8> {
+        to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_serve_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("abc.mp3");
+        let content: Vec<u8> = (0..=255).collect();
+        std::fs::write(&path, &content).unwrap();
+
+        let full = serve(&path, &[]).await;
+        assert_eq!(full.status(), StatusCode::OK);
+        assert_eq!(full.headers()[header::CONTENT_TYPE], "audio/mpeg");
+        assert_eq!(full.headers()[header::CONTENT_LENGTH], "256");
+        assert_eq!(full.headers()[header::ACCEPT_RANGES], "bytes");
+        let etag = full.headers()[header::ETAG].to_str().unwrap().to_string();
+        let last_modified = full.headers()[header::LAST_MODIFIED]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(body(full).await, content);
+
+        let partial = serve(&path, &[(header::RANGE, "bytes=10-19")]).await;
+        assert_eq!(partial.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(partial.headers()[header::CONTENT_RANGE], "bytes 10-19/256");
+        assert_eq!(partial.headers()[header::CONTENT_LENGTH], "10");
+        assert_eq!(body(partial).await, content[10..20]);
+
+        let stale = serve(
+            &path,
+            &[
+                (header::RANGE, "bytes=10-19"),
+                (header::IF_RANGE, "\"old\""),
+            ],
+        )
+        .await;
+        assert_eq!(stale.status(), StatusCode::OK);
+
+        let beyond = serve(&path, &[(header::RANGE, "bytes=300-")]).await;
+        assert_eq!(beyond.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(beyond.headers()[header::CONTENT_RANGE], "bytes */256");
+
+        let cached = serve(&path, &[(header::IF_NONE_MATCH, etag.as_str())]).await;
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+        assert!(body(cached).await.is_empty());
+
+        let cached = serve(
+            &path,
+            &[(header::IF_MODIFIED_SINCE, last_modified.as_str())],
+        )
+        .await;
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+
+        let changed = serve(&path, &[(header::IF_NONE_MATCH, "\"other\"")]).await;
+        assert_eq!(changed.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,252 @@
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+
+use crate::config::data_dir;
+use crate::error::AppError;
+use crate::media::{audio_content_type, image_content_type};
+use crate::utils::{character_slug, write_file_atomic};
+
+/// Kinds of media kept in the data directory, each in its own subdirectory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Audio,
+    Image,
+}
+
+impl MediaKind {
+    fn dir(self) -> &'static str {
+        match self {
+            MediaKind::Audio => "audio",
+            MediaKind::Image => "images",
+        }
+    }
+
+    /// The MIME type of a file of this kind, or `None` when it is not a type that is served
+    pub fn content_type(self, filename: &str) -> Option<&'static str> {
+        match self {
+            MediaKind::Audio => audio_content_type(filename),
+            MediaKind::Image => image_content_type(filename),
+        }
+    }
+}
+
+/// Generated audio and images, stored as `{kind}/{character slug}/{filename}` under a root
+/// Every name is checked to be a single path segment, and files that exist are canonicalized
+/// so that symlinks cannot lead outside the root either
+pub struct MediaStore {
+    root: PathBuf,
+}
+
+impl MediaStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The store in the configured data directory
+    pub fn data() -> Self {
+        Self::new(data_dir())
+    }
+
+    /// Where a media file belongs, without checking that it exists
+    /// `dir` is the character's directory, such as the slug in a media URL
+    pub fn path(&self, kind: MediaKind, dir: &str, filename: &str) -> Result<PathBuf, AppError> {
+        check_segment(dir)?;
+        check_segment(filename)?;
+        Ok(self.root.join(kind.dir()).join(dir).join(filename))
+    }
+
+    /// The canonical path of an existing media file
+    /// Fails with `NotFound` when it does not exist, and `Forbidden` when it resolves to a
+    /// file outside the kind's directory
+    pub async fn resolve(
+        &self,
+        kind: MediaKind,
+        dir: &str,
+        filename: &str,
+    ) -> Result<PathBuf, AppError> {
+        let path = self.path(kind, dir, filename)?;
+        let kind_root = fs::canonicalize(self.root.join(kind.dir()))
+            .await
+            .map_err(|e| AppError::from_io(e, "Media file not found"))?;
+        let resolved = fs::canonicalize(&path)
+            .await
+            .map_err(|e| AppError::from_io(e, "Media file not found"))?;
+        if !resolved.starts_with(&kind_root) {
+            tracing::warn!(
+                "Refusing media path '{}', which resolves outside '{}'",
+                path.display(),
+                kind_root.display()
+            );
+            return Err(AppError::Forbidden(
+                "Media path is outside the data directory".to_string(),
+            ));
+        }
+        Ok(resolved)
+    }
+
+    /// The canonical path of an existing media file of `character`
+    pub async fn resolve_for(
+        &self,
+        kind: MediaKind,
+        character: &str,
+        filename: &str,
+    ) -> Result<PathBuf, AppError> {
+        self.resolve(kind, &character_slug(character), filename)
+            .await
+    }
+
+    /// Save a media file of `character`, returning where it was written
+    pub async fn write(
+        &self,
+        kind: MediaKind,
+        character: &str,
+        filename: &str,
+        contents: &[u8],
+    ) -> Result<PathBuf, AppError> {
+        let path = self.path(kind, &character_slug(character), filename)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        write_file_atomic(&path, contents).await?;
+        Ok(path)
+    }
+
+    /// Delete a media file of `character`
+    pub async fn delete(
+        &self,
+        kind: MediaKind,
+        character: &str,
+        filename: &str,
+    ) -> Result<(), AppError> {
+        let path = self.resolve_for(kind, character, filename).await?;
+        fs::remove_file(&path)
+            .await
+            .map_err(|e| AppError::from_io(e, "Media file not found"))
+    }
+}
+
+/// Reject names that are not exactly one ordinary path segment, such as `..`, `a/b` or `C:`
+fn check_segment(segment: &str) -> Result<(), AppError> {
+    let invalid = || AppError::Validation(format!("Invalid media path segment '{segment}'"));
+    if segment.is_empty() || segment.contains(['/', '\\', ':', '\0']) {
+        return Err(invalid());
+    }
+    let mut components = Path::new(segment).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn store_with_file() -> (tempfile::TempDir, MediaStore) {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("data");
+        std::fs::create_dir_all(root.join("audio/brave-knight")).unwrap();
+        std::fs::write(root.join("audio/brave-knight/abc.mp3"), b"ID3").unwrap();
+        std::fs::write(root.join("settings.json"), b"{}").unwrap();
+        (dir, MediaStore::new(root))
+    }
+
+    #[test]
+    fn test_check_segment() {
+        assert!(check_segment("brave-knight").is_ok());
+        assert!(check_segment("abc.mp3").is_ok());
+        assert!(check_segment("..abc.mp3").is_ok());
+        for bad in [
+            "", ".", "..", "../x", "a/b", "..\\x", "/etc", "C:x", "x\0.mp3",
+        ] {
+            assert!(check_segment(bad).is_err(), "{bad:?} should be rejected");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let (_dir, store) = store_with_file();
+        let path = store
+            .resolve(MediaKind::Audio, "brave-knight", "abc.mp3")
+            .await
+            .unwrap();
+        assert!(path.is_absolute());
+        assert!(path.ends_with("audio/brave-knight/abc.mp3"));
+
+        assert!(matches!(
+            store
+                .resolve(MediaKind::Audio, "brave-knight", "missing.mp3")
+                .await,
+            Err(AppError::NotFound(_))
+        ));
+        assert!(matches!(
+            store.resolve(MediaKind::Audio, "..", "settings.json").await,
+            Err(AppError::Validation(_))
+        ));
+        assert!(matches!(
+            store
+                .resolve(MediaKind::Image, "brave-knight", "abc.mp3")
+                .await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlinks_cannot_escape() {
+        let (dir, store) = store_with_file();
+        let root = dir.path().join("data");
+        std::os::unix::fs::symlink(
+            root.join("settings.json"),
+            root.join("audio/brave-knight/link.mp3"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(dir.path(), root.join("audio/outside")).unwrap();
+
+        assert!(matches!(
+            store
+                .resolve(MediaKind::Audio, "brave-knight", "link.mp3")
+                .await,
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(matches!(
+            store.resolve(MediaKind::Audio, "outside", "data").await,
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(
+            store
+                .delete(MediaKind::Audio, "Brave Knight", "link.mp3")
+                .await
+                .is_err()
+        );
+        assert!(root.join("settings.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_write_and_delete() {
+        let (_dir, store) = store_with_file();
+        let path = store
+            .write(MediaKind::Image, "Brave Knight", "portrait.png", b"PNG")
+            .await
+            .unwrap();
+        assert!(path.ends_with("images/brave-knight/portrait.png"));
+        assert!(
+            store
+                .write(MediaKind::Image, "Brave Knight", "../portrait.png", b"PNG")
+                .await
+                .is_err()
+        );
+
+        store
+            .delete(MediaKind::Audio, "Brave Knight", "abc.mp3")
+            .await
+            .unwrap();
+        assert!(matches!(
+            store
+                .delete(MediaKind::Audio, "Brave Knight", "abc.mp3")
+                .await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+}
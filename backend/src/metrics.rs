@@ -0,0 +1,309 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::config::data_dir;
+use crate::error::AppError;
+
+/// Label used for requests that did not match an API route, such as static frontend files
+pub const UNMATCHED_ROUTE: &str = "static";
+
+/// Prometheus metrics for the whole process
+pub struct Metrics {
+    registry: Registry,
+    /// HTTP requests by method, route template and status code
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    /// LLM and TTS calls by service and outcome
+    pub upstream_requests: IntCounterVec,
+    pub upstream_request_duration: HistogramVec,
+    /// Tokens generated by the LLM, when the backend reports them
+    pub generated_tokens: IntCounter,
+    /// Job runs by outcome, either "success" or an error code
+    pub jobs_run: IntCounterVec,
+    /// How long after its planned time a scheduled job started
+    pub scheduler_lag: Histogram,
+    /// Scheduled job runs that have started but not finished
+    pub jobs_in_flight: IntGauge,
+    pub scheduled_jobs: IntGauge,
+    /// Webhook deliveries by final outcome, after any retries
+    pub webhook_deliveries: IntCounterVec,
+    /// Web Push notifications by outcome
+    pub push_notifications: IntCounterVec,
+    /// Bytes used by each top-level entry of the data directory, measured on scrape
+    pub data_dir_bytes: IntGaugeVec,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Get the global metrics, registering them on first use
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(|| Metrics::new().expect("metric definitions are valid"))
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("storytime".to_string()), None)?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests",
+            ),
+            &["method", "route"],
+        )?;
+        let upstream_requests = IntCounterVec::new(
+            Opts::new(
+                "upstream_requests_total",
+                "Calls to the LLM and TTS backends",
+            ),
+            &["service", "outcome"],
+        )?;
+        let upstream_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_request_duration_seconds",
+                "Time taken by calls to the LLM and TTS backends",
+            )
+            .buckets(vec![0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]),
+            &["service"],
+        )?;
+        let generated_tokens = IntCounter::new(
+            "llm_generated_tokens_total",
+            "Tokens generated by the LLM backend",
+        )?;
+        let jobs_run = IntCounterVec::new(
+            Opts::new("jobs_run_total", "Job runs by outcome"),
+            &["outcome"],
+        )?;
+        let scheduler_lag = Histogram::with_opts(
+            HistogramOpts::new(
+                "scheduler_lag_seconds",
+                "Delay between a job's planned run time and when it started",
+            )
+            .buckets(vec![1.0, 5.0, 15.0, 30.0, 60.0, 90.0, 120.0, 300.0, 600.0]),
+        )?;
+        let jobs_in_flight = IntGauge::new(
+            "jobs_in_flight",
+            "Scheduled job runs that have not finished",
+        )?;
+        let scheduled_jobs = IntGauge::new("scheduled_jobs", "Enabled jobs in the scheduler")?;
+        let webhook_deliveries = IntCounterVec::new(
+            Opts::new(
+                "webhook_deliveries_total",
+                "Webhook deliveries by final outcome",
+            ),
+            &["outcome"],
+        )?;
+        let push_notifications = IntCounterVec::new(
+            Opts::new(
+                "push_notifications_total",
+                "Web Push notifications by outcome",
+            ),
+            &["outcome"],
+        )?;
+        let data_dir_bytes = IntGaugeVec::new(
+            Opts::new("data_dir_bytes", "Disk space used by the data directory"),
+            &["dir"],
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(upstream_requests.clone()))?;
+        registry.register(Box::new(upstream_request_duration.clone()))?;
+        registry.register(Box::new(generated_tokens.clone()))?;
+        registry.register(Box::new(jobs_run.clone()))?;
+        registry.register(Box::new(scheduler_lag.clone()))?;
+        registry.register(Box::new(jobs_in_flight.clone()))?;
+        registry.register(Box::new(scheduled_jobs.clone()))?;
+        registry.register(Box::new(webhook_deliveries.clone()))?;
+        registry.register(Box::new(push_notifications.clone()))?;
+        registry.register(Box::new(data_dir_bytes.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            upstream_requests,
+            upstream_request_duration,
+            generated_tokens,
+            jobs_run,
+            scheduler_lag,
+            jobs_in_flight,
+            scheduled_jobs,
+            webhook_deliveries,
+            push_notifications,
+            data_dir_bytes,
+        })
+    }
+
+    /// Record one call to the LLM or TTS backend
+    pub fn record_upstream(&self, service: &str, duration: Duration, success: bool) {
+        let outcome = if success { "success" } else { "failure" };
+        self.upstream_requests
+            .with_label_values(&[service, outcome])
+            .inc();
+        self.upstream_request_duration
+            .with_label_values(&[service])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Record the outcome of a job run
+    pub fn record_job<T>(&self, result: &Result<T, AppError>) {
+        let outcome = match result {
+            Ok(_) => "success".to_string(),
+            Err(e) => e.code().to_string(),
+        };
+        self.jobs_run.with_label_values(&[&outcome]).inc();
+    }
+
+    /// Render every metric in the Prometheus text format
+    pub async fn render(&self) -> Result<String, AppError> {
+        let sizes = tokio::task::spawn_blocking(|| measure_data_dir(data_dir())).await??;
+        self.data_dir_bytes.reset();
+        for (dir, bytes) in sizes {
+            self.data_dir_bytes
+                .with_label_values(&[&dir])
+                .set(bytes as i64);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| AppError::Internal(format!("Failed to encode metrics: {e}")))?;
+        String::from_utf8(buffer)
+            .map_err(|e| AppError::Internal(format!("Failed to encode metrics: {e}")))
+    }
+}
+
+/// Time a call to the LLM or TTS backend and count its outcome
+pub async fn observe_upstream<T>(
+    service: &str,
+    call: impl Future<Output = Result<T, AppError>>,
+) -> Result<T, AppError> {
+    let timer = Instant::now();
+    let result = call.await;
+    metrics().record_upstream(service, timer.elapsed(), result.is_ok());
+    result
+}
+
+/// Middleware counting and timing requests by their route template, so ids in paths do not
+/// create a label per entity
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+
+    let timer = Instant::now();
+    let response = next.run(request).await;
+    let metrics = metrics();
+    metrics
+        .http_requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    metrics
+        .http_request_duration
+        .with_label_values(&[&method, &route])
+        .observe(timer.elapsed().as_secs_f64());
+    response
+}
+
+/// Total size of each top-level entry in the data directory
+/// Files directly in the data directory are grouped under "root"
+fn measure_data_dir(data_dir: &Path) -> std::io::Result<Vec<(String, u64)>> {
+    let mut sizes = Vec::new();
+    let mut root = 0;
+    for entry in std::fs::read_dir(data_dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            sizes.push((name, dir_size(&entry.path())?));
+        } else {
+            root += metadata.len();
+        }
+    }
+    sizes.push(("root".to_string(), root));
+    sizes.sort();
+    Ok(sizes)
+}
+
+fn dir_size(dir: &Path) -> std::io::Result<u64> {
+    let mut total = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        total += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_data_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("audio/aria")).unwrap();
+        std::fs::write(dir.path().join("audio/aria/a.mp3"), [0; 100]).unwrap();
+        std::fs::write(dir.path().join("audio/b.mp3"), [0; 20]).unwrap();
+        std::fs::create_dir(dir.path().join("jobs")).unwrap();
+        std::fs::write(dir.path().join("settings.json"), [0; 5]).unwrap();
+
+        assert_eq!(
+            measure_data_dir(dir.path()).unwrap(),
+            vec![
+                ("audio".to_string(), 120),
+                ("jobs".to_string(), 0),
+                ("root".to_string(), 5)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_observe_upstream_counts_outcomes() {
+        let failures = || {
+            metrics()
+                .upstream_requests
+                .with_label_values(&["test", "failure"])
+                .get()
+        };
+        let before = failures();
+
+        let result: Result<(), _> =
+            observe_upstream("test", async { Err(AppError::UpstreamTts("down".into())) }).await;
+        assert!(result.is_err());
+        assert_eq!(failures(), before + 1);
+    }
+
+    #[test]
+    fn test_record_job_uses_error_codes() {
+        let metrics = metrics();
+        let before = metrics.jobs_run.with_label_values(&["not_found"]).get();
+        metrics.record_job::<()>(&Err(AppError::NotFound("Character 'x' not found".into())));
+        assert_eq!(
+            metrics.jobs_run.with_label_values(&["not_found"]).get(),
+            before + 1
+        );
+    }
+}
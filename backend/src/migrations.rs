@@ -0,0 +1,606 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use tokio::fs;
+
+use crate::auth::DEFAULT_USER;
+use crate::backup::create_safety_backup;
+use crate::error::AppError;
+use crate::models::{Character, Chat, Job, Prompt};
+use crate::utils::{to_slug, user_slug};
+
+/// Version of the data directory layout written by this build
+/// Bump this and add an entry to `MIGRATIONS` whenever the on-disk format changes
+pub const SCHEMA_VERSION: u32 = 4;
+
+const SCHEMA_FILE: &str = "schema.json";
+
+type MigrationResult = Result<Vec<String>, AppError>;
+
+/// A single step that upgrades the data directory from `version - 1` to `version`
+struct Migration {
+    version: u32,
+    description: &'static str,
+    apply: fn(PathBuf) -> Pin<Box<dyn Future<Output = MigrationResult> + Send>>,
+}
+
+/// Every migration, in the order they must run
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Assign UUIDs to jobs and name job files after them",
+        apply: |data_dir| Box::pin(async move { migrate_jobs_to_uuids(&data_dir).await }),
+    },
+    Migration {
+        version: 2,
+        description: "Write default values for fields added to characters, prompts, jobs and chats",
+        apply: |data_dir| Box::pin(async move { fill_defaults(&data_dir).await }),
+    },
+    Migration {
+        version: 3,
+        description: "Rename files to transliterated slugs",
+        apply: |data_dir| Box::pin(async move { migrate_slugs(&data_dir).await }),
+    },
+    Migration {
+        version: 4,
+        description: "Move chats into a folder for the default user",
+        apply: |data_dir| Box::pin(async move { migrate_chats_to_users(&data_dir).await }),
+    },
+];
+
+/// Stored in `data/schema.json`
+#[derive(Serialize, Deserialize, Debug)]
+struct SchemaInfo {
+    schema_version: u32,
+}
+
+/// A migration that was applied by `run_migrations`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AppliedMigration {
+    pub version: u32,
+    pub description: String,
+    pub changes: Vec<String>,
+}
+
+/// Schema version of a data directory and the migrations it still needs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MigrationStatus {
+    pub data_version: u32,
+    pub supported_version: u32,
+    pub pending: Vec<PendingMigration>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingMigration {
+    pub version: u32,
+    pub description: String,
+}
+
+/// Describe which migrations would run on a data directory
+pub fn migration_status(data_dir: &Path) -> Result<MigrationStatus, AppError> {
+    let data_version = read_schema_version(data_dir)?;
+    let pending = MIGRATIONS
+        .iter()
+        .filter(|m| m.version > data_version)
+        .map(|m| PendingMigration {
+            version: m.version,
+            description: m.description.to_string(),
+        })
+        .collect();
+
+    Ok(MigrationStatus {
+        data_version,
+        supported_version: SCHEMA_VERSION,
+        pending,
+    })
+}
+
+/// Read the schema version of a data directory
+/// Data directories written before versioning was introduced have no schema file and are version 0
+pub fn read_schema_version(data_dir: &Path) -> Result<u32, AppError> {
+    let path = data_dir.join(SCHEMA_FILE);
+    if !path.exists() {
+        return Ok(0);
+    }
+    let info: SchemaInfo = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+    Ok(info.schema_version)
+}
+
+/// Record the schema version of a data directory
+pub fn write_schema_version(data_dir: &Path, version: u32) -> Result<(), AppError> {
+    let info = SchemaInfo {
+        schema_version: version,
+    };
+    std::fs::create_dir_all(data_dir)?;
+    std::fs::write(
+        data_dir.join(SCHEMA_FILE),
+        serde_json::to_string_pretty(&info)?,
+    )?;
+    Ok(())
+}
+
+/// Bring the data directory up to `SCHEMA_VERSION`, running every pending migration in order
+/// A backup is written to `data/backups` before each migration, and the schema version is
+/// recorded after each one so an interrupted upgrade resumes where it stopped.
+/// Fails without touching anything if the data was written by a newer version of Storytime
+pub async fn run_migrations(data_dir: &Path) -> Result<Vec<AppliedMigration>, AppError> {
+    migrate(data_dir, SCHEMA_VERSION, true).await
+}
+
+/// Bring restored files staged in `dir` up to `version`, the version of the data they are
+/// merged into
+/// No backups are written, since the archive the files came from already is one
+pub async fn migrate_staged(dir: &Path, version: u32) -> Result<Vec<AppliedMigration>, AppError> {
+    migrate(dir, version, false).await
+}
+
+async fn migrate(
+    data_dir: &Path,
+    target: u32,
+    backup: bool,
+) -> Result<Vec<AppliedMigration>, AppError> {
+    let current = read_schema_version(data_dir)?;
+    if current > SCHEMA_VERSION {
+        return Err(AppError::Conflict(format!(
+            "Data directory schema version {current} is newer than the supported version {SCHEMA_VERSION}; \
+             upgrade Storytime or restore an older backup"
+        )));
+    }
+
+    // A new data directory is already in the current format
+    if current < target && !has_entities(data_dir).await? {
+        write_schema_version(data_dir, target)?;
+        return Ok(Vec::new());
+    }
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|m| m.version > current && m.version <= target)
+    {
+        tracing::info!(
+            "Applying data migration {}: {}",
+            migration.version,
+            migration.description
+        );
+
+        if backup {
+            let backup_dir = data_dir.to_path_buf();
+            let label = format!("pre-migration-v{}", migration.version);
+            let backup = tokio::task::spawn_blocking(move || {
+                create_safety_backup(&backup_dir, "pre-migration", &label)
+            })
+            .await??;
+            tracing::info!("Saved data to {} before migrating", backup.display());
+        }
+
+        let changes = (migration.apply)(data_dir.to_path_buf()).await?;
+        write_schema_version(data_dir, migration.version)?;
+
+        tracing::info!(
+            "Data migration {} complete with {} changes",
+            migration.version,
+            changes.len()
+        );
+        applied.push(AppliedMigration {
+            version: migration.version,
+            description: migration.description.to_string(),
+            changes,
+        });
+    }
+
+    Ok(applied)
+}
+
+/// Whether any characters, prompts, jobs or chats have been saved
+async fn has_entities(data_dir: &Path) -> Result<bool, AppError> {
+    for dir in ["characters", "prompts", "jobs", "chats"] {
+        if !list_json_files(&data_dir.join(dir)).await?.is_empty() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Give every job a UUID and rename its file to `{uuid}.json`
+async fn migrate_jobs_to_uuids(data_dir: &Path) -> MigrationResult {
+    let mut migrated_jobs = Vec::new();
+    let jobs_dir = data_dir.join("jobs");
+
+    for (path, _) in list_json_files(&jobs_dir).await? {
+        let content = fs::read_to_string(&path).await?;
+        let mut job: Job = serde_json::from_str(&content)?;
+
+        // Only migrate jobs that don't already have UUIDs
+        if job.id.is_some() {
+            continue;
+        }
+
+        let id = uuid::Uuid::new_v4();
+        job.id = Some(id);
+        let new_file_path = jobs_dir.join(format!("{id}.json"));
+
+        // Save the job with the new UUID-based filename, then remove the old file
+        fs::write(&new_file_path, serde_json::to_string_pretty(&job)?).await?;
+        fs::remove_file(&path).await?;
+
+        tracing::info!("Migrated job {} to UUID {}", path.display(), id);
+        migrated_jobs.push(format!("job '{}' -> '{}'", path.display(), id));
+    }
+
+    Ok(migrated_jobs)
+}
+
+/// Rewrite every entity so fields that were added later with `#[serde(default)]` are written out
+async fn fill_defaults(data_dir: &Path) -> MigrationResult {
+    let mut rewritten = Vec::new();
+    rewritten.extend(rewrite_entities::<Character>(&data_dir.join("characters")).await?);
+    rewritten.extend(rewrite_entities::<Prompt>(&data_dir.join("prompts")).await?);
+    rewritten.extend(rewrite_entities::<Job>(&data_dir.join("jobs")).await?);
+    rewritten.extend(rewrite_entities::<Chat>(&data_dir.join("chats")).await?);
+    Ok(rewritten)
+}
+
+/// Parse and re-save every JSON file in a directory whose contents change by doing so
+/// Files that fail to parse are left alone for the integrity checker to report
+async fn rewrite_entities<T: Serialize + DeserializeOwned>(dir: &Path) -> MigrationResult {
+    let mut rewritten = Vec::new();
+    for (path, _) in list_json_files(dir).await? {
+        let content = fs::read_to_string(&path).await?;
+        let original: serde_json::Value = match serde_json::from_str(&content) {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::warn!("Skipping unreadable file {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let entity: T = match serde_json::from_value(original.clone()) {
+            Ok(entity) => entity,
+            Err(e) => {
+                tracing::warn!("Skipping unreadable file {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        if serde_json::to_value(&entity)? != original {
+            fs::write(&path, serde_json::to_string_pretty(&entity)?).await?;
+            rewritten.push(format!("rewrote '{}'", path.display()));
+        }
+    }
+    Ok(rewritten)
+}
+
+/// Rename character and prompt files whose slug no longer matches their name
+/// Files written before slugs were transliterated (e.g. "zo.json" for "Zoë") are moved to
+/// their current slug, and a character's chat file and media folders are moved with it.
+/// Returns a description of every rename that was performed
+async fn migrate_slugs(data_dir: &Path) -> Result<Vec<String>, AppError> {
+    let mut renamed = Vec::new();
+
+    let characters_dir = data_dir.join("characters");
+    for (path, old_slug) in list_json_files(&characters_dir).await? {
+        let content = fs::read_to_string(&path).await?;
+        let character: Character = match serde_json::from_str(&content) {
+            Ok(character) => character,
+            Err(e) => {
+                tracing::warn!(
+                    "Skipping unreadable character file {}: {}",
+                    path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        let new_slug = to_slug(&character.name);
+        if new_slug == old_slug || new_slug.is_empty() {
+            continue;
+        }
+
+        let new_path = characters_dir.join(format!("{new_slug}.json"));
+        if fs::try_exists(&new_path).await? {
+            tracing::warn!(
+                "Cannot migrate character '{}' from '{}' to '{}': slug already in use",
+                character.name,
+                old_slug,
+                new_slug
+            );
+            continue;
+        }
+        fs::rename(&path, &new_path).await?;
+
+        // Keep the chat history and generated media attached to the character
+        move_if_exists(
+            &data_dir.join("chats").join(format!("{old_slug}.json")),
+            &data_dir.join("chats").join(format!("{new_slug}.json")),
+        )
+        .await?;
+        for media_dir in ["audio", "images"] {
+            move_if_exists(
+                &data_dir.join(media_dir).join(&old_slug),
+                &data_dir.join(media_dir).join(&new_slug),
+            )
+            .await?;
+        }
+
+        tracing::info!(
+            "Migrated character '{}' from slug '{}' to '{}'",
+            character.name,
+            old_slug,
+            new_slug
+        );
+        renamed.push(format!(
+            "character '{}': '{}' -> '{}'",
+            character.name, old_slug, new_slug
+        ));
+    }
+
+    let prompts_dir = data_dir.join("prompts");
+    for (path, old_slug) in list_json_files(&prompts_dir).await? {
+        let content = fs::read_to_string(&path).await?;
+        let prompt: Prompt = match serde_json::from_str(&content) {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                tracing::warn!("Skipping unreadable prompt file {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        let new_slug = to_slug(&prompt.title);
+        if new_slug == old_slug || new_slug.is_empty() {
+            continue;
+        }
+
+        let new_path = prompts_dir.join(format!("{new_slug}.json"));
+        if fs::try_exists(&new_path).await? {
+            tracing::warn!(
+                "Cannot migrate prompt '{}' from '{}' to '{}': slug already in use",
+                prompt.title,
+                old_slug,
+                new_slug
+            );
+            continue;
+        }
+        fs::rename(&path, &new_path).await?;
+
+        tracing::info!(
+            "Migrated prompt '{}' from slug '{}' to '{}'",
+            prompt.title,
+            old_slug,
+            new_slug
+        );
+        renamed.push(format!(
+            "prompt '{}': '{}' -> '{}'",
+            prompt.title, old_slug, new_slug
+        ));
+    }
+
+    Ok(renamed)
+}
+
+/// Chats now belong to a user, so give the existing ones to the default user
+async fn migrate_chats_to_users(data_dir: &Path) -> MigrationResult {
+    let mut moved = Vec::new();
+    let chats_dir = data_dir.join("chats");
+    let user_dir = chats_dir.join(user_slug(DEFAULT_USER));
+
+    for (path, slug) in list_json_files(&chats_dir).await? {
+        fs::create_dir_all(&user_dir).await?;
+        let target = user_dir.join(format!("{slug}.json"));
+        move_if_exists(&path, &target).await?;
+        moved.push(format!("chat '{slug}' -> user '{DEFAULT_USER}'"));
+    }
+
+    Ok(moved)
+}
+
+/// List the JSON files in a directory along with their slug (file name without ".json")
+/// A file named just ".json" (written for names that used to slug to "") has an empty slug
+async fn list_json_files(dir: &Path) -> Result<Vec<(PathBuf, String)>, AppError> {
+    let mut files = Vec::new();
+    if !fs::try_exists(dir).await? {
+        return Ok(files);
+    }
+
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        if let Some(slug) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_suffix(".json"))
+        {
+            files.push((path, slug.to_string()));
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Move a file or directory if it exists, refusing to overwrite an existing target
+async fn move_if_exists(from: &Path, to: &Path) -> Result<(), AppError> {
+    if !fs::try_exists(from).await? {
+        return Ok(());
+    }
+    if fs::try_exists(to).await? {
+        tracing::warn!(
+            "Not moving {} to {}: target already exists",
+            from.display(),
+            to.display()
+        );
+        return Ok(());
+    }
+    fs::rename(from, to).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn write_json(path: &Path, value: &serde_json::Value) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, serde_json::to_string(value).unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migrate_slugs_moves_character_files() {
+        let dir = tempdir().unwrap();
+        let data = dir.path();
+
+        write_json(
+            &data.join("characters/zo.json"),
+            &serde_json::json!({
+                "name": "Zoë",
+                "description": "",
+                "personality": "",
+                "background": ""
+            }),
+        );
+        write_json(
+            &data.join("chats/zo.json"),
+            &serde_json::json!({ "character": "Zoë", "messages": [] }),
+        );
+        std::fs::create_dir_all(data.join("audio/zo")).unwrap();
+        std::fs::write(data.join("audio/zo/clip.mp3"), b"mp3").unwrap();
+        write_json(
+            &data.join("prompts/.json"),
+            &serde_json::json!({
+                "title": "さくら",
+                "description": "",
+                "context": "",
+                "setup": []
+            }),
+        );
+
+        let renamed = migrate_slugs(data).await.unwrap();
+
+        assert_eq!(renamed.len(), 2);
+        assert!(data.join("characters/zoe.json").exists());
+        assert!(!data.join("characters/zo.json").exists());
+        assert!(data.join("chats/zoe.json").exists());
+        assert!(data.join("audio/zoe/clip.mp3").exists());
+        assert!(data.join("prompts/sakura.json").exists());
+        assert!(!data.join("prompts/.json").exists());
+
+        // A second run has nothing left to do
+        assert!(migrate_slugs(data).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_slugs_skips_collisions() {
+        let dir = tempdir().unwrap();
+        let data = dir.path();
+
+        for (file, name) in [("zo.json", "Zoë"), ("zoe.json", "Zoe")] {
+            write_json(
+                &data.join("characters").join(file),
+                &serde_json::json!({
+                    "name": name,
+                    "description": "",
+                    "personality": "",
+                    "background": ""
+                }),
+            );
+        }
+
+        let renamed = migrate_slugs(data).await.unwrap();
+
+        assert!(renamed.is_empty());
+        assert!(data.join("characters/zo.json").exists());
+        assert!(data.join("characters/zoe.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_run_migrations_upgrades_legacy_data() {
+        let dir = tempdir().unwrap();
+        let data = dir.path();
+
+        write_json(
+            &data.join("jobs/knight-joke.json"),
+            &serde_json::json!({
+                "id": null,
+                "characters": ["Knight"],
+                "prompts": ["Joke"],
+                "cadence": "0 0 9 * * *",
+                "prompt-override": null
+            }),
+        );
+        write_json(
+            &data.join("prompts/joke.json"),
+            &serde_json::json!({
+                "title": "Joke",
+                "description": "",
+                "context": "",
+                "setup": []
+            }),
+        );
+        write_json(
+            &data.join("chats/knight.json"),
+            &serde_json::json!({ "character": "Knight", "messages": [] }),
+        );
+
+        assert_eq!(read_schema_version(data).unwrap(), 0);
+        let applied = run_migrations(data).await.unwrap();
+
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(read_schema_version(data).unwrap(), SCHEMA_VERSION);
+
+        let jobs: Vec<_> = std::fs::read_dir(data.join("jobs")).unwrap().collect();
+        assert_eq!(jobs.len(), 1);
+        let job: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(jobs[0].as_ref().unwrap().path()).unwrap(),
+        )
+        .unwrap();
+        assert!(job["id"].is_string());
+        assert_eq!(job["enabled"], true);
+
+        let prompt: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(data.join("prompts/joke.json")).unwrap())
+                .unwrap();
+        assert_eq!(prompt["create_audio"], false);
+
+        assert!(data.join("chats/default/knight.json").exists());
+        assert!(!data.join("chats/knight.json").exists());
+
+        // One backup per migration
+        let backups = std::fs::read_dir(data.join("backups")).unwrap().count();
+        assert_eq!(backups, MIGRATIONS.len());
+
+        // Up to date data needs no migrations
+        assert!(run_migrations(data).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_run_migrations_refuses_newer_data() {
+        let dir = tempdir().unwrap();
+        write_schema_version(dir.path(), SCHEMA_VERSION + 1).unwrap();
+
+        assert!(run_migrations(dir.path()).await.is_err());
+        assert!(!dir.path().join("backups").exists());
+    }
+
+    #[tokio::test]
+    async fn test_run_migrations_on_new_data_dir() {
+        let dir = tempdir().unwrap();
+
+        assert!(run_migrations(dir.path()).await.unwrap().is_empty());
+        assert_eq!(read_schema_version(dir.path()).unwrap(), SCHEMA_VERSION);
+        assert!(!dir.path().join("backups").exists());
+    }
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as u32 + 1);
+        }
+        assert_eq!(MIGRATIONS.last().unwrap().version, SCHEMA_VERSION);
+    }
+}
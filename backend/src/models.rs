@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Serialize, Deserialize)]
 pub struct HealthResponse {
@@ -12,6 +13,64 @@ pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
     pub message: String,
+    /// Set on failures so clients can react without parsing the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+}
+
+impl<T> ApiResponse<T> {
+    pub fn success(data: T, message: impl Into<String>) -> Self {
+        Self {
+            success: true,
+            data: Some(data),
+            message: message.into(),
+            code: None,
+        }
+    }
+}
+
+/// A character or chat as returned by the API, with the slug its URLs use
+/// Clients use this slug rather than computing their own, which would not transliterate names
+/// the way the backend does
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WithSlug<T> {
+    pub slug: String,
+    #[serde(flatten)]
+    pub item: T,
+}
+
+/// Machine-readable reason for a failed request
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    NotFound,
+    Conflict,
+    Validation,
+    Unauthorized,
+    Forbidden,
+    UpstreamLlm,
+    UpstreamTts,
+    Unavailable,
+    Storage,
+    Internal,
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let code = match self {
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::Validation => "validation",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::UpstreamLlm => "upstream_llm",
+            ErrorCode::UpstreamTts => "upstream_tts",
+            ErrorCode::Unavailable => "unavailable",
+            ErrorCode::Storage => "storage",
+            ErrorCode::Internal => "internal",
+        };
+        write!(f, "{code}")
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -54,6 +113,344 @@ pub struct Settings {
     pub tts_api: String,
     #[serde(rename = "llmApi")]
     pub llm_api: String,
+    /// Sent as a bearer token to the TTS API when set
+    #[serde(rename = "ttsApiKey", default, skip_serializing_if = "Option::is_none")]
+    pub tts_api_key: Option<String>,
+    /// Sent as a bearer token to the LLM API when set
+    #[serde(rename = "llmApiKey", default, skip_serializing_if = "Option::is_none")]
+    pub llm_api_key: Option<String>,
+    #[serde(default)]
+    pub backup: BackupSettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    /// Address Storytime is reachable at from outside, used for links such as audio URLs in
+    /// webhooks; links are relative to the server when unset
+    #[serde(rename = "publicUrl", default, skip_serializing_if = "Option::is_none")]
+    pub public_url: Option<String>,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookSettings>,
+    #[serde(default)]
+    pub push: PushSettings,
+    #[serde(default)]
+    pub telegram: TelegramSettings,
+    #[serde(default)]
+    pub mqtt: MqttSettings,
+    #[serde(default)]
+    pub email: EmailSettings,
+}
+
+/// An SMTP server that new messages and the daily digest of unread messages are sent through
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmailSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_smtp_host")]
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Sender of every email, such as `Storytime <storytime@example.com>`
+    #[serde(default = "default_email_from")]
+    pub from: String,
+    #[serde(default)]
+    pub recipients: Vec<EmailRecipient>,
+    /// When the digest of unread messages is sent, as a cron expression in UTC
+    #[serde(rename = "digestCadence", default = "default_digest_cadence")]
+    pub digest_cadence: String,
+}
+
+impl Default for EmailSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: default_smtp_host(),
+            port: default_smtp_port(),
+            security: SmtpSecurity::default(),
+            username: None,
+            password: None,
+            from: default_email_from(),
+            recipients: Vec::new(),
+            digest_cadence: default_digest_cadence(),
+        }
+    }
+}
+
+fn default_smtp_host() -> String {
+    "localhost".to_string()
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_email_from() -> String {
+    "Storytime <storytime@localhost>".to_string()
+}
+
+fn default_digest_cadence() -> String {
+    "0 0 18 * * *".to_string()
+}
+
+/// How the connection to the SMTP server is encrypted
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Upgrade a plain connection, usually on port 587
+    #[default]
+    StartTls,
+    /// Connect with TLS straight away, usually on port 465
+    Tls,
+    /// No encryption, only for servers on a trusted network
+    None,
+}
+
+/// An email address that receives one user's messages, from every character or the chosen ones
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmailRecipient {
+    pub address: String,
+    #[serde(default = "default_user")]
+    pub user: String,
+    /// Characters whose messages are sent; every character when empty
+    #[serde(default)]
+    pub characters: Vec<String>,
+    /// Whether each new message is emailed as it arrives
+    #[serde(default = "default_true")]
+    pub messages: bool,
+    /// Whether the daily digest of unread messages is emailed
+    #[serde(default)]
+    pub digest: bool,
+}
+
+/// An MQTT broker that new messages are published to and job commands are taken from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MqttSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_mqtt_host")]
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(rename = "clientId", default = "default_mqtt_client_id")]
+    pub client_id: String,
+    /// Topic each new message is published to, where `{character}` and `{user}` are
+    /// replaced by their slugs
+    #[serde(rename = "messageTopic", default = "default_mqtt_message_topic")]
+    pub message_topic: String,
+    /// Topic that runs the job whose slug is at `{job}` when anything is published to it
+    /// A JSON body is passed to the job's prompts as `{{payload}}` variables
+    #[serde(rename = "commandTopic", default = "default_mqtt_command_topic")]
+    pub command_topic: String,
+}
+
+impl Default for MqttSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: default_mqtt_host(),
+            port: default_mqtt_port(),
+            username: None,
+            password: None,
+            client_id: default_mqtt_client_id(),
+            message_topic: default_mqtt_message_topic(),
+            command_topic: default_mqtt_command_topic(),
+        }
+    }
+}
+
+fn default_mqtt_host() -> String {
+    "localhost".to_string()
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    "storytime".to_string()
+}
+
+fn default_mqtt_message_topic() -> String {
+    "storytime/{character}/message".to_string()
+}
+
+fn default_mqtt_command_topic() -> String {
+    "storytime/jobs/{job}/run".to_string()
+}
+
+/// A Telegram bot that delivers characters' messages and passes replies back to them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TelegramSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Token from @BotFather
+    #[serde(rename = "botToken", default, skip_serializing_if = "Option::is_none")]
+    pub bot_token: Option<String>,
+    /// Bot API server, which can be changed to a self-hosted server or a mock
+    #[serde(rename = "apiUrl", default = "default_telegram_api_url")]
+    pub api_url: String,
+    /// Telegram chats the bot writes to, each receiving one user's messages
+    #[serde(default)]
+    pub chats: Vec<TelegramChat>,
+    /// Whether replies generated for Telegram messages include a voice note
+    #[serde(rename = "voiceReplies", default)]
+    pub voice_replies: bool,
+}
+
+impl Default for TelegramSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bot_token: None,
+            api_url: default_telegram_api_url(),
+            chats: Vec::new(),
+            voice_replies: false,
+        }
+    }
+}
+
+fn default_telegram_api_url() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+/// Links a Telegram chat to the user whose messages it receives and whose replies it sends
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TelegramChat {
+    #[serde(rename = "chatId")]
+    pub chat_id: i64,
+    #[serde(default = "default_user")]
+    pub user: String,
+}
+
+fn default_user() -> String {
+    crate::auth::DEFAULT_USER.to_string()
+}
+
+/// Web Push notifications for new messages
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PushSettings {
+    /// Contact push services can reach the operator at, as a `mailto:` or `https:` URL
+    /// Falls back to `publicUrl`, and to a placeholder address when that is unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+}
+
+/// An HTTP endpoint that is sent a JSON payload when the chosen events happen
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookSettings {
+    /// Identifies the webhook in the delivery log and the test endpoint
+    pub name: String,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    /// Deliveries are signed with HMAC-SHA256 over `{timestamp}.{body}` when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+/// Events a webhook can be sent
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    MessageCreated,
+    JobFailed,
+    /// Sent only by the test endpoint
+    Ping,
+}
+
+impl std::fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            WebhookEvent::MessageCreated => "message_created",
+            WebhookEvent::JobFailed => "job_failed",
+            WebhookEvent::Ping => "ping",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One attempt to deliver a webhook, as recorded in the delivery log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookDelivery {
+    /// Shared by every attempt of the same delivery
+    pub id: uuid::Uuid,
+    pub webhook: String,
+    pub event: WebhookEvent,
+    pub attempt: u32,
+    pub timestamp: DateTime<Utc>,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Optional authentication for the API; when disabled every route is open, as before
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Origins allowed to call the API from another site with credentials
+    /// When empty, any origin may call the API but without cookies
+    #[serde(rename = "corsOrigins", default)]
+    pub cors_origins: Vec<String>,
+    /// How long a login lasts before the session cookie expires
+    #[serde(rename = "sessionHours", default = "default_session_hours")]
+    pub session_hours: u64,
+}
+
+impl Default for AuthSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cors_origins: Vec::new(),
+            session_hours: default_session_hours(),
+        }
+    }
+}
+
+fn default_session_hours() -> u64 {
+    24 * 7
+}
+
+/// Automatic backups of the data directory, written to `data/backups`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_backup_cadence")]
+    pub cadence: String,
+    /// Number of automatic backups to keep; older ones are deleted
+    #[serde(default = "default_backup_retention")]
+    pub retention: usize,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cadence: default_backup_cadence(),
+            retention: default_backup_retention(),
+        }
+    }
+}
+
+fn default_backup_cadence() -> String {
+    "0 0 3 * * *".to_string()
+}
+
+fn default_backup_retention() -> usize {
+    7
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -76,6 +473,9 @@ pub struct KoboldCppGenerate {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct KoboldCppResult {
     pub text: String,
+    /// Reported by recent KoboldCpp versions only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -115,6 +515,30 @@ pub struct Character {
     pub background: String,
     #[serde(default)]
     pub voice: Option<Voice>,
+    /// Greeting that opens every conversation, as in a character card's `first_mes`
+    #[serde(default)]
+    pub first_message: String,
+    /// Sample conversation showing the character's voice, as in a character card's `mes_example`
+    #[serde(default)]
+    pub example_dialogue: String,
+}
+
+/// Who the characters are talking to, described to the model so messages can be personal
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Persona {
+    pub name: String,
+    #[serde(default)]
+    pub pronouns: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub interests: Vec<String>,
+    /// How the persona relates to each character ("your younger sister"), keyed by character name
+    #[serde(default)]
+    pub relationships: BTreeMap<String, String>,
+    /// The user this persona describes; a persona without one describes the default user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -153,6 +577,10 @@ pub struct CreateCharacterRequest {
     pub personality: String,
     pub background: String,
     pub voice: Option<Voice>,
+    #[serde(default)]
+    pub first_message: String,
+    #[serde(default)]
+    pub example_dialogue: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -161,6 +589,33 @@ pub struct UpdateCharacterRequest {
     pub personality: Option<String>,
     pub background: Option<String>,
     pub voice: Option<Voice>,
+    pub first_message: Option<String>,
+    pub example_dialogue: Option<String>,
+}
+
+// Persona CRUD request/response models
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreatePersonaRequest {
+    pub name: String,
+    #[serde(default)]
+    pub pronouns: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub interests: Vec<String>,
+    #[serde(default)]
+    pub relationships: BTreeMap<String, String>,
+    pub user: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdatePersonaRequest {
+    pub pronouns: Option<String>,
+    pub description: Option<String>,
+    pub interests: Option<Vec<String>>,
+    pub relationships: Option<BTreeMap<String, String>>,
+    /// An empty name unassigns the persona, making it the default user's
+    pub user: Option<String>,
 }
 
 // Prompt CRUD request/response models
@@ -196,12 +651,22 @@ pub struct Message {
     #[serde(default)]
     pub read: bool,
     pub timestamp: DateTime<Utc>,
+    /// Written by the user to the character, such as a reply from Telegram
+    #[serde(
+        rename = "fromUser",
+        default,
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub from_user: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Chat {
     pub character: String,
     pub messages: Vec<Message>,
+    /// Muted chats still receive messages but send no push notifications
+    #[serde(default)]
+    pub muted: bool,
 }
 
 // Chat CRUD request/response models
@@ -213,6 +678,7 @@ pub struct CreateChatRequest {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UpdateChatRequest {
     pub character: Option<String>,
+    pub muted: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -226,6 +692,8 @@ pub struct AddMessageRequest {
     pub read: bool,
     #[serde(default)]
     pub timestamp: Option<DateTime<Utc>>,
+    #[serde(rename = "fromUser", default)]
+    pub from_user: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -246,6 +714,31 @@ pub struct Job {
     pub cadence: String,
     #[serde(rename = "prompt-override")]
     pub prompt_override: Option<String>,
+    /// Disabled jobs are kept on disk but never scheduled
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Users who receive the job's messages, each in their own chat
+    /// When empty, every user receives them
+    #[serde(default)]
+    pub subscribers: Vec<String>,
+    /// Hash of the secret token in the job's trigger URL, if it has one
+    #[serde(
+        rename = "trigger-hash",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub trigger_hash: Option<String>,
+}
+
+/// A job's trigger URL, returned once when it is created
+/// The job runs when `url` is POSTed to with the token in the `header` header. Tools that
+/// cannot send headers may append `/{token}` to the URL instead, at the cost of the token
+/// showing up wherever URLs are logged
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobTrigger {
+    pub token: String,
+    pub url: String,
+    pub header: String,
 }
 
 // Job CRUD request/response models
@@ -256,6 +749,10 @@ pub struct CreateJobRequest {
     pub cadence: String,
     #[serde(rename = "prompt-override")]
     pub prompt_override: Option<String>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub subscribers: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -266,6 +763,11 @@ pub struct UpdateJobRequest {
     pub cadence: String,
     #[serde(rename = "prompt-override")]
     pub prompt_override: Option<String>,
+    /// Left as it is when omitted, so that editing a job does not re-enable it
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    #[serde(default)]
+    pub subscribers: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -299,3 +801,202 @@ pub struct TestCharacterRequest {
 fn default_false() -> bool {
     false
 }
+
+// Auth models
+
+/// What an API token or session may do
+/// `Admin` grants every other scope as well
+#[derive(
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Read characters, prompts, jobs, chats and media
+    Read,
+    /// Create, change and delete characters, prompts, jobs and chats
+    Write,
+    /// Run jobs and tests, which call the LLM and TTS backends
+    Run,
+    /// Settings, backups, migrations, data repair and user management
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 4] = [Scope::Read, Scope::Write, Scope::Run, Scope::Admin];
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scope::Read => write!(f, "read"),
+            Scope::Write => write!(f, "write"),
+            Scope::Run => write!(f, "run"),
+            Scope::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub name: String,
+    /// Argon2 PHC string; never returned by the API
+    /// Users without a password cannot sign in, which is fine while authentication is disabled
+    #[serde(
+        rename = "passwordHash",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub password_hash: Option<String>,
+    #[serde(default)]
+    pub admin: bool,
+}
+
+impl User {
+    /// Scopes granted to a session signed in as this user
+    pub fn scopes(&self) -> Vec<Scope> {
+        if self.admin {
+            Scope::ALL.to_vec()
+        } else {
+            vec![Scope::Read, Scope::Write, Scope::Run]
+        }
+    }
+}
+
+/// A bearer token for scripts, stored with a hash of its secret rather than the secret itself
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiToken {
+    pub id: uuid::Uuid,
+    pub name: String,
+    /// The user the token acts as
+    pub user: String,
+    pub scopes: Vec<Scope>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "secretHash")]
+    pub secret_hash: String,
+}
+
+/// A browser's Web Push subscription, as given by `PushSubscription.toJSON()`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PushSubscription {
+    /// The push service URL notifications are POSTed to
+    pub endpoint: String,
+    pub keys: PushSubscriptionKeys,
+    #[serde(rename = "createdAt", default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// The keys a browser decrypts notifications with, both base64url encoded
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PushSubscriptionKeys {
+    /// The browser's P-256 public key
+    pub p256dh: String,
+    /// A 16 byte secret mixed into the encryption key
+    pub auth: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UnsubscribePushRequest {
+    pub endpoint: String,
+}
+
+/// The key browsers need to subscribe, passed as `applicationServerKey`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PushPublicKey {
+    #[serde(rename = "publicKey")]
+    pub public_key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LoginRequest {
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateUserRequest {
+    pub name: String,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub admin: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateUserRequest {
+    /// A new password; an empty string removes the password
+    pub password: Option<String>,
+    pub admin: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// A user as returned by the API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserInfo {
+    pub name: String,
+    pub admin: bool,
+    pub scopes: Vec<Scope>,
+}
+
+impl From<&User> for UserInfo {
+    fn from(user: &User) -> Self {
+        Self {
+            name: user.name.clone(),
+            admin: user.admin,
+            scopes: user.scopes(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionInfo {
+    #[serde(rename = "authEnabled")]
+    pub auth_enabled: bool,
+    pub user: Option<UserInfo>,
+}
+
+/// A newly created token; the secret is only ever shown here
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreatedToken {
+    #[serde(flatten)]
+    pub token: TokenInfo,
+    pub secret: String,
+}
+
+/// An API token as returned by the API, without its secret hash
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TokenInfo {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub user: String,
+    pub scopes: Vec<Scope>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<&ApiToken> for TokenInfo {
+    fn from(token: &ApiToken) -> Self {
+        Self {
+            id: token.id,
+            name: token.name.clone(),
+            user: token.user.clone(),
+            scopes: token.scopes.clone(),
+            created_at: token.created_at,
+        }
+    }
+}
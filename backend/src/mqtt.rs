@@ -0,0 +1,405 @@
+use chrono::{DateTime, Utc};
+use rumqttc::{AsyncClient, EventLoop, MqttOptions, Packet, QoS};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::controllers::job_controller::load_job_by_slug;
+use crate::events::{Event, events, next_event};
+use crate::job_scheduler::run_job_now;
+use crate::models::{Message, MqttSettings, Settings};
+use crate::settings::SharedSettings;
+use crate::utils::{character_slug, user_slug};
+use crate::webhooks::audio_urls;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// Wait before reconnecting after the broker could not be reached
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Publishes queued for the broker before further ones are dropped
+const QUEUE_SIZE: usize = 64;
+
+/// JSON body published for each new message
+#[derive(Serialize, Debug)]
+pub struct MessagePayload {
+    pub user: String,
+    pub character: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<uuid::Uuid>,
+    pub text: Vec<String>,
+    /// Links to the message's audio, absolute when `publicUrl` is set
+    pub audio: Vec<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The topic a message from `character` to `user` is published to
+pub fn message_topic(mqtt: &MqttSettings, user: &str, character: &str) -> String {
+    mqtt.message_topic
+        .replace("{character}", &character_slug(character))
+        .replace("{user}", &user_slug(user))
+}
+
+/// The filter subscribed to for every job's command topic
+pub fn command_filter(mqtt: &MqttSettings) -> String {
+    mqtt.command_topic.replace("{job}", "+")
+}
+
+/// The slug of the job a command topic runs, if `topic` is one
+pub fn job_from_topic<'a>(mqtt: &MqttSettings, topic: &'a str) -> Option<&'a str> {
+    let levels: Vec<&str> = mqtt.command_topic.split('/').collect();
+    let topic_levels: Vec<&str> = topic.split('/').collect();
+    if levels.len() != topic_levels.len() {
+        return None;
+    }
+
+    let mut job = None;
+    for (level, topic_level) in levels.into_iter().zip(topic_levels) {
+        if level == "{job}" {
+            job = Some(topic_level);
+        } else if level != topic_level {
+            return None;
+        }
+    }
+    job.filter(|slug| !slug.is_empty())
+}
+
+/// Publish new messages to the MQTT broker and run jobs when their command topic is written to
+/// Reconnects whenever the MQTT settings change
+pub fn start_mqtt_bridge(settings: SharedSettings) {
+    let (mut receiver, closed) = events().subscribe();
+    let mut changes = settings.subscribe();
+
+    events().spawn(async move {
+        loop {
+            let mqtt = settings.current().mqtt.clone();
+            let (client, mut eventloop) = match mqtt.enabled.then(|| connect(&mqtt)) {
+                Some((client, eventloop)) => (Some(client), Some(eventloop)),
+                None => (None, None),
+            };
+
+            loop {
+                tokio::select! {
+                    changed = changes.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                        if settings.current().mqtt != mqtt {
+                            disconnect(client.as_ref());
+                            break;
+                        }
+                    }
+                    event = next_event(&mut receiver, &closed) => match event {
+                        None => {
+                            disconnect(client.as_ref());
+                            flush(eventloop.as_mut()).await;
+                            return;
+                        }
+                        Some(Ok(Event::MessageCreated {
+                            user,
+                            character,
+                            message,
+                            job,
+                            ..
+                        })) if !message.from_user => {
+                            if let Some(client) = &client {
+                                let payload = MessagePayload::new(
+                                    &settings.current(),
+                                    user,
+                                    character,
+                                    job,
+                                    &message,
+                                );
+                                publish_message(client, &mqtt, &payload);
+                            }
+                        }
+                        Some(Ok(_)) => {}
+                        Some(Err(RecvError::Lagged(missed))) => {
+                            tracing::warn!("MQTT bridge fell behind, {missed} messages not published");
+                        }
+                        Some(Err(RecvError::Closed)) => return,
+                    },
+                    packet = next_packet(eventloop.as_mut()) => match packet {
+                        Packet::ConnAck(_) => {
+                            tracing::info!("Connected to MQTT broker {}:{}", mqtt.host, mqtt.port);
+                            // Sessions are clean, so the subscription is made again on every connect
+                            if let Some(client) = &client
+                                && let Err(e) = client.try_subscribe(command_filter(&mqtt), QoS::AtLeastOnce)
+                            {
+                                tracing::warn!("Failed to subscribe to MQTT job commands: {e}");
+                            }
+                        }
+                        Packet::Publish(publish) => {
+                            if let Some(slug) = job_from_topic(&mqtt, &publish.topic) {
+                                tokio::spawn(run_command(
+                                    slug.to_string(),
+                                    publish.payload.to_vec(),
+                                    settings.current(),
+                                ));
+                            }
+                        }
+                        _ => {}
+                    },
+                }
+            }
+        }
+    });
+}
+
+impl MessagePayload {
+    fn new(
+        settings: &Settings,
+        user: String,
+        character: String,
+        job: Option<uuid::Uuid>,
+        message: &Message,
+    ) -> Self {
+        Self {
+            audio: audio_urls(settings, &character, message),
+            user,
+            character,
+            job,
+            text: message.text.clone(),
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+fn connect(mqtt: &MqttSettings) -> (AsyncClient, EventLoop) {
+    let mut options = MqttOptions::new(&mqtt.client_id, &mqtt.host, mqtt.port);
+    options.set_keep_alive(KEEP_ALIVE);
+    if let Some(username) = &mqtt.username {
+        options.set_credentials(username, mqtt.password.as_deref().unwrap_or_default());
+    }
+    AsyncClient::new(options, QUEUE_SIZE)
+}
+
+fn disconnect(client: Option<&AsyncClient>) {
+    if let Some(client) = client {
+        let _ = client.try_disconnect();
+    }
+}
+
+/// Send what is still queued, up to the disconnect, without reconnecting
+async fn flush(eventloop: Option<&mut EventLoop>) {
+    let Some(eventloop) = eventloop else {
+        return;
+    };
+    loop {
+        match eventloop.poll().await {
+            Ok(rumqttc::Event::Outgoing(rumqttc::Outgoing::Disconnect)) | Err(_) => return,
+            Ok(_) => {}
+        }
+    }
+}
+
+/// The next packet from the broker, reconnecting after errors
+/// Never resolves while the bridge is disabled
+async fn next_packet(eventloop: Option<&mut EventLoop>) -> Packet {
+    let Some(eventloop) = eventloop else {
+        return std::future::pending().await;
+    };
+    loop {
+        match eventloop.poll().await {
+            Ok(rumqttc::Event::Incoming(packet)) => return packet,
+            Ok(rumqttc::Event::Outgoing(_)) => {}
+            Err(e) => {
+                tracing::warn!("MQTT connection failed: {e}");
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+fn publish_message(client: &AsyncClient, mqtt: &MqttSettings, payload: &MessagePayload) {
+    let topic = message_topic(mqtt, &payload.user, &payload.character);
+    let body = match serde_json::to_vec(payload) {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to serialize MQTT message: {e}");
+            return;
+        }
+    };
+    if let Err(e) = client.try_publish(&topic, QoS::AtLeastOnce, false, body) {
+        tracing::warn!("Failed to publish message to MQTT topic '{topic}': {e}");
+    }
+}
+
+/// Run the job `slug` for a command, passing a JSON body to its prompts
+async fn run_command(slug: String, body: Vec<u8>, settings: Arc<Settings>) {
+    let payload = if body.iter().all(u8::is_ascii_whitespace) {
+        None
+    } else {
+        match serde_json::from_slice::<Value>(&body) {
+            Ok(payload) => Some(payload),
+            Err(e) => {
+                tracing::warn!("Ignoring MQTT command for job '{slug}', body is not JSON: {e}");
+                return;
+            }
+        }
+    };
+    let job = match load_job_by_slug(&slug).await {
+        Ok(job) => job,
+        Err(e) => {
+            tracing::warn!("Ignoring MQTT command for job '{slug}': {e}");
+            return;
+        }
+    };
+    match run_job_now(job, settings, payload).await {
+        Ok(()) => tracing::info!("Running job '{slug}' for an MQTT command"),
+        Err(e) => tracing::error!("Failed to run job '{slug}' for an MQTT command: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use rumqttc::{ConnAck, ConnectReturnCode, PubAck, SubAck, SubscribeReasonCode};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc;
+
+    const MAX_PACKET: usize = 1024 * 1024;
+
+    #[test]
+    fn test_topics() {
+        let mqtt = MqttSettings {
+            message_topic: "home/{user}/{character}".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            message_topic(&mqtt, "default", "Brave Knight"),
+            "home/default/brave-knight"
+        );
+        assert_eq!(command_filter(&mqtt), "storytime/jobs/+/run");
+        assert_eq!(
+            job_from_topic(&mqtt, "storytime/jobs/bedtime/run"),
+            Some("bedtime")
+        );
+        assert_eq!(job_from_topic(&mqtt, "storytime/jobs//run"), None);
+        assert_eq!(job_from_topic(&mqtt, "storytime/jobs/bedtime"), None);
+        assert_eq!(job_from_topic(&mqtt, "other/jobs/bedtime/run"), None);
+    }
+
+    /// Read the next packet from a client, or `None` once it disconnects
+    async fn read_packet(stream: &mut TcpStream, buffer: &mut BytesMut) -> Option<Packet> {
+        loop {
+            match Packet::read(buffer, MAX_PACKET) {
+                Ok(packet) => return Some(packet),
+                Err(rumqttc::Error::InsufficientBytes(_)) => {
+                    if stream.read_buf(buffer).await.ok()? == 0 {
+                        return None;
+                    }
+                }
+                Err(e) => panic!("Invalid MQTT packet: {e}"),
+            }
+        }
+    }
+
+    async fn write_packet(stream: &mut TcpStream, packet: Packet) {
+        let mut buffer = BytesMut::new();
+        packet.write(&mut buffer, MAX_PACKET).unwrap();
+        stream.write_all(&buffer).await.unwrap();
+    }
+
+    /// A local broker for one client that acknowledges everything and reports the
+    /// subscriptions and publishes it receives
+    async fn local_broker() -> (u16, mpsc::UnboundedReceiver<Packet>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = BytesMut::new();
+            while let Some(packet) = read_packet(&mut stream, &mut buffer).await {
+                let reply = match &packet {
+                    Packet::Connect(_) => Some(Packet::ConnAck(ConnAck::new(
+                        ConnectReturnCode::Success,
+                        false,
+                    ))),
+                    Packet::Subscribe(subscribe) => Some(Packet::SubAck(SubAck::new(
+                        subscribe.pkid,
+                        vec![SubscribeReasonCode::Success(QoS::AtLeastOnce)],
+                    ))),
+                    Packet::Publish(publish) if publish.qos == QoS::AtLeastOnce => {
+                        Some(Packet::PubAck(PubAck::new(publish.pkid)))
+                    }
+                    Packet::PingReq => Some(Packet::PingResp),
+                    _ => None,
+                };
+                if let Some(reply) = reply {
+                    write_packet(&mut stream, reply).await;
+                }
+                if matches!(packet, Packet::Subscribe(_) | Packet::Publish(_)) {
+                    let _ = sender.send(packet);
+                }
+            }
+        });
+        (port, receiver)
+    }
+
+    async fn next_from_broker(receiver: &mut mpsc::UnboundedReceiver<Packet>) -> Packet {
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .expect("Timed out waiting for the MQTT bridge")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_publishes_messages_to_broker() {
+        let (port, mut broker) = local_broker().await;
+        let mut settings: Settings =
+            serde_json::from_str(r#"{"ttsApi": "https://tts.test", "llmApi": "https://llm.test"}"#)
+                .unwrap();
+        settings.public_url = Some("https://story.example".to_string());
+        settings.mqtt = MqttSettings {
+            enabled: true,
+            host: "127.0.0.1".to_string(),
+            port,
+            client_id: "storytime-test".to_string(),
+            message_topic: "test/{character}/message".to_string(),
+            ..Default::default()
+        };
+        start_mqtt_bridge(SharedSettings::new(settings));
+
+        let Packet::Subscribe(subscribe) = next_from_broker(&mut broker).await else {
+            panic!("Expected the bridge to subscribe first");
+        };
+        assert_eq!(subscribe.filters[0].path, "storytime/jobs/+/run");
+
+        let message = |text: &str, from_user: bool| Message {
+            text: vec![text.to_string()],
+            audio: vec!["abc".to_string()],
+            images: vec![],
+            read: false,
+            timestamp: Utc::now(),
+            from_user,
+        };
+        for (text, from_user) in [("Hello?", true), ("I ride at dawn.", false)] {
+            events().publish(Event::MessageCreated {
+                user: "default".to_string(),
+                character: "Mqtt Knight".to_string(),
+                index: 0,
+                message: message(text, from_user),
+                job: None,
+            });
+        }
+
+        // Other tests share the event bus, so only this test's character is looked at
+        let publish = loop {
+            if let Packet::Publish(publish) = next_from_broker(&mut broker).await
+                && publish.topic == "test/mqtt-knight/message"
+            {
+                break publish;
+            }
+        };
+        let payload: Value = serde_json::from_slice(&publish.payload).unwrap();
+        assert_eq!(payload["character"], "Mqtt Knight");
+        assert_eq!(payload["text"], serde_json::json!(["I ride at dawn."]));
+        assert_eq!(
+            payload["audio"],
+            serde_json::json!(["https://story.example/audio/mqtt-knight/abc.mp3"])
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use chrono::{DateTime, Utc};
+use tokio::fs;
+
+use crate::error::AppError;
+use crate::media_store::{MediaKind, MediaStore};
+use crate::models::{Chat, Message};
+use crate::utils::{character_slug, chat_dir};
+
+/// Longest episode title taken from the first line of a message
+const MAX_TITLE_CHARS: usize = 100;
+
+/// A podcast feed of audio messages
+#[derive(Debug, Clone)]
+pub struct Feed {
+    pub title: String,
+    pub description: String,
+    /// Page the feed links back to, relative to the base URL
+    pub link: String,
+    pub episodes: Vec<Episode>,
+}
+
+/// One MP3 attached to a character's message
+#[derive(Debug, Clone)]
+pub struct Episode {
+    pub character: String,
+    pub audio: String,
+    pub title: String,
+    pub description: String,
+    pub published: DateTime<Utc>,
+    /// Size of the MP3 in bytes
+    pub length: u64,
+}
+
+impl Episode {
+    /// One episode per audio file of `message`, numbered when there are several
+    fn from_message(character: &str, message: &Message) -> Vec<Self> {
+        let first_line = message
+            .text
+            .iter()
+            .flat_map(|text| text.lines())
+            .map(str::trim)
+            .find(|line| !line.is_empty());
+        let title = match first_line {
+            Some(line) => {
+                let mut title: String = line.chars().take(MAX_TITLE_CHARS).collect();
+                if title.len() < line.len() {
+                    title.push('…');
+                }
+                title
+            }
+            None => format!("Message from {character}"),
+        };
+
+        let parts = message.audio.len();
+        message
+            .audio
+            .iter()
+            .enumerate()
+            .map(|(i, audio)| Self {
+                character: character.to_string(),
+                audio: audio.clone(),
+                title: if parts > 1 {
+                    format!("{title} ({}/{parts})", i + 1)
+                } else {
+                    title.clone()
+                },
+                description: message.text.join("\n\n"),
+                published: message.timestamp,
+                length: 0,
+            })
+            .collect()
+    }
+}
+
+/// Episodes from the audio messages characters sent `user`, newest first
+/// Only the given character's messages are included when `character` is set, and episodes
+/// whose MP3 is missing are left out
+pub async fn load_episodes(user: &str, character: Option<&str>) -> Result<Vec<Episode>, AppError> {
+    let store = MediaStore::data();
+    let mut episodes = Vec::new();
+    let mut entries = match fs::read_dir(chat_dir(user)).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(episodes),
+        Err(e) => return Err(e.into()),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.path().extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let content = fs::read_to_string(entry.path()).await?;
+        let chat: Chat = match serde_json::from_str(&content) {
+            Ok(chat) => chat,
+            Err(e) => {
+                tracing::warn!(
+                    "Skipping unreadable chat '{}' in podcast: {e}",
+                    entry.path().display()
+                );
+                continue;
+            }
+        };
+        if character.is_some_and(|wanted| character_slug(wanted) != character_slug(&chat.character))
+        {
+            continue;
+        }
+
+        for message in chat.messages.iter().filter(|message| !message.from_user) {
+            for mut episode in Episode::from_message(&chat.character, message) {
+                let filename = format!("{}.mp3", episode.audio);
+                let length = match store
+                    .resolve_for(MediaKind::Audio, &chat.character, &filename)
+                    .await
+                {
+                    Ok(path) => fs::metadata(&path)
+                        .await
+                        .map(|metadata| metadata.len())
+                        .map_err(AppError::from),
+                    Err(e) => Err(e),
+                };
+                match length {
+                    Ok(length) => {
+                        episode.length = length;
+                        episodes.push(episode);
+                    }
+                    Err(e) => tracing::debug!("Leaving '{filename}' out of podcast: {e}"),
+                }
+            }
+        }
+    }
+
+    episodes.sort_by_key(|episode| std::cmp::Reverse(episode.published));
+    Ok(episodes)
+}
+
+/// RSS 2.0 with iTunes tags, with every link made absolute with `base`
+pub fn render_feed(feed: &Feed, base: &str) -> String {
+    let base = base.trim_end_matches('/');
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\">\n\
+         <channel>\n",
+    );
+    xml.push_str(&format!("<title>{}</title>\n", escape_xml(&feed.title)));
+    xml.push_str(&format!(
+        "<link>{}</link>\n",
+        escape_xml(&format!("{base}{}", feed.link))
+    ));
+    xml.push_str(&format!(
+        "<description>{}</description>\n",
+        escape_xml(&feed.description)
+    ));
+    xml.push_str(&format!(
+        "<itunes:author>{}</itunes:author>\n",
+        escape_xml(&feed.title)
+    ));
+    xml.push_str("<itunes:category text=\"Kids &amp; Family\"/>\n");
+    xml.push_str("<itunes:explicit>false</itunes:explicit>\n");
+    if let Some(latest) = feed.episodes.iter().map(|episode| episode.published).max() {
+        xml.push_str(&format!(
+            "<lastBuildDate>{}</lastBuildDate>\n",
+            latest.to_rfc2822()
+        ));
+    }
+
+    for episode in &feed.episodes {
+        let slug = character_slug(&episode.character);
+        let url = format!("{base}/audio/{slug}/{}.mp3", episode.audio);
+        xml.push_str("<item>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape_xml(&episode.title)));
+        xml.push_str(&format!(
+            "<description>{}</description>\n",
+            escape_xml(&episode.description)
+        ));
+        xml.push_str(&format!(
+            "<itunes:author>{}</itunes:author>\n",
+            escape_xml(&episode.character)
+        ));
+        xml.push_str(&format!(
+            "<pubDate>{}</pubDate>\n",
+            episode.published.to_rfc2822()
+        ));
+        xml.push_str(&format!(
+            "<guid isPermaLink=\"false\">{}</guid>\n",
+            escape_xml(&format!("{slug}/{}", episode.audio))
+        ));
+        xml.push_str(&format!(
+            "<enclosure url=\"{}\" length=\"{}\" type=\"audio/mpeg\"/>\n",
+            escape_xml(&url),
+            episode.length
+        ));
+        xml.push_str("</item>\n");
+    }
+
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn message(text: &str, audio: &[&str]) -> Message {
+        Message {
+            text: vec![text.to_string()],
+            audio: audio.iter().map(|id| id.to_string()).collect(),
+            images: vec![],
+            read: false,
+            timestamp: Utc.with_ymd_and_hms(2025, 3, 1, 19, 30, 0).unwrap(),
+            from_user: false,
+        }
+    }
+
+    #[test]
+    fn test_episode_titles() {
+        let episodes = Episode::from_message(
+            "Brave Knight",
+            &message("\n  Once upon a time  \nthere was a dragon.", &["a", "b"]),
+        );
+        assert_eq!(episodes.len(), 2);
+        assert_eq!(episodes[0].title, "Once upon a time (1/2)");
+        assert_eq!(episodes[1].title, "Once upon a time (2/2)");
+
+        let episodes = Episode::from_message("Brave Knight", &message("", &["a"]));
+        assert_eq!(episodes[0].title, "Message from Brave Knight");
+
+        let long = "z".repeat(150);
+        let episodes = Episode::from_message("Brave Knight", &message(&long, &["a"]));
+        assert_eq!(episodes[0].title.chars().count(), MAX_TITLE_CHARS + 1);
+
+        assert!(Episode::from_message("Brave Knight", &message("Hi", &[])).is_empty());
+    }
+
+    #[test]
+    fn test_render_feed() {
+        let mut episodes =
+            Episode::from_message("Brave Knight", &message("Dragons & <castles>", &["abc"]));
+        episodes[0].length = 4321;
+        let feed = Feed {
+            title: "Brave Knight".to_string(),
+            description: "Bedtime stories from the knight".to_string(),
+            link: "/chats/brave-knight".to_string(),
+            episodes,
+        };
+
+        let xml = render_feed(&feed, "http://story.lan:3000/");
+        assert!(xml.contains("<title>Dragons &amp; &lt;castles&gt;</title>"));
+        assert!(xml.contains("<link>http://story.lan:3000/chats/brave-knight</link>"));
+        assert!(xml.contains("<pubDate>Sat, 1 Mar 2025 19:30:00 +0000</pubDate>"));
+        assert!(xml.contains(
+            "<enclosure url=\"http://story.lan:3000/audio/brave-knight/abc.mp3\" length=\"4321\" type=\"audio/mpeg\"/>"
+        ));
+        assert!(xml.contains("<guid isPermaLink=\"false\">brave-knight/abc</guid>"));
+    }
+}
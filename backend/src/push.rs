@@ -0,0 +1,590 @@
+use aes_gcm::{Aes128Gcm, KeyInit, Nonce, aead::Aead};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use hkdf::Hkdf;
+use p256::ecdsa::{Signature, SigningKey, signature::Signer};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::{PublicKey, SecretKey, ecdh::EphemeralSecret};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::Duration;
+use tokio::fs;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::config::data_dir;
+use crate::error::AppError;
+use crate::events::{Event, events, next_event};
+use crate::metrics::metrics;
+use crate::models::{Chat, Message, PushSubscription, PushSubscriptionKeys, Settings};
+use crate::settings::SharedSettings;
+use crate::utils::{
+    character_slug, chat_file_path, push_subscriptions_file_path, write_file_atomic,
+};
+
+/// How long a push service keeps trying to reach an offline browser
+const TTL_SECONDS: u32 = 24 * 60 * 60;
+/// How long a VAPID token is valid for; push services reject ones over 24 hours
+const VAPID_EXPIRY_SECONDS: i64 = 12 * 60 * 60;
+/// Record size written into the encryption header; a notification always fits in one record
+const RECORD_SIZE: u32 = 4096;
+/// Longest notification body, so the encrypted payload stays within one record
+const MAX_BODY_CHARS: usize = 200;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_SUBJECT: &str = "mailto:storytime@localhost";
+
+/// The server's VAPID key pair, which identifies it to push services
+pub struct VapidKeys {
+    signing_key: SigningKey,
+    /// Uncompressed public key, base64url encoded as browsers expect
+    pub public_key: String,
+}
+
+/// VAPID keys as stored in `data/push/vapid.json`
+#[derive(Serialize, Deserialize)]
+struct StoredVapidKeys {
+    #[serde(rename = "privateKey")]
+    private_key: String,
+}
+
+impl VapidKeys {
+    pub fn generate() -> Self {
+        Self::from_secret(SecretKey::random(&mut OsRng))
+    }
+
+    fn from_secret(secret: SecretKey) -> Self {
+        let public_key = URL_SAFE_NO_PAD.encode(secret.public_key().to_encoded_point(false));
+        Self {
+            signing_key: SigningKey::from(secret),
+            public_key,
+        }
+    }
+
+    /// The `Authorization` header for a request to `endpoint`
+    fn authorization(&self, endpoint: &str, subject: &str) -> Result<String, String> {
+        let audience = reqwest::Url::parse(endpoint)
+            .map_err(|e| format!("Invalid push endpoint: {e}"))?
+            .origin()
+            .ascii_serialization();
+        let header = URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#);
+        let claims = serde_json::json!({
+            "aud": audience,
+            "exp": Utc::now().timestamp() + VAPID_EXPIRY_SECONDS,
+            "sub": subject,
+        });
+        let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let signed = format!("{header}.{claims}");
+        let signature: Signature = self.signing_key.sign(signed.as_bytes());
+        Ok(format!(
+            "vapid t={signed}.{}, k={}",
+            URL_SAFE_NO_PAD.encode(signature.to_bytes()),
+            self.public_key
+        ))
+    }
+}
+
+static VAPID_KEYS: OnceCell<VapidKeys> = OnceCell::const_new();
+
+fn vapid_keys_path() -> std::path::PathBuf {
+    data_dir().join("push").join("vapid.json")
+}
+
+/// The server's VAPID keys, created on first use
+/// Browsers subscribe with the public key, so replacing it would silence every subscription
+pub async fn vapid_keys() -> Result<&'static VapidKeys, AppError> {
+    VAPID_KEYS
+        .get_or_try_init(|| async {
+            let path = vapid_keys_path();
+            if let Ok(content) = fs::read_to_string(&path).await {
+                let stored: StoredVapidKeys = serde_json::from_str(&content)?;
+                let secret = URL_SAFE_NO_PAD
+                    .decode(&stored.private_key)
+                    .ok()
+                    .and_then(|bytes| SecretKey::from_slice(&bytes).ok())
+                    .ok_or_else(|| {
+                        AppError::Internal(format!("Invalid VAPID key in {}", path.display()))
+                    })?;
+                return Ok(VapidKeys::from_secret(secret));
+            }
+
+            tracing::info!("Creating VAPID keys for Web Push");
+            let secret = SecretKey::random(&mut OsRng);
+            let stored = StoredVapidKeys {
+                private_key: URL_SAFE_NO_PAD.encode(secret.to_bytes()),
+            };
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            write_file_atomic(&path, serde_json::to_vec_pretty(&stored)?).await?;
+            Ok(VapidKeys::from_secret(secret))
+        })
+        .await
+}
+
+/// What the service worker shows for a new message
+#[derive(Serialize, Debug)]
+pub struct PushNotification {
+    pub title: String,
+    pub body: String,
+    pub character: String,
+    /// Page to open when the notification is clicked
+    pub url: String,
+}
+
+impl PushNotification {
+    /// The character's name and the first line of the message
+    pub fn for_message(character: &str, message: &Message) -> Self {
+        let first_line = message
+            .text
+            .iter()
+            .flat_map(|text| text.lines())
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default();
+        let mut body: String = first_line.chars().take(MAX_BODY_CHARS).collect();
+        if body.len() < first_line.len() {
+            body.push('…');
+        }
+        Self {
+            title: character.to_string(),
+            body,
+            character: character.to_string(),
+            url: format!("/chats/{}", character_slug(character)),
+        }
+    }
+}
+
+/// Send a notification for every new message to the browsers of the user it was written to
+pub fn start_push_dispatcher(settings: SharedSettings) {
+    let (mut receiver, closed) = events().subscribe();
+
+    events().spawn(async move {
+        while let Some(event) = next_event(&mut receiver, &closed).await {
+            match event {
+                Ok(Event::MessageCreated {
+                    user,
+                    character,
+                    message,
+                    ..
+                }) => {
+                    let settings = settings.current();
+                    events().spawn(async move {
+                        notify(&user, &character, &message, &settings).await;
+                    });
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Push dispatcher fell behind, {missed} events not sent");
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+/// Push a new message from a character to each of `user`'s subscriptions unless they muted the chat
+/// Subscriptions the push service reports as gone are forgotten
+async fn notify(user: &str, character: &str, message: &Message, settings: &Settings) {
+    if message.from_user || is_muted(user, character).await {
+        return;
+    }
+    let subscriptions = match load_subscriptions(user).await {
+        Ok(subscriptions) => subscriptions,
+        Err(e) => {
+            tracing::warn!("Failed to load push subscriptions for '{user}': {e}");
+            return;
+        }
+    };
+    if subscriptions.is_empty() {
+        return;
+    }
+    let keys = match vapid_keys().await {
+        Ok(keys) => keys,
+        Err(e) => {
+            tracing::error!("Failed to load VAPID keys: {e}");
+            return;
+        }
+    };
+
+    let notification = PushNotification::for_message(character, message);
+    let Ok(payload) = serde_json::to_vec(&notification) else {
+        return;
+    };
+    let subject = subject(settings);
+    for subscription in subscriptions {
+        let outcome = match send(keys, &subscription, &subject, &payload).await {
+            Ok(status) if (200..300).contains(&status) => "success",
+            Ok(404 | 410) => {
+                tracing::info!("Push subscription for '{user}' expired, removing it");
+                if let Err(e) = remove_subscription(user, &subscription.endpoint).await {
+                    tracing::warn!("Failed to remove expired push subscription: {e}");
+                }
+                "expired"
+            }
+            Ok(status) => {
+                tracing::warn!("Push service rejected notification with HTTP {status}");
+                "failure"
+            }
+            Err(e) => {
+                tracing::warn!("Failed to send push notification: {e}");
+                "failure"
+            }
+        };
+        metrics()
+            .push_notifications
+            .with_label_values(&[outcome])
+            .inc();
+    }
+}
+
+/// Whether the user muted their chat with a character; unreadable chats are not muted
+async fn is_muted(user: &str, character: &str) -> bool {
+    match fs::read_to_string(chat_file_path(user, character)).await {
+        Ok(content) => serde_json::from_str::<Chat>(&content).is_ok_and(|chat| chat.muted),
+        Err(_) => false,
+    }
+}
+
+/// The contact sent to push services, from `push.subject` or `publicUrl`
+fn subject(settings: &Settings) -> String {
+    settings
+        .push
+        .subject
+        .clone()
+        .or_else(|| {
+            settings
+                .public_url
+                .clone()
+                .filter(|url| url.starts_with("https://"))
+        })
+        .unwrap_or_else(|| DEFAULT_SUBJECT.to_string())
+}
+
+/// Encrypt and POST a payload to a subscription's push service, returning the response status
+pub async fn send(
+    keys: &VapidKeys,
+    subscription: &PushSubscription,
+    subject: &str,
+    payload: &[u8],
+) -> Result<u16, String> {
+    let body = encrypt(&subscription.keys, payload)?;
+    reqwest::Client::new()
+        .post(&subscription.endpoint)
+        .timeout(REQUEST_TIMEOUT)
+        .header(
+            reqwest::header::AUTHORIZATION,
+            keys.authorization(&subscription.endpoint, subject)?,
+        )
+        .header(reqwest::header::CONTENT_ENCODING, "aes128gcm")
+        .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+        .header("ttl", TTL_SECONDS.to_string())
+        .header("urgency", "normal")
+        .body(body)
+        .send()
+        .await
+        .map(|response| response.status().as_u16())
+        .map_err(|e| e.to_string())
+}
+
+/// Encrypt a payload for a browser with the `aes128gcm` content encoding of RFC 8291
+pub fn encrypt(keys: &PushSubscriptionKeys, payload: &[u8]) -> Result<Vec<u8>, String> {
+    let client_key = decode_client_key(&keys.p256dh)?;
+    let auth = decode_auth_secret(&keys.auth)?;
+
+    let ephemeral = EphemeralSecret::random(&mut OsRng);
+    let server_key = ephemeral.public_key().to_encoded_point(false);
+    let shared = ephemeral.diffie_hellman(&client_key);
+    let salt: [u8; 16] = rand::random();
+
+    let (cek, nonce) = content_keys(
+        shared.raw_secret_bytes(),
+        &auth,
+        client_key.to_encoded_point(false).as_bytes(),
+        server_key.as_bytes(),
+        &salt,
+    );
+
+    // A single record, ended by the last-record delimiter
+    let mut plaintext = payload.to_vec();
+    plaintext.push(2);
+    let ciphertext = Aes128Gcm::new_from_slice(&cek)
+        .map_err(|e| e.to_string())?
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+        .map_err(|e| format!("Failed to encrypt push payload: {e}"))?;
+    if ciphertext.len() > RECORD_SIZE as usize {
+        return Err("Push payload is too large".to_string());
+    }
+
+    let mut body = Vec::with_capacity(86 + ciphertext.len());
+    body.extend_from_slice(&salt);
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(server_key.as_bytes().len() as u8);
+    body.extend_from_slice(server_key.as_bytes());
+    body.extend_from_slice(&ciphertext);
+    Ok(body)
+}
+
+/// The content encryption key and nonce shared by the server and the browser
+fn content_keys(
+    shared_secret: &[u8],
+    auth: &[u8],
+    client_key: &[u8],
+    server_key: &[u8],
+    salt: &[u8],
+) -> ([u8; 16], [u8; 12]) {
+    let mut key_info = b"WebPush: info\0".to_vec();
+    key_info.extend_from_slice(client_key);
+    key_info.extend_from_slice(server_key);
+    let mut ikm = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(auth), shared_secret)
+        .expand(&key_info, &mut ikm)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+    let hkdf = Hkdf::<Sha256>::new(Some(salt), &ikm);
+    let mut cek = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    hkdf.expand(b"Content-Encoding: aes128gcm\0", &mut cek)
+        .expect("16 bytes is a valid HKDF-SHA256 output length");
+    hkdf.expand(b"Content-Encoding: nonce\0", &mut nonce)
+        .expect("12 bytes is a valid HKDF-SHA256 output length");
+    (cek, nonce)
+}
+
+fn decode_client_key(p256dh: &str) -> Result<PublicKey, String> {
+    URL_SAFE_NO_PAD
+        .decode(p256dh.trim_end_matches('='))
+        .ok()
+        .and_then(|bytes| PublicKey::from_sec1_bytes(&bytes).ok())
+        .ok_or_else(|| "keys.p256dh is not a P-256 public key".to_string())
+}
+
+fn decode_auth_secret(auth: &str) -> Result<Vec<u8>, String> {
+    URL_SAFE_NO_PAD
+        .decode(auth.trim_end_matches('='))
+        .ok()
+        .filter(|bytes| bytes.len() == 16)
+        .ok_or_else(|| "keys.auth is not a 16 byte secret".to_string())
+}
+
+/// Check that a subscription can be encrypted for before storing it
+pub fn validate_subscription(subscription: &PushSubscription) -> Result<(), String> {
+    match reqwest::Url::parse(&subscription.endpoint) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+        _ => return Err("endpoint must be an http or https URL".to_string()),
+    }
+    decode_client_key(&subscription.keys.p256dh)?;
+    decode_auth_secret(&subscription.keys.auth)?;
+    Ok(())
+}
+
+// Subscription storage
+
+/// Serializes changes to subscription files, which the dispatcher and API both modify
+static SUBSCRIPTIONS_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// A user's push subscriptions, oldest first
+pub async fn load_subscriptions(user: &str) -> Result<Vec<PushSubscription>, AppError> {
+    match fs::read_to_string(push_subscriptions_file_path(user)).await {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn save_subscriptions(
+    user: &str,
+    subscriptions: &[PushSubscription],
+) -> Result<(), AppError> {
+    let path = std::path::PathBuf::from(push_subscriptions_file_path(user));
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    write_file_atomic(&path, serde_json::to_vec_pretty(subscriptions)?).await?;
+    Ok(())
+}
+
+/// Store a subscription, replacing any with the same endpoint
+pub async fn add_subscription(user: &str, subscription: PushSubscription) -> Result<(), AppError> {
+    let _guard = SUBSCRIPTIONS_LOCK.lock().await;
+    let mut subscriptions = load_subscriptions(user).await?;
+    subscriptions.retain(|existing| existing.endpoint != subscription.endpoint);
+    subscriptions.push(subscription);
+    save_subscriptions(user, &subscriptions).await
+}
+
+/// Forget a subscription, returning whether the user had it
+pub async fn remove_subscription(user: &str, endpoint: &str) -> Result<bool, AppError> {
+    let _guard = SUBSCRIPTIONS_LOCK.lock().await;
+    let mut subscriptions = load_subscriptions(user).await?;
+    let count = subscriptions.len();
+    subscriptions.retain(|existing| existing.endpoint != endpoint);
+    if subscriptions.len() == count {
+        return Ok(false);
+    }
+    save_subscriptions(user, &subscriptions).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Bytes, http::HeaderMap, routing::post};
+    use p256::ecdsa::{VerifyingKey, signature::Verifier};
+    use std::sync::Arc;
+
+    /// Decrypt a payload the way a browser does
+    fn decrypt(secret: &SecretKey, auth: &[u8], body: &[u8]) -> Vec<u8> {
+        let salt = &body[..16];
+        assert_eq!(u32::from_be_bytes(body[16..20].try_into().unwrap()), 4096);
+        let key_length = body[20] as usize;
+        let server_key = &body[21..21 + key_length];
+        let ciphertext = &body[21 + key_length..];
+
+        let shared = p256::ecdh::diffie_hellman(
+            secret.to_nonzero_scalar(),
+            PublicKey::from_sec1_bytes(server_key).unwrap().as_affine(),
+        );
+        let (cek, nonce) = content_keys(
+            shared.raw_secret_bytes(),
+            auth,
+            secret.public_key().to_encoded_point(false).as_bytes(),
+            server_key,
+            salt,
+        );
+        let mut plaintext = Aes128Gcm::new_from_slice(&cek)
+            .unwrap()
+            .decrypt(Nonce::from_slice(&nonce), ciphertext)
+            .unwrap();
+        assert_eq!(plaintext.pop(), Some(2));
+        plaintext
+    }
+
+    /// A local stand-in for a browser vendor's push service that records what it is sent
+    async fn push_service(status: u16) -> (String, Arc<Mutex<Vec<(HeaderMap, Bytes)>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&received);
+        let app = Router::new().route(
+            "/push/{id}",
+            post(move |headers: HeaderMap, body: Bytes| async move {
+                recorded.lock().await.push((headers, body));
+                axum::http::StatusCode::from_u16(status).unwrap()
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{address}/push/abc"), received)
+    }
+
+    fn subscription(endpoint: &str, browser: &SecretKey, auth: &[u8]) -> PushSubscription {
+        PushSubscription {
+            endpoint: endpoint.to_string(),
+            keys: PushSubscriptionKeys {
+                p256dh: URL_SAFE_NO_PAD.encode(browser.public_key().to_encoded_point(false)),
+                auth: URL_SAFE_NO_PAD.encode(auth),
+            },
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_to_push_service() {
+        let (endpoint, received) = push_service(201).await;
+        let vapid = VapidKeys::generate();
+        let browser = SecretKey::random(&mut OsRng);
+        let auth: [u8; 16] = rand::random();
+        let subscription = subscription(&endpoint, &browser, &auth);
+        assert!(validate_subscription(&subscription).is_ok());
+
+        let message = Message {
+            text: vec!["\nWell met, traveller!\nThe road is long.".to_string()],
+            audio: vec![],
+            images: vec![],
+            read: false,
+            timestamp: Utc::now(),
+            from_user: false,
+        };
+        let payload =
+            serde_json::to_vec(&PushNotification::for_message("Brave Knight", &message)).unwrap();
+        let status = send(&vapid, &subscription, "mailto:admin@story.test", &payload)
+            .await
+            .unwrap();
+        assert_eq!(status, 201);
+
+        let received = received.lock().await;
+        let (headers, body) = &received[0];
+        assert_eq!(headers["content-encoding"], "aes128gcm");
+        assert_eq!(headers["ttl"], TTL_SECONDS.to_string());
+
+        let notification: serde_json::Value =
+            serde_json::from_slice(&decrypt(&browser, &auth, body)).unwrap();
+        assert_eq!(notification["title"], "Brave Knight");
+        assert_eq!(notification["body"], "Well met, traveller!");
+        assert_eq!(notification["url"], "/chats/brave-knight");
+
+        // The VAPID token is signed by the key browsers subscribed with
+        let authorization = headers["authorization"].to_str().unwrap();
+        let (token, key) = authorization
+            .strip_prefix("vapid t=")
+            .unwrap()
+            .split_once(", k=")
+            .unwrap();
+        assert_eq!(key, vapid.public_key);
+        let (signed, signature) = token.rsplit_once('.').unwrap();
+        let verifying_key =
+            VerifyingKey::from_sec1_bytes(&URL_SAFE_NO_PAD.decode(key).unwrap()).unwrap();
+        let signature = Signature::from_slice(&URL_SAFE_NO_PAD.decode(signature).unwrap()).unwrap();
+        assert!(verifying_key.verify(signed.as_bytes(), &signature).is_ok());
+
+        let claims = signed.split('.').nth(1).unwrap();
+        let claims: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).unwrap()).unwrap();
+        assert_eq!(claims["aud"], endpoint.trim_end_matches("/push/abc"));
+        assert_eq!(claims["sub"], "mailto:admin@story.test");
+    }
+
+    #[tokio::test]
+    async fn test_expired_subscription_status() {
+        let (endpoint, _) = push_service(410).await;
+        let browser = SecretKey::random(&mut OsRng);
+        let subscription = subscription(&endpoint, &browser, &[7; 16]);
+        let status = send(
+            &VapidKeys::generate(),
+            &subscription,
+            DEFAULT_SUBJECT,
+            b"{}",
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, 410);
+    }
+
+    #[test]
+    fn test_content_keys_match_rfc_8291_example() {
+        let decode = |value: &str| URL_SAFE_NO_PAD.decode(value).unwrap();
+        let (cek, nonce) = content_keys(
+            &decode("kyrL1jIIOHEzg3sM2ZWRHDRB62YACZhhSlknJ672kSs"),
+            &decode("BTBZMqHH6r4Tts7J_aSIgg"),
+            &decode(
+                "BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4",
+            ),
+            &decode(
+                "BP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS6TlzAC8wEqKK6PBru3jl7A8",
+            ),
+            &decode("DGv6ra1nlYgDCS1FRnbzlw"),
+        );
+        assert_eq!(URL_SAFE_NO_PAD.encode(cek), "oIhVW04MRdy2XN9CiKLxTg");
+        assert_eq!(URL_SAFE_NO_PAD.encode(nonce), "4h_95klXJ5E_qnoN");
+    }
+
+    #[test]
+    fn test_invalid_subscription_keys() {
+        let browser = SecretKey::random(&mut OsRng);
+        let mut subscription = subscription("https://push.test/abc", &browser, &[1; 8]);
+        assert!(validate_subscription(&subscription).is_err());
+        subscription.keys.auth = URL_SAFE_NO_PAD.encode([1; 16]);
+        subscription.keys.p256dh = "bm90IGEga2V5".to_string();
+        assert!(validate_subscription(&subscription).is_err());
+    }
+}
@@ -1,42 +1,88 @@
 use axum::{
     Router,
-    routing::{get, post, put},
+    extract::{DefaultBodyLimit, MatchedPath, Request},
+    http::{HeaderValue, header, request::Parts},
+    middleware,
+    routing::{delete, get, post, put},
 };
-use std::sync::Arc;
 use tower::ServiceBuilder;
 use tower_http::{
-    cors::CorsLayer,
+    cors::{AllowCredentials, AllowHeaders, AllowMethods, AllowOrigin, CorsLayer},
     services::{ServeDir, ServeFile},
     trace::{self, TraceLayer},
 };
-use tracing::Level;
+use tracing::{Level, Span};
 
+use crate::auth::require_auth;
+use crate::config::assets_dir;
 use crate::controllers::{
-    audio_controller::serve_audio,
+    auth_controller::{create_token, delete_token, get_session, get_tokens, login, logout},
+    backup_controller::{download_backup, restore_backup},
     character_controller::{
-        create_character, delete_character, get_character, get_characters, update_character,
+        create_character, delete_character, export_character_card, get_character, get_characters,
+        import_character_card, update_character,
     },
     chat_controller::{
         add_message, create_chat, delete_chat_endpoint, delete_message, get_chat, get_chats,
         mark_all_messages_as_read, mark_message_as_read, update_chat, update_message,
     },
-    health_controller::{health_check, hello, hello_name},
+    event_controller::stream_events,
+    fsck_controller::{check_data, repair_data},
+    health_controller::{health_check, hello, hello_name, readiness_check},
     job_controller::{
-        create_job, delete_job, get_job, get_jobs, migrate_jobs, run_job, run_job_by_slug,
-        test_character_with_prompt, test_prompt_with_character, update_job,
+        create_job, create_job_trigger, delete_job, delete_job_trigger, get_job, get_jobs, run_job,
+        run_job_by_slug, test_character_with_prompt, test_prompt_with_character, trigger_job,
+        trigger_job_by_path, update_job,
     },
+    media_controller::{serve_audio, serve_image},
+    metrics_controller::get_metrics,
+    migration_controller::{get_migration_status, run_pending_migrations},
+    persona_controller::{
+        create_persona, delete_persona, get_persona, get_personas, update_persona,
+    },
+    podcast_controller::{get_character_podcast, get_podcast},
     prompt_controller::{create_prompt, delete_prompt, get_prompt, get_prompts, update_prompt},
+    push_controller::{get_public_key, subscribe, unsubscribe},
     scheduler_controller::{get_scheduler_status, reload_scheduler_jobs},
+    settings_controller::{get_settings, reload_settings, update_settings},
+    user_controller::{create_user, delete_user, get_user, get_users, update_user},
+    webhook_controller::{get_deliveries, test_webhook},
 };
-use crate::models::Settings;
+use crate::metrics::{UNMATCHED_ROUTE, track_http};
+use crate::settings::SharedSettings;
+
+/// PNG character cards embed a full portrait, so allow more than the default 2MB body
+const CHARACTER_CARD_LIMIT: usize = 20 * 1024 * 1024;
 
-pub fn create_app(settings: Arc<Settings>) -> Router {
+pub fn create_app(settings: SharedSettings) -> Router {
     Router::new()
         .route("/health", get(health_check))
+        .route("/health/live", get(health_check))
+        .route("/health/ready", get(readiness_check))
+        .route("/metrics", get(get_metrics))
         .route("/api/hello", get(hello))
         .route("/api/hello/{name}", get(hello_name))
-        // Audio file serving
+        // Authentication routes
+        .route("/api/auth/login", post(login))
+        .route("/api/auth/logout", post(logout))
+        .route("/api/auth/session", get(get_session))
+        .route("/api/auth/tokens", get(get_tokens).post(create_token))
+        .route("/api/auth/tokens/{id}", delete(delete_token))
+        // Media file serving
         .route("/audio/{character}/{filename}", get(serve_audio))
+        .route("/images/{character}/{filename}", get(serve_image))
+        // Podcast feeds of audio messages
+        .route("/api/podcasts/feed.xml", get(get_podcast))
+        .route(
+            "/api/podcasts/{character}/feed.xml",
+            get(get_character_podcast),
+        )
+        // User CRUD routes
+        .route("/api/users", get(get_users).post(create_user))
+        .route(
+            "/api/users/{name}",
+            get(get_user).put(update_user).delete(delete_user),
+        )
         // Character CRUD routes
         .route(
             "/api/characters",
@@ -48,6 +94,18 @@ pub fn create_app(settings: Arc<Settings>) -> Router {
                 .put(update_character)
                 .delete(delete_character),
         )
+        // Character card import/export routes
+        .route(
+            "/api/character-cards",
+            post(import_character_card).layer(DefaultBodyLimit::max(CHARACTER_CARD_LIMIT)),
+        )
+        .route("/api/characters/{slug}/card", get(export_character_card))
+        // Persona CRUD routes
+        .route("/api/personas", get(get_personas).post(create_persona))
+        .route(
+            "/api/personas/{slug}",
+            get(get_persona).put(update_persona).delete(delete_persona),
+        )
         // Prompt CRUD routes
         .route("/api/prompts", get(get_prompts).post(create_prompt))
         .route(
@@ -84,27 +142,111 @@ pub fn create_app(settings: Arc<Settings>) -> Router {
         // Job execution routes
         .route("/api/jobs/{slug}/run", post(run_job_by_slug))
         .route("/api/jobs/run", post(run_job))
-        // Job migration route
-        .route("/api/jobs/migrate", post(migrate_jobs))
+        // Job trigger routes; the token in a hook URL is its own authentication
+        .route(
+            "/api/jobs/{slug}/trigger",
+            post(create_job_trigger).delete(delete_job_trigger),
+        )
+        .route("/api/hooks", post(trigger_job))
+        .route("/api/hooks/{token}", post(trigger_job_by_path))
+        // Data migration routes
+        .route(
+            "/api/migrations",
+            get(get_migration_status).post(run_pending_migrations),
+        )
         // Test routes
         .route("/api/test/prompt", post(test_prompt_with_character))
         .route("/api/test/character", post(test_character_with_prompt))
+        // Data integrity routes
+        .route("/api/fsck", get(check_data))
+        .route("/api/fsck/repair", post(repair_data))
+        // Backup and restore routes
+        .route("/api/backup", get(download_backup))
+        .route(
+            "/api/restore",
+            post(restore_backup).layer(DefaultBodyLimit::disable()),
+        )
+        // Real-time event stream
+        .route("/api/events", get(stream_events))
+        // Web Push routes
+        .route("/api/push/key", get(get_public_key))
+        .route(
+            "/api/push/subscriptions",
+            post(subscribe).delete(unsubscribe),
+        )
         // Scheduler routes
         .route("/api/scheduler/status", get(get_scheduler_status))
         .route("/api/scheduler/reload", post(reload_scheduler_jobs))
+        // Webhook routes
+        .route("/api/webhooks/deliveries", get(get_deliveries))
+        .route("/api/webhooks/{name}/test", post(test_webhook))
+        // Settings routes
+        .route("/api/settings", get(get_settings).put(update_settings))
+        .route("/api/settings/reload", post(reload_settings))
         // Static file serving from assets directory - serve at root for SPA compatibility
         .fallback_service(
-            ServeDir::new("assets").not_found_service(ServeFile::new("assets/index.html")),
+            ServeDir::new(assets_dir())
+                .not_found_service(ServeFile::new(assets_dir().join("index.html"))),
         )
-        .with_state(settings)
+        // Route layers see the matched route, which decides the scope a request needs
+        .route_layer(middleware::from_fn_with_state(
+            settings.clone(),
+            require_auth,
+        ))
+        .with_state(settings.clone())
         .layer(
             ServiceBuilder::new()
                 .layer(
                     TraceLayer::new_for_http()
-                        .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
+                        .make_span_with(request_span)
                         .on_request(trace::DefaultOnRequest::new().level(Level::INFO))
                         .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
                 )
-                .layer(CorsLayer::permissive()),
+                .layer(cors_layer(settings))
+                .layer(middleware::from_fn(track_http)),
         )
 }
+
+/// The span requests are traced in, naming the route template rather than the URI
+/// Paths and queries can hold secrets, such as the token at the end of a trigger URL
+fn request_span(request: &Request) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str())
+        .unwrap_or(UNMATCHED_ROUTE);
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        route,
+        version = ?request.version(),
+    )
+}
+
+/// CORS that follows `auth.corsOrigins` as settings change
+/// With no origins listed any site may call the API, but only listed origins may send cookies
+fn cors_layer(settings: SharedSettings) -> CorsLayer {
+    let listed = move |origin: &HeaderValue| {
+        let auth = &settings.current().auth;
+        let is_listed = auth
+            .cors_origins
+            .iter()
+            .any(|allowed| allowed.trim_end_matches('/').as_bytes() == origin.as_bytes());
+        (is_listed, auth.cors_origins.is_empty())
+    };
+    let credentials = listed.clone();
+
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(
+            move |origin: &HeaderValue, _: &Parts| {
+                let (is_listed, open) = listed(origin);
+                is_listed || open
+            },
+        ))
+        .allow_credentials(AllowCredentials::predicate(
+            move |origin: &HeaderValue, _: &Parts| credentials(origin).0,
+        ))
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+        .expose_headers([header::CONTENT_DISPOSITION])
+}
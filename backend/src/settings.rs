@@ -1,13 +1,73 @@
-use crate::models::Settings;
+use cron::Schedule;
 use std::fs;
-use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+use crate::config::data_dir;
+use crate::models::{EmailSettings, MqttSettings, Settings, TelegramSettings, WebhookSettings};
+use crate::utils::write_file_atomic;
+
+/// Placeholder returned in place of secrets; sending it back leaves the secret unchanged
+pub const REDACTED: &str = "********";
+
+/// Environment variables that override values from settings.json, for Docker deployments
+pub const ENV_OVERRIDES: [&str; 13] = [
+    "STORYTIME_TTS_API",
+    "STORYTIME_LLM_API",
+    "STORYTIME_TTS_API_KEY",
+    "STORYTIME_LLM_API_KEY",
+    "STORYTIME_BACKUP_ENABLED",
+    "STORYTIME_BACKUP_CADENCE",
+    "STORYTIME_BACKUP_RETENTION",
+    "STORYTIME_AUTH_ENABLED",
+    "STORYTIME_CORS_ORIGINS",
+    "STORYTIME_PUBLIC_URL",
+    "STORYTIME_TELEGRAM_BOT_TOKEN",
+    "STORYTIME_MQTT_PASSWORD",
+    "STORYTIME_SMTP_PASSWORD",
+];
+
+/// Settings shared by the HTTP handlers and the schedulers
+/// Cloning is cheap, and every clone sees settings replaced through any other clone
+#[derive(Clone)]
+pub struct SharedSettings(Arc<watch::Sender<Arc<Settings>>>);
+
+impl SharedSettings {
+    pub fn new(settings: Settings) -> Self {
+        Self(Arc::new(watch::Sender::new(Arc::new(settings))))
+    }
+
+    /// The settings currently in effect
+    /// Callers should hold on to the returned value for the duration of one operation
+    pub fn current(&self) -> Arc<Settings> {
+        Arc::clone(&self.0.borrow())
+    }
+
+    /// Replace the settings for everything that reads them from now on
+    pub fn replace(&self, settings: Settings) {
+        self.0.send_replace(Arc::new(settings));
+    }
+
+    /// Re-read settings.json and the environment, replacing the current settings
+    pub fn reload(&self) -> Result<Arc<Settings>, Box<dyn std::error::Error>> {
+        self.replace(load_settings()?);
+        Ok(self.current())
+    }
+
+    /// Receive a notification whenever the settings are replaced
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Settings>> {
+        self.0.subscribe()
+    }
+}
 
 /// Ensures that the data directory structure and settings.json file exist.
 /// Creates them with default values if they don't exist.
 fn ensure_data_directory_and_settings() -> Result<(), Box<dyn std::error::Error>> {
-    let data_dir = Path::new("./data");
+    let data_dir = data_dir();
     let characters_dir = data_dir.join("characters");
     let prompts_dir = data_dir.join("prompts");
+    let personas_dir = data_dir.join("personas");
     let jobs_dir = data_dir.join("jobs");
     let chats_dir = data_dir.join("chats");
     let audio_dir = data_dir.join("audio");
@@ -15,7 +75,7 @@ fn ensure_data_directory_and_settings() -> Result<(), Box<dyn std::error::Error>
 
     // Create data directory structure if it doesn't exist
     if !data_dir.exists() {
-        tracing::info!("Creating data directory at ./data");
+        tracing::info!("Creating data directory at {}", data_dir.display());
         fs::create_dir_all(data_dir)?;
     }
 
@@ -23,12 +83,13 @@ fn ensure_data_directory_and_settings() -> Result<(), Box<dyn std::error::Error>
     for (dir_path, dir_name) in [
         (&characters_dir, "characters"),
         (&prompts_dir, "prompts"),
+        (&personas_dir, "personas"),
         (&jobs_dir, "jobs"),
         (&chats_dir, "chats"),
         (&audio_dir, "audio"),
     ] {
         if !dir_path.exists() {
-            tracing::info!("Creating {} directory at ./data/{}", dir_name, dir_name);
+            tracing::info!("Creating {} directory at {}", dir_name, dir_path.display());
             fs::create_dir_all(dir_path)?;
         }
     }
@@ -41,20 +102,29 @@ fn ensure_data_directory_and_settings() -> Result<(), Box<dyn std::error::Error>
     "llmApi": "https://www.example.com/api/v1/generate"
 }"#;
         fs::write(&settings_path, default_settings)?;
-        tracing::info!("Settings file created at ./data/settings.json");
+        tracing::info!("Settings file created at {}", settings_path.display());
         tracing::warn!(
-            "Please update the settings in ./data/settings.json with your actual API endpoints"
+            "Please update the settings in {} with your actual API endpoints",
+            settings_path.display()
         );
     }
 
     Ok(())
 }
 
+/// Load settings.json with any `STORYTIME_*` environment overrides applied
 pub fn load_settings() -> Result<Settings, Box<dyn std::error::Error>> {
+    let mut settings = load_settings_file()?;
+    apply_env_overrides(&mut settings, |name| std::env::var(name).ok())?;
+    Ok(settings)
+}
+
+/// Load settings.json exactly as stored, without environment overrides
+pub fn load_settings_file() -> Result<Settings, Box<dyn std::error::Error>> {
     // Ensure data directory and settings file exist
     ensure_data_directory_and_settings()?;
 
-    let settings_path = Path::new("./data/settings.json");
+    let settings_path = data_dir().join("settings.json");
 
     let contents = fs::read_to_string(settings_path)?;
     let settings: Settings = serde_json::from_str(&contents)?;
@@ -62,6 +132,369 @@ pub fn load_settings() -> Result<Settings, Box<dyn std::error::Error>> {
     Ok(settings)
 }
 
+/// Write settings.json, replacing it in one step so that a crash cannot leave it truncated
+pub async fn save_settings_file(settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+    ensure_data_directory_and_settings()?;
+    write_file_atomic(
+        data_dir().join("settings.json"),
+        serde_json::to_string_pretty(settings)?,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Names of the `STORYTIME_*` environment variables that are currently set
+pub fn active_env_overrides() -> Vec<String> {
+    ENV_OVERRIDES
+        .iter()
+        .filter(|name| std::env::var_os(name).is_some())
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Overwrite settings with values from `STORYTIME_*` variables, looked up through `var`
+pub fn apply_env_overrides(
+    settings: &mut Settings,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(value) = var("STORYTIME_TTS_API") {
+        settings.tts_api = value;
+    }
+    if let Some(value) = var("STORYTIME_LLM_API") {
+        settings.llm_api = value;
+    }
+    if let Some(value) = var("STORYTIME_TTS_API_KEY") {
+        settings.tts_api_key = Some(value);
+    }
+    if let Some(value) = var("STORYTIME_LLM_API_KEY") {
+        settings.llm_api_key = Some(value);
+    }
+    if let Some(value) = var("STORYTIME_BACKUP_ENABLED") {
+        settings.backup.enabled = value.parse().map_err(|_| {
+            format!("STORYTIME_BACKUP_ENABLED must be true or false, got '{value}'")
+        })?;
+    }
+    if let Some(value) = var("STORYTIME_BACKUP_CADENCE") {
+        settings.backup.cadence = value;
+    }
+    if let Some(value) = var("STORYTIME_BACKUP_RETENTION") {
+        settings.backup.retention = value
+            .parse()
+            .map_err(|_| format!("STORYTIME_BACKUP_RETENTION must be a number, got '{value}'"))?;
+    }
+    if let Some(value) = var("STORYTIME_AUTH_ENABLED") {
+        settings.auth.enabled = value
+            .parse()
+            .map_err(|_| format!("STORYTIME_AUTH_ENABLED must be true or false, got '{value}'"))?;
+    }
+    if let Some(value) = var("STORYTIME_CORS_ORIGINS") {
+        // Comma separated, since a list does not fit in one environment variable otherwise
+        settings.auth.cors_origins = value
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+    if let Some(value) = var("STORYTIME_PUBLIC_URL") {
+        settings.public_url = Some(value).filter(|url| !url.is_empty());
+    }
+    if let Some(value) = var("STORYTIME_TELEGRAM_BOT_TOKEN") {
+        settings.telegram.bot_token = Some(value);
+    }
+    if let Some(value) = var("STORYTIME_MQTT_PASSWORD") {
+        settings.mqtt.password = Some(value);
+    }
+    if let Some(value) = var("STORYTIME_SMTP_PASSWORD") {
+        settings.email.password = Some(value);
+    }
+    Ok(())
+}
+
+/// Put back the stored value of every field that a `STORYTIME_*` variable overrides
+/// Settings read through the API show the overriding values, which must not be written to
+/// settings.json, or they would outlive the variable
+pub fn keep_stored_overridden(
+    settings: &mut Settings,
+    stored: &Settings,
+    var: impl Fn(&str) -> Option<String>,
+) {
+    let overridden = |name: &str| var(name).is_some();
+    if overridden("STORYTIME_TTS_API") {
+        settings.tts_api = stored.tts_api.clone();
+    }
+    if overridden("STORYTIME_LLM_API") {
+        settings.llm_api = stored.llm_api.clone();
+    }
+    if overridden("STORYTIME_TTS_API_KEY") {
+        settings.tts_api_key = stored.tts_api_key.clone();
+    }
+    if overridden("STORYTIME_LLM_API_KEY") {
+        settings.llm_api_key = stored.llm_api_key.clone();
+    }
+    if overridden("STORYTIME_BACKUP_ENABLED") {
+        settings.backup.enabled = stored.backup.enabled;
+    }
+    if overridden("STORYTIME_BACKUP_CADENCE") {
+        settings.backup.cadence = stored.backup.cadence.clone();
+    }
+    if overridden("STORYTIME_BACKUP_RETENTION") {
+        settings.backup.retention = stored.backup.retention;
+    }
+    if overridden("STORYTIME_AUTH_ENABLED") {
+        settings.auth.enabled = stored.auth.enabled;
+    }
+    if overridden("STORYTIME_CORS_ORIGINS") {
+        settings.auth.cors_origins = stored.auth.cors_origins.clone();
+    }
+    if overridden("STORYTIME_PUBLIC_URL") {
+        settings.public_url = stored.public_url.clone();
+    }
+    if overridden("STORYTIME_TELEGRAM_BOT_TOKEN") {
+        settings.telegram.bot_token = stored.telegram.bot_token.clone();
+    }
+    if overridden("STORYTIME_MQTT_PASSWORD") {
+        settings.mqtt.password = stored.mqtt.password.clone();
+    }
+    if overridden("STORYTIME_SMTP_PASSWORD") {
+        settings.email.password = stored.email.password.clone();
+    }
+}
+
+/// Check settings for mistakes before they are saved
+/// Returns a description of every problem found
+pub fn validate_settings(settings: &Settings) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+
+    for (name, url) in [("ttsApi", &settings.tts_api), ("llmApi", &settings.llm_api)] {
+        match reqwest::Url::parse(url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+            Ok(parsed) => errors.push(format!(
+                "{name} must be an http or https URL, got scheme '{}'",
+                parsed.scheme()
+            )),
+            Err(e) => errors.push(format!("{name} is not a valid URL: {e}")),
+        }
+    }
+
+    if let Err(e) = Schedule::from_str(&settings.backup.cadence) {
+        errors.push(format!(
+            "backup.cadence '{}' is not a valid cron expression: {e}",
+            settings.backup.cadence
+        ));
+    }
+    if settings.backup.retention == 0 {
+        errors.push("backup.retention must be at least 1".to_string());
+    }
+
+    for origin in &settings.auth.cors_origins {
+        match reqwest::Url::parse(origin) {
+            Ok(parsed)
+                if matches!(parsed.scheme(), "http" | "https")
+                    && parsed.path() == "/"
+                    && parsed.query().is_none() => {}
+            Ok(_) => errors.push(format!(
+                "auth.corsOrigins entry '{origin}' must be an http or https origin without a path"
+            )),
+            Err(e) => errors.push(format!(
+                "auth.corsOrigins entry '{origin}' is not a valid URL: {e}"
+            )),
+        }
+    }
+    if settings.auth.session_hours == 0 {
+        errors.push("auth.sessionHours must be at least 1".to_string());
+    }
+
+    if let Some(public_url) = &settings.public_url
+        && !is_http_url(public_url)
+    {
+        errors.push(format!(
+            "publicUrl '{public_url}' must be an http or https URL"
+        ));
+    }
+
+    if let Some(subject) = &settings.push.subject
+        && !subject.starts_with("mailto:")
+        && !subject.starts_with("https://")
+    {
+        errors.push(format!(
+            "push.subject '{subject}' must be a mailto: or https: URL"
+        ));
+    }
+
+    let telegram = &settings.telegram;
+    if telegram.enabled
+        && telegram
+            .bot_token
+            .as_deref()
+            .is_none_or(|token| token.trim().is_empty())
+    {
+        errors.push("telegram.botToken is required when Telegram is enabled".to_string());
+    }
+    if !is_http_url(&telegram.api_url) {
+        errors.push(format!(
+            "telegram.apiUrl '{}' must be an http or https URL",
+            telegram.api_url
+        ));
+    }
+    let mut chat_ids = std::collections::HashSet::new();
+    for chat in &telegram.chats {
+        if !chat_ids.insert(chat.chat_id) {
+            errors.push(format!(
+                "Telegram chat {} is linked more than once",
+                chat.chat_id
+            ));
+        }
+    }
+
+    let mqtt = &settings.mqtt;
+    if mqtt.enabled && mqtt.host.trim().is_empty() {
+        errors.push("mqtt.host is required when MQTT is enabled".to_string());
+    }
+    if mqtt.client_id.trim().is_empty() {
+        errors.push("mqtt.clientId must not be empty".to_string());
+    }
+    let message_topic = crate::mqtt::message_topic(mqtt, "user", "character");
+    if message_topic.is_empty() || !rumqttc::valid_topic(&message_topic) {
+        errors.push(format!(
+            "mqtt.messageTopic '{}' must be a topic without wildcards",
+            mqtt.message_topic
+        ));
+    }
+    if !mqtt.command_topic.split('/').any(|level| level == "{job}")
+        || mqtt.command_topic.contains(['+', '#'])
+        || !rumqttc::valid_filter(&crate::mqtt::command_filter(mqtt))
+    {
+        errors.push(format!(
+            "mqtt.commandTopic '{}' must be a topic with '{{job}}' as one of its levels",
+            mqtt.command_topic
+        ));
+    }
+
+    let email = &settings.email;
+    if email.enabled && email.host.trim().is_empty() {
+        errors.push("email.host is required when email is enabled".to_string());
+    }
+    if email.from.parse::<lettre::message::Mailbox>().is_err() {
+        errors.push(format!(
+            "email.from '{}' is not a valid email address",
+            email.from
+        ));
+    }
+    for recipient in &email.recipients {
+        if recipient
+            .address
+            .parse::<lettre::message::Mailbox>()
+            .is_err()
+        {
+            errors.push(format!(
+                "Email recipient '{}' is not a valid email address",
+                recipient.address
+            ));
+        }
+    }
+    if let Err(e) = Schedule::from_str(&email.digest_cadence) {
+        errors.push(format!(
+            "email.digestCadence '{}' is not a valid cron expression: {e}",
+            email.digest_cadence
+        ));
+    }
+
+    let mut names = std::collections::HashSet::new();
+    for webhook in &settings.webhooks {
+        if webhook.name.trim().is_empty() {
+            errors.push("Every webhook needs a name".to_string());
+        } else if !names.insert(webhook.name.as_str()) {
+            errors.push(format!("Webhook name '{}' is used twice", webhook.name));
+        }
+        if !is_http_url(&webhook.url) {
+            errors.push(format!(
+                "Webhook '{}' url '{}' must be an http or https URL",
+                webhook.name, webhook.url
+            ));
+        }
+        if webhook.events.is_empty() {
+            errors.push(format!("Webhook '{}' has no events", webhook.name));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|parsed| matches!(parsed.scheme(), "http" | "https"))
+}
+
+impl Settings {
+    /// A copy that is safe to return from the API, with secrets replaced by `REDACTED`
+    pub fn redacted(&self) -> Settings {
+        let redact = |secret: &Option<String>| {
+            secret
+                .as_ref()
+                .filter(|secret| !secret.is_empty())
+                .map(|_| REDACTED.to_string())
+        };
+        Settings {
+            tts_api_key: redact(&self.tts_api_key),
+            llm_api_key: redact(&self.llm_api_key),
+            webhooks: self
+                .webhooks
+                .iter()
+                .map(|webhook| WebhookSettings {
+                    secret: redact(&webhook.secret),
+                    ..webhook.clone()
+                })
+                .collect(),
+            telegram: TelegramSettings {
+                bot_token: redact(&self.telegram.bot_token),
+                ..self.telegram.clone()
+            },
+            mqtt: MqttSettings {
+                password: redact(&self.mqtt.password),
+                ..self.mqtt.clone()
+            },
+            email: EmailSettings {
+                password: redact(&self.email.password),
+                ..self.email.clone()
+            },
+            ..self.clone()
+        }
+    }
+
+    /// Replace any secrets that came back as `REDACTED` with their values from `previous`
+    pub fn restore_redacted(&mut self, previous: &Settings) {
+        if self.tts_api_key.as_deref() == Some(REDACTED) {
+            self.tts_api_key = previous.tts_api_key.clone();
+        }
+        if self.llm_api_key.as_deref() == Some(REDACTED) {
+            self.llm_api_key = previous.llm_api_key.clone();
+        }
+        if self.telegram.bot_token.as_deref() == Some(REDACTED) {
+            self.telegram.bot_token = previous.telegram.bot_token.clone();
+        }
+        if self.mqtt.password.as_deref() == Some(REDACTED) {
+            self.mqtt.password = previous.mqtt.password.clone();
+        }
+        if self.email.password.as_deref() == Some(REDACTED) {
+            self.email.password = previous.email.password.clone();
+        }
+        // Webhooks are matched by name, so a renamed webhook needs its secret sent again
+        for webhook in &mut self.webhooks {
+            if webhook.secret.as_deref() == Some(REDACTED) {
+                webhook.secret = previous
+                    .webhooks
+                    .iter()
+                    .find(|old| old.name == webhook.name)
+                    .and_then(|old| old.secret.clone());
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,4 +531,143 @@ mod tests {
         assert_eq!(settings.tts_api, "https://test.tts.com");
         assert_eq!(settings.llm_api, "https://test.llm.com");
     }
+
+    fn test_settings() -> Settings {
+        serde_json::from_str(r#"{"ttsApi": "https://tts.test", "llmApi": "https://llm.test"}"#)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let mut settings = test_settings();
+        let env = |name: &str| match name {
+            "STORYTIME_LLM_API" => Some("http://kobold:5001/api/v1/generate".to_string()),
+            "STORYTIME_LLM_API_KEY" => Some("secret".to_string()),
+            "STORYTIME_BACKUP_ENABLED" => Some("true".to_string()),
+            "STORYTIME_CORS_ORIGINS" => Some("https://a.test, https://b.test,".to_string()),
+            "STORYTIME_TELEGRAM_BOT_TOKEN" => Some("123:telegram".to_string()),
+            "STORYTIME_MQTT_PASSWORD" => Some("mqtt-secret".to_string()),
+            "STORYTIME_SMTP_PASSWORD" => Some("smtp-secret".to_string()),
+            _ => None,
+        };
+
+        apply_env_overrides(&mut settings, env).unwrap();
+
+        assert_eq!(settings.tts_api, "https://tts.test");
+        assert_eq!(settings.llm_api, "http://kobold:5001/api/v1/generate");
+        assert_eq!(settings.llm_api_key.as_deref(), Some("secret"));
+        assert!(settings.backup.enabled);
+        assert!(!settings.auth.enabled);
+        assert_eq!(
+            settings.auth.cors_origins,
+            vec!["https://a.test", "https://b.test"]
+        );
+        assert_eq!(settings.telegram.bot_token.as_deref(), Some("123:telegram"));
+        assert_eq!(settings.mqtt.password.as_deref(), Some("mqtt-secret"));
+        assert_eq!(settings.email.password.as_deref(), Some("smtp-secret"));
+
+        let bad = |name: &str| (name == "STORYTIME_BACKUP_RETENTION").then(|| "lots".to_string());
+        assert!(apply_env_overrides(&mut settings, bad).is_err());
+    }
+
+    #[test]
+    fn test_overridden_fields_keep_stored_values() {
+        let stored = test_settings();
+        let env = |name: &str| match name {
+            "STORYTIME_LLM_API" => Some("http://kobold:5001/api/v1/generate".to_string()),
+            "STORYTIME_BACKUP_ENABLED" => Some("true".to_string()),
+            "STORYTIME_PUBLIC_URL" => Some("https://story.test".to_string()),
+            "STORYTIME_TELEGRAM_BOT_TOKEN" => Some("123:telegram".to_string()),
+            "STORYTIME_MQTT_PASSWORD" => Some("mqtt-secret".to_string()),
+            "STORYTIME_SMTP_PASSWORD" => Some("smtp-secret".to_string()),
+            _ => None,
+        };
+        let mut settings = stored.clone();
+        apply_env_overrides(&mut settings, env).unwrap();
+        settings.tts_api = "https://other-tts.test".to_string();
+
+        keep_stored_overridden(&mut settings, &stored, env);
+
+        assert_eq!(settings.llm_api, "https://llm.test");
+        assert!(!settings.backup.enabled);
+        assert_eq!(settings.public_url, stored.public_url);
+        assert_eq!(settings.telegram.bot_token, None);
+        assert_eq!(settings.mqtt.password, None);
+        assert_eq!(settings.email.password, None);
+        assert_eq!(settings.tts_api, "https://other-tts.test");
+    }
+
+    #[test]
+    fn test_validate_settings() {
+        assert!(validate_settings(&test_settings()).is_ok());
+
+        let mut settings = test_settings();
+        settings.tts_api = "not a url".to_string();
+        settings.llm_api = "ftp://llm.test".to_string();
+        settings.backup.cadence = "every day".to_string();
+        settings.backup.retention = 0;
+        settings.auth.cors_origins = vec![
+            "https://ok.test".to_string(),
+            "https://bad.test/app".to_string(),
+        ];
+        settings.auth.session_hours = 0;
+        settings.webhooks = serde_json::from_str(
+            r#"[
+                {"name": "ha", "url": "http://ha.local/hook", "events": ["message_created"]},
+                {"name": "ha", "url": "mqtt://broker", "events": []}
+            ]"#,
+        )
+        .unwrap();
+        settings.push.subject = Some("admin@story.test".to_string());
+        settings.telegram.enabled = true;
+        settings.mqtt.message_topic = "storytime/+/message".to_string();
+        settings.mqtt.command_topic = "storytime/run".to_string();
+        settings.email.from = "storytime".to_string();
+        settings.email.digest_cadence = "daily".to_string();
+
+        assert_eq!(validate_settings(&settings).unwrap_err().len(), 15);
+    }
+
+    #[test]
+    fn test_redaction_round_trip() {
+        let mut settings = test_settings();
+        settings.llm_api_key = Some("secret".to_string());
+        settings.telegram.bot_token = Some("123:secret".to_string());
+        settings.mqtt.password = Some("broker-secret".to_string());
+        settings.email.password = Some("smtp-secret".to_string());
+
+        let mut redacted = settings.redacted();
+        assert_eq!(redacted.llm_api_key.as_deref(), Some(REDACTED));
+        assert_eq!(redacted.tts_api_key, None);
+        assert!(!serde_json::to_string(&redacted).unwrap().contains("secret"));
+
+        redacted.restore_redacted(&settings);
+        assert_eq!(redacted.llm_api_key.as_deref(), Some("secret"));
+        assert_eq!(redacted.telegram.bot_token.as_deref(), Some("123:secret"));
+        assert_eq!(redacted.mqtt.password.as_deref(), Some("broker-secret"));
+        assert_eq!(redacted.email.password.as_deref(), Some("smtp-secret"));
+
+        settings.webhooks = serde_json::from_str(
+            r#"[{"name": "ha", "url": "http://ha.local", "events": ["ping"], "secret": "hush"}]"#,
+        )
+        .unwrap();
+        let mut redacted = settings.redacted();
+        assert_eq!(redacted.webhooks[0].secret.as_deref(), Some(REDACTED));
+        redacted.restore_redacted(&settings);
+        assert_eq!(redacted.webhooks[0].secret.as_deref(), Some("hush"));
+    }
+
+    #[test]
+    fn test_shared_settings_replace() {
+        let shared = SharedSettings::new(test_settings());
+        let clone = shared.clone();
+        let changes = shared.subscribe();
+
+        let mut updated = test_settings();
+        updated.llm_api = "https://other.test".to_string();
+        clone.replace(updated);
+
+        assert_eq!(shared.current().llm_api, "https://other.test");
+        assert!(changes.has_changed().unwrap());
+    }
 }
@@ -0,0 +1,537 @@
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+
+use crate::controllers::job_controller::reply_to_user;
+use crate::events::{Event, events, next_event};
+use crate::job_scheduler::spawn_tracked;
+use crate::media_store::{MediaKind, MediaStore};
+use crate::models::{Chat, Message, TelegramSettings};
+use crate::settings::SharedSettings;
+use crate::utils::{chat_dir, user_slug};
+
+/// How long each `getUpdates` call waits for new messages before returning empty
+const POLL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Wait before polling again after the Bot API could not be reached
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A client for the Telegram Bot API of one bot
+pub struct BotApi {
+    client: reqwest::Client,
+    /// `{apiUrl}/bot{token}`, which every method name is appended to
+    base: String,
+}
+
+/// Every Bot API response wraps its result like this
+#[derive(Deserialize)]
+struct BotResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Update {
+    pub update_id: i64,
+    pub message: Option<TelegramMessage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TelegramMessage {
+    pub chat: TelegramChatInfo,
+    pub text: Option<String>,
+    pub caption: Option<String>,
+    pub reply_to_message: Option<Box<TelegramMessage>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TelegramChatInfo {
+    pub id: i64,
+}
+
+impl BotApi {
+    /// A client for the configured bot, or `None` while the bridge is disabled
+    pub fn from_settings(telegram: &TelegramSettings) -> Option<Self> {
+        let token = telegram
+            .bot_token
+            .as_deref()
+            .filter(|token| telegram.enabled && !token.trim().is_empty())?;
+        Some(Self {
+            client: reqwest::Client::new(),
+            base: format!("{}/bot{}", telegram.api_url.trim_end_matches('/'), token),
+        })
+    }
+
+    /// Updates after `offset`, waiting up to `timeout` for one to arrive
+    pub async fn get_updates(&self, offset: i64, timeout: Duration) -> Result<Vec<Update>, String> {
+        let request = self
+            .client
+            .post(format!("{}/getUpdates", self.base))
+            .timeout(timeout + REQUEST_TIMEOUT)
+            .json(&serde_json::json!({
+                "offset": offset,
+                "timeout": timeout.as_secs(),
+                "allowed_updates": ["message"],
+            }));
+        Self::result(request).await
+    }
+
+    /// Send a text message formatted with Telegram's HTML subset
+    pub async fn send_message(&self, chat_id: i64, html: &str) -> Result<(), String> {
+        let request = self
+            .client
+            .post(format!("{}/sendMessage", self.base))
+            .timeout(REQUEST_TIMEOUT)
+            .json(&serde_json::json!({
+                "chat_id": chat_id,
+                "text": html,
+                "parse_mode": "HTML",
+            }));
+        Self::result::<serde_json::Value>(request).await.map(|_| ())
+    }
+
+    /// Upload an MP3 as a voice note
+    pub async fn send_voice(&self, chat_id: i64, path: &Path, caption: &str) -> Result<(), String> {
+        let audio = fs::read(path)
+            .await
+            .map_err(|e| format!("Failed to read '{}': {e}", path.display()))?;
+        let voice = reqwest::multipart::Part::bytes(audio)
+            .file_name("voice.mp3")
+            .mime_str("audio/mpeg")
+            .map_err(|e| e.to_string())?;
+        let form = reqwest::multipart::Form::new()
+            .text("chat_id", chat_id.to_string())
+            .text("caption", caption.to_string())
+            .part("voice", voice);
+        let request = self
+            .client
+            .post(format!("{}/sendVoice", self.base))
+            .timeout(REQUEST_TIMEOUT)
+            .multipart(form);
+        Self::result::<serde_json::Value>(request).await.map(|_| ())
+    }
+
+    /// Show "typing…" in a chat until the next message is sent
+    pub async fn send_typing(&self, chat_id: i64) -> Result<(), String> {
+        let request = self
+            .client
+            .post(format!("{}/sendChatAction", self.base))
+            .timeout(REQUEST_TIMEOUT)
+            .json(&serde_json::json!({ "chat_id": chat_id, "action": "typing" }));
+        Self::result::<serde_json::Value>(request).await.map(|_| ())
+    }
+
+    /// Send a request and unwrap the Bot API's response envelope
+    /// Errors never include the URL, since it contains the bot token
+    async fn result<T: DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T, String> {
+        let response = request
+            .send()
+            .await
+            .map_err(|e| e.without_url().to_string())?;
+        let status = response.status();
+        let body: BotResponse<T> = response
+            .json()
+            .await
+            .map_err(|e| format!("HTTP {status}: {}", e.without_url()))?;
+        match body {
+            BotResponse {
+                ok: true,
+                result: Some(result),
+                ..
+            } => Ok(result),
+            BotResponse { description, .. } => {
+                Err(description
+                    .unwrap_or_else(|| format!("Bot API request failed with HTTP {status}")))
+            }
+        }
+    }
+}
+
+/// Deliver characters' messages through the Telegram bot and pass replies back to them
+pub fn start_telegram_bridge(settings: SharedSettings) {
+    let (mut receiver, closed) = events().subscribe();
+    tokio::spawn(poll_updates(settings.clone(), closed.clone()));
+
+    events().spawn(async move {
+        while let Some(event) = next_event(&mut receiver, &closed).await {
+            match event {
+                Ok(Event::MessageCreated {
+                    user,
+                    character,
+                    message,
+                    ..
+                }) if !message.from_user => {
+                    let telegram = settings.current().telegram.clone();
+                    events().spawn(async move {
+                        deliver(&telegram, &user, &character, &message).await;
+                    });
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Telegram bridge fell behind, {missed} messages not sent");
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+/// Send a character's message to every Telegram chat linked to `user`
+/// The character's name heads each message so replies can be routed back to them
+async fn deliver(telegram: &TelegramSettings, user: &str, character: &str, message: &Message) {
+    let Some(api) = BotApi::from_settings(telegram) else {
+        return;
+    };
+    let text = format!(
+        "<b>{}</b>\n{}",
+        escape_html(character),
+        escape_html(&message.text.join("\n"))
+    );
+
+    for chat in linked_chats(telegram, user) {
+        if let Err(e) = api.send_message(chat, &text).await {
+            tracing::warn!("Failed to send message to Telegram chat {chat}: {e}");
+            continue;
+        }
+        for id in &message.audio {
+            let path = match MediaStore::data()
+                .resolve_for(MediaKind::Audio, character, &format!("{id}.mp3"))
+                .await
+            {
+                Ok(path) => path,
+                Err(e) => {
+                    tracing::warn!("Failed to find voice note '{id}.mp3': {e}");
+                    continue;
+                }
+            };
+            if let Err(e) = api.send_voice(chat, &path, character).await {
+                tracing::warn!("Failed to send voice note to Telegram chat {chat}: {e}");
+            }
+        }
+    }
+}
+
+fn linked_chats<'a>(
+    telegram: &'a TelegramSettings,
+    user: &'a str,
+) -> impl Iterator<Item = i64> + 'a {
+    telegram
+        .chats
+        .iter()
+        .filter(move |chat| user_slug(&chat.user) == user_slug(user))
+        .map(|chat| chat.chat_id)
+}
+
+/// Long-poll the Bot API for messages until the event bus closes at shutdown
+/// Settings are re-read before every poll, so the bridge follows changes to them
+async fn poll_updates(settings: SharedSettings, closed: CancellationToken) {
+    let mut changes = settings.subscribe();
+    let mut offset = 0;
+
+    loop {
+        let current = settings.current();
+        let Some(api) = BotApi::from_settings(&current.telegram) else {
+            tokio::select! {
+                _ = closed.cancelled() => return,
+                changed = changes.changed() => if changed.is_err() { return },
+            }
+            continue;
+        };
+
+        let updates = tokio::select! {
+            _ = closed.cancelled() => return,
+            updates = api.get_updates(offset, POLL_TIMEOUT) => updates,
+        };
+        match updates {
+            Ok(updates) => {
+                for update in updates {
+                    if let Some(message) = update.message {
+                        let settings = settings.clone();
+                        let handled =
+                            spawn_tracked(async move { handle_message(settings, message).await })
+                                .await;
+                        if let Err(e) = handled {
+                            // Only the updates before this one are confirmed, so Telegram sends
+                            // this one again once the bridge is back
+                            tracing::info!("Leaving Telegram messages for later: {e}");
+                            if let Err(e) = api.get_updates(offset, Duration::ZERO).await {
+                                tracing::warn!("Failed to confirm Telegram updates: {e}");
+                            }
+                            return;
+                        }
+                    }
+                    // Telegram forgets updates once a later offset is asked for
+                    offset = offset.max(update.update_id + 1);
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Failed to get Telegram updates: {e}");
+                tokio::select! {
+                    _ = closed.cancelled() => return,
+                    _ = tokio::time::sleep(RETRY_DELAY) => {}
+                }
+            }
+        }
+    }
+}
+
+/// Pass a message sent to the bot to the character it answers, and send back their reply
+async fn handle_message(settings: SharedSettings, message: TelegramMessage) {
+    let current = settings.current();
+    let Some(api) = BotApi::from_settings(&current.telegram) else {
+        return;
+    };
+    let chat_id = message.chat.id;
+    let Some(text) = message.text.as_deref().map(str::trim) else {
+        return;
+    };
+
+    let Some(link) = current
+        .telegram
+        .chats
+        .iter()
+        .find(|chat| chat.chat_id == chat_id)
+    else {
+        let notice = format!(
+            "This chat is not linked to Storytime yet. Add chat ID <code>{chat_id}</code> to \
+             telegram.chats in the settings to receive messages here."
+        );
+        if let Err(e) = api.send_message(chat_id, &notice).await {
+            tracing::warn!("Failed to answer unlinked Telegram chat {chat_id}: {e}");
+        }
+        return;
+    };
+    if text.is_empty() || text.starts_with('/') {
+        return;
+    }
+
+    let character = match replied_character(&message) {
+        Some(character) => Some(character),
+        None => latest_character(&link.user).await,
+    };
+    let Some(character) = character else {
+        let notice = "Nobody has written to you yet, so there is no one to reply to.";
+        if let Err(e) = api.send_message(chat_id, notice).await {
+            tracing::warn!("Failed to answer Telegram chat {chat_id}: {e}");
+        }
+        return;
+    };
+
+    if let Err(e) = api.send_typing(chat_id).await {
+        tracing::debug!("Failed to show typing in Telegram chat {chat_id}: {e}");
+    }
+    // The reply reaches Telegram through the bus like any other new message
+    if let Err(e) = reply_to_user(
+        &character,
+        text,
+        current.clone(),
+        &link.user,
+        current.telegram.voice_replies,
+    )
+    .await
+    {
+        tracing::warn!("Failed to reply to Telegram message as '{character}': {e}");
+        let notice = format!("{} could not reply: {e}", escape_html(&character));
+        if let Err(e) = api.send_message(chat_id, &notice).await {
+            tracing::warn!("Failed to answer Telegram chat {chat_id}: {e}");
+        }
+    }
+}
+
+/// The character whose message was replied to, from the name heading it or its caption
+fn replied_character(message: &TelegramMessage) -> Option<String> {
+    let original = message.reply_to_message.as_deref()?;
+    original
+        .text
+        .as_deref()
+        .or(original.caption.as_deref())?
+        .lines()
+        .next()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+/// The character who wrote to `user` most recently
+async fn latest_character(user: &str) -> Option<String> {
+    let mut entries = fs::read_dir(chat_dir(user)).await.ok()?;
+    let mut latest = None;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let Ok(content) = fs::read_to_string(entry.path()).await else {
+            continue;
+        };
+        let Ok(chat) = serde_json::from_str::<Chat>(&content) else {
+            continue;
+        };
+        if let Some(message) = chat
+            .messages
+            .iter()
+            .rev()
+            .find(|message| !message.from_user)
+            && latest
+                .as_ref()
+                .is_none_or(|(timestamp, _)| message.timestamp > *timestamp)
+        {
+            latest = Some((message.timestamp, chat.character));
+        }
+    }
+    latest.map(|(_, character)| character)
+}
+
+/// Escape text for Telegram's HTML parse mode
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TelegramChat;
+    use axum::{Json, Router, body::Bytes, extract::Path as UrlPath, routing::post};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    type Requests = Arc<Mutex<Vec<(String, Bytes)>>>;
+
+    /// A local stand-in for the Bot API that records each call and answers `getUpdates`
+    /// with a single reply
+    async fn mock_bot_api() -> (String, Requests) {
+        let requests: Requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        let app = Router::new().route(
+            "/{bot}/{method}",
+            post(
+                move |UrlPath((bot, method)): UrlPath<(String, String)>, body: Bytes| async move {
+                    assert_eq!(bot, "bot123:abc");
+                    recorded.lock().await.push((method.clone(), body));
+                    let result = match method.as_str() {
+                        "getUpdates" => serde_json::json!([{
+                            "update_id": 41,
+                            "message": {
+                                "message_id": 7,
+                                "chat": {"id": 99, "type": "private"},
+                                "text": "Where are you?",
+                                "reply_to_message": {
+                                    "message_id": 6,
+                                    "chat": {"id": 99, "type": "private"},
+                                    "text": "Brave Knight\nI ride at dawn."
+                                }
+                            }
+                        }]),
+                        _ => serde_json::json!({"message_id": 8}),
+                    };
+                    Json(serde_json::json!({"ok": true, "result": result}))
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{address}"), requests)
+    }
+
+    fn telegram_settings(api_url: &str) -> TelegramSettings {
+        TelegramSettings {
+            enabled: true,
+            bot_token: Some("123:abc".to_string()),
+            api_url: api_url.to_string(),
+            chats: vec![
+                TelegramChat {
+                    chat_id: 99,
+                    user: "Alice".to_string(),
+                },
+                TelegramChat {
+                    chat_id: 100,
+                    user: "Bob".to_string(),
+                },
+            ],
+            voice_replies: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_updates_from_mock_bot_api() {
+        let (api_url, requests) = mock_bot_api().await;
+        let api = BotApi::from_settings(&telegram_settings(&api_url)).unwrap();
+
+        let updates = api.get_updates(41, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(updates[0].update_id, 41);
+        let message = updates[0].message.as_ref().unwrap();
+        assert_eq!(message.chat.id, 99);
+        assert_eq!(replied_character(message).as_deref(), Some("Brave Knight"));
+
+        let requests = requests.lock().await;
+        let (method, body) = &requests[0];
+        assert_eq!(method, "getUpdates");
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(body["offset"], 41);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_to_linked_chats() {
+        let (api_url, requests) = mock_bot_api().await;
+        let message = Message {
+            text: vec!["Meet me <here> & now".to_string()],
+            audio: vec![],
+            images: vec![],
+            read: false,
+            timestamp: chrono::Utc::now(),
+            from_user: false,
+        };
+        deliver(
+            &telegram_settings(&api_url),
+            "alice",
+            "Brave Knight",
+            &message,
+        )
+        .await;
+
+        let requests = requests.lock().await;
+        assert_eq!(requests.len(), 1);
+        let (method, body) = &requests[0];
+        assert_eq!(method, "sendMessage");
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(body["chat_id"], 99);
+        assert_eq!(
+            body["text"],
+            "<b>Brave Knight</b>\nMeet me &lt;here&gt; &amp; now"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_voice_note() {
+        let (api_url, requests) = mock_bot_api().await;
+        let api = BotApi::from_settings(&telegram_settings(&api_url)).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("note.mp3");
+        std::fs::write(&path, b"ID3 fake mp3").unwrap();
+
+        api.send_voice(99, &path, "Brave Knight").await.unwrap();
+
+        let requests = requests.lock().await;
+        let (method, body) = &requests[0];
+        assert_eq!(method, "sendVoice");
+        let body = String::from_utf8_lossy(body);
+        assert!(body.contains("name=\"voice\"; filename=\"voice.mp3\""));
+        assert!(body.contains("ID3 fake mp3"));
+        assert!(body.contains("Brave Knight"));
+    }
+
+    #[test]
+    fn test_disabled_bridge_has_no_client() {
+        let mut telegram = telegram_settings("https://api.telegram.org");
+        assert!(BotApi::from_settings(&telegram).is_some());
+        telegram.enabled = false;
+        assert!(BotApi::from_settings(&telegram).is_none());
+        telegram.enabled = true;
+        telegram.bot_token = None;
+        assert!(BotApi::from_settings(&telegram).is_none());
+    }
+}
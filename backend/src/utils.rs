@@ -1,11 +1,21 @@
 use regex::Regex;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::OwnedMutexGuard;
+
+use crate::config::data_dir;
+use crate::error::AppError;
 
 /// Generate a URL-safe slug from a string
-/// Converts to lowercase, replaces spaces and special characters with hyphens,
-/// removes consecutive hyphens, and trims hyphens from ends
+/// Transliterates non-ASCII text to ASCII, converts to lowercase, replaces spaces and
+/// special characters with hyphens, removes consecutive hyphens, and trims hyphens from ends
 pub fn to_slug(input: &str) -> String {
+    // Transliterate first so that "Zoë" becomes "zoe" rather than "zo"
+    let transliterated = deunicode::deunicode(input);
+
     let re = Regex::new(r"[^a-zA-Z0-9\s-]").unwrap();
-    let cleaned = re.replace_all(input, "");
+    let cleaned = re.replace_all(&transliterated, "");
 
     let re_spaces = Regex::new(r"[\s_]+").unwrap();
     let with_hyphens = re_spaces.replace_all(&cleaned, "-");
@@ -39,50 +49,207 @@ pub fn job_slug(character: &str, prompt: &str) -> String {
     format!("{character_slug}-{prompt_slug}")
 }
 
+/// Check that a new `kind` can be saved under `slug`, the slug of its `field`, without
+/// overwriting another
+/// `existing` loads the name of what is stored under the slug. Names that differ only in
+/// punctuation or accents share a file, so anything found there is a conflict; only `NotFound`
+/// means the slug is free, and any other error is returned so that an unreadable file is reported
+/// rather than overwritten
+pub async fn check_new_slug(
+    kind: &str,
+    field: &str,
+    name: &str,
+    slug: &str,
+    existing: impl Future<Output = Result<String, AppError>>,
+) -> Result<(), AppError> {
+    if slug.is_empty() {
+        return Err(AppError::Validation(format!(
+            "{kind} {field} '{name}' does not produce a usable slug"
+        )));
+    }
+
+    match existing.await {
+        Ok(existing) if existing == name => Err(AppError::Conflict(format!(
+            "{kind} '{name}' already exists"
+        ))),
+        Ok(existing) => Err(AppError::Conflict(format!(
+            "{kind} '{name}' conflicts with existing {} '{existing}' (slug '{slug}')",
+            kind.to_lowercase()
+        ))),
+        Err(AppError::NotFound(_)) => Ok(()),
+        Err(e) => Err(e.context(format!(
+            "Failed to check for an existing {}",
+            kind.to_lowercase()
+        ))),
+    }
+}
+
+/// Write a file by writing a temporary sibling and renaming it into place
+/// Readers never see a partially written file, even if the process is killed mid-write
+pub async fn write_file_atomic(
+    path: impl AsRef<Path>,
+    contents: impl AsRef<[u8]>,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::other(format!("'{}' is not a file", path.display())))?;
+    let temp_path = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+
+    tokio::fs::write(&temp_path, contents).await?;
+    if let Err(e) = tokio::fs::rename(&temp_path, path).await {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Resolve a path relative to the data directory
+fn data_path(relative: String) -> String {
+    data_dir().join(relative).to_string_lossy().into_owned()
+}
+
 /// Generate a file path for a character
 pub fn character_file_path(name: &str) -> String {
-    format!("./data/characters/{}.json", character_slug(name))
+    data_path(format!("characters/{}.json", character_slug(name)))
 }
 
 /// Generate a file path for a prompt
 pub fn prompt_file_path(title: &str) -> String {
-    format!("./data/prompts/{}.json", prompt_slug(title))
+    data_path(format!("prompts/{}.json", prompt_slug(title)))
 }
 
 /// Generate a file path for a job using UUID
 pub fn job_file_path_from_id(id: &uuid::Uuid) -> String {
-    format!("./data/jobs/{}.json", id)
+    data_path(format!("jobs/{}.json", id))
 }
 
 /// Generate a file path for a job (legacy function for backward compatibility)
 pub fn job_file_path(character: &str, prompt: &str) -> String {
-    format!("./data/jobs/{}.json", job_slug(character, prompt))
+    data_path(format!("jobs/{}.json", job_slug(character, prompt)))
 }
 
 /// Generate a file path for a job from its slug (legacy function for backward compatibility)
 pub fn job_file_path_from_slug(slug: &str) -> String {
-    format!("./data/jobs/{slug}.json")
+    data_path(format!("jobs/{slug}.json"))
+}
+
+/// Generate a slug for a persona based on its name
+pub fn persona_slug(name: &str) -> String {
+    to_slug(name)
+}
+
+/// Generate a file path for a persona based on its name
+pub fn persona_file_path(name: &str) -> String {
+    data_path(format!("personas/{}.json", persona_slug(name)))
 }
 
-/// Generate a file path for a chat based on character name
-pub fn chat_file_path_from_character(character: &str) -> String {
-    format!("./data/chats/{}.json", character_slug(character))
+/// Generate a slug for a user based on their name
+pub fn user_slug(name: &str) -> String {
+    to_slug(name)
+}
+
+/// Generate the directory holding a user's chats
+pub fn chat_dir(user: &str) -> String {
+    data_path(format!("chats/{}", user_slug(user)))
 }
 
-/// Generate a file path for an audio file
-pub fn audio_file_path(character: &str, filename: &str) -> String {
-    format!("./data/audio/{}/{}", character_slug(character), filename)
+/// Generate a file path for the Web Push subscriptions of a user
+pub fn push_subscriptions_file_path(user: &str) -> String {
+    data_path(format!("push/subscriptions/{}.json", user_slug(user)))
 }
 
-/// Generate a file path for an image file
-pub fn image_file_path(character: &str, filename: &str) -> String {
-    format!("./data/images/{}/{}", character_slug(character), filename)
+/// Generate a file path for a user's chat based on character name
+pub fn chat_file_path(user: &str, character: &str) -> String {
+    data_path(format!(
+        "chats/{}/{}.json",
+        user_slug(user),
+        character_slug(character)
+    ))
+}
+
+/// Locks of the chat files that have been changed, by path
+static CHAT_LOCKS: LazyLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+/// Lock a user's chat with a character until the guard is dropped
+/// The API, jobs and replies all load a chat, change it and save it again, so each holds the
+/// lock for all of that or one of two concurrent changes would be lost
+pub async fn lock_chat(user: &str, character: &str) -> OwnedMutexGuard<()> {
+    lock_chats(user, &[character]).await.remove(0)
+}
+
+/// Lock several of a user's chats, always in the same order so that two callers cannot each
+/// hold one of the locks the other is waiting for
+pub async fn lock_chats(user: &str, characters: &[&str]) -> Vec<OwnedMutexGuard<()>> {
+    let mut paths: Vec<String> = characters
+        .iter()
+        .map(|character| chat_file_path(user, character))
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    let locks: Vec<_> = {
+        let mut all = CHAT_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+        paths
+            .into_iter()
+            .map(|path| Arc::clone(all.entry(path).or_default()))
+            .collect()
+    };
+    let mut guards = Vec::with_capacity(locks.len());
+    for lock in locks {
+        guards.push(lock.lock_owned().await);
+    }
+    guards
+}
+
+/// Generate a file path for a user
+pub fn user_file_path(name: &str) -> String {
+    data_path(format!("users/{}.json", user_slug(name)))
+}
+
+/// Generate a file path for an API token
+pub fn token_file_path(id: &uuid::Uuid) -> String {
+    data_path(format!("tokens/{id}.json"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_check_new_slug() {
+        let missing = async { Err(AppError::NotFound("missing".to_string())) };
+        assert!(
+            check_new_slug("Prompt", "title", "Joke", "joke", missing)
+                .await
+                .is_ok()
+        );
+
+        let empty = check_new_slug("Prompt", "title", "!!!", "", async { unreachable!() }).await;
+        assert!(matches!(empty, Err(AppError::Validation(_))));
+
+        let same = check_new_slug("Prompt", "title", "Joke", "joke", async {
+            Ok("Joke".to_string())
+        })
+        .await;
+        assert!(matches!(same, Err(AppError::Conflict(m)) if m == "Prompt 'Joke' already exists"));
+
+        let similar = check_new_slug("Prompt", "title", "Joke!", "joke", async {
+            Ok("Joke".to_string())
+        })
+        .await;
+        assert!(
+            matches!(similar, Err(AppError::Conflict(m)) if m.contains("existing prompt 'Joke'"))
+        );
+
+        let unreadable = check_new_slug("Prompt", "title", "Joke", "joke", async {
+            Err(AppError::Storage("Invalid JSON".to_string()))
+        })
+        .await;
+        assert!(matches!(unreadable, Err(AppError::Storage(_))));
+    }
+
     #[test]
     fn test_to_slug() {
         assert_eq!(to_slug("Brave Knight"), "brave-knight");
@@ -94,6 +261,15 @@ mod tests {
         assert_eq!(to_slug(""), "");
     }
 
+    #[test]
+    fn test_to_slug_unicode() {
+        assert_eq!(to_slug("Zoë"), "zoe");
+        assert_eq!(to_slug("Señor Müller"), "senor-muller");
+        assert_eq!(to_slug("さくら"), "sakura");
+        assert!(!to_slug("田中").is_empty());
+        assert_eq!(to_slug("Bob!"), to_slug("Bob"));
+    }
+
     #[test]
     fn test_job_slug() {
         assert_eq!(
@@ -121,4 +297,16 @@ mod tests {
             "./data/jobs/brave-knight-send-a-joke-text.json"
         );
     }
+
+    #[tokio::test]
+    async fn test_write_file_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chat.json");
+
+        write_file_atomic(&path, "first").await.unwrap();
+        write_file_atomic(&path, "second").await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
 }
@@ -0,0 +1,409 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::data_dir;
+use crate::error::AppError;
+use crate::events::{Event, events, next_event};
+use crate::metrics::metrics;
+use crate::models::{Message, Settings, WebhookDelivery, WebhookEvent, WebhookSettings};
+use crate::settings::SharedSettings;
+use crate::utils::{character_slug, write_file_atomic};
+
+/// Attempts made to deliver each payload before giving up
+const MAX_ATTEMPTS: u32 = 5;
+/// Wait before the first retry, doubling after every further failure
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Attempts kept in the delivery log; older ones are dropped
+const DELIVERY_LOG_SIZE: usize = 500;
+
+pub const SIGNATURE_HEADER: &str = "x-storytime-signature";
+pub const TIMESTAMP_HEADER: &str = "x-storytime-timestamp";
+pub const EVENT_HEADER: &str = "x-storytime-event";
+pub const DELIVERY_HEADER: &str = "x-storytime-delivery";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// JSON body POSTed to webhooks
+#[derive(Serialize, Debug, Clone)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    pub timestamp: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub character: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<uuid::Uuid>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub text: Vec<String>,
+    /// Links to the message's audio, absolute when `publicUrl` is set
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub audio: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl WebhookPayload {
+    fn new(event: WebhookEvent) -> Self {
+        Self {
+            event,
+            timestamp: Utc::now(),
+            user: None,
+            character: None,
+            job: None,
+            text: Vec::new(),
+            audio: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// The payload for a bus event, if webhooks can be sent that event
+    fn from_event(event: &Event, settings: &Settings) -> Option<Self> {
+        match event {
+            Event::MessageCreated {
+                user,
+                character,
+                message,
+                job,
+                ..
+            } => Some(Self {
+                user: Some(user.clone()),
+                character: Some(character.clone()),
+                job: *job,
+                text: message.text.clone(),
+                audio: audio_urls(settings, character, message),
+                ..Self::new(WebhookEvent::MessageCreated)
+            }),
+            Event::JobFinished {
+                success: false,
+                job,
+                user,
+                character,
+                error,
+            } => Some(Self {
+                user: Some(user.clone()),
+                character: Some(character.clone()),
+                job: *job,
+                error: error.clone(),
+                ..Self::new(WebhookEvent::JobFailed)
+            }),
+            _ => None,
+        }
+    }
+
+    /// The payload sent by the test endpoint
+    pub fn ping() -> Self {
+        Self::new(WebhookEvent::Ping)
+    }
+}
+
+/// Where a message's audio can be downloaded
+pub(crate) fn audio_urls(settings: &Settings, character: &str, message: &Message) -> Vec<String> {
+    let base = settings
+        .public_url
+        .as_deref()
+        .unwrap_or_default()
+        .trim_end_matches('/');
+    message
+        .audio
+        .iter()
+        .map(|id| format!("{base}/audio/{}/{id}.mp3", character_slug(character)))
+        .collect()
+}
+
+/// Send webhooks for events from the bus until it is closed
+pub fn start_webhook_dispatcher(settings: SharedSettings) {
+    let (mut receiver, closed) = events().subscribe();
+
+    events().spawn(async move {
+        while let Some(event) = next_event(&mut receiver, &closed).await {
+            match event {
+                Ok(event) => dispatch(&event, &settings.current()),
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Webhook dispatcher fell behind, {missed} events not sent");
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+/// Start delivering an event to every enabled webhook that wants it
+fn dispatch(event: &Event, settings: &Settings) {
+    let Some(payload) = WebhookPayload::from_event(event, settings) else {
+        return;
+    };
+    for webhook in &settings.webhooks {
+        if webhook.enabled && webhook.events.contains(&payload.event) {
+            let webhook = webhook.clone();
+            let payload = payload.clone();
+            events().spawn(async move { deliver(&webhook, &payload, MAX_ATTEMPTS).await });
+        }
+    }
+}
+
+/// POST a payload to a webhook, retrying with backoff until it succeeds or `max_attempts` are used
+/// Every attempt is recorded in the delivery log; returns the last one
+pub async fn deliver(
+    webhook: &WebhookSettings,
+    payload: &WebhookPayload,
+    max_attempts: u32,
+) -> WebhookDelivery {
+    let id = uuid::Uuid::new_v4();
+    let body = match serde_json::to_vec(payload) {
+        Ok(body) => body,
+        Err(e) => {
+            let delivery = WebhookDelivery {
+                id,
+                webhook: webhook.name.clone(),
+                event: payload.event,
+                attempt: 1,
+                timestamp: Utc::now(),
+                success: false,
+                status: None,
+                error: Some(format!("Failed to serialize payload: {e}")),
+                duration_ms: 0,
+            };
+            record_delivery(delivery.clone()).await;
+            return delivery;
+        }
+    };
+
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        let started = Instant::now();
+        let result = send(webhook, payload.event, id, &body).await;
+        let (status, error) = match &result {
+            Ok(status) if (200..300).contains(status) => (Some(*status), None),
+            Ok(status) => (Some(*status), Some(format!("HTTP {status}"))),
+            Err(e) => (None, Some(e.clone())),
+        };
+        let delivery = WebhookDelivery {
+            id,
+            webhook: webhook.name.clone(),
+            event: payload.event,
+            attempt,
+            timestamp: Utc::now(),
+            success: error.is_none(),
+            status,
+            error,
+            duration_ms: started.elapsed().as_millis() as u64,
+        };
+        record_delivery(delivery.clone()).await;
+
+        if delivery.success {
+            metrics()
+                .webhook_deliveries
+                .with_label_values(&["success"])
+                .inc();
+            return delivery;
+        }
+        if attempt >= max_attempts || !is_retryable(status) {
+            tracing::warn!(
+                "Giving up on webhook '{}' after {} attempts: {}",
+                webhook.name,
+                attempt,
+                delivery.error.as_deref().unwrap_or_default()
+            );
+            metrics()
+                .webhook_deliveries
+                .with_label_values(&["failure"])
+                .inc();
+            return delivery;
+        }
+
+        tracing::debug!("Webhook '{}' failed, retrying in {:?}", webhook.name, delay);
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// Make one delivery attempt, returning the response status
+async fn send(
+    webhook: &WebhookSettings,
+    event: WebhookEvent,
+    id: uuid::Uuid,
+    body: &[u8],
+) -> Result<u16, String> {
+    let mut request = reqwest::Client::new()
+        .post(&webhook.url)
+        .timeout(REQUEST_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, event.to_string())
+        .header(DELIVERY_HEADER, id.to_string());
+
+    if let Some(secret) = webhook
+        .secret
+        .as_deref()
+        .filter(|secret| !secret.is_empty())
+    {
+        let timestamp = Utc::now().timestamp();
+        request = request
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, sign(secret, timestamp, body)?);
+    }
+
+    request
+        .body(body.to_vec())
+        .send()
+        .await
+        .map(|response| response.status().as_u16())
+        .map_err(|e| e.to_string())
+}
+
+/// Signature receivers can check: `sha256=` and the hex HMAC of `{timestamp}.{body}`
+/// Including the timestamp lets receivers reject replayed deliveries
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> Result<String, String> {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+        .map_err(|e| format!("Invalid webhook secret: {e}"))?;
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body);
+    Ok(format!(
+        "sha256={}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
+
+/// Client errors other than timeouts and rate limits will fail the same way again
+fn is_retryable(status: Option<u16>) -> bool {
+    match status {
+        Some(status) if (400..500).contains(&status) => matches!(status, 408 | 429),
+        _ => true,
+    }
+}
+
+/// Recent delivery attempts, loaded from disk on first use
+static DELIVERY_LOG: Mutex<Option<VecDeque<WebhookDelivery>>> = Mutex::const_new(None);
+
+fn delivery_log_path() -> std::path::PathBuf {
+    data_dir().join("webhooks").join("deliveries.json")
+}
+
+/// Add an attempt to the delivery log, dropping the oldest once it is full
+async fn record_delivery(delivery: WebhookDelivery) {
+    let mut log = DELIVERY_LOG.lock().await;
+    let log = match &mut *log {
+        Some(log) => log,
+        None => log.insert(read_delivery_log().await),
+    };
+    log.push_back(delivery);
+    while log.len() > DELIVERY_LOG_SIZE {
+        log.pop_front();
+    }
+
+    if let Err(e) = write_delivery_log(log).await {
+        tracing::warn!("Failed to save webhook delivery log: {}", e);
+    }
+}
+
+/// Delivery attempts, newest first
+pub async fn delivery_log() -> Vec<WebhookDelivery> {
+    let mut log = DELIVERY_LOG.lock().await;
+    let log = match &mut *log {
+        Some(log) => log,
+        None => log.insert(read_delivery_log().await),
+    };
+    log.iter().rev().cloned().collect()
+}
+
+async fn read_delivery_log() -> VecDeque<WebhookDelivery> {
+    match fs::read_to_string(delivery_log_path()).await {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring unreadable webhook delivery log: {}", e);
+            VecDeque::new()
+        }),
+        Err(_) => VecDeque::new(),
+    }
+}
+
+async fn write_delivery_log(log: &VecDeque<WebhookDelivery>) -> Result<(), AppError> {
+    let path = delivery_log_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    write_file_atomic(&path, serde_json::to_vec_pretty(log)?).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature() {
+        // Receivers recompute the HMAC over the timestamp header, a dot and the raw body
+        let signature = sign("secret", 1700000000, br#"{"event":"ping"}"#).unwrap();
+        let mut mac = HmacSha256::new_from_slice(b"secret").unwrap();
+        mac.update(br#"1700000000.{"event":"ping"}"#);
+        assert_eq!(
+            signature,
+            format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+        );
+        assert_ne!(
+            signature,
+            sign("other", 1700000000, br#"{"event":"ping"}"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_retryable_statuses() {
+        assert!(is_retryable(None));
+        assert!(is_retryable(Some(500)));
+        assert!(is_retryable(Some(429)));
+        assert!(!is_retryable(Some(404)));
+    }
+
+    #[test]
+    fn test_message_payload() {
+        let mut settings: Settings = serde_json::from_str(
+            r#"{"ttsApi": "https://tts.test", "llmApi": "https://llm.test",
+                "publicUrl": "https://story.example/"}"#,
+        )
+        .unwrap();
+        let event = Event::MessageCreated {
+            user: "default".to_string(),
+            character: "Brave Knight".to_string(),
+            index: 0,
+            message: Message {
+                text: vec!["Hail!".to_string()],
+                audio: vec!["abc".to_string()],
+                images: vec![],
+                read: false,
+                timestamp: Utc::now(),
+                from_user: false,
+            },
+            job: None,
+        };
+
+        let payload = WebhookPayload::from_event(&event, &settings).unwrap();
+        assert_eq!(payload.event, WebhookEvent::MessageCreated);
+        assert_eq!(payload.text, vec!["Hail!"]);
+        assert_eq!(
+            payload.audio,
+            vec!["https://story.example/audio/brave-knight/abc.mp3"]
+        );
+
+        settings.public_url = None;
+        let payload = WebhookPayload::from_event(&event, &settings).unwrap();
+        assert_eq!(payload.audio, vec!["/audio/brave-knight/abc.mp3"]);
+
+        let succeeded = Event::JobFinished {
+            job: None,
+            user: "default".to_string(),
+            character: "Brave Knight".to_string(),
+            success: true,
+            error: None,
+        };
+        assert!(WebhookPayload::from_event(&succeeded, &settings).is_none());
+    }
+}
@@ -0,0 +1,28 @@
+export type Scope = 'read' | 'write' | 'run' | 'admin';
+
+export interface UserInfo {
+	name: string;
+	admin: boolean;
+	scopes: Scope[];
+}
+
+export interface SessionInfo {
+	authEnabled: boolean;
+	user: UserInfo | null;
+}
+
+export interface LoginRequest {
+	name: string;
+	password: string;
+}
+
+export interface ApiResponse<T> {
+	success: boolean;
+	data?: T;
+	message: string;
+	/** Machine-readable reason, set when success is false */
+	code?: string;
+}
+
+export type SessionResponse = ApiResponse<SessionInfo>;
+export type LoginResponse = ApiResponse<UserInfo>;
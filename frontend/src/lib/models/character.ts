@@ -7,11 +7,14 @@ export interface Voice {
 }
 
 export interface Character {
+	slug: string; // Used in character URLs; computed by the backend
 	name: string;
 	description: string;
 	personality: string;
 	background: string;
 	voice?: Voice;
+	first_message?: string;
+	example_dialogue?: string;
 }
 
 export interface CreateCharacterRequest {
@@ -33,6 +36,8 @@ export interface ApiResponse<T> {
 	success: boolean;
 	data?: T;
 	message: string;
+	/** Machine-readable reason, set when success is false */
+	code?: string;
 }
 
 export type CharacterListResponse = ApiResponse<Character[]>;
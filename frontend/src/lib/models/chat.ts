@@ -9,11 +9,14 @@ export interface Message {
 	images: string[];
 	read?: boolean;
 	timestamp: string; // ISO 8601 timestamp string from backend
+	fromUser?: boolean; // Written by the user, such as a reply from Telegram
 }
 
 export interface Chat {
+	slug: string; // Used in chat and media URLs; computed by the backend
 	character: string;
 	messages: Message[];
+	muted?: boolean; // Muted chats send no push notifications
 }
 
 export interface CreateChatRequest {
@@ -22,6 +25,7 @@ export interface CreateChatRequest {
 
 export interface UpdateChatRequest {
 	character?: string;
+	muted?: boolean;
 }
 
 export interface AddMessageRequest {
@@ -42,6 +46,7 @@ export interface UpdateMessageRequest {
 
 // Helper type for chat list display
 export interface ChatListItem {
+	slug: string;
 	character: string;
 	lastMessage?: string;
 	messageCount: number;
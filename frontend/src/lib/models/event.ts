@@ -0,0 +1,22 @@
+import type { Message } from './chat.js';
+
+interface ChatEvent {
+	user: string;
+	character: string;
+}
+
+interface JobEvent extends ChatEvent {
+	job: string | null; // UUID, null for test runs
+}
+
+/** Events published on `/api/events`, told apart by `type` */
+export type ServerEvent =
+	| ({ type: 'message_created'; index: number; message: Message } & ChatEvent)
+	| ({ type: 'message_updated'; index: number; message: Message } & ChatEvent)
+	| ({ type: 'message_deleted'; index: number } & ChatEvent)
+	| ({ type: 'message_read'; index?: number } & ChatEvent) // No index means every message
+	| ({ type: 'job_started'; prompt: string } & JobEvent)
+	| ({ type: 'job_progress'; step: number; steps: number } & JobEvent)
+	| ({ type: 'job_finished'; success: boolean; error?: string } & JobEvent)
+	| { type: 'scheduler_reloaded'; jobs: number }
+	| { type: 'lagged'; missed: number }; // Events were missed, so reload what is shown
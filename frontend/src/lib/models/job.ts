@@ -4,6 +4,16 @@ export interface Job {
 	prompts: string[];
 	cadence: string;
 	'prompt-override': string | null;
+	enabled?: boolean; // Defaults to true; disabled jobs are never scheduled
+	subscribers?: string[]; // Users whose chats receive the results; empty means every user
+	'trigger-hash'?: string; // Set when the job has a trigger URL
+}
+
+/** A job's trigger URL; the token is only returned when the trigger is created */
+export interface JobTrigger {
+	token: string;
+	url: string;
+	header: string; // POST to the URL with the token in this header
 }
 
 export interface CreateJobRequest {
@@ -11,6 +21,8 @@ export interface CreateJobRequest {
 	prompts: string[];
 	cadence: string;
 	'prompt-override': string | null;
+	enabled?: boolean;
+	subscribers?: string[];
 }
 
 export interface UpdateJobRequest {
@@ -19,6 +31,8 @@ export interface UpdateJobRequest {
 	prompts: string[];
 	cadence: string;
 	'prompt-override': string | null;
+	enabled?: boolean; // Left unchanged when omitted
+	subscribers?: string[];
 }
 
 export interface RunJobRequest {
@@ -30,8 +44,11 @@ export interface ApiResponse<T> {
 	success: boolean;
 	data?: T;
 	message: string;
+	/** Machine-readable reason, set when success is false */
+	code?: string;
 }
 
 export type JobListResponse = ApiResponse<Job[]>;
 export type JobResponse = ApiResponse<Job>;
 export type JobDeleteResponse = ApiResponse<null>;
+export type JobTriggerResponse = ApiResponse<JobTrigger>;
@@ -0,0 +1,40 @@
+export interface Persona {
+	name: string;
+	pronouns: string;
+	description: string;
+	interests: string[];
+	/** How the persona relates to each character, keyed by character name */
+	relationships: Record<string, string>;
+	/** The user this persona describes; unset means the default user */
+	user?: string;
+}
+
+export interface CreatePersonaRequest {
+	name: string;
+	pronouns?: string;
+	description?: string;
+	interests?: string[];
+	relationships?: Record<string, string>;
+	user?: string;
+}
+
+export interface UpdatePersonaRequest {
+	pronouns?: string;
+	description?: string;
+	interests?: string[];
+	relationships?: Record<string, string>;
+	/** An empty name gives the persona to the default user */
+	user?: string;
+}
+
+export interface ApiResponse<T> {
+	success: boolean;
+	data?: T;
+	message: string;
+	/** Machine-readable reason, set when success is false */
+	code?: string;
+}
+
+export type PersonaListResponse = ApiResponse<Persona[]>;
+export type PersonaResponse = ApiResponse<Persona>;
+export type PersonaDeleteResponse = ApiResponse<null>;
@@ -28,6 +28,8 @@ export interface ApiResponse<T> {
 	success: boolean;
 	data?: T;
 	message: string;
+	/** Machine-readable reason, set when success is false */
+	code?: string;
 }
 
 export type PromptListResponse = ApiResponse<Prompt[]>;
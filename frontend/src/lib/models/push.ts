@@ -0,0 +1,7 @@
+import type { ApiResponse } from './job.js';
+
+export interface PushPublicKey {
+	publicKey: string; // VAPID key, base64url encoded
+}
+
+export type PushPublicKeyResponse = ApiResponse<PushPublicKey>;
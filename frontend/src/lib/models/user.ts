@@ -0,0 +1,17 @@
+import type { UserInfo } from './auth.js';
+
+/** Chats without a signed-in or picked user belong to this user */
+export const DEFAULT_USER = 'default';
+
+/** Cookie picking the current user while authentication is disabled */
+export const USER_COOKIE = 'storytime_user';
+
+export interface ApiResponse<T> {
+	success: boolean;
+	data?: T;
+	message: string;
+	/** Machine-readable reason, set when success is false */
+	code?: string;
+}
+
+export type UserListResponse = ApiResponse<UserInfo[]>;
@@ -0,0 +1,61 @@
+import type { LoginRequest, LoginResponse, SessionInfo, SessionResponse, UserInfo } from '../models/auth.js';
+
+const API_BASE = '';
+
+/**
+ * Fetch whether authentication is enabled and who is signed in
+ */
+export async function fetchSession(): Promise<SessionInfo> {
+	const response = await fetch(`${API_BASE}/api/auth/session`, {
+		method: 'GET',
+		headers: {
+			'Content-Type': 'application/json'
+		}
+	});
+
+	if (!response.ok) {
+		throw new Error(`Failed to fetch session: ${response.statusText}`);
+	}
+
+	const data: SessionResponse = await response.json();
+
+	if (!data.success || !data.data) {
+		throw new Error(data.message || 'Failed to fetch session');
+	}
+
+	return data.data;
+}
+
+/**
+ * Sign in, which sets the session cookie
+ */
+export async function login(request: LoginRequest): Promise<UserInfo> {
+	const response = await fetch(`${API_BASE}/api/auth/login`, {
+		method: 'POST',
+		headers: {
+			'Content-Type': 'application/json'
+		},
+		body: JSON.stringify(request)
+	});
+
+	const data: LoginResponse = await response.json();
+
+	if (!response.ok || !data.success || !data.data) {
+		throw new Error(data.message || 'Failed to sign in');
+	}
+
+	return data.data;
+}
+
+/**
+ * Sign out, which clears the session cookie
+ */
+export async function logout(): Promise<void> {
+	const response = await fetch(`${API_BASE}/api/auth/logout`, {
+		method: 'POST'
+	});
+
+	if (!response.ok) {
+		throw new Error(`Failed to sign out: ${response.statusText}`);
+	}
+}
@@ -6,7 +6,6 @@ import type {
 	CharacterResponse,
 	CharacterDeleteResponse
 } from '../models/character.js';
-import { characterSlug } from '../utils/slug.js';
 
 const API_BASE = '';
 
@@ -35,10 +34,9 @@ export async function fetchCharacters(): Promise<Character[]> {
 }
 
 /**
- * Fetch a single character by name
+ * Fetch a single character by its slug
  */
-export async function fetchCharacter(name: string): Promise<Character> {
-	const slug = characterSlug(name);
+export async function fetchCharacter(slug: string): Promise<Character> {
 	const response = await fetch(`${API_BASE}/api/characters/${slug}`, {
 		method: 'GET',
 		headers: {
@@ -48,7 +46,7 @@ export async function fetchCharacter(name: string): Promise<Character> {
 
 	if (!response.ok) {
 		if (response.status === 404) {
-			throw new Error(`Character '${name}' not found`);
+			throw new Error(`Character '${slug}' not found`);
 		}
 		throw new Error(`Failed to fetch character: ${response.statusText}`);
 	}
@@ -93,8 +91,7 @@ export async function createCharacter(character: CreateCharacterRequest): Promis
 /**
  * Update an existing character
  */
-export async function updateCharacter(name: string, updates: UpdateCharacterRequest): Promise<Character> {
-	const slug = characterSlug(name);
+export async function updateCharacter(slug: string, updates: UpdateCharacterRequest): Promise<Character> {
 	const response = await fetch(`${API_BASE}/api/characters/${slug}`, {
 		method: 'PUT',
 		headers: {
@@ -105,7 +102,7 @@ export async function updateCharacter(name: string, updates: UpdateCharacterRequ
 
 	if (!response.ok) {
 		if (response.status === 404) {
-			throw new Error(`Character '${name}' not found`);
+			throw new Error(`Character '${slug}' not found`);
 		}
 		throw new Error(`Failed to update character: ${response.statusText}`);
 	}
@@ -122,8 +119,7 @@ export async function updateCharacter(name: string, updates: UpdateCharacterRequ
 /**
  * Delete a character
  */
-export async function deleteCharacter(name: string): Promise<void> {
-	const slug = characterSlug(name);
+export async function deleteCharacter(slug: string): Promise<void> {
 	const response = await fetch(`${API_BASE}/api/characters/${slug}`, {
 		method: 'DELETE',
 		headers: {
@@ -133,7 +129,7 @@ export async function deleteCharacter(name: string): Promise<void> {
 
 	if (!response.ok) {
 		if (response.status === 404) {
-			throw new Error(`Character '${name}' not found`);
+			throw new Error(`Character '${slug}' not found`);
 		}
 		throw new Error(`Failed to delete character: ${response.statusText}`);
 	}
@@ -6,7 +6,6 @@
 
 import type { Chat, CreateChatRequest, UpdateChatRequest, AddMessageRequest, ChatListItem } from '$lib/models/chat';
 import { base64ToAudioURL } from '$lib/utils/audio-utils';
-import { toSlug } from '$lib/utils/slug';
 
 const API_BASE_URL = '/api';
 
@@ -14,6 +13,8 @@ export interface ApiResponse<T> {
 	success: boolean;
 	data?: T;
 	message: string;
+	/** Machine-readable reason, set when success is false */
+	code?: string;
 }
 
 /**
@@ -34,17 +35,17 @@ export async function getAllChats(): Promise<string[]> {
 }
 
 /**
- * Get a specific chat by character name
+ * Get a specific chat by its slug
  */
-export async function getChat(character: string, fetch: typeof window.fetch): Promise<Chat> {
-	const response = await fetch(`${API_BASE_URL}/chats/${encodeURIComponent(character)}`);
+export async function getChat(slug: string, fetch: typeof window.fetch): Promise<Chat> {
+	const response = await fetch(`${API_BASE_URL}/chats/${encodeURIComponent(slug)}`);
 	if (!response.ok) {
-		throw new Error(`Failed to fetch chat for ${character}: ${response.statusText}`);
+		throw new Error(`Failed to fetch chat for ${slug}: ${response.statusText}`);
 	}
 
 	const result: ApiResponse<Chat> = await response.json();
 	if (!result.success || !result.data) {
-		throw new Error(result.message || `Failed to fetch chat for ${character}`);
+		throw new Error(result.message || `Failed to fetch chat for ${slug}`);
 	}
 
 	return result.data;
@@ -77,8 +78,8 @@ export async function createChat(request: CreateChatRequest): Promise<Chat> {
 /**
  * Update an existing chat
  */
-export async function updateChat(character: string, request: UpdateChatRequest): Promise<Chat> {
-	const response = await fetch(`${API_BASE_URL}/chats/${encodeURIComponent(character)}`, {
+export async function updateChat(slug: string, request: UpdateChatRequest): Promise<Chat> {
+	const response = await fetch(`${API_BASE_URL}/chats/${encodeURIComponent(slug)}`, {
 		method: 'PUT',
 		headers: {
 			'Content-Type': 'application/json'
@@ -101,8 +102,8 @@ export async function updateChat(character: string, request: UpdateChatRequest):
 /**
  * Delete a chat
  */
-export async function deleteChat(character: string): Promise<void> {
-	const response = await fetch(`${API_BASE_URL}/chats/${encodeURIComponent(character)}`, {
+export async function deleteChat(slug: string): Promise<void> {
+	const response = await fetch(`${API_BASE_URL}/chats/${encodeURIComponent(slug)}`, {
 		method: 'DELETE'
 	});
 
@@ -119,8 +120,8 @@ export async function deleteChat(character: string): Promise<void> {
 /**
  * Add a message to a chat
  */
-export async function addMessage(character: string, request: AddMessageRequest): Promise<Chat> {
-	const response = await fetch(`${API_BASE_URL}/chats/${encodeURIComponent(character)}/messages`, {
+export async function addMessage(slug: string, request: AddMessageRequest): Promise<Chat> {
+	const response = await fetch(`${API_BASE_URL}/chats/${encodeURIComponent(slug)}/messages`, {
 		method: 'POST',
 		headers: {
 			'Content-Type': 'application/json'
@@ -147,14 +148,15 @@ export async function getChatListItems(): Promise<ChatListItem[]> {
 	const chatNames = await getAllChats();
 	const chatListItems: ChatListItem[] = [];
 
-	// Fetch each chat to get last message info
-	for (const name of chatNames) {
+	// Chat names are the slugs of their files, so fetch each chat to get its name and last message
+	for (const slug of chatNames) {
 		try {
-			const chat = await getChat(name, window.fetch);
+			const chat = await getChat(slug, window.fetch);
 			const lastMessage = chat.messages.length > 0 ? chat.messages[chat.messages.length - 1].text.join(' ') : '';
 			const unreadCount = chat.messages.filter((message) => !message.read).length;
 
 			chatListItems.push({
+				slug: chat.slug,
 				character: chat.character,
 				lastMessage: lastMessage || 'No messages yet',
 				messageCount: chat.messages.length,
@@ -162,10 +164,11 @@ export async function getChatListItems(): Promise<ChatListItem[]> {
 				// Note: We don't have timestamp data from backend, so we skip lastActivity for now
 			});
 		} catch (error) {
-			console.error(`Failed to fetch chat for ${name}:`, error);
+			console.error(`Failed to fetch chat for ${slug}:`, error);
 			// Still add the chat to the list even if we can't get details
 			chatListItems.push({
-				character: name,
+				slug,
+				character: slug,
 				lastMessage: 'Error loading messages',
 				messageCount: 0,
 				unreadCount: 0
@@ -209,8 +212,7 @@ export function convertTestAudioToUrl(base64Audio: string): string {
 /**
  * Mark a specific message as read
  */
-export async function markMessageAsRead(character: string, messageIndex: number): Promise<Chat> {
-	const slug = toSlug(character);
+export async function markMessageAsRead(slug: string, messageIndex: number): Promise<Chat> {
 	const response = await fetch(`${API_BASE_URL}/chats/${encodeURIComponent(slug)}/messages/${messageIndex}/read`, {
 		method: 'PUT',
 		headers: {
@@ -233,8 +235,7 @@ export async function markMessageAsRead(character: string, messageIndex: number)
 /**
  * Mark all messages in a chat as read
  */
-export async function markAllMessagesAsRead(character: string): Promise<Chat> {
-	const slug = toSlug(character);
+export async function markAllMessagesAsRead(slug: string): Promise<Chat> {
 	const response = await fetch(`${API_BASE_URL}/chats/${encodeURIComponent(slug)}/read-all`, {
 		method: 'PUT',
 		headers: {
@@ -257,8 +258,7 @@ export async function markAllMessagesAsRead(character: string): Promise<Chat> {
 /**
  * Delete a specific message from a chat
  */
-export async function deleteMessage(character: string, messageIndex: number): Promise<Chat> {
-	const slug = toSlug(character);
+export async function deleteMessage(slug: string, messageIndex: number): Promise<Chat> {
 	const response = await fetch(`${API_BASE_URL}/chats/${encodeURIComponent(slug)}/messages/${messageIndex}`, {
 		method: 'DELETE',
 		headers: {
@@ -0,0 +1,21 @@
+import type { ServerEvent } from '../models/event.js';
+
+const API_BASE = '';
+
+/**
+ * Listen for new messages and job progress as they happen
+ * The browser reconnects on its own if the stream drops; returns a function that stops listening
+ */
+export function subscribeToEvents(onEvent: (event: ServerEvent) => void): () => void {
+	const source = new EventSource(`${API_BASE}/api/events`, { withCredentials: true });
+
+	source.onmessage = (message) => {
+		try {
+			onEvent(JSON.parse(message.data) as ServerEvent);
+		} catch (err) {
+			console.warn('Ignoring malformed event:', err);
+		}
+	};
+
+	return () => source.close();
+}
@@ -1,4 +1,4 @@
-import type { Job, CreateJobRequest, UpdateJobRequest, RunJobRequest, JobListResponse, JobResponse, JobDeleteResponse } from '../models/job.js';
+import type { Job, JobTrigger, CreateJobRequest, UpdateJobRequest, RunJobRequest, JobListResponse, JobResponse, JobDeleteResponse, JobTriggerResponse } from '../models/job.js';
 import type { Message } from '../models/chat.js';
 import { jobSlug } from '../utils/slug.js';
 
@@ -236,6 +236,52 @@ export async function deleteJob(character: string, prompt: string): Promise<void
 	}
 }
 
+/**
+ * Create a trigger URL for a job by ID, replacing any it had
+ */
+export async function createJobTrigger(id: string): Promise<JobTrigger> {
+	const response = await fetch(`${API_BASE}/api/jobs/${id}/trigger`, {
+		method: 'POST'
+	});
+
+	if (!response.ok) {
+		if (response.status === 404) {
+			throw new Error(`Job with ID '${id}' not found`);
+		}
+		throw new Error(`Failed to create job trigger: ${response.statusText}`);
+	}
+
+	const result: JobTriggerResponse = await response.json();
+
+	if (!result.success || !result.data) {
+		throw new Error(result.message || 'Failed to create job trigger');
+	}
+
+	return result.data;
+}
+
+/**
+ * Remove a job's trigger URL by ID
+ */
+export async function deleteJobTrigger(id: string): Promise<void> {
+	const response = await fetch(`${API_BASE}/api/jobs/${id}/trigger`, {
+		method: 'DELETE'
+	});
+
+	if (!response.ok) {
+		if (response.status === 404) {
+			throw new Error(`Job with ID '${id}' has no trigger`);
+		}
+		throw new Error(`Failed to delete job trigger: ${response.statusText}`);
+	}
+
+	const result: JobDeleteResponse = await response.json();
+
+	if (!result.success) {
+		throw new Error(result.message || 'Failed to delete job trigger');
+	}
+}
+
 /**
  * Execute a job by ID
  */
@@ -0,0 +1,104 @@
+import type {
+	Persona,
+	CreatePersonaRequest,
+	UpdatePersonaRequest,
+	PersonaListResponse,
+	PersonaResponse,
+	PersonaDeleteResponse
+} from '../models/persona.js';
+import { personaSlug } from '../utils/slug.js';
+
+const API_BASE = '';
+
+/**
+ * Fetch all personas
+ */
+export async function fetchPersonas(): Promise<Persona[]> {
+	const response = await fetch(`${API_BASE}/api/personas`, {
+		method: 'GET',
+		headers: {
+			'Content-Type': 'application/json'
+		}
+	});
+
+	if (!response.ok) {
+		throw new Error(`Failed to fetch personas: ${response.statusText}`);
+	}
+
+	const data: PersonaListResponse = await response.json();
+
+	if (!data.success) {
+		throw new Error(data.message || 'Failed to fetch personas');
+	}
+
+	return data.data || [];
+}
+
+/**
+ * Create a new persona
+ */
+export async function createPersona(persona: CreatePersonaRequest): Promise<Persona> {
+	const response = await fetch(`${API_BASE}/api/personas`, {
+		method: 'POST',
+		headers: {
+			'Content-Type': 'application/json'
+		},
+		body: JSON.stringify(persona)
+	});
+
+	const data: PersonaResponse = await response.json();
+
+	if (!response.ok || !data.success || !data.data) {
+		throw new Error(data.message || 'Failed to create persona');
+	}
+
+	return data.data;
+}
+
+/**
+ * Update an existing persona
+ */
+export async function updatePersona(name: string, updates: UpdatePersonaRequest): Promise<Persona> {
+	const slug = personaSlug(name);
+	const response = await fetch(`${API_BASE}/api/personas/${slug}`, {
+		method: 'PUT',
+		headers: {
+			'Content-Type': 'application/json'
+		},
+		body: JSON.stringify(updates)
+	});
+
+	const data: PersonaResponse = await response.json();
+
+	if (!response.ok || !data.success || !data.data) {
+		throw new Error(data.message || 'Failed to update persona');
+	}
+
+	return data.data;
+}
+
+/**
+ * Delete a persona
+ */
+export async function deletePersona(name: string): Promise<void> {
+	const slug = personaSlug(name);
+	const response = await fetch(`${API_BASE}/api/personas/${slug}`, {
+		method: 'DELETE',
+		headers: {
+			'Content-Type': 'application/json'
+		}
+	});
+
+	if (!response.ok) {
+		if (response.status === 404) {
+			throw new Error(`Persona '${name}' not found`);
+		}
+		throw new Error(`Failed to delete persona: ${response.statusText}`);
+	}
+
+	const data: PersonaDeleteResponse = await response.json();
+
+	if (!data.success) {
+		throw new Error(data.message || 'Failed to delete persona');
+	}
+}
@@ -0,0 +1,77 @@
+import type { PushPublicKeyResponse } from '../models/push.js';
+
+const API_BASE = '';
+
+/**
+ * Whether this browser can receive push notifications
+ */
+export function pushSupported(): boolean {
+	return 'serviceWorker' in navigator && 'PushManager' in window && 'Notification' in window;
+}
+
+/**
+ * The browser's current push subscription, if it has one
+ */
+export async function currentSubscription(): Promise<PushSubscription | null> {
+	const registration = await navigator.serviceWorker.ready;
+	return registration.pushManager.getSubscription();
+}
+
+/**
+ * Ask for permission and send new messages to this browser
+ */
+export async function enableNotifications(): Promise<void> {
+	const permission = await Notification.requestPermission();
+	if (permission !== 'granted') {
+		throw new Error('Notifications were not allowed');
+	}
+
+	const keyResponse = await fetch(`${API_BASE}/api/push/key`);
+	const key: PushPublicKeyResponse = await keyResponse.json();
+	if (!keyResponse.ok || !key.success || !key.data) {
+		throw new Error(key.message || 'Failed to fetch push key');
+	}
+
+	const registration = await navigator.serviceWorker.ready;
+	const subscription = await registration.pushManager.subscribe({
+		userVisibleOnly: true,
+		applicationServerKey: base64UrlToBytes(key.data.publicKey)
+	});
+
+	const response = await fetch(`${API_BASE}/api/push/subscriptions`, {
+		method: 'POST',
+		headers: {
+			'Content-Type': 'application/json'
+		},
+		body: JSON.stringify(subscription.toJSON())
+	});
+
+	if (!response.ok) {
+		await subscription.unsubscribe();
+		throw new Error(`Failed to save push subscription: ${response.statusText}`);
+	}
+}
+
+/**
+ * Stop sending new messages to this browser
+ */
+export async function disableNotifications(): Promise<void> {
+	const subscription = await currentSubscription();
+	if (!subscription) {
+		return;
+	}
+
+	await fetch(`${API_BASE}/api/push/subscriptions`, {
+		method: 'DELETE',
+		headers: {
+			'Content-Type': 'application/json'
+		},
+		body: JSON.stringify({ endpoint: subscription.endpoint })
+	});
+	await subscription.unsubscribe();
+}
+
+function base64UrlToBytes(value: string) {
+	const base64 = (value + '='.repeat((4 - (value.length % 4)) % 4)).replace(/-/g, '+').replace(/_/g, '/');
+	return Uint8Array.from(atob(base64), (char) => char.charCodeAt(0));
+}
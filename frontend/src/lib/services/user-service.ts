@@ -0,0 +1,48 @@
+import type { UserInfo } from '../models/auth.js';
+import { USER_COOKIE, type UserListResponse } from '../models/user.js';
+
+const API_BASE = '';
+
+/**
+ * Fetch all users
+ */
+export async function fetchUsers(): Promise<UserInfo[]> {
+	const response = await fetch(`${API_BASE}/api/users`, {
+		method: 'GET',
+		headers: {
+			'Content-Type': 'application/json'
+		}
+	});
+
+	if (!response.ok) {
+		throw new Error(`Failed to fetch users: ${response.statusText}`);
+	}
+
+	const data: UserListResponse = await response.json();
+
+	if (!data.success || !data.data) {
+		throw new Error(data.message || 'Failed to fetch users');
+	}
+
+	return data.data;
+}
+
+/**
+ * The user picked while authentication is disabled, if any
+ */
+export function pickedUser(): string | null {
+	const cookie = document.cookie
+		.split(';')
+		.map((part) => part.trim())
+		.find((part) => part.startsWith(`${USER_COOKIE}=`));
+	return cookie ? decodeURIComponent(cookie.slice(USER_COOKIE.length + 1)) : null;
+}
+
+/**
+ * Pick whose chats to show while authentication is disabled
+ */
+export function pickUser(name: string | null): void {
+	document.cookie = name
+		? `${USER_COOKIE}=${encodeURIComponent(name)}; path=/; SameSite=Lax`
+		: `${USER_COOKIE}=; path=/; Max-Age=0`;
+}
@@ -1,10 +1,12 @@
 /**
  * Generate a URL-safe slug from a string
- * Converts to lowercase, replaces spaces and special characters with hyphens,
+ * Strips accents, converts to lowercase, replaces spaces and special characters with hyphens,
  * removes consecutive hyphens, and trims hyphens from ends
  */
 export function toSlug(input: string): string {
 	return input
+		.normalize('NFKD')
+		.replace(/[\u0300-\u036f]/g, '') // Strip combining accents so "Zoë" matches the backend's "zoe"
 		.toLowerCase()
 		.replace(/[^a-zA-Z0-9\s-]/g, '') // Remove special characters except spaces and hyphens
 		.replace(/[\s_]+/g, '-') // Replace spaces and underscores with hyphens
@@ -12,13 +14,6 @@ export function toSlug(input: string): string {
 		.replace(/^-+|-+$/g, ''); // Trim hyphens from start and end
 }
 
-/**
- * Generate a slug for a character based on their name
- */
-export function characterSlug(name: string): string {
-	return toSlug(name);
-}
-
 /**
  * Generate a slug for a prompt based on its title
  */
@@ -35,3 +30,10 @@ export function jobSlug(character: string, prompt: string): string {
 	const promptSlugStr = toSlug(prompt);
 	return `${characterSlugStr}-${promptSlugStr}`;
 }
+
+/**
+ * Generate a slug for a persona based on its name
+ */
+export function personaSlug(name: string): string {
+	return toSlug(name);
+}
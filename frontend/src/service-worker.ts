@@ -0,0 +1,40 @@
+/// <reference types="@sveltejs/kit" />
+/// <reference no-default-lib="true"/>
+/// <reference lib="esnext" />
+/// <reference lib="webworker" />
+
+// Shows push notifications for new messages; see backend/src/push.rs for the payload
+const sw = self as unknown as ServiceWorkerGlobalScope;
+
+interface MessageNotification {
+	title: string;
+	body: string;
+	character: string;
+	url: string;
+}
+
+sw.addEventListener('push', (event) => {
+	if (!event.data) {
+		return;
+	}
+	const notification: MessageNotification = event.data.json();
+	event.waitUntil(
+		sw.registration.showNotification(notification.title, {
+			body: notification.body,
+			// Newer messages from the same character replace the older notification
+			tag: notification.character,
+			data: { url: notification.url }
+		})
+	);
+});
+
+sw.addEventListener('notificationclick', (event) => {
+	event.notification.close();
+	const url: string = event.notification.data?.url ?? '/';
+	event.waitUntil(
+		sw.clients.matchAll({ type: 'window', includeUncontrolled: true }).then((windows) => {
+			const open = windows[0];
+			return open ? open.navigate(url).then((client) => client?.focus()) : sw.clients.openWindow(url);
+		})
+	);
+});